@@ -0,0 +1,168 @@
+use anyhow::Result;
+use glob::Pattern;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::tls::PeerCertificate;
+use crate::types::error::AuthError;
+use crate::types::{AccessLevel, AuthConfig, IdentityConfig};
+
+/// An authenticated caller and the directories it may access.
+///
+/// Every identity owns the private directories named `<identity>:*`.
+#[derive(Debug)]
+pub struct Identity {
+    pub name: String,
+    rules: Vec<(Pattern, AccessLevel)>,
+}
+
+impl Identity {
+    fn without_rules(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            rules: Vec::new(),
+        }
+    }
+
+    fn from_config(config: &IdentityConfig) -> Result<Self> {
+        let mut rules = Vec::new();
+        for rule in &config.permissions {
+            let pattern = Pattern::new(&rule.directory).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid directory pattern '{}' for identity '{}': {}",
+                    rule.directory,
+                    config.name,
+                    e
+                )
+            })?;
+            rules.push((pattern, rule.access));
+        }
+
+        Ok(Self {
+            name: config.name.clone(),
+            rules,
+        })
+    }
+
+    /// Highest access level granted on `directory` by any matching rule
+    pub fn access_to(&self, directory: &str) -> Option<AccessLevel> {
+        if self.owns(directory) {
+            return Some(AccessLevel::Admin);
+        }
+        self.rules
+            .iter()
+            .filter(|(pattern, _)| pattern.matches(directory))
+            .map(|(_, access)| *access)
+            .max()
+    }
+
+    /// Whether `directory` is one of this identity's private directories
+    pub fn owns(&self, directory: &str) -> bool {
+        directory
+            .strip_prefix(self.name.as_str())
+            .is_some_and(|rest| rest.starts_with(':'))
+    }
+
+    pub fn allows(&self, directory: &str, access: AccessLevel) -> bool {
+        self.access_to(directory)
+            .is_some_and(|granted| granted >= access)
+    }
+}
+
+/// Resolves bearer tokens and client certificates to identities
+#[derive(Debug)]
+pub struct Authenticator {
+    // Keyed by the SHA-256 of the token so the plain tokens aren't kept around
+    tokens: HashMap<String, Arc<Identity>>,
+    identities: HashMap<String, Arc<Identity>>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig) -> Result<Self> {
+        let mut tokens = HashMap::new();
+        let mut identities = HashMap::new();
+
+        for identity_config in &config.identities {
+            let identity = Arc::new(Identity::from_config(identity_config)?);
+            if identities
+                .insert(identity.name.clone(), identity.clone())
+                .is_some()
+            {
+                return Err(anyhow::anyhow!(
+                    "Identity '{}' is configured more than once",
+                    identity.name
+                ));
+            }
+            for token in &identity_config.tokens {
+                if token.is_empty() {
+                    return Err(anyhow::anyhow!(
+                        "Empty token configured for identity '{}'",
+                        identity_config.name
+                    ));
+                }
+                if tokens.insert(hash_token(token), identity.clone()).is_some() {
+                    return Err(anyhow::anyhow!(
+                        "Token for identity '{}' is already assigned to another identity",
+                        identity_config.name
+                    ));
+                }
+            }
+        }
+
+        Ok(Self { tokens, identities })
+    }
+
+    /// Authenticate the value of an `Authorization` header
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<Arc<Identity>, AuthError> {
+        let header = authorization.ok_or(AuthError::MissingCredentials)?;
+        let token = parse_bearer(header).ok_or(AuthError::InvalidCredentials)?;
+
+        self.tokens
+            .get(&hash_token(token))
+            .cloned()
+            .ok_or(AuthError::InvalidCredentials)
+    }
+
+    /// Identity of a client that presented a verified certificate.
+    ///
+    /// The first certificate name matching a configured identity wins; otherwise
+    /// the caller is known by its first name and only owns its private directories.
+    pub fn authenticate_certificate(&self, peer: &PeerCertificate) -> Arc<Identity> {
+        peer.names
+            .iter()
+            .find_map(|name| self.identities.get(name).cloned())
+            .unwrap_or_else(|| Arc::new(Identity::without_rules(peer.name())))
+    }
+}
+
+/// Check that `identity` holds at least `access` on `directory`
+pub fn authorize(
+    identity: &Identity,
+    directory: &str,
+    access: AccessLevel,
+) -> Result<(), AuthError> {
+    if identity.allows(directory, access) {
+        Ok(())
+    } else {
+        Err(AuthError::Forbidden {
+            identity: identity.name.clone(),
+            directory: directory.to_string(),
+            access: format!("{:?}", access).to_lowercase(),
+        })
+    }
+}
+
+fn parse_bearer(header: &str) -> Option<&str> {
+    let (scheme, token) = header.trim().split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("bearer") {
+        let token = token.trim();
+        (!token.is_empty()).then_some(token)
+    } else {
+        None
+    }
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
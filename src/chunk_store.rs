@@ -0,0 +1,373 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::storage::{is_not_found, ObjectInfo, ObjectReader, StorageBackend};
+use crate::utils::{split_blocks, TempFile};
+
+/// Prefix of the keys chunks are stored under
+const CHUNKS_PREFIX: &str = ".syncpair/chunks/";
+
+/// Key of the object that marks storage as having a chunk store
+const STORE_MARKER_KEY: &str = ".syncpair/chunk-store";
+
+/// Prefix of the keys manifests are stored under, each followed by the key of
+/// the content it stands for. Only the server writes there, so no uploaded
+/// file is ever taken for a manifest.
+const MANIFESTS_PREFIX: &str = ".syncpair/manifests/";
+
+/// A stored file's content as the chunks it is made of, in order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub hash: String,
+    pub size: u64,
+    pub chunks: Vec<ChunkRef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkRef {
+    pub hash: String,
+    pub length: u64,
+}
+
+/// Content-addressed store of file chunks, shared by every directory on the server.
+///
+/// Each chunk is kept once under `.syncpair/chunks/` in the server's storage,
+/// named after its SHA-256 hash. With deduplication, stored files, their prior
+/// versions and trashed files are manifests listing their chunks, kept under
+/// `.syncpair/manifests/` in place of the content, so content repeated across
+/// files, versions and directories takes space once. Chunks no manifest
+/// references any more are removed by `collect_garbage`.
+pub struct ChunkStore {
+    storage: Arc<dyn StorageBackend>,
+    average_size: u64,
+    // Chunks stored since the last collection started
+    stored: Mutex<HashSet<String>>,
+    // Chunks the last collection found unreferenced
+    unreferenced: Mutex<HashSet<String>>,
+}
+
+impl ChunkStore {
+    /// Open the chunk store in `storage`, creating it if needed
+    pub fn open(storage: Arc<dyn StorageBackend>, average_size: u64) -> Result<Self> {
+        if !Self::exists(storage.as_ref())? {
+            storage.put(STORE_MARKER_KEY, &mut std::io::empty(), 0)?;
+        }
+        Ok(Self {
+            storage,
+            average_size,
+            stored: Mutex::new(HashSet::new()),
+            unreferenced: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Whether `storage` has a chunk store, so its files can be manifests
+    pub fn exists(storage: &dyn StorageBackend) -> Result<bool> {
+        Ok(storage.stat(STORE_MARKER_KEY)?.is_some())
+    }
+
+    /// The key of the chunk with `hash`. Hashes come from clients too, so
+    /// anything but a SHA-256 hex digest has none.
+    pub fn chunk_key(hash: &str) -> Option<String> {
+        let valid = hash.len() == 64
+            && hash
+                .bytes()
+                .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte));
+        valid.then(|| format!("{}{}/{}", CHUNKS_PREFIX, &hash[..2], hash))
+    }
+
+    /// The key of the chunk with `hash` and `length`, if the store has it
+    pub fn find(&self, hash: &str, length: u64) -> Result<Option<String>> {
+        let Some(key) = Self::chunk_key(hash) else {
+            return Ok(None);
+        };
+        Ok(self
+            .storage
+            .stat(&key)?
+            .filter(|chunk| chunk.size == length)
+            .map(|_| key))
+    }
+
+    /// Split the file at `source` into chunks, add the ones the store lacks, and
+    /// return the file's manifest
+    pub fn store(&self, source: &Path) -> Result<Manifest> {
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut chunks = Vec::new();
+        split_blocks(File::open(source)?, self.average_size, |block, content| {
+            hasher.update(content);
+            size += block.length;
+            self.put_chunk(&block.hash, content)?;
+            chunks.push(ChunkRef {
+                hash: block.hash,
+                length: block.length,
+            });
+            Ok(())
+        })?;
+        Ok(Manifest {
+            hash: format!("{:x}", hasher.finalize()),
+            size,
+            chunks,
+        })
+    }
+
+    fn put_chunk(&self, hash: &str, content: &[u8]) -> Result<()> {
+        let key =
+            Self::chunk_key(hash).ok_or_else(|| anyhow::anyhow!("Invalid chunk hash: {}", hash))?;
+        // A chunk in use again is recent, so garbage collection spares it until
+        // the manifest that uses it is in place
+        self.stored.lock().unwrap().insert(hash.to_string());
+        if self.storage.stat(&key)?.is_some() {
+            return Ok(());
+        }
+        self.storage
+            .put(&key, &mut Cursor::new(content), content.len() as u64)
+    }
+
+    /// A reader of the content `manifest` lists
+    pub fn reader(&self, manifest: &Manifest) -> Result<ChunkReader> {
+        let mut chunks = Vec::with_capacity(manifest.chunks.len());
+        let mut offset = 0;
+        for chunk in &manifest.chunks {
+            let key = Self::chunk_key(&chunk.hash)
+                .ok_or_else(|| anyhow::anyhow!("Invalid chunk hash: {}", chunk.hash))?;
+            chunks.push((key, offset, chunk.length));
+            offset += chunk.length;
+        }
+        Ok(ChunkReader {
+            storage: self.storage.clone(),
+            chunks,
+            size: offset,
+            position: 0,
+            current: None,
+        })
+    }
+
+    /// The chunks holding `length` bytes at `start` of the content `manifest`
+    /// lists, as each chunk's key with the offset and length of its part
+    pub fn chunk_ranges(
+        manifest: &Manifest,
+        start: u64,
+        length: u64,
+    ) -> Result<Vec<(String, u64, u64)>> {
+        let end = start + length;
+        let mut ranges = Vec::new();
+        let mut offset = 0;
+        for chunk in &manifest.chunks {
+            let chunk_end = offset + chunk.length;
+            if chunk_end > start && offset < end {
+                let key = Self::chunk_key(&chunk.hash)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid chunk hash: {}", chunk.hash))?;
+                let from = start.max(offset);
+                ranges.push((key, from - offset, chunk_end.min(end) - from));
+            }
+            offset = chunk_end;
+        }
+        Ok(ranges)
+    }
+
+    /// The keys of the content stored as manifests whose keys start with `prefix`
+    pub fn manifest_keys(storage: &dyn StorageBackend, prefix: &str) -> Result<Vec<String>> {
+        Ok(storage
+            .list(&manifest_key(prefix))?
+            .into_iter()
+            .filter_map(|object| {
+                object
+                    .key
+                    .strip_prefix(MANIFESTS_PREFIX)
+                    .map(str::to_string)
+            })
+            .collect())
+    }
+
+    /// How many of the manifests of the content under `keys` reference each chunk
+    pub fn reference_counts(
+        storage: &dyn StorageBackend,
+        keys: &[String],
+    ) -> Result<HashMap<String, usize>> {
+        let mut counts = HashMap::new();
+        for key in keys {
+            // Moved or deleted since it was listed, or not a manifest
+            let Some(manifest) = read_manifest(storage, key)? else {
+                continue;
+            };
+            for chunk in manifest.chunks {
+                *counts.entry(chunk.hash).or_insert(0) += 1;
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Remove the chunks that no manifest references. A chunk goes only once two
+    /// collections in a row found it unreferenced and it wasn't stored again in
+    /// between, which spares the chunks of writes still in progress. Returns how
+    /// many chunks were removed.
+    pub fn collect_garbage(&self) -> Result<usize> {
+        let stored_since_last = std::mem::take(&mut *self.stored.lock().unwrap());
+        let keys = Self::manifest_keys(self.storage.as_ref(), "")?;
+        let counts = Self::reference_counts(self.storage.as_ref(), &keys)?;
+
+        let mut previously_unreferenced = self.unreferenced.lock().unwrap();
+        let mut still_unreferenced = HashSet::new();
+        let mut removed = 0;
+        for chunk in self.storage.list(CHUNKS_PREFIX)? {
+            let Some((_, hash)) = chunk.key.rsplit_once('/') else {
+                continue;
+            };
+            if Self::chunk_key(hash).as_deref() != Some(chunk.key.as_str())
+                || counts.contains_key(hash)
+            {
+                continue;
+            }
+            let stored_since =
+                stored_since_last.contains(hash) || self.stored.lock().unwrap().contains(hash);
+            if previously_unreferenced.contains(hash) && !stored_since {
+                self.storage.delete(&chunk.key)?;
+                removed += 1;
+            } else {
+                still_unreferenced.insert(hash.to_string());
+            }
+        }
+        *previously_unreferenced = still_unreferenced;
+        Ok(removed)
+    }
+}
+
+/// The key the manifest of the content under `key` is stored under
+pub fn manifest_key(key: &str) -> String {
+    format!("{}{}", MANIFESTS_PREFIX, key)
+}
+
+/// The manifest of the content under `key`, or None when the content is stored
+/// as itself
+pub fn read_manifest(storage: &dyn StorageBackend, key: &str) -> Result<Option<Manifest>> {
+    let object = match storage.get(&manifest_key(key), None) {
+        Ok(object) => object,
+        Err(e) if is_not_found(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some(serde_json::from_reader(std::io::BufReader::new(
+        object,
+    ))?))
+}
+
+/// A temporary file for `target` holding `manifest`, to commit as its manifest
+pub fn write_manifest(target: &Path, manifest: &Manifest) -> Result<TempFile> {
+    TempFile::write(target, &serde_json::to_vec(manifest)?)
+}
+
+/// The object holding the content under `key`: the content itself or its manifest
+pub fn stat_content(storage: &dyn StorageBackend, key: &str) -> Result<Option<ObjectInfo>> {
+    match storage.stat(key)? {
+        Some(object) => Ok(Some(object)),
+        None => storage.stat(&manifest_key(key)),
+    }
+}
+
+/// Move the content under `from`, stored as itself or as a manifest, to `to`
+pub fn rename_content(storage: &dyn StorageBackend, from: &str, to: &str) -> Result<()> {
+    match storage.rename(from, to) {
+        Err(e) if is_not_found(&e) => storage.rename(&manifest_key(from), &manifest_key(to)),
+        result => result,
+    }
+}
+
+/// Remove the content under `key`, stored as itself or as a manifest
+pub fn delete_content(storage: &dyn StorageBackend, key: &str) -> Result<()> {
+    storage.delete(key)?;
+    storage.delete(&manifest_key(key))
+}
+
+/// Reads the content a manifest lists from its chunks, opening each chunk as
+/// the position reaches it
+pub struct ChunkReader {
+    storage: Arc<dyn StorageBackend>,
+    // Each chunk's key, offset in the content and length
+    chunks: Vec<(String, u64, u64)>,
+    size: u64,
+    position: u64,
+    current: Option<(usize, Box<dyn Read + Send>)>,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self
+            .chunks
+            .partition_point(|(_, offset, length)| offset + length <= self.position);
+        let (key, offset, length) = &self.chunks[index];
+        let chunk = match &mut self.current {
+            Some((current, chunk)) if *current == index => chunk,
+            current => {
+                let start = self.position - offset;
+                let chunk = self
+                    .storage
+                    .get(key, Some((start, length - start)))
+                    .map_err(std::io::Error::other)?;
+                &mut current.insert((index, chunk)).1
+            }
+        };
+        let available = (offset + length - self.position).min(buf.len() as u64) as usize;
+        let read = chunk.read(&mut buf[..available])?;
+        if read == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("Chunk {} is shorter than its manifest says", key),
+            ));
+        }
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ChunkReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek to a negative position",
+            )
+        })?;
+        if position != self.position {
+            self.position = position;
+            self.current = None;
+        }
+        Ok(position)
+    }
+}
+
+/// Reads stored content, which is either an object holding it or the chunks of a manifest
+pub enum ContentReader {
+    Object(ObjectReader),
+    Chunks(ChunkReader),
+}
+
+impl Read for ContentReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ContentReader::Object(object) => object.read(buf),
+            ContentReader::Chunks(chunks) => chunks.read(buf),
+        }
+    }
+}
+
+impl Seek for ContentReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            ContentReader::Object(object) => object.seek(pos),
+            ContentReader::Chunks(chunks) => chunks.seek(pos),
+        }
+    }
+}
@@ -1,60 +1,115 @@
 use anyhow::Result;
 use futures::stream::{self, StreamExt};
 use notify::{Event, EventKind, RecursiveMode, Result as NotifyResult, Watcher};
-use reqwest::Client;
+use reqwest::header::{CONTENT_TYPE, IF_RANGE, RANGE};
+use reqwest::{Body, Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::io::{Read, Seek, SeekFrom};
-use std::path::PathBuf;
-use std::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tokio::sync::broadcast;
 use tokio::time::{interval, sleep, MissedTickBehavior};
+use tokio_util::io::ReaderStream;
 use tracing::{debug, error, info, warn};
 
+use crate::paths::{resolve_within, RESERVED_DIR_NAME};
+use crate::types::error::{FolderIdentityError, WriteConflict};
 use crate::types::{
-    BlockUploadRequest, BlockUploadResponse, DeleteRequest, DeleteResponse, DeltaCompleteRequest,
-    DeltaCompleteResponse, DeltaInitRequest, DeltaInitResponse, DownloadResponse, FileInfo,
-    SyncRequest, SyncResponse, UploadRequest, UploadResponse,
+    BlockMsg, BlockUploadResponse, ChangeEvent, ChangesRequest, ClientState, ConflictPolicy,
+    ConflictRecord, DeleteRequest, DeleteResponse, DeletionPause, DeltaCompleteRequest,
+    DeltaCompleteResponse, DeltaDownloadRequest, DeltaDownloadResponse, DeltaInitRequest,
+    DeltaInitResponse, DownloadProgress, DownloadResponse, FileChange, FileInfo, RestoreRequest,
+    RestoreResponse, SyncCursor, SyncRequest, SyncResponse, UploadProgress, UploadResponse,
+    UploadSessionRequest, UploadSessionResponse, VersionVector, WriteConflictResponse,
+    BASE_HASH_HEADER, CLIENT_ID_HEADER, HASH_HEADER, MODIFIED_HEADER, OFFSET_HEADER, PATH_HEADER,
+    VERSION_HEADER,
 };
 use crate::utils::{
-    calculate_block_hashes, calculate_file_hash, get_file_info, load_client_state_db,
-    save_client_state_db, scan_directory_with_patterns,
+    calculate_block_hashes, calculate_file_hash, conflict_copy_path, copy_range,
+    copy_to_local_trash, decode_version_header, encode_version_header, get_file_info,
+    load_client_state_db, load_conflicts, load_deletion_pause, load_download_progress,
+    load_folder_id, load_known_folder, load_replica_id, load_sync_cursor, load_upload_progress,
+    move_to_local_trash, partial_download_path, purge_local_trash, read_folder_marker,
+    received_prefix, record_conflict, remove_stale_downloads, remove_temp_files, replace_file,
+    resolve_removed_conflicts, save_client_state_db, save_deletion_pause, save_download_progress,
+    save_folder_id, save_known_folder, save_sync_cursor, save_upload_progress,
+    scan_directory_cached, write_folder_marker, TempFile,
 };
 
 const DELTA_SYNC_THRESHOLD: u64 = 1024 * 1024; // 1 MB
-const BLOCK_SIZE: u64 = 1024 * 1024; // 1 MB
+const BLOCK_SIZE: u64 = 1024 * 1024; // 1 MB on average
+
+/// Files larger than this are uploaded in chunks of this size through a resumable
+/// upload session, and their download progress is saved every this many bytes
+const TRANSFER_CHUNK_SIZE: u64 = 8 * 1024 * 1024; // 8 MB
+/// Limit on a single upload or download request, instead of the default 30 seconds
+const TRANSFER_REQUEST_TIMEOUT: Duration = Duration::from_secs(3600);
+/// A download that receives nothing for this long is abandoned, to resume later
+const TRANSFER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The percentage rule of the mass-deletion guard only applies to at least this many deletions
+const DELETION_GUARD_MIN_FILES: usize = 10;
+
+/// How long to wait for more deletions after a file is deleted, before syncing
+const DELETION_BATCH_DELAY: Duration = Duration::from_millis(500);
+/// First delay before resubscribing to change notifications; it doubles up to the sync interval
+const EVENTS_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// The server sends a keep-alive every 15 seconds; a quieter stream is considered dead
+const EVENTS_IDLE_TIMEOUT: Duration = Duration::from_secs(45);
+/// Subscriptions are renewed at least this often
+const EVENTS_REQUEST_TIMEOUT: Duration = Duration::from_secs(3600);
 
 #[derive(Clone)]
 pub struct SimpleClient {
     server_url: String,
     watch_dir: PathBuf,
     state_db: PathBuf,
+    // Records the folder outside of it, to notice it missing (see `with_folder_registry`)
+    folder_registry: Option<PathBuf>,
     http_client: Client,
     sync_interval: Duration,
     client_id: Option<String>,
     directory: Option<String>,
     exclude_patterns: Vec<String>,
+    auth_token: Option<String>,
+    conflict_policy: ConflictPolicy,
+    // Days files removed or overwritten by sync stay in the local trash; 0 deletes them
+    trash_retention_days: u32,
+    // Deleting more than this percentage, or this many, of the tracked files in one
+    // sync pauses the directory; 0 turns a rule off
+    deletion_guard_percent: u32,
+    deletion_guard_files: usize,
+    // Set until a scan has rehashed every file instead of trusting the hash cache
+    verify_pending: Arc<AtomicBool>,
 }
 
 impl SimpleClient {
     pub fn new(server_url: String, watch_dir: PathBuf) -> Self {
         let state_db = watch_dir.join(".syncpair_state.db");
 
-        // Create HTTP client with timeouts
-        let http_client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .connect_timeout(Duration::from_secs(10))
-            .build()
-            .expect("Failed to create HTTP client");
+        let http_client = build_http_client(None).expect("Failed to create HTTP client");
 
         Self {
             server_url,
             watch_dir,
             state_db,
+            folder_registry: None,
             http_client,
             sync_interval: Duration::from_secs(30), // Default: sync every 30 seconds
             client_id: None,
             directory: None,
             exclude_patterns: Vec::new(),
+            auth_token: None,
+            conflict_policy: ConflictPolicy::default(),
+            trash_retention_days: 30, // Default: keep trashed files for 30 days
+            deletion_guard_percent: 50,
+            deletion_guard_files: 500,
+            verify_pending: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -78,6 +133,157 @@ impl SimpleClient {
         self
     }
 
+    /// Verify the server with a custom TLS configuration (see `tls::client_tls_config`)
+    pub fn with_tls_config(mut self, tls_config: rustls::ClientConfig) -> Result<Self> {
+        self.http_client = build_http_client(Some(tls_config))?;
+        Ok(self)
+    }
+
+    /// Send `token` as a bearer token with every request
+    pub fn with_token(mut self, token: String) -> Self {
+        self.auth_token = Some(token);
+        self
+    }
+
+    /// How to settle files that changed both locally and on the server
+    pub fn with_conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// Keep files that sync deletes or overwrites in `.syncpair/trash/` for `days`
+    /// days; with 0 they are deleted right away
+    pub fn with_trash_retention_days(mut self, days: u32) -> Self {
+        self.trash_retention_days = days;
+        self
+    }
+
+    /// Pause the directory instead of syncing when more than `percent`% or more
+    /// than `files` of the tracked files were deleted since the last sync, until
+    /// the deletions are confirmed; 0 turns a rule off
+    pub fn with_deletion_guard(mut self, percent: u32, files: usize) -> Self {
+        self.deletion_guard_percent = percent;
+        self.deletion_guard_files = files;
+        self
+    }
+
+    /// Record the watched folder in the folder registry at `db_path`, outside the
+    /// folder. Once synced, a folder that is missing or lost its marker is never
+    /// created or marked again, even when its state database went with it.
+    pub fn with_folder_registry(mut self, db_path: PathBuf) -> Self {
+        self.folder_registry = Some(db_path);
+        self
+    }
+
+    /// Rehash every file on the next scan instead of trusting the hash cache
+    pub fn with_full_verification(self) -> Self {
+        self.verify_pending.store(true, Ordering::SeqCst);
+        self
+    }
+
+    fn get(&self, url: &str) -> RequestBuilder {
+        self.authorized(self.http_client.get(url))
+    }
+
+    fn post(&self, url: &str) -> RequestBuilder {
+        self.authorized(self.http_client.post(url))
+    }
+
+    fn put(&self, url: &str) -> RequestBuilder {
+        self.authorized(self.http_client.put(url))
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.auth_token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// This client's key in version vectors: its client ID, or a random ID kept in
+    /// the state database when it has none
+    fn replica_id(&self) -> Result<String> {
+        match &self.client_id {
+            Some(client_id) => Ok(client_id.clone()),
+            None => load_replica_id(&self.state_db),
+        }
+    }
+
+    /// Check that the watched directory is the folder synced before, creating and
+    /// marking it on the first sync. A folder known to the folder registry or its
+    /// state database must exist and carry the matching marker, so a missing or
+    /// swapped folder is never synced.
+    fn verify_folder_identity(&self) -> Result<()> {
+        let known = match &self.folder_registry {
+            Some(registry) => load_known_folder(registry, &self.watch_dir)?,
+            None => None,
+        };
+        if !self.watch_dir.is_dir() {
+            if known.is_some() {
+                return Err(FolderIdentityError::MissingFolder(
+                    self.watch_dir.display().to_string(),
+                )
+                .into());
+            }
+            std::fs::create_dir_all(&self.watch_dir)?;
+        }
+
+        let marker = read_folder_marker(&self.watch_dir)?;
+        let expected = match known {
+            Some(folder_id) => Some(folder_id),
+            None => load_folder_id(&self.state_db)?,
+        };
+        match (expected, marker) {
+            (Some(expected), Some(found)) if expected == found => self.record_folder(&expected),
+            (Some(expected), Some(found)) => Err(FolderIdentityError::Mismatch {
+                path: self.watch_dir.display().to_string(),
+                expected,
+                found,
+            }
+            .into()),
+            (Some(_), None) => {
+                Err(FolderIdentityError::MissingMarker(self.watch_dir.display().to_string()).into())
+            }
+            // State from before folder markers, or a marked folder with a new state database
+            (None, marker) => {
+                let folder_id = match marker {
+                    Some(folder_id) => folder_id,
+                    None => write_folder_marker(&self.watch_dir)?,
+                };
+                save_folder_id(&self.state_db, &folder_id)?;
+                self.record_folder(&folder_id)
+            }
+        }
+    }
+
+    /// Remember outside the folder that it was synced as `folder_id`
+    fn record_folder(&self, folder_id: &str) -> Result<()> {
+        match &self.folder_registry {
+            Some(registry) => save_known_folder(registry, &self.watch_dir, folder_id),
+            None => Ok(()),
+        }
+    }
+
+    /// Scan the watched directory, rehashing only files whose metadata changed
+    fn scan_local_files(&self) -> Result<Vec<FileInfo>> {
+        let full_verification = self.verify_pending.load(Ordering::SeqCst);
+        if full_verification {
+            info!(
+                "Verifying the hashes of all files in {}",
+                self.watch_dir.display()
+            );
+        }
+
+        let files = scan_directory_cached(
+            &self.watch_dir,
+            &self.exclude_patterns,
+            &self.state_db,
+            full_verification,
+        )?;
+        self.verify_pending.store(false, Ordering::SeqCst);
+        Ok(files)
+    }
+
     pub async fn initial_sync(&self) -> Result<()> {
         if self.directory.is_none() {
             return Err(anyhow::anyhow!(
@@ -85,48 +291,173 @@ impl SimpleClient {
             ));
         }
 
+        self.verify_folder_identity()?;
+
         info!("Starting bidirectional sync...");
 
-        let current_files = scan_directory_with_patterns(&self.watch_dir, &self.exclude_patterns)?;
+        if self.trash_retention_days > 0 {
+            match purge_local_trash(
+                &self.watch_dir,
+                self.trash_retention_days,
+                chrono::Utc::now(),
+            ) {
+                Ok(0) => {}
+                Ok(purged) => info!("🗑️  Purged {} expired folders from the local trash", purged),
+                Err(e) => warn!("Failed to purge the local trash: {}", e),
+            }
+        }
+
+        let replica = self.replica_id()?;
+        let current_files = self.scan_local_files()?;
         let mut state = load_client_state_db(&self.state_db)?;
 
         // Build client file map
         let mut client_files = std::collections::HashMap::new();
         for file_info in current_files {
+            let known = state.files.get(&file_info.path);
+            let file_info = with_version(file_info, known, &replica);
             client_files.insert(file_info.path.clone(), file_info);
         }
 
+        // Hold back a mass deletion until it is confirmed
+        let deleted = state
+            .files
+            .keys()
+            .filter(|path| !client_files.contains_key(*path))
+            .count();
+        if !self.deletions_allowed(deleted, state.files.len())? {
+            return Ok(());
+        }
+
         // Detect files that were deleted since last sync
         let mut newly_deleted_files = std::collections::HashMap::new();
-        for old_path in state.files.keys() {
+        for (old_path, old_file) in &state.files {
             if !client_files.contains_key(old_path) {
                 info!("🗑️  Detected deletion: {}", old_path);
                 let deletion_time = chrono::Utc::now();
                 newly_deleted_files.insert(old_path.clone(), deletion_time);
+                state
+                    .deleted_versions
+                    .insert(old_path.clone(), old_file.version.incremented(&replica));
+            }
+        }
+
+        // Define concurrency limit
+        const CONCURRENCY_LIMIT: usize = 10;
+
+        // Deletions go to the server first, based on the version they removed, so
+        // that one can't remove an edit made on the server since the last sync
+        if !newly_deleted_files.is_empty() {
+            let delete_tasks = stream::iter(newly_deleted_files.keys().cloned())
+                .map(|path| {
+                    let client = self.clone();
+                    let base_hash = state.files[&path].hash.clone();
+                    let version = state.deleted_versions[&path].clone();
+                    async move {
+                        let result = client
+                            .send_delete_request(&path, Some(&base_hash), version)
+                            .await;
+                        (path, result)
+                    }
+                })
+                .buffer_unordered(CONCURRENCY_LIMIT);
+
+            let results: Vec<_> = delete_tasks.collect().await;
+            for (path, result) in results {
+                let conflict = match result {
+                    Ok(()) => {
+                        debug!("✓ Deletion synced to server: {}", path);
+                        continue;
+                    }
+                    Err(e) => match e.downcast::<WriteConflict>() {
+                        Ok(conflict) => conflict,
+                        // The sync request below carries the deletion too
+                        Err(e) => {
+                            error!("Failed to send delete request for {}: {}", path, e);
+                            continue;
+                        }
+                    },
+                };
+                match self.resolve_write_conflict(&conflict, None).await {
+                    Ok(Some(restored)) => {
+                        // The server version was restored
+                        newly_deleted_files.remove(&path);
+                        state.deleted_versions.remove(&path);
+                        state.files.insert(path.clone(), restored.clone());
+                        client_files.insert(path, restored);
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!("✗ Failed to settle the deletion of {}: {}", path, e),
+                }
             }
         }
 
+        // The sync request carries the deletions too, in case sending one failed
+        let deleted_base_hashes: HashMap<String, String> = newly_deleted_files
+            .keys()
+            .map(|path| (path.clone(), state.files[path].hash.clone()))
+            .collect();
+
+        // Local changes since the last sync, for an incremental sync
+        let mut local_changes: Vec<FileChange> = client_files
+            .values()
+            .filter(|file_info| {
+                state
+                    .files
+                    .get(&file_info.path)
+                    .is_none_or(|old| old.hash != file_info.hash)
+            })
+            .map(|file_info| FileChange::Modified(file_info.clone()))
+            .collect();
+        local_changes.extend(newly_deleted_files.iter().map(|(path, deleted_at)| {
+            FileChange::Deleted {
+                path: path.clone(),
+                deleted_at: *deleted_at,
+                version: state.deleted_versions[path].clone(),
+                base_hash: deleted_base_hashes.get(path).cloned(),
+            }
+        }));
+
         // Add newly deleted files to the deleted files map
         state.deleted_files.extend(newly_deleted_files);
 
-        // Send sync request to server
-        let sync_request = SyncRequest {
-            files: client_files.clone(),
-            deleted_files: state.deleted_files.clone(),
-            last_sync: state.last_sync,
-            client_id: self.client_id.clone(),
-            directory: self.directory.clone(),
+        let sync_response = match load_sync_cursor(&self.state_db)? {
+            Some(cursor) => {
+                let response = self.request_changes(cursor, local_changes).await?;
+                if response.cursor_expired {
+                    info!("Sync cursor expired, falling back to a full sync");
+                    self.request_full_sync(&client_files, &state, &deleted_base_hashes)
+                        .await?
+                } else {
+                    response
+                }
+            }
+            None => {
+                self.request_full_sync(&client_files, &state, &deleted_base_hashes)
+                    .await?
+            }
+        };
+        for path in &sync_response.rejected_paths {
+            warn!(
+                "⚠️  Server rejected invalid path, not syncing it: {:?}",
+                path
+            );
+        }
+        let mut failures = 0;
+        let keep_both = self.conflict_policy == ConflictPolicy::KeepBoth;
+        // Versions that settled a write conflict, to record in the state
+        let mut settled_versions = HashMap::new();
+
+        // A local file the server wants to replace or delete loses a conflict
+        // if it changed since the last sync
+        let changed_locally = |path: &str| {
+            client_files.get(path).is_some_and(|local| {
+                state
+                    .files
+                    .get(path)
+                    .is_none_or(|base| base.hash != local.hash)
+            })
         };
-
-        let url = format!("{}/sync", self.server_url);
-        let sync_response: SyncResponse = self
-            .http_client
-            .post(&url)
-            .json(&sync_request)
-            .send()
-            .await?
-            .json()
-            .await?;
 
         // Handle conflicts first
         for conflict in &sync_response.conflicts {
@@ -142,26 +473,38 @@ impl SimpleClient {
                 &conflict.server_file.hash[..8]
             );
 
-            // For now, use a simple strategy: newer file wins, client wins on tie
+            if keep_both {
+                // The server version is downloaded below, after keeping a copy of ours
+                continue;
+            }
+
+            // Newer file wins, client wins on tie
             if conflict.server_file.modified > conflict.client_file.modified {
                 info!("   → Downloading server version (newer)");
                 if let Err(e) = self.download_file(&conflict.path).await {
                     error!("   ✗ Failed to download {}: {}", conflict.path, e);
                     warn!("   → Keeping client version instead");
+                    failures += 1;
                 }
             } else {
                 info!("   → Uploading client version (newer or same time)");
                 if let Some(file_info) = client_files.get(&conflict.path) {
-                    if let Err(e) = self.upload_file(file_info).await {
-                        error!("   ✗ Failed to upload {}: {}", conflict.path, e);
+                    // Deliberately replaces the server version we were told about
+                    let file_info = overriding(file_info, &conflict.server_file, &replica);
+                    let base_hash = Some(conflict.server_file.hash.as_str());
+                    match self.upload_file(&file_info, base_hash).await {
+                        Ok(()) => {
+                            settled_versions.insert(conflict.path.clone(), file_info);
+                        }
+                        Err(e) => {
+                            error!("   ✗ Failed to upload {}: {}", conflict.path, e);
+                            failures += 1;
+                        }
                     }
                 }
             }
         }
 
-        // Define concurrency limit
-        const CONCURRENCY_LIMIT: usize = 10;
-
         if !sync_response.files_to_upload.is_empty() {
             info!(
                 "Processing {} uploads...",
@@ -169,35 +512,82 @@ impl SimpleClient {
             );
             // Clone the vector to own the data for the stream
             let files_to_upload = sync_response.files_to_upload.clone();
-            let upload_tasks = stream::iter(files_to_upload.into_iter())
+            let upload_tasks = stream::iter(files_to_upload)
                 .map(|file_path| {
                     // Clone file_info if found to move into async block
                     let file_info = client_files.get(&file_path).cloned(); // file_info needs to be Clone
                     let client = self.clone(); // Clone client for shared state
+                    let base_hash = last_synced_hash(&state, &file_path);
 
+                    // Ok(Some(_)) when a write conflict left another version at the path
                     async move {
-                        if let Some(file_info) = file_info {
-                            debug!("↑ Uploading: {}", file_path);
-                            if let Err(e) = client.upload_file(&file_info).await {
-                                error!("✗ Failed to upload {}: {}", file_path, e);
-                            }
-                        }
+                        let Some(file_info) = file_info else {
+                            return Ok(None);
+                        };
+                        debug!("↑ Uploading: {}", file_path);
+                        let result = match client.upload_file(&file_info, Some(&base_hash)).await {
+                            Err(e) => match e.downcast::<WriteConflict>() {
+                                Ok(conflict) => client
+                                    .resolve_write_conflict(&conflict, Some(&file_info))
+                                    .await
+                                    .map(|settled| {
+                                        settled.map(|settled| (file_path.clone(), settled))
+                                    }),
+                                Err(e) => Err(e),
+                            },
+                            Ok(()) => Ok(None),
+                        };
+                        result.map_err(|e| error!("✗ Failed to upload {}: {}", file_path, e))
                     }
                 })
                 .buffer_unordered(CONCURRENCY_LIMIT);
 
-            upload_tasks.collect::<Vec<()>>().await;
+            let results: Vec<_> = upload_tasks.collect().await;
+            for result in results {
+                match result {
+                    Ok(settled) => settled_versions.extend(settled),
+                    Err(()) => failures += 1,
+                }
+            }
+        }
+
+        // Remote changes the client already has (e.g. its own uploads) need no download,
+        // nor do conflicts that were settled above
+        let files_to_download: Vec<FileInfo> = sync_response
+            .files_to_download
+            .iter()
+            .filter(|file_info| {
+                client_files
+                    .get(&file_info.path)
+                    .is_none_or(|local| local.hash != file_info.hash)
+                    && !settled_versions.contains_key(&file_info.path)
+            })
+            .cloned()
+            .collect();
+
+        // Move losing local versions aside so the downloads and deletions don't discard them
+        let mut conflict_copies = Vec::new();
+        if keep_both {
+            let losing_paths = files_to_download
+                .iter()
+                .map(|file_info| &file_info.path)
+                .chain(&sync_response.files_to_delete)
+                .filter(|path| changed_locally(path));
+            for path in losing_paths {
+                match self.keep_conflict_copy(path) {
+                    Ok(copy) => conflict_copies.push(copy),
+                    Err(e) => {
+                        error!("✗ Failed to keep a conflict copy of {}: {}", path, e);
+                        failures += 1;
+                    }
+                }
+            }
         }
 
         // Process downloads in parallel
-        if !sync_response.files_to_download.is_empty() {
-            info!(
-                "Processing {} downloads...",
-                sync_response.files_to_download.len()
-            );
-            // Clone the vector to own the data for the stream
-            let files_to_download = sync_response.files_to_download.clone();
-            let download_tasks = stream::iter(files_to_download.into_iter())
+        if !files_to_download.is_empty() {
+            info!("Processing {} downloads...", files_to_download.len());
+            let download_tasks = stream::iter(files_to_download)
                 .map(|file_info| {
                     let client = self.clone();
                     async move {
@@ -205,41 +595,82 @@ impl SimpleClient {
                         if let Err(e) = client.download_file(&file_info.path).await {
                             error!("✗ Failed to download {}: {}", file_info.path, e);
                             warn!("  → File may have been deleted from server or is inaccessible");
+                            return false;
                         }
+                        true
                     }
                 })
                 .buffer_unordered(CONCURRENCY_LIMIT);
 
-            download_tasks.collect::<Vec<()>>().await;
+            failures += download_tasks
+                .filter(|ok| futures::future::ready(!ok))
+                .count()
+                .await;
         }
 
         // Process deletions in parallel
-        if !sync_response.files_to_delete.is_empty() {
-            info!(
-                "Processing {} deletions...",
-                sync_response.files_to_delete.len()
-            );
-            // Clone the vector to own the data for the stream
-            let files_to_delete = sync_response.files_to_delete.clone();
-            let delete_tasks = stream::iter(files_to_delete.into_iter())
+        let files_to_delete: Vec<String> = sync_response
+            .files_to_delete
+            .iter()
+            .filter(|path| {
+                client_files.contains_key(*path) && !(keep_both && changed_locally(path))
+            })
+            .cloned()
+            .collect();
+        if !files_to_delete.is_empty() {
+            info!("Processing {} deletions...", files_to_delete.len());
+            let delete_tasks = stream::iter(files_to_delete)
                 .map(|file_path| {
                     let client = self.clone();
                     async move {
                         debug!("🗑️  Deleting: {}", file_path);
                         if let Err(e) = client.delete_file(&file_path).await {
                             error!("✗ Failed to delete {}: {}", file_path, e);
+                            return false;
                         }
+                        true
                     }
                 })
                 .buffer_unordered(CONCURRENCY_LIMIT);
 
-            delete_tasks.collect::<Vec<()>>().await;
+            failures += delete_tasks
+                .filter(|ok| futures::future::ready(!ok))
+                .count()
+                .await;
         }
 
-        // Update state with all current files (re-scan after downloads)
-        let final_files = scan_directory_with_patterns(&self.watch_dir, &self.exclude_patterns)?;
+        // Conflict copies are new files and sync like any other
+        for copy in &conflict_copies {
+            let copy = with_version(copy.clone(), None, &replica);
+            if let Err(e) = self.upload_file(&copy, Some("")).await {
+                error!("✗ Failed to upload conflict copy {}: {}", copy.path, e);
+                failures += 1;
+            }
+        }
+        let resolved = resolve_removed_conflicts(&self.state_db, &self.watch_dir)?;
+        if resolved > 0 {
+            info!("{} conflicts resolved", resolved);
+        }
+
+        // Update state with all current files (re-scan after downloads). Content that
+        // came from the server, or settled a conflict, takes on that version.
+        for file_info in &sync_response.files_to_download {
+            settled_versions
+                .entry(file_info.path.clone())
+                .or_insert_with(|| file_info.clone());
+        }
+        let final_files = self.scan_local_files()?;
         state.files.clear();
-        for file_info in final_files {
+        for mut file_info in final_files {
+            match settled_versions.get(&file_info.path) {
+                Some(settled) if settled.hash == file_info.hash => {
+                    file_info.version = settled.version.clone();
+                }
+                _ => {
+                    let known = client_files.get(&file_info.path);
+                    file_info = with_version(file_info, known, &replica);
+                }
+            }
             state.files.insert(file_info.path.clone(), file_info);
         }
 
@@ -248,14 +679,67 @@ impl SimpleClient {
         state
             .deleted_files
             .retain(|_, deletion_time| *deletion_time > cutoff_time);
+        let deleted_files = &state.deleted_files;
+        state
+            .deleted_versions
+            .retain(|path, _| deleted_files.contains_key(path));
 
         state.last_sync = chrono::Utc::now();
         save_client_state_db(&state, &self.state_db)?;
 
+        // Changes that failed to apply are only picked up again by a full sync
+        if failures > 0 {
+            warn!(
+                "{} files failed to sync, the next sync will be a full sync",
+                failures
+            );
+            save_sync_cursor(&self.state_db, None)?;
+        } else {
+            save_sync_cursor(&self.state_db, sync_response.cursor.as_ref())?;
+        }
+
         info!("✓ Bidirectional sync completed");
         Ok(())
     }
 
+    /// Exchange the full file lists with the server
+    async fn request_full_sync(
+        &self,
+        client_files: &HashMap<String, FileInfo>,
+        state: &ClientState,
+        deleted_base_hashes: &HashMap<String, String>,
+    ) -> Result<SyncResponse> {
+        let sync_request = SyncRequest {
+            files: client_files.clone(),
+            deleted_files: state.deleted_files.clone(),
+            deleted_versions: state.deleted_versions.clone(),
+            deleted_base_hashes: deleted_base_hashes.clone(),
+            last_sync: state.last_sync,
+            client_id: self.client_id.clone(),
+            directory: self.directory.clone(),
+        };
+
+        let url = format!("{}/sync", self.server_url);
+        read_json(self.post(&url).json(&sync_request).send().await?).await
+    }
+
+    /// Exchange only the changes since `cursor` with the server
+    async fn request_changes(
+        &self,
+        cursor: SyncCursor,
+        changes: Vec<FileChange>,
+    ) -> Result<SyncResponse> {
+        let changes_request = ChangesRequest {
+            cursor,
+            changes,
+            client_id: self.client_id.clone(),
+            directory: self.directory.clone(),
+        };
+
+        let url = format!("{}/sync/changes", self.server_url);
+        read_json(self.post(&url).json(&changes_request).send().await?).await
+    }
+
     async fn initial_sync_with_retries(&self) -> Result<()> {
         let max_retries = 5;
         let mut retry_delay = std::time::Duration::from_secs(1);
@@ -265,6 +749,8 @@ impl SimpleClient {
                 Ok(()) => {
                     return Ok(());
                 }
+                // Retrying won't bring the right folder back
+                Err(e) if e.is::<FolderIdentityError>() => return Err(e),
                 Err(e) => {
                     if attempt == max_retries {
                         return Err(anyhow::anyhow!(
@@ -309,6 +795,22 @@ impl SimpleClient {
             self.sync_interval.as_secs()
         );
 
+        // Nothing may touch a folder that isn't the one synced before
+        self.verify_folder_identity()?;
+
+        // Writes interrupted by a crash left their temporary files behind
+        match remove_temp_files(&self.watch_dir) {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} leftover temporary files", removed),
+            Err(e) => warn!("Failed to remove leftover temporary files: {}", e),
+        }
+        // Partial downloads are kept only while they can resume
+        match remove_stale_downloads(&self.watch_dir, &self.state_db) {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {} stale partial downloads", removed),
+            Err(e) => warn!("Failed to remove stale partial downloads: {}", e),
+        }
+
         // Perform initial sync with retries
         self.initial_sync_with_retries().await?;
 
@@ -338,6 +840,11 @@ impl SimpleClient {
         let mut sync_timer = interval(self.sync_interval);
         sync_timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
+        // Pull remote changes as soon as the server announces them; the timer
+        // above keeps syncing whenever the notification stream is down
+        let (remote_tx, mut remote_rx) = tokio::sync::mpsc::unbounded_channel();
+        let notifications = tokio::spawn(self.clone().watch_remote_changes(remote_tx));
+
         // Process file change events with proper shutdown handling and periodic sync
         loop {
             tokio::select! {
@@ -374,9 +881,52 @@ impl SimpleClient {
                     }
                 }
 
+                // Remote change notifications
+                Some(event) = remote_rx.recv() => {
+                    // Coalesce bursts of notifications into a single sync
+                    sleep(Duration::from_millis(100)).await;
+                    let mut events = vec![event];
+                    while let Ok(event) = remote_rx.try_recv() {
+                        events.push(event);
+                    }
+
+                    match self.has_unseen_changes(&events) {
+                        Ok(false) => {}
+                        Ok(true) => {
+                            debug!("🔔 Remote changes announced, syncing...");
+                            if let Err(e) = self.initial_sync().await {
+                                error!("Error during sync after change notification: {}", e);
+                            }
+                        }
+                        Err(e) => error!("Error handling change notification: {}", e),
+                    }
+                }
+
                 // Check for file system events
                 event = async_rx.recv() => {
                     match event {
+                        Some(event) if matches!(event.kind, EventKind::Remove(_)) => {
+                            // Collect a burst of deletions into one sync, so the
+                            // mass-deletion guard sees all of it
+                            sleep(DELETION_BATCH_DELAY).await;
+                            let mut events = vec![event];
+                            while let Ok(event) = async_rx.try_recv() {
+                                events.push(event);
+                            }
+                            let mut deletions = false;
+                            for event in events {
+                                if matches!(event.kind, EventKind::Remove(_)) {
+                                    deletions |= self.has_deletions(&event);
+                                } else if let Err(e) = self.handle_file_event(event).await {
+                                    error!("Error handling file event: {}", e);
+                                }
+                            }
+                            if deletions {
+                                if let Err(e) = self.initial_sync().await {
+                                    error!("Error syncing deletions: {}", e);
+                                }
+                            }
+                        }
                         Some(event) => {
                             if let Err(e) = self.handle_file_event(event).await {
                                 error!("Error handling file event: {}", e);
@@ -391,6 +941,8 @@ impl SimpleClient {
             }
         }
 
+        notifications.abort();
+
         // Save final state before stopping
         if let Err(e) = self.save_final_state().await {
             warn!("Warning: Failed to save final client state: {}", e);
@@ -400,7 +952,71 @@ impl SimpleClient {
         Ok(())
     }
 
+    /// Follow the server's change notifications for the directory, resubscribing
+    /// with backoff whenever the stream drops. `None` is sent when notifications
+    /// may have been missed and a sync is due regardless.
+    async fn watch_remote_changes(
+        self,
+        notify: tokio::sync::mpsc::UnboundedSender<Option<ChangeEvent>>,
+    ) {
+        let mut retry_delay = EVENTS_RETRY_DELAY;
+
+        while !notify.is_closed() {
+            match self.subscribe_to_changes().await {
+                Ok(response) => {
+                    // Changes made before subscribing, or while the stream was down, were not announced
+                    let _ = notify.send(None);
+                    info!("🔔 Subscribed to change notifications");
+                    retry_delay = EVENTS_RETRY_DELAY;
+
+                    if let Err(e) = read_change_events(response, &notify).await {
+                        debug!("Change notification stream failed: {}", e);
+                    }
+                    if notify.is_closed() {
+                        break;
+                    }
+                    warn!("Change notification stream dropped, relying on periodic sync");
+                }
+                Err(e) => debug!("Failed to subscribe to change notifications: {}", e),
+            }
+
+            sleep(retry_delay).await;
+            retry_delay = (retry_delay * 2).min(self.sync_interval.max(EVENTS_RETRY_DELAY));
+        }
+    }
+
+    async fn subscribe_to_changes(&self) -> Result<Response> {
+        let url = format!(
+            "{}/events?directory={}",
+            self.server_url,
+            urlencoding::encode(self.directory.as_deref().unwrap_or_default())
+        );
+        let response = self
+            .get(&url)
+            .timeout(EVENTS_REQUEST_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response)
+    }
+
+    /// Whether notifications announce changes the last sync hasn't covered yet
+    fn has_unseen_changes(&self, events: &[Option<ChangeEvent>]) -> Result<bool> {
+        let cursor = load_sync_cursor(&self.state_db)?;
+        Ok(events.iter().any(|event| match (event, &cursor) {
+            (Some(event), Some(cursor)) => {
+                event.cursor.journal_id != cursor.journal_id || event.cursor.seq > cursor.seq
+            }
+            _ => true,
+        }))
+    }
+
     async fn handle_file_event(&self, event: Event) -> Result<()> {
+        // Local changes wait for the deletions to be confirmed or undone
+        if self.is_paused()? {
+            return Ok(());
+        }
+
         match event.kind {
             EventKind::Create(_) | EventKind::Modify(_) => {
                 for path in event.paths {
@@ -414,20 +1030,20 @@ impl SimpleClient {
                     }
                 }
             }
-            EventKind::Remove(_) => {
-                for path in event.paths {
-                    if self.should_sync_file(&path) {
-                        if let Err(e) = self.handle_file_deletion(&path).await {
-                            error!("Error handling file deletion {}: {}", path.display(), e);
-                        }
-                    }
-                }
-            }
             _ => {}
         }
         Ok(())
     }
 
+    /// Whether a remove event deleted files that are synced
+    fn has_deletions(&self, event: &Event) -> bool {
+        // A path that exists again was replaced, e.g. by a download
+        event
+            .paths
+            .iter()
+            .any(|path| self.should_sync_file(path) && !path.exists())
+    }
+
     async fn handle_file_change(&self, file_path: &std::path::Path) -> Result<()> {
         if let Ok(relative_path) = file_path.strip_prefix(&self.watch_dir) {
             let relative_path_str = relative_path.to_string_lossy().to_string();
@@ -440,47 +1056,34 @@ impl SimpleClient {
                 Some(existing) => existing.hash != file_info.hash,
                 None => true,
             };
+            let file_info = with_version(
+                file_info,
+                state.files.get(&relative_path_str),
+                &self.replica_id()?,
+            );
 
             if should_upload {
                 debug!("Detected change in: {}", relative_path_str);
-                self.upload_file(&file_info).await?;
-
-                state.files.insert(file_info.path.clone(), file_info);
-                state.last_sync = chrono::Utc::now();
-                save_client_state_db(&state, &self.state_db)?;
-            }
-        }
-
-        Ok(())
-    }
-
-    async fn handle_file_deletion(&self, file_path: &std::path::Path) -> Result<()> {
-        if let Ok(relative_path) = file_path.strip_prefix(&self.watch_dir) {
-            let relative_path_str = relative_path.to_string_lossy().to_string();
-
-            debug!("Detected deletion: {}", relative_path_str);
-
-            // Load current state
-            let mut state = load_client_state_db(&self.state_db)?;
-
-            // Check if this file was in our tracked files
-            if state.files.contains_key(&relative_path_str) {
-                let deletion_time = chrono::Utc::now();
-
-                // Remove from tracked files and add to deleted list with timestamp
-                state.files.remove(&relative_path_str);
-                state
-                    .deleted_files
-                    .insert(relative_path_str.clone(), deletion_time);
-
-                // Send delete request to server immediately
-                if let Err(e) = self.send_delete_request(&relative_path_str).await {
-                    error!(
-                        "Failed to send delete request for {}: {}",
-                        relative_path_str, e
-                    );
-                } else {
-                    debug!("✓ Deletion synced to server: {}", relative_path_str);
+                let base_hash = last_synced_hash(&state, &file_info.path);
+                match self.upload_file(&file_info, Some(&base_hash)).await {
+                    Ok(()) => {
+                        state.files.insert(file_info.path.clone(), file_info);
+                    }
+                    Err(e) => {
+                        let conflict = e.downcast::<WriteConflict>()?;
+                        // The path now holds whatever version won, if any
+                        match self
+                            .resolve_write_conflict(&conflict, Some(&file_info))
+                            .await?
+                        {
+                            Some(settled) => {
+                                state.files.insert(file_info.path.clone(), settled);
+                            }
+                            None => {
+                                state.files.remove(&file_info.path);
+                            }
+                        }
+                    }
                 }
 
                 state.last_sync = chrono::Utc::now();
@@ -491,7 +1094,8 @@ impl SimpleClient {
         Ok(())
     }
 
-    async fn upload_file(&self, file_info: &FileInfo) -> Result<()> {
+    /// Upload a file in place of the version with `base_hash` (see `UploadRequest::base_hash`)
+    async fn upload_file(&self, file_info: &FileInfo, base_hash: Option<&str>) -> Result<()> {
         let file_path = self.watch_dir.join(&file_info.path);
 
         // Verify hash before upload
@@ -505,9 +1109,13 @@ impl SimpleClient {
 
         // Check for Delta Sync
         if file_info.size > DELTA_SYNC_THRESHOLD {
-            match self.upload_file_delta(file_info, &file_path).await {
+            match self
+                .upload_file_delta(file_info, &file_path, base_hash)
+                .await
+            {
                 Ok(true) => return Ok(()), // Delta sync succeeded
                 Ok(false) => debug!("Delta sync recommended full upload for {}", file_info.path), // Fallback
+                Err(e) if e.is::<WriteConflict>() => return Err(e),
                 Err(e) => {
                     error!(
                         "Delta sync failed for {}, falling back to full upload: {}",
@@ -518,25 +1126,37 @@ impl SimpleClient {
             }
         }
 
-        // Full Upload Fallback
-        let content = std::fs::read(&file_path)?;
+        if file_info.size > TRANSFER_CHUNK_SIZE {
+            return self
+                .upload_file_resumable(file_info, &file_path, base_hash)
+                .await;
+        }
 
-        let upload_request = UploadRequest {
-            file_info: file_info.clone(),
-            content,
-            client_id: self.client_id.clone(),
-            directory: self.directory.clone(),
-        };
+        // Full Upload Fallback, streamed from disk
+        let file = tokio::fs::File::open(&file_path).await?;
+        let directory = self.directory.as_deref().unwrap_or_default();
+        let url = format!(
+            "{}/files/{}?directory={}",
+            self.server_url,
+            urlencoding::encode(&file_info.path),
+            urlencoding::encode(directory)
+        );
 
-        let url = format!("{}/upload", self.server_url);
-        let response: UploadResponse = self
-            .http_client
-            .post(&url)
-            .json(&upload_request)
-            .send()
-            .await?
-            .json()
-            .await?;
+        let mut request = self
+            .put(&url)
+            .timeout(TRANSFER_REQUEST_TIMEOUT)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .header(HASH_HEADER, &file_info.hash)
+            .header(MODIFIED_HEADER, file_info.modified.to_rfc3339())
+            .header(VERSION_HEADER, encode_version_header(&file_info.version))
+            .body(Body::wrap_stream(ReaderStream::new(file)));
+        if let Some(ref client_id) = self.client_id {
+            request = request.header(CLIENT_ID_HEADER, client_id);
+        }
+        if let Some(base_hash) = base_hash {
+            request = request.header(BASE_HASH_HEADER, base_hash);
+        }
+        let response: UploadResponse = read_json(request.send().await?).await?;
 
         if response.success {
             debug!("✓ Uploaded (Full): {}", file_info.path);
@@ -547,10 +1167,116 @@ impl SimpleClient {
         Ok(())
     }
 
+    /// Upload a large file in chunks through a resumable upload session. An
+    /// upload interrupted earlier, even by a restart, continues from the last
+    /// byte the server acknowledged.
+    async fn upload_file_resumable(
+        &self,
+        file_info: &FileInfo,
+        file_path: &Path,
+        base_hash: Option<&str>,
+    ) -> Result<()> {
+        let directory = self.directory.as_deref().unwrap_or_default();
+        let session_url = |session_id: &str| {
+            format!(
+                "{}/uploads/{}?directory={}",
+                self.server_url,
+                urlencoding::encode(session_id),
+                urlencoding::encode(directory)
+            )
+        };
+
+        // Pick up the session of an interrupted upload of the same content
+        let mut session = None;
+        let progress = load_upload_progress(&self.state_db)?
+            .remove(&file_info.path)
+            .filter(|progress| progress.hash == file_info.hash);
+        if let Some(progress) = progress {
+            let response = self.get(&session_url(&progress.session_id)).send().await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                debug!(
+                    "Upload session for {} expired, starting over",
+                    file_info.path
+                );
+            } else {
+                session = Some(read_json::<UploadSessionResponse>(response).await?);
+            }
+        }
+        let mut session = match session {
+            Some(session) => session,
+            None => {
+                let session_req = UploadSessionRequest {
+                    file_info: file_info.clone(),
+                    client_id: self.client_id.clone(),
+                    directory: self.directory.clone(),
+                    base_hash: base_hash.map(str::to_string),
+                };
+                let url = format!("{}/uploads", self.server_url);
+                read_json(self.post(&url).json(&session_req).send().await?).await?
+            }
+        };
+        let session_id = match session.session_id.clone() {
+            Some(session_id) if session.success => session_id,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Opening upload session failed: {}",
+                    session.message
+                ))
+            }
+        };
+
+        let mut received = received_prefix(&session.received);
+        if received > 0 {
+            info!(
+                "Resuming upload of {} at {} of {} bytes",
+                file_info.path, received, file_info.size
+            );
+        }
+        while !session.completed {
+            let progress = UploadProgress {
+                session_id: session_id.clone(),
+                hash: file_info.hash.clone(),
+                received,
+            };
+            save_upload_progress(&self.state_db, &file_info.path, Some(&progress))?;
+
+            let mut file = tokio::fs::File::open(file_path).await?;
+            file.seek(SeekFrom::Start(received)).await?;
+            let chunk = file.take(TRANSFER_CHUNK_SIZE.min(file_info.size - received));
+            let request = self
+                .put(&session_url(&session_id))
+                .timeout(TRANSFER_REQUEST_TIMEOUT)
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .header(OFFSET_HEADER, received)
+                .body(Body::wrap_stream(ReaderStream::new(chunk)));
+            session = read_json(request.send().await?).await?;
+            if !session.success {
+                // The server dropped the session
+                save_upload_progress(&self.state_db, &file_info.path, None)?;
+                return Err(anyhow::anyhow!("Upload failed: {}", session.message));
+            }
+
+            let acknowledged = received_prefix(&session.received);
+            if acknowledged <= received && !session.completed {
+                return Err(anyhow::anyhow!(
+                    "Upload of {} made no progress at byte {}",
+                    file_info.path,
+                    received
+                ));
+            }
+            received = acknowledged;
+        }
+        save_upload_progress(&self.state_db, &file_info.path, None)?;
+
+        debug!("✓ Uploaded (Resumable): {}", file_info.path);
+        Ok(())
+    }
+
     async fn upload_file_delta(
         &self,
         file_info: &FileInfo,
         file_path: &std::path::Path,
+        base_hash: Option<&str>,
     ) -> Result<bool> {
         debug!("Attempting delta sync for: {}", file_info.path);
 
@@ -558,30 +1284,20 @@ impl SimpleClient {
 
         let init_req = DeltaInitRequest {
             file_info: file_info.clone(),
-            block_hashes,
+            block_hashes: block_hashes.clone(),
             block_size: BLOCK_SIZE,
             client_id: self.client_id.clone(),
             directory: self.directory.clone(),
+            base_hash: base_hash.map(str::to_string),
         };
 
         let url = format!("{}/delta/init", self.server_url);
-        let init_res: DeltaInitResponse = self
-            .http_client
-            .post(&url)
-            .json(&init_req)
-            .send()
-            .await?
-            .json()
-            .await?;
+        let init_res: DeltaInitResponse =
+            read_json(self.post(&url).json(&init_req).send().await?).await?;
 
-        if init_res.should_full_upload {
+        let Some(session_id) = init_res.session_id.filter(|_| !init_res.should_full_upload) else {
             return Ok(false);
-        }
-
-        if init_res.missing_block_indices.is_empty() {
-            debug!("✓ No blocks need uploading for {}", file_info.path);
-            return Ok(true);
-        }
+        };
 
         debug!(
             "Uploading {} missing blocks for {}",
@@ -592,32 +1308,27 @@ impl SimpleClient {
         let mut file = std::fs::File::open(file_path)?;
 
         for index in init_res.missing_block_indices {
-            let offset = index * BLOCK_SIZE;
-            file.seek(SeekFrom::Start(offset))?;
-
-            let mut buffer = vec![0u8; BLOCK_SIZE as usize];
-            let bytes_read = file.read(&mut buffer)?;
-
-            // Truncate buffer to actual bytes read
-            buffer.truncate(bytes_read);
-
-            let upload_req = BlockUploadRequest {
-                path: file_info.path.clone(),
-                directory: self.directory.clone().unwrap_or_default(), // Should ensure directory is set
+            let block = block_hashes
+                .get(index as usize)
+                .ok_or_else(|| anyhow::anyhow!("Server asked for unknown block {}", index))?;
+            file.seek(SeekFrom::Start(block.offset))?;
+
+            let mut buffer = vec![0u8; block.length as usize];
+            file.read_exact(&mut buffer)?;
+
+            let url = format!(
+                "{}/delta/{}/blocks/{}?directory={}",
+                self.server_url,
+                urlencoding::encode(&session_id),
                 index,
-                content: buffer,
-                client_id: self.client_id.clone(),
-            };
-
-            let url = format!("{}/delta/upload", self.server_url);
-            let res: BlockUploadResponse = self
-                .http_client
-                .post(&url)
-                .json(&upload_req)
-                .send()
-                .await?
-                .json()
-                .await?;
+                urlencoding::encode(self.directory.as_deref().unwrap_or_default())
+            );
+            let request = self
+                .put(&url)
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .header(PATH_HEADER, urlencoding::encode(&file_info.path).as_ref())
+                .body(buffer);
+            let res: BlockUploadResponse = read_json(request.send().await?).await?;
 
             if !res.success {
                 return Err(anyhow::anyhow!(
@@ -630,23 +1341,19 @@ impl SimpleClient {
 
         debug!("✓ Delta upload (blocks) complete: {}", file_info.path);
 
-        // Finalize delta sync
+        // Finalize delta sync; this also applies a shrunk size when no block changed
         let complete_req = DeltaCompleteRequest {
+            session_id: Some(session_id),
             path: file_info.path.clone(),
             directory: self.directory.clone(),
             client_id: self.client_id.clone(),
             expected_hash: file_info.hash.clone(),
+            version: file_info.version.clone(),
         };
 
         let url = format!("{}/delta/complete", self.server_url);
-        let res: DeltaCompleteResponse = self
-            .http_client
-            .post(&url)
-            .json(&complete_req)
-            .send()
-            .await?
-            .json()
-            .await?;
+        let res: DeltaCompleteResponse =
+            read_json(self.post(&url).json(&complete_req).send().await?).await?;
 
         if !res.success {
             return Err(anyhow::anyhow!("Delta completion failed: {}", res.message));
@@ -655,66 +1362,290 @@ impl SimpleClient {
         Ok(true)
     }
 
-    async fn download_file(&self, file_path: &str) -> Result<()> {
+    /// Download a file, returning the version now on disk
+    async fn download_file(&self, file_path: &str) -> Result<FileInfo> {
         let directory = self
             .directory
             .as_ref()
             .ok_or_else(|| anyhow::anyhow!("Directory must be specified for client operations"))?;
-        let directory_param = format!("?directory={}", urlencoding::encode(directory));
         let url = format!(
-            "{}/download/{}{}",
+            "{}/files/{}?directory={}",
             self.server_url,
             urlencoding::encode(file_path),
-            directory_param
+            urlencoding::encode(directory)
         );
-        let response: DownloadResponse = self.http_client.get(&url).send().await?.json().await?;
 
-        if response.success {
-            if let (Some(file_info), Some(content)) = (response.file_info, response.content) {
-                let local_path = self.watch_dir.join(&file_info.path);
+        // Ask for the rest of an interrupted download whose partial file is intact
+        let partial_path = partial_download_path(&self.watch_dir, file_path);
+        let mut resume = load_download_progress(&self.state_db)?
+            .remove(file_path)
+            .filter(|progress| {
+                partial_path
+                    .metadata()
+                    .is_ok_and(|metadata| metadata.len() >= progress.received)
+            });
+        let had_progress = resume.is_some();
+
+        // Otherwise patch the local copy of a large file with the blocks that changed
+        let (local_path, _) = resolve_within(&self.watch_dir, file_path)?;
+        if !had_progress
+            && local_path
+                .metadata()
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() > DELTA_SYNC_THRESHOLD)
+        {
+            match self.download_file_delta(file_path, &local_path).await {
+                Ok(Some(file_info)) => return Ok(file_info),
+                Ok(None) => debug!("Delta download recommended full download for {}", file_path),
+                Err(e) => error!(
+                    "Delta download failed for {}, falling back to full download: {}",
+                    file_path, e
+                ),
+            }
+        }
 
-                // Create parent directories if needed
-                if let Some(parent) = local_path.parent() {
-                    std::fs::create_dir_all(parent)?;
-                }
+        let response = loop {
+            let mut request = self.get(&url).timeout(TRANSFER_REQUEST_TIMEOUT);
+            if let Some(ref progress) = resume {
+                // The server sends the whole file instead when it changed since
+                request = request
+                    .header(RANGE, format!("bytes={}-", progress.received))
+                    .header(IF_RANGE, format!("\"{}\"", progress.hash));
+            }
+            let response = request.send().await?;
+            if response.status() == StatusCode::RANGE_NOT_SATISFIABLE && resume.take().is_some() {
+                continue;
+            }
+            break response;
+        };
+
+        if !response.status().is_success() {
+            let response: DownloadResponse = read_json(response).await?;
+            return Err(anyhow::anyhow!("Download failed: {}", response.message));
+        }
+
+        let header = |name: &str| -> Result<String> {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+                .ok_or_else(|| anyhow::anyhow!("Download response missing '{}' header", name))
+        };
+        let expected_hash = header(HASH_HEADER)?;
+        let remote_path = urlencoding::decode(&header(PATH_HEADER)?)?.into_owned();
+        let version = header(VERSION_HEADER)
+            .ok()
+            .and_then(|value| decode_version_header(&value))
+            .unwrap_or_default();
+
+        // Never trust the server to keep paths inside the watched directory
+        let (local_path, _) = resolve_within(&self.watch_dir, &remote_path)?;
+
+        // Create parent directories if needed
+        if let Some(parent) = local_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Continue the partial file if the server sent the rest of the same version
+        let mut received = match resume {
+            Some(progress)
+                if response.status() == StatusCode::PARTIAL_CONTENT
+                    && progress.hash == expected_hash =>
+            {
+                progress.received
+            }
+            _ => 0,
+        };
+        // Progress of large downloads is saved as they go, so they can resume
+        let save_progress =
+            received + response.content_length().unwrap_or_default() > TRANSFER_CHUNK_SIZE;
+
+        // Stream the file to the partial file, hashing it on the way
+        if let Some(parent) = partial_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut partial = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&partial_path)?;
+        partial.set_len(received)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut (&mut partial).take(received), &mut hasher)?;
+        let mut file = tokio::fs::File::from_std(partial);
+        let mut saved = received;
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = tokio::time::timeout(TRANSFER_IDLE_TIMEOUT, stream.next())
+            .await
+            .map_err(|_| anyhow::anyhow!("Download of {} stalled", remote_path))?
+        {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+            if save_progress && received - saved >= TRANSFER_CHUNK_SIZE {
+                file.sync_data().await?;
+                let progress = DownloadProgress {
+                    hash: expected_hash.clone(),
+                    received,
+                };
+                save_download_progress(&self.state_db, file_path, Some(&progress))?;
+                saved = received;
+            }
+        }
+        file.flush().await?;
+        file.sync_all().await?;
+        drop(file);
+
+        // Verify the hash; the local version stays untouched until then
+        let actual_hash = format!("{:x}", hasher.finalize());
+        if actual_hash != expected_hash {
+            std::fs::remove_file(&partial_path)?;
+            save_download_progress(&self.state_db, file_path, None)?;
+            return Err(anyhow::anyhow!(
+                "Hash mismatch for downloaded file: {}",
+                remote_path
+            ));
+        }
+
+        // Keep the local version in the trash before the rename below replaces it
+        if self.trash_retention_days > 0 && local_path.is_file() {
+            self.trash_local_copy(&local_path, &remote_path)?;
+        }
+        replace_file(&partial_path, &local_path)?;
+        if had_progress || saved > 0 {
+            save_download_progress(&self.state_db, file_path, None)?;
+        }
 
-                // Write the file
-                std::fs::write(&local_path, &content)?;
-
-                // Verify the hash
-                let actual_hash = calculate_file_hash(&local_path)?;
-                if actual_hash != file_info.hash {
-                    std::fs::remove_file(&local_path)?;
-                    return Err(anyhow::anyhow!(
-                        "Hash mismatch for downloaded file: {}",
-                        file_info.path
-                    ));
+        debug!("✓ Downloaded: {}", remote_path);
+        let mut file_info = get_file_info(&local_path, &remote_path)?;
+        file_info.version = version;
+        Ok(file_info)
+    }
+
+    /// Patch the local copy of a file into the server's version in a staged copy,
+    /// fetching only the blocks that differ, and swap it in. Returns None when
+    /// the file needs a full download instead.
+    async fn download_file_delta(
+        &self,
+        file_path: &str,
+        local_path: &Path,
+    ) -> Result<Option<FileInfo>> {
+        debug!("Attempting delta download for: {}", file_path);
+
+        let download_req = DeltaDownloadRequest {
+            path: file_path.to_string(),
+            directory: self.directory.clone(),
+            block_hashes: calculate_block_hashes(local_path, BLOCK_SIZE)?,
+            block_size: BLOCK_SIZE,
+        };
+        let url = format!("{}/delta/download", self.server_url);
+        let response: DeltaDownloadResponse =
+            read_json(self.post(&url).json(&download_req).send().await?).await?;
+        let Some(file_info) = response
+            .file_info
+            .filter(|_| response.success && !response.should_full_download)
+        else {
+            return Ok(None);
+        };
+
+        // Stage the new version with the blocks the local copy already has, from
+        // wherever they are in it
+        let local_blocks: HashMap<&str, &BlockMsg> = download_req
+            .block_hashes
+            .iter()
+            .map(|block| (block.hash.as_str(), block))
+            .collect();
+        let staging = TempFile::new(local_path);
+        let mut staged = std::fs::File::create(staging.path())?;
+        staged.set_len(file_info.size)?;
+        let mut local = std::fs::File::open(local_path)?;
+        for block in &response.blocks {
+            if let Some(known) = local_blocks.get(block.hash.as_str()) {
+                if known.length == block.length {
+                    copy_range(
+                        &mut local,
+                        known.offset,
+                        &mut staged,
+                        block.offset,
+                        block.length,
+                    )?;
                 }
+            }
+        }
+        drop(local);
+        let mut file = tokio::fs::File::from_std(staged);
 
-                debug!("✓ Downloaded: {}", file_info.path);
-            } else {
-                return Err(anyhow::anyhow!(
-                    "Download response missing file info or content"
-                ));
+        let directory = self.directory.as_deref().unwrap_or_default();
+        let file_url = format!(
+            "{}/files/{}?directory={}",
+            self.server_url,
+            urlencoding::encode(file_path),
+            urlencoding::encode(directory)
+        );
+        for (first, last) in block_runs(&response.missing_block_indices) {
+            let (Some(first), Some(last)) = (
+                response.blocks.get(first as usize),
+                response.blocks.get(last as usize),
+            ) else {
+                return Ok(None);
+            };
+            let start = first.offset;
+            let end = last.offset + last.length - 1;
+            let response = self
+                .get(&file_url)
+                .timeout(TRANSFER_REQUEST_TIMEOUT)
+                .header(RANGE, format!("bytes={}-{}", start, end))
+                .header(IF_RANGE, format!("\"{}\"", file_info.hash))
+                .send()
+                .await?;
+            // Anything else means the file changed on the server since the comparison
+            if response.status() != StatusCode::PARTIAL_CONTENT {
+                return Ok(None);
+            }
+
+            file.seek(SeekFrom::Start(start)).await?;
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = tokio::time::timeout(TRANSFER_IDLE_TIMEOUT, stream.next())
+                .await
+                .map_err(|_| anyhow::anyhow!("Download of {} stalled", file_path))?
+            {
+                file.write_all(&chunk?).await?;
             }
-        } else {
-            return Err(anyhow::anyhow!("Download failed: {}", response.message));
         }
+        file.flush().await?;
+        file.sync_all().await?;
+        drop(file);
 
-        Ok(())
+        // Verify the hash; the local version stays untouched until then
+        if calculate_file_hash(staging.path())? != file_info.hash {
+            return Err(anyhow::anyhow!(
+                "Hash mismatch after patching: {}",
+                file_path
+            ));
+        }
+        if self.trash_retention_days > 0 {
+            self.trash_local_copy(local_path, file_path)?;
+        }
+        staging.commit()?;
+
+        debug!(
+            "✓ Downloaded (Delta, {} blocks): {}",
+            response.missing_block_indices.len(),
+            file_path
+        );
+        let mut local_info = get_file_info(local_path, &file_info.path)?;
+        local_info.version = file_info.version;
+        Ok(Some(local_info))
     }
 
     async fn delete_file(&self, file_path: &str) -> Result<()> {
-        let local_path = self.watch_dir.join(file_path);
+        let (local_path, _) = resolve_within(&self.watch_dir, file_path)?;
 
         if local_path.exists() {
-            if local_path.is_file() {
-                std::fs::remove_file(&local_path)?;
-                debug!("✓ Deleted: {}", file_path);
-            } else if local_path.is_dir() {
-                std::fs::remove_dir_all(&local_path)?;
-                debug!("✓ Deleted directory: {}", file_path);
-            }
+            self.discard_local(&local_path, file_path)?;
+            debug!("✓ Deleted: {}", file_path);
         } else {
             // File already doesn't exist, which is fine
             debug!("ℹ️  File already deleted: {}", file_path);
@@ -723,22 +1654,226 @@ impl SimpleClient {
         Ok(())
     }
 
-    async fn send_delete_request(&self, file_path: &str) -> Result<()> {
+    /// Move a local file or folder that sync is about to delete or overwrite into
+    /// the local trash, or delete it when the trash is disabled
+    fn discard_local(&self, local_path: &Path, file_path: &str) -> Result<()> {
+        if self.trash_retention_days == 0 {
+            if local_path.is_dir() {
+                std::fs::remove_dir_all(local_path)?;
+            } else {
+                std::fs::remove_file(local_path)?;
+            }
+            return Ok(());
+        }
+
+        let trash_path =
+            move_to_local_trash(&self.watch_dir, local_path, file_path, chrono::Utc::now())?;
+        debug!("Moved {} to {}", file_path, trash_path.display());
+        Ok(())
+    }
+
+    /// Keep a copy of a local file that sync is about to overwrite in the local
+    /// trash, leaving the file itself for the rename that replaces it
+    fn trash_local_copy(&self, local_path: &Path, file_path: &str) -> Result<()> {
+        let trash_path =
+            copy_to_local_trash(&self.watch_dir, local_path, file_path, chrono::Utc::now())?;
+        debug!("Kept {} in {}", file_path, trash_path.display());
+        Ok(())
+    }
+
+    /// Rename the local version of `file_path` to a conflict copy and record the conflict
+    fn keep_conflict_copy(&self, file_path: &str) -> Result<FileInfo> {
+        let (local_path, _) = resolve_within(&self.watch_dir, file_path)?;
+        // The copy is named after this device, whose version lost
+        let origin = self.client_id.as_deref().unwrap_or("local");
+        let copy_path =
+            conflict_copy_path(&self.watch_dir, file_path, origin, chrono::Local::now());
+        let (local_copy_path, _) = resolve_within(&self.watch_dir, &copy_path)?;
+
+        std::fs::rename(&local_path, &local_copy_path)?;
+        record_conflict(
+            &self.state_db,
+            &ConflictRecord {
+                path: file_path.to_string(),
+                copy_path: copy_path.clone(),
+                detected_at: chrono::Utc::now(),
+                resolved_at: None,
+            },
+        )?;
+        warn!(
+            "⚠️  Conflict on {}: local version kept as {}",
+            file_path, copy_path
+        );
+
+        get_file_info(&local_copy_path, &copy_path)
+    }
+
+    /// Settle a write the server rejected because the file changed there since
+    /// the last sync. `local` is the version that was uploaded, or None for a deletion.
+    /// Returns the version the path holds afterwards, if any.
+    async fn resolve_write_conflict(
+        &self,
+        conflict: &WriteConflict,
+        local: Option<&FileInfo>,
+    ) -> Result<Option<FileInfo>> {
+        let path = &conflict.path;
+        warn!("⚠️  {} changed on the server since the last sync", path);
+        let newest_wins = self.conflict_policy == ConflictPolicy::NewestWins;
+        let replica = self.replica_id()?;
+
+        let Some(local) = local else {
+            let Some(current) = &conflict.current else {
+                return Ok(None);
+            };
+            if newest_wins {
+                // The deletion just happened, so it is the newest change
+                info!("   → Deleting the server version");
+                let version = current.version.incremented(&replica);
+                self.send_delete_request(path, None, version).await?;
+                return Ok(None);
+            }
+            info!("   → Restoring the server version");
+            return self.download_file(path).await.map(Some);
+        };
+
+        let local_is_newer = conflict
+            .current
+            .as_ref()
+            .is_none_or(|current| local.modified >= current.modified);
+        if newest_wins && local_is_newer {
+            info!("   → Uploading client version (newer or same time)");
+            let local = match &conflict.current {
+                Some(current) => overriding(local, current, &replica),
+                None => local.clone(),
+            };
+            self.upload_file(&local, None).await?;
+            return Ok(Some(local));
+        }
+
+        if !newest_wins {
+            let copy = with_version(self.keep_conflict_copy(path)?, None, &replica);
+            self.upload_file(&copy, Some("")).await?;
+        }
+        if conflict.current.is_none() {
+            return Ok(None);
+        }
+        info!("   → Downloading server version");
+        self.download_file(path).await.map(Some)
+    }
+
+    /// Check `deleted` of the `tracked` files against the mass-deletion guard,
+    /// pausing the directory when they cross it. A confirmed pause lets up to the
+    /// number of deletions it was confirmed for through.
+    fn deletions_allowed(&self, deleted: usize, tracked: usize) -> Result<bool> {
+        let pause = load_deletion_pause(&self.state_db)?;
+        let confirmed = pause
+            .as_ref()
+            .is_some_and(|pause| pause.confirmed && deleted <= pause.deleted);
+
+        if confirmed || !self.exceeds_deletion_guard(deleted, tracked) {
+            if pause.is_some() {
+                if confirmed {
+                    info!("🗑️  Syncing {} confirmed deletions", deleted);
+                } else {
+                    info!("▶️  Deleted files are back, resuming sync");
+                }
+                save_deletion_pause(&self.state_db, None)?;
+            }
+            return Ok(true);
+        }
+
+        let pause = DeletionPause {
+            deleted,
+            tracked,
+            paused_at: pause.map_or_else(chrono::Utc::now, |pause| pause.paused_at),
+            confirmed: false,
+        };
+        save_deletion_pause(&self.state_db, Some(&pause))?;
+        error!(
+            "⚠️  {} of {} files in {} were deleted, sync is paused. Restore the files, or run `syncpair confirm-deletions` to delete them everywhere.",
+            deleted,
+            tracked,
+            self.watch_dir.display()
+        );
+        Ok(false)
+    }
+
+    fn exceeds_deletion_guard(&self, deleted: usize, tracked: usize) -> bool {
+        let too_many = self.deletion_guard_files > 0 && deleted > self.deletion_guard_files;
+        let too_large_share = self.deletion_guard_percent > 0
+            && deleted >= DELETION_GUARD_MIN_FILES
+            && deleted as u64 * 100 > tracked as u64 * u64::from(self.deletion_guard_percent);
+        too_many || too_large_share
+    }
+
+    /// The pause the mass-deletion guard put this directory in, if any
+    pub fn deletion_pause(&self) -> Result<Option<DeletionPause>> {
+        load_deletion_pause(&self.state_db)
+    }
+
+    /// Confirm the deletions that paused this directory, so the next sync sends
+    /// them. Returns the confirmed pause, or None when the directory isn't paused.
+    pub fn confirm_deletions(&self) -> Result<Option<DeletionPause>> {
+        let Some(mut pause) = load_deletion_pause(&self.state_db)? else {
+            return Ok(None);
+        };
+        pause.confirmed = true;
+        save_deletion_pause(&self.state_db, Some(&pause))?;
+        Ok(Some(pause))
+    }
+
+    /// Whether the mass-deletion guard holds this directory until deletions are confirmed
+    fn is_paused(&self) -> Result<bool> {
+        Ok(load_deletion_pause(&self.state_db)?.is_some_and(|pause| !pause.confirmed))
+    }
+
+    /// Conflicts recorded in this directory, oldest first
+    pub fn conflicts(&self) -> Result<Vec<ConflictRecord>> {
+        load_conflicts(&self.state_db)
+    }
+
+    /// Ask the server to roll the directory, or the files at and below `path`,
+    /// back to how they were at `at`. Clients pick the result up on their next sync.
+    pub async fn restore(
+        &self,
+        path: Option<&str>,
+        at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<RestoreResponse> {
+        let restore_request = RestoreRequest {
+            directory: self.directory.clone(),
+            path: path.map(str::to_string),
+            at,
+            client_id: self.client_id.clone(),
+        };
+
+        let url = format!("{}/restore", self.server_url);
+        let response: RestoreResponse =
+            read_json(self.post(&url).json(&restore_request).send().await?).await?;
+
+        if response.success {
+            Ok(response)
+        } else {
+            Err(anyhow::anyhow!("Restore failed: {}", response.message))
+        }
+    }
+
+    async fn send_delete_request(
+        &self,
+        file_path: &str,
+        base_hash: Option<&str>,
+        version: VersionVector,
+    ) -> Result<()> {
         let delete_request = DeleteRequest {
             path: file_path.to_string(),
             client_id: self.client_id.clone(),
             directory: self.directory.clone(),
+            base_hash: base_hash.map(str::to_string),
+            version,
         };
 
         let url = format!("{}/delete", self.server_url);
-        let response: DeleteResponse = self
-            .http_client
-            .post(&url)
-            .json(&delete_request)
-            .send()
-            .await?
-            .json()
-            .await?;
+        let response: DeleteResponse =
+            read_json(self.post(&url).json(&delete_request).send().await?).await?;
 
         if response.success {
             Ok(())
@@ -751,6 +1886,14 @@ impl SimpleClient {
     }
 
     fn should_sync_file(&self, path: &std::path::Path) -> bool {
+        // Skip syncpair's own folder with the local trash
+        if path
+            .strip_prefix(&self.watch_dir)
+            .is_ok_and(|relative_path| relative_path.starts_with(RESERVED_DIR_NAME))
+        {
+            return false;
+        }
+
         // Skip hidden files and the state database file
         if let Some(file_name) = path.file_name() {
             let name = file_name.to_string_lossy();
@@ -762,11 +1905,14 @@ impl SimpleClient {
 
     async fn save_final_state(&self) -> Result<()> {
         // Perform one final scan to ensure state is up to date
-        let current_files = scan_directory_with_patterns(&self.watch_dir, &self.exclude_patterns)?;
+        let replica = self.replica_id()?;
+        let current_files = self.scan_local_files()?;
         let mut state = load_client_state_db(&self.state_db)?;
 
         // Update state with current files
         for file_info in current_files {
+            let known = state.files.get(&file_info.path);
+            let file_info = with_version(file_info, known, &replica);
             state.files.insert(file_info.path.clone(), file_info);
         }
 
@@ -776,3 +1922,148 @@ impl SimpleClient {
         Ok(())
     }
 }
+
+/// Forward the events of a server-sent event stream until it ends
+async fn read_change_events(
+    response: Response,
+    notify: &tokio::sync::mpsc::UnboundedSender<Option<ChangeEvent>>,
+) -> Result<()> {
+    let mut body = response.bytes_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+
+    loop {
+        let chunk = match tokio::time::timeout(EVENTS_IDLE_TIMEOUT, body.next()).await {
+            Ok(Some(chunk)) => chunk?,
+            Ok(None) => return Ok(()),
+            Err(_) => return Err(anyhow::anyhow!("No keep-alive received from the server")),
+        };
+        buffer.extend_from_slice(&chunk);
+
+        // Events are separated by a blank line
+        while let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+            let block: Vec<u8> = buffer.drain(..end + 2).collect();
+            let event = match parse_sse_event(&String::from_utf8_lossy(&block)) {
+                Some((name, data)) if name == "change" => {
+                    match serde_json::from_str::<ChangeEvent>(&data) {
+                        Ok(event) => Some(event),
+                        Err(e) => {
+                            warn!("Ignoring malformed change notification: {}", e);
+                            continue;
+                        }
+                    }
+                }
+                // The server dropped notifications for us
+                Some((name, _)) if name == "lagged" => None,
+                // Keep-alives and unknown events
+                _ => continue,
+            };
+
+            if notify.send(event).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// The name and data of a server-sent event block; None for comment-only blocks
+fn parse_sse_event(block: &str) -> Option<(String, String)> {
+    let mut name = None;
+    let mut data: Vec<&str> = Vec::new();
+
+    for line in block.lines() {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => name = Some(value.to_string()),
+            "data" => data.push(value),
+            _ => {}
+        }
+    }
+
+    if name.is_none() && data.is_empty() {
+        return None;
+    }
+    Some((
+        name.unwrap_or_else(|| "message".to_string()),
+        data.join("\n"),
+    ))
+}
+
+/// Create the HTTP client with timeouts
+fn build_http_client(tls_config: Option<rustls::ClientConfig>) -> reqwest::Result<Client> {
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(30))
+        .connect_timeout(Duration::from_secs(10));
+    if let Some(tls_config) = tls_config {
+        builder = builder.use_preconfigured_tls(tls_config);
+    }
+    builder.build()
+}
+
+/// Runs of consecutive block indices, as first and last index, to fetch with one
+/// range request each
+fn block_runs(indices: &[u64]) -> Vec<(u64, u64)> {
+    let mut runs: Vec<(u64, u64)> = Vec::new();
+    for &index in indices {
+        match runs.last_mut() {
+            Some((_, last)) if *last + 1 == index => *last = index,
+            _ => runs.push((index, index)),
+        }
+    }
+    runs
+}
+
+/// `file_info` with its version vector: that of `known` if it has the same content,
+/// otherwise one more edit by `replica` on top of it
+fn with_version(mut file_info: FileInfo, known: Option<&FileInfo>, replica: &str) -> FileInfo {
+    file_info.version = match known {
+        Some(known) if known.hash == file_info.hash => known.version.clone(),
+        Some(known) => known.version.incremented(replica),
+        None => VersionVector::default().incremented(replica),
+    };
+    file_info
+}
+
+/// `local` as a deliberate replacement of `current`, which it has now seen
+fn overriding(local: &FileInfo, current: &FileInfo, replica: &str) -> FileInfo {
+    let mut file_info = local.clone();
+    file_info.version = local.version.merged(&current.version).incremented(replica);
+    file_info
+}
+
+/// Hash of the version of `path` the client last synced, or an empty string
+/// when it has none (see `UploadRequest::base_hash`)
+fn last_synced_hash(state: &ClientState, path: &str) -> String {
+    state
+        .files
+        .get(path)
+        .map(|file_info| file_info.hash.clone())
+        .unwrap_or_default()
+}
+
+/// Decode a JSON response, turning authentication failures into errors
+async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let status = response.status();
+    if status == StatusCode::CONFLICT {
+        let response: WriteConflictResponse = response.json().await?;
+        return Err(WriteConflict {
+            path: response.path,
+            current: response.current,
+        }
+        .into());
+    }
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        let body: serde_json::Value = response.json().await.unwrap_or_default();
+        let message = body
+            .get("message")
+            .and_then(|message| message.as_str())
+            .unwrap_or("no details");
+        return Err(anyhow::anyhow!(
+            "Server rejected request ({}): {}",
+            status,
+            message
+        ));
+    }
+
+    Ok(response.json().await?)
+}
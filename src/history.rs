@@ -0,0 +1,288 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::chunk_store::{delete_content, rename_content, stat_content};
+use crate::journal::change_type;
+use crate::paths::{RESERVED_DIR_NAME, SERVER_STATE_FILE};
+use crate::storage::{join_key, not_found, StorageBackend};
+use crate::types::{ChangeKind, FileInfo, FileVersion, HistoryConfig};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Per-directory store of the versions of files that were replaced or deleted.
+///
+/// The content of each version is kept under `.syncpair/versions/` in the
+/// directory's storage, where client paths can never point, and its metadata
+/// in the directory's state database.
+pub struct VersionHistory {
+    conn: Connection,
+    storage: Arc<dyn StorageBackend>,
+    versions_prefix: String,
+}
+
+impl VersionHistory {
+    /// Create the history's tables in a directory's state database, once, when
+    /// the directory state is first loaded or created
+    pub fn create_schema(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS file_versions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                file_path TEXT NOT NULL,
+                file_hash TEXT NOT NULL,
+                file_size INTEGER NOT NULL,
+                modified_at TEXT NOT NULL,
+                version TEXT NOT NULL,
+                stored_at INTEGER NOT NULL,
+                archived_at INTEGER NOT NULL,
+                replaced_by TEXT NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS file_versions_path ON file_versions (file_path)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Open the history of the directory whose state is at `directory_dir` and
+    /// whose content is under `directory_key` in `storage`. Its schema must
+    /// have been created with `create_schema`.
+    pub fn open(
+        directory_dir: &Path,
+        storage: Arc<dyn StorageBackend>,
+        directory_key: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            conn: Connection::open(directory_dir.join(SERVER_STATE_FILE))?,
+            storage,
+            versions_prefix: join_key(directory_key, &format!("{}/versions", RESERVED_DIR_NAME)),
+        })
+    }
+
+    /// Move the file stored under `file_key`, described by `file_info`, into the history
+    pub fn archive(
+        &self,
+        file_key: &str,
+        file_info: &FileInfo,
+        replaced_by: ChangeKind,
+    ) -> Result<u64> {
+        self.insert(file_key, file_info, replaced_by, Utc::now())
+    }
+
+    /// Like `archive`, for a file that was already deleted at `deleted_at`
+    pub fn archive_deleted(
+        &self,
+        file_key: &str,
+        file_info: &FileInfo,
+        deleted_at: DateTime<Utc>,
+    ) -> Result<u64> {
+        self.insert(file_key, file_info, ChangeKind::Deleted, deleted_at)
+    }
+
+    fn insert(
+        &self,
+        file_key: &str,
+        file_info: &FileInfo,
+        replaced_by: ChangeKind,
+        archived_at: DateTime<Utc>,
+    ) -> Result<u64> {
+        let stored_at = stat_content(self.storage.as_ref(), file_key)?
+            .ok_or_else(|| not_found(file_key))?
+            .modified;
+
+        self.conn.execute(
+            "INSERT INTO file_versions (file_path, file_hash, file_size, modified_at, version, stored_at, archived_at, replaced_by)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                file_info.path,
+                file_info.hash,
+                file_info.size,
+                file_info.modified.to_rfc3339(),
+                serde_json::to_string(&file_info.version)?,
+                stored_at.timestamp_millis(),
+                archived_at.timestamp_millis(),
+                change_type(replaced_by)
+            ],
+        )?;
+        let id = self.conn.last_insert_rowid() as u64;
+
+        if let Err(e) = rename_content(self.storage.as_ref(), file_key, &self.content_key(id)) {
+            self.conn
+                .execute("DELETE FROM file_versions WHERE id = ?", params![id as i64])?;
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// The kept versions of `path`, newest first
+    pub fn list(&self, path: &str) -> Result<Vec<FileVersion>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, file_path, file_hash, file_size, modified_at, version, stored_at, archived_at, replaced_by
+             FROM file_versions WHERE file_path = ? ORDER BY archived_at DESC, id DESC",
+        )?;
+        let versions = stmt
+            .query_map(params![path], read_version)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(versions)
+    }
+
+    /// A version of `path` and the key of its content
+    pub fn get(&self, path: &str, id: u64) -> Result<Option<(FileVersion, String)>> {
+        let version = self
+            .conn
+            .query_row(
+                "SELECT id, file_path, file_hash, file_size, modified_at, version, stored_at, archived_at, replaced_by
+                 FROM file_versions WHERE file_path = ? AND id = ?",
+                params![path, id as i64],
+                read_version,
+            )
+            .optional()?;
+        Ok(version.map(|version| {
+            let content_key = self.content_key(version.id);
+            (version, content_key)
+        }))
+    }
+
+    /// The version that was current for `path` at `at`, if the history still has
+    /// it, and the key of its content
+    pub fn version_at(
+        &self,
+        path: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<(FileVersion, String)>> {
+        let at = at.timestamp_millis();
+        let version = self
+            .conn
+            .query_row(
+                "SELECT id, file_path, file_hash, file_size, modified_at, version, stored_at, archived_at, replaced_by
+                 FROM file_versions WHERE file_path = ? AND stored_at <= ? AND archived_at > ?
+                 ORDER BY stored_at DESC, id DESC LIMIT 1",
+                params![path, at, at],
+                read_version,
+            )
+            .optional()?;
+        Ok(version.map(|version| {
+            let content_key = self.content_key(version.id);
+            (version, content_key)
+        }))
+    }
+
+    /// Every path with versions in the history
+    pub fn paths(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT file_path FROM file_versions ORDER BY file_path")?;
+        let paths = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(paths)
+    }
+
+    /// Drop the versions of `path`, or of every file, that `policy` no longer
+    /// keeps. Returns how many were dropped.
+    pub fn prune(&self, policy: &HistoryConfig, path: Option<&str>) -> Result<usize> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, file_path, archived_at FROM file_versions
+             WHERE ?1 IS NULL OR file_path = ?1
+             ORDER BY file_path, archived_at DESC, id DESC",
+        )?;
+        let rows = stmt
+            .query_map(params![path], |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as u64,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let now = Utc::now();
+        let mut expired = Vec::new();
+        for file_versions in rows.chunk_by(|a, b| a.1 == b.1) {
+            let versions: Vec<(u64, DateTime<Utc>)> = file_versions
+                .iter()
+                .map(|(id, _, archived_at)| {
+                    (
+                        *id,
+                        DateTime::from_timestamp_millis(*archived_at).unwrap_or_default(),
+                    )
+                })
+                .collect();
+            expired.extend(expired_versions(&versions, policy, now));
+        }
+
+        for id in &expired {
+            self.conn.execute(
+                "DELETE FROM file_versions WHERE id = ?",
+                params![*id as i64],
+            )?;
+            delete_content(self.storage.as_ref(), &self.content_key(*id))?;
+        }
+        Ok(expired.len())
+    }
+
+    fn content_key(&self, id: u64) -> String {
+        format!("{}/{}", self.versions_prefix, id)
+    }
+}
+
+fn read_version(row: &Row) -> rusqlite::Result<FileVersion> {
+    let modified = DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
+        .map_err(|_| rusqlite::Error::InvalidColumnIndex(4))?
+        .with_timezone(&Utc);
+    let version = serde_json::from_str(&row.get::<_, String>(5)?)
+        .map_err(|_| rusqlite::Error::InvalidColumnIndex(5))?;
+    let replaced_by = match row.get::<_, String>(8)?.as_str() {
+        "deleted" => ChangeKind::Deleted,
+        _ => ChangeKind::Modified,
+    };
+
+    Ok(FileVersion {
+        id: row.get::<_, i64>(0)? as u64,
+        file_info: FileInfo {
+            path: row.get(1)?,
+            hash: row.get(2)?,
+            size: row.get(3)?,
+            modified,
+            version,
+        },
+        stored_at: DateTime::from_timestamp_millis(row.get(6)?).unwrap_or_default(),
+        archived_at: DateTime::from_timestamp_millis(row.get(7)?).unwrap_or_default(),
+        replaced_by,
+    })
+}
+
+/// The versions of one file, given newest first with the time they were
+/// archived, that `policy` no longer keeps
+fn expired_versions(
+    versions: &[(u64, DateTime<Utc>)],
+    policy: &HistoryConfig,
+    now: DateTime<Utc>,
+) -> Vec<u64> {
+    let keep_all_until = i64::from(policy.keep_all_days);
+    let daily_until = keep_all_until + i64::from(policy.daily_days);
+    let weekly_until = daily_until + 7 * i64::from(policy.weekly_weeks);
+
+    // Days and weeks that already kept their latest version
+    let mut kept_days = HashSet::new();
+    let mut kept_weeks = HashSet::new();
+
+    let mut expired = Vec::new();
+    for (position, (id, archived_at)) in versions.iter().enumerate() {
+        let age_days = (now - *archived_at).num_days();
+        let day = archived_at.timestamp().div_euclid(SECONDS_PER_DAY);
+        let keep = position < policy.keep_versions
+            || age_days < keep_all_until
+            || (age_days < daily_until && kept_days.insert(day))
+            || (age_days < weekly_until && kept_weeks.insert(day.div_euclid(7)));
+        if !keep {
+            expired.push(*id);
+        }
+    }
+    expired
+}
@@ -0,0 +1,155 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use crate::types::{ChangeKind, SyncCursor};
+
+pub(crate) fn change_type(kind: ChangeKind) -> &'static str {
+    match kind {
+        ChangeKind::Modified => "modified",
+        ChangeKind::Deleted => "deleted",
+    }
+}
+
+/// Per-directory, monotonically sequenced log of changed paths.
+///
+/// The journal lives in the directory's state database. Entries only name the
+/// path that changed; the directory state holds what the path looks like now.
+/// Each journal has a unique ID so that cursors from a journal that was
+/// recreated are never mistaken for positions in the new one.
+pub struct ChangeJournal {
+    conn: Connection,
+}
+
+impl ChangeJournal {
+    /// Create the journal's tables in the state database at `db_path`, once,
+    /// when the directory state is first loaded or created
+    pub fn create_schema(conn: &Connection, db_path: &Path) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS change_log (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                file_path TEXT NOT NULL,
+                change_type TEXT NOT NULL,
+                recorded_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        // Entries up to `pruned_through` were dropped, so older cursors have expired
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS change_log_meta (
+                journal_id TEXT NOT NULL,
+                pruned_through INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        let count: i64 =
+            conn.query_row("SELECT COUNT(*) FROM change_log_meta", [], |row| row.get(0))?;
+        if count == 0 {
+            conn.execute(
+                "INSERT INTO change_log_meta (journal_id, pruned_through) VALUES (?, 0)",
+                params![new_journal_id(db_path)],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Open the journal in the state database at `db_path`, whose schema was
+    /// created with `create_schema`
+    pub fn open(db_path: &Path) -> Result<Self> {
+        Ok(Self {
+            conn: Connection::open(db_path)?,
+        })
+    }
+
+    /// Append a change and return the position right after it
+    pub fn record(&self, path: &str, kind: ChangeKind) -> Result<SyncCursor> {
+        self.conn.execute(
+            "INSERT INTO change_log (file_path, change_type, recorded_at) VALUES (?, ?, ?)",
+            params![path, change_type(kind), Utc::now().timestamp_millis()],
+        )?;
+        let seq = self.conn.last_insert_rowid() as u64;
+        let (journal_id, _) = self.meta()?;
+        Ok(SyncCursor { journal_id, seq })
+    }
+
+    /// The position after the latest recorded change
+    pub fn cursor(&self) -> Result<SyncCursor> {
+        let (journal_id, pruned_through) = self.meta()?;
+        let latest: Option<i64> =
+            self.conn
+                .query_row("SELECT MAX(seq) FROM change_log", [], |row| row.get(0))?;
+
+        Ok(SyncCursor {
+            journal_id,
+            seq: latest.map_or(pruned_through, |seq| seq as u64),
+        })
+    }
+
+    /// Paths changed after `cursor`, or None when the cursor has expired or
+    /// belongs to another journal and the caller needs a full reconcile
+    pub fn changed_since(&self, cursor: &SyncCursor) -> Result<Option<Vec<String>>> {
+        let latest = self.cursor()?;
+        let (_, pruned_through) = self.meta()?;
+        if cursor.journal_id != latest.journal_id
+            || cursor.seq > latest.seq
+            || cursor.seq < pruned_through
+        {
+            return Ok(None);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT file_path FROM change_log WHERE seq > ? GROUP BY file_path ORDER BY MAX(seq)",
+        )?;
+        let paths = stmt
+            .query_map(params![cursor.seq as i64], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(Some(paths))
+    }
+
+    /// Drop entries recorded before `cutoff`, expiring cursors that point at them
+    pub fn prune(&self, cutoff: DateTime<Utc>) -> Result<()> {
+        let last_expired: Option<i64> = self.conn.query_row(
+            "SELECT MAX(seq) FROM change_log WHERE recorded_at < ?",
+            params![cutoff.timestamp_millis()],
+            |row| row.get(0),
+        )?;
+
+        if let Some(last_expired) = last_expired {
+            self.conn.execute(
+                "DELETE FROM change_log WHERE seq <= ?",
+                params![last_expired],
+            )?;
+            self.conn.execute(
+                "UPDATE change_log_meta SET pruned_through = ?",
+                params![last_expired],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn meta(&self) -> Result<(String, u64)> {
+        let meta = self
+            .conn
+            .query_row(
+                "SELECT journal_id, pruned_through FROM change_log_meta LIMIT 1",
+                [],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64)),
+            )
+            .optional()?;
+        meta.ok_or_else(|| anyhow::anyhow!("Change journal metadata is missing"))
+    }
+}
+
+fn new_journal_id(db_path: &Path) -> String {
+    let seed = format!(
+        "{}:{}:{}",
+        db_path.display(),
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    );
+    format!("{:x}", Sha256::digest(seed.as_bytes()))[..16].to_string()
+}
@@ -1,5 +1,14 @@
+pub mod auth;
+pub mod chunk_store;
 pub mod client;
+pub mod history;
+pub mod journal;
 pub mod multi_client;
+pub mod paths;
+pub mod s3;
 pub mod server;
+pub mod storage;
+pub mod tls;
+pub mod trash;
 pub mod types;
 pub mod utils;
@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
 use std::fs::OpenOptions;
 use std::path::PathBuf;
@@ -9,6 +10,7 @@ use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, Env
 
 use syncpair::multi_client::MultiDirectoryClient;
 use syncpair::server::SimpleServer;
+use syncpair::types::ServerConfig;
 
 #[derive(Parser)]
 #[command(author, version, about = "A bidirectional file synchronization tool", long_about = None)]
@@ -42,14 +44,90 @@ enum Commands {
         port: u16,
         #[arg(short, long, help = "Directory to store uploaded files")]
         storage_dir: PathBuf,
+        #[arg(short, long, help = "Path to the server YAML configuration file")]
+        config: Option<PathBuf>,
+        #[arg(
+            long,
+            requires = "tls_key",
+            help = "PEM certificate chain to serve HTTPS with"
+        )]
+        tls_cert: Option<PathBuf>,
+        #[arg(
+            long,
+            requires = "tls_cert",
+            help = "PEM private key for the TLS certificate"
+        )]
+        tls_key: Option<PathBuf>,
+        #[arg(
+            long,
+            requires = "tls_cert",
+            help = "PEM CA certificate for authenticating clients by certificate"
+        )]
+        tls_client_ca: Option<PathBuf>,
     },
     /// Start the client using a YAML configuration file for multi-directory sync
     Client {
         #[arg(short, long, help = "Path to the YAML configuration file")]
         file: PathBuf,
+
+        #[arg(
+            long,
+            help = "Rehash every file on the first scan instead of trusting cached hashes"
+        )]
+        verify: bool,
+    },
+    /// List the conflicts recorded in each directory of a client configuration
+    Conflicts {
+        #[arg(short, long, help = "Path to the YAML configuration file")]
+        file: PathBuf,
+
+        #[arg(long, help = "Also list conflicts that were already resolved")]
+        all: bool,
+    },
+    /// Roll a directory, or a file or folder in it, back to a point in time
+    Restore {
+        #[arg(short, long, help = "Path to the YAML configuration file")]
+        file: PathBuf,
+
+        #[arg(long, help = "Name of the configured directory to restore")]
+        dir: String,
+
+        #[arg(
+            long,
+            help = "File or folder to restore instead of the whole directory"
+        )]
+        path: Option<String>,
+
+        #[arg(
+            long,
+            value_parser = parse_time,
+            help = "Point in time to restore to, e.g. 2026-10-01T12:00Z"
+        )]
+        at: DateTime<Utc>,
+    },
+    /// List directories paused by the mass-deletion guard, or confirm the deletions in one
+    ConfirmDeletions {
+        #[arg(short, long, help = "Path to the YAML configuration file")]
+        file: PathBuf,
+
+        #[arg(
+            long,
+            help = "Name of the configured directory whose deletions to sync"
+        )]
+        dir: Option<String>,
     },
 }
 
+/// Parse an RFC 3339 time, also accepting one without seconds such as `2026-10-01T12:00Z`
+fn parse_time(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M%#z")
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| format!("'{}' is not a time like 2026-10-01T12:00Z", value))
+}
+
 fn init_logging(
     log_level: &str,
     log_file: Option<&PathBuf>,
@@ -100,24 +178,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     init_logging(&args.log_level, args.log_file.as_ref(), args.quiet)?;
 
     match args.command {
-        Commands::Server { port, storage_dir } => {
+        Commands::Server {
+            port,
+            storage_dir,
+            config,
+            tls_cert,
+            tls_key,
+            tls_client_ca,
+        } => {
             info!(
                 "Starting syncpair server on port {} with storage directory: {}",
                 port,
                 storage_dir.display()
             );
 
-            let server = SimpleServer::new(storage_dir)?;
+            let server_config = match config {
+                Some(config_path) => {
+                    info!("Using server configuration: {}", config_path.display());
+                    serde_yaml::from_str(&std::fs::read_to_string(&config_path)?)?
+                }
+                None => ServerConfig::default(),
+            };
+
+            let mut server = SimpleServer::from_config(storage_dir, &server_config)?;
+            if let (Some(cert), Some(key)) = (tls_cert, tls_key) {
+                server = server.with_tls(&cert, &key, tls_client_ca.as_deref())?;
+            }
             server.start(port).await?;
         }
-        Commands::Client { file } => {
+        Commands::Client { file, verify } => {
             info!(
                 "Starting syncpair multi-directory client using config file: {}",
                 file.display()
             );
 
             // Load configuration and create multi-directory client
-            let multi_client = MultiDirectoryClient::from_config_file(&file)?;
+            let mut multi_client = MultiDirectoryClient::from_config_file(&file)?;
+            if verify {
+                multi_client = multi_client.with_full_verification();
+            }
 
             info!(
                 "Multi-directory client configured for: {}",
@@ -206,6 +305,79 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 error!("Error during multi-client shutdown: {}", e);
             }
         }
+        Commands::Conflicts { file, all } => {
+            let multi_client = MultiDirectoryClient::from_config_file(&file)?;
+            for (directory, conflicts) in multi_client.conflicts()? {
+                for conflict in conflicts {
+                    match conflict.resolved_at {
+                        None => println!(
+                            "{}: {} -> {} (detected {})",
+                            directory,
+                            conflict.path,
+                            conflict.copy_path,
+                            conflict.detected_at.with_timezone(&chrono::Local)
+                        ),
+                        Some(resolved_at) if all => println!(
+                            "{}: {} -> {} (resolved {})",
+                            directory,
+                            conflict.path,
+                            conflict.copy_path,
+                            resolved_at.with_timezone(&chrono::Local)
+                        ),
+                        Some(_) => {}
+                    }
+                }
+            }
+            return Ok(());
+        }
+        Commands::Restore {
+            file,
+            dir,
+            path,
+            at,
+        } => {
+            let multi_client = MultiDirectoryClient::from_config_file(&file)?;
+            let response = multi_client.restore(&dir, path.as_deref(), at).await?;
+            for path in &response.restored {
+                println!("restored {}", path);
+            }
+            for path in &response.deleted {
+                println!("deleted {}", path);
+            }
+            for path in &response.unavailable {
+                println!(
+                    "unavailable {} (not in the version history, or failed to restore)",
+                    path
+                );
+            }
+            println!("{}", response.message);
+            return Ok(());
+        }
+        Commands::ConfirmDeletions { file, dir } => {
+            let multi_client = MultiDirectoryClient::from_config_file(&file)?;
+            match dir {
+                None => {
+                    for (directory, pause) in multi_client.deletion_pauses()? {
+                        println!(
+                            "{}: {} of {} files deleted (paused {}){}",
+                            directory,
+                            pause.deleted,
+                            pause.tracked,
+                            pause.paused_at.with_timezone(&chrono::Local),
+                            if pause.confirmed { ", confirmed" } else { "" }
+                        );
+                    }
+                }
+                Some(dir) => match multi_client.confirm_deletions(&dir)? {
+                    Some(pause) => println!(
+                        "{}: confirmed {} deletions, they are synced on the next sync",
+                        dir, pause.deleted
+                    ),
+                    None => println!("{}: not paused", dir),
+                },
+            }
+            return Ok(());
+        }
     }
 
     info!("SyncPair stopped successfully!");
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -7,7 +8,11 @@ use tokio::task::JoinHandle;
 use tracing::{debug, error, info, warn};
 
 use crate::client::SimpleClient;
-use crate::types::{ClientConfig, DirectoryConfig};
+use crate::tls::{client_tls_config, ClientTlsOptions};
+use crate::types::{ClientConfig, ConflictRecord, DeletionPause, DirectoryConfig, RestoreResponse};
+
+/// The folder registry in the client's state directory
+const FOLDER_REGISTRY_FILE: &str = "known_folders.db";
 
 pub struct MultiDirectoryClient {
     pub config: ClientConfig,
@@ -17,6 +22,9 @@ pub struct MultiDirectoryClient {
 impl MultiDirectoryClient {
     pub fn new(config: ClientConfig) -> Result<Self> {
         let mut clients = HashMap::new();
+        let token = Self::load_token(&config)?;
+        let tls_config = Self::load_tls_config(&config)?;
+        let folder_registry = Self::state_dir(&config)?.join(FOLDER_REGISTRY_FILE);
 
         for dir_config in &config.directories {
             // Apply default settings to directory settings
@@ -31,12 +39,11 @@ impl MultiDirectoryClient {
                 continue;
             }
 
-            // Expand the home directory if present
+            // Expand the home directory if present. A missing directory is created
+            // on its first sync only, never when it was synced before (see
+            // `SimpleClient::with_folder_registry`)
             let local_path = Self::expand_path(&dir_config.local_path)?;
 
-            // Create the directory if it doesn't exist
-            std::fs::create_dir_all(&local_path)?;
-
             // Use appropriate directory naming based on whether directory is shared
             let directory_name = if effective.shared {
                 // Shared directories don't get client_id prefix
@@ -46,11 +53,24 @@ impl MultiDirectoryClient {
                 format!("{}:{}", config.client_id, dir_config.name)
             };
 
-            let client = SimpleClient::new(config.server.clone(), local_path)
+            let mut client = SimpleClient::new(config.server.clone(), local_path)
                 .with_sync_interval(Duration::from_secs(effective.sync_interval_seconds))
                 .with_client_id(format!("{}:{}", config.client_id, dir_config.name))
+                .with_folder_registry(folder_registry.clone())
                 .with_directory(directory_name)
-                .with_exclude_patterns(effective.ignore_patterns.clone());
+                .with_exclude_patterns(effective.ignore_patterns.clone())
+                .with_conflict_policy(effective.conflict_policy)
+                .with_trash_retention_days(effective.trash_retention_days)
+                .with_deletion_guard(
+                    effective.deletion_guard_percent,
+                    effective.deletion_guard_files,
+                );
+            if let Some(ref token) = token {
+                client = client.with_token(token.clone());
+            }
+            if let Some(ref tls_config) = tls_config {
+                client = client.with_tls_config(tls_config.clone())?;
+            }
 
             clients.insert(dir_config.name.clone(), client);
 
@@ -81,6 +101,16 @@ impl MultiDirectoryClient {
         Self::new(config)
     }
 
+    /// Rehash every file on the first scan instead of trusting the hash cache
+    pub fn with_full_verification(mut self) -> Self {
+        self.clients = self
+            .clients
+            .into_iter()
+            .map(|(name, client)| (name, client.with_full_verification()))
+            .collect();
+        self
+    }
+
     pub async fn start_watching(&self) -> Result<()> {
         self.start_watching_with_shutdown(None).await
     }
@@ -189,6 +219,54 @@ impl MultiDirectoryClient {
         Ok(())
     }
 
+    /// Conflicts recorded in each configured directory, by directory name
+    pub fn conflicts(&self) -> Result<Vec<(String, Vec<ConflictRecord>)>> {
+        let mut conflicts = self
+            .clients
+            .iter()
+            .map(|(name, client)| Ok((name.clone(), client.conflicts()?)))
+            .collect::<Result<Vec<_>>>()?;
+        conflicts.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(conflicts)
+    }
+
+    /// Configured directories the mass-deletion guard paused, by directory name
+    pub fn deletion_pauses(&self) -> Result<Vec<(String, DeletionPause)>> {
+        let mut pauses = Vec::new();
+        for (name, client) in &self.clients {
+            if let Some(pause) = client.deletion_pause()? {
+                pauses.push((name.clone(), pause));
+            }
+        }
+        pauses.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(pauses)
+    }
+
+    /// Confirm the deletions that paused the configured directory `name`, so its
+    /// next sync sends them
+    pub fn confirm_deletions(&self, name: &str) -> Result<Option<DeletionPause>> {
+        let client = self
+            .clients
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Directory '{}' is not configured", name))?;
+        client.confirm_deletions()
+    }
+
+    /// Roll the configured directory `name`, or the files at and below `path` in
+    /// it, back to how they were at `at`
+    pub async fn restore(
+        &self,
+        name: &str,
+        path: Option<&str>,
+        at: DateTime<Utc>,
+    ) -> Result<RestoreResponse> {
+        let client = self
+            .clients
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Directory '{}' is not configured", name))?;
+        client.restore(path, at).await
+    }
+
     pub fn get_client_id(&self) -> &str {
         &self.config.client_id
     }
@@ -217,6 +295,70 @@ impl MultiDirectoryClient {
             .collect()
     }
 
+    /// Resolve the bearer token from `token` or `token_file`
+    fn load_token(config: &ClientConfig) -> Result<Option<String>> {
+        if let Some(ref token) = config.token {
+            return Ok(Some(token.clone()));
+        }
+
+        let Some(ref token_file) = config.token_file else {
+            return Ok(None);
+        };
+
+        let token_path = Self::expand_path(token_file)?;
+        let token = std::fs::read_to_string(&token_path).map_err(|e| {
+            anyhow::anyhow!("Failed to read token file {}: {}", token_path.display(), e)
+        })?;
+        let token = token.trim();
+        if token.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Token file {} is empty",
+                token_path.display()
+            ));
+        }
+
+        Ok(Some(token.to_string()))
+    }
+
+    /// Build the TLS settings for `https://` servers with a custom CA, a pinned
+    /// certificate or a client certificate
+    fn load_tls_config(config: &ClientConfig) -> Result<Option<rustls::ClientConfig>> {
+        if config.ca_cert.is_none()
+            && config.pinned_cert_sha256.is_none()
+            && config.client_cert.is_none()
+            && config.client_key.is_none()
+        {
+            return Ok(None);
+        }
+        if !config.server.starts_with("https://") {
+            warn!(
+                "TLS settings are ignored for non-https server {}",
+                config.server
+            );
+        }
+
+        let expand = |path: &Option<PathBuf>| path.as_deref().map(Self::expand_path).transpose();
+        let options = ClientTlsOptions {
+            ca_cert: expand(&config.ca_cert)?,
+            pinned_cert_sha256: config.pinned_cert_sha256.clone(),
+            client_cert: expand(&config.client_cert)?,
+            client_key: expand(&config.client_key)?,
+        };
+        Ok(Some(client_tls_config(&options)?))
+    }
+
+    /// Where client state outside the synced folders is kept
+    fn state_dir(config: &ClientConfig) -> Result<PathBuf> {
+        match &config.state_dir {
+            Some(state_dir) => Self::expand_path(state_dir),
+            None => dirs::data_local_dir()
+                .map(|data_dir| data_dir.join("syncpair"))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Could not determine the local data directory; set state_dir")
+                }),
+        }
+    }
+
     fn expand_path(path: &Path) -> Result<PathBuf> {
         let path_str = path.to_string_lossy();
 
@@ -0,0 +1,125 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::types::error::PathError;
+
+/// Name of the per-directory folder reserved for syncpair's own bookkeeping
+pub const RESERVED_DIR_NAME: &str = ".syncpair";
+
+/// Name of the marker file in a synced folder's reserved folder that holds the folder's ID
+pub const FOLDER_ID_FILE: &str = "folder-id";
+
+/// Prefix of the temporary files sync writes go to before they are renamed into place
+pub const TEMP_FILE_PREFIX: &str = ".syncpair-tmp-";
+
+/// Name of the server state database stored at the root of each directory
+pub const SERVER_STATE_FILE: &str = "server_state.db";
+
+/// Normalize a client-supplied relative path.
+///
+/// Both `/` and `\` are treated as separators, empty and `.` components are
+/// dropped, and the result is joined with `/`. Absolute paths, `..`
+/// components, NUL bytes and names reserved for syncpair internals are
+/// rejected.
+pub fn normalize_relative_path(path: &str) -> Result<String, PathError> {
+    if path.contains('\0') {
+        return Err(PathError::NulByte(path.replace('\0', "\\0")));
+    }
+
+    if path.starts_with('/') || path.starts_with('\\') || has_drive_prefix(path) {
+        return Err(PathError::Absolute(path.to_string()));
+    }
+
+    let mut components = Vec::new();
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => continue,
+            ".." => return Err(PathError::ParentTraversal(path.to_string())),
+            RESERVED_DIR_NAME => return Err(PathError::Reserved(path.to_string())),
+            // Leftover temporary files are removed at startup
+            name if name.starts_with(TEMP_FILE_PREFIX) => {
+                return Err(PathError::Reserved(path.to_string()))
+            }
+            _ => components.push(component),
+        }
+    }
+
+    if components.is_empty() {
+        return Err(PathError::Empty);
+    }
+
+    // The state database (and SQLite's sidecar files) live next to the synced files
+    if components.len() == 1 && is_server_state_file(components[0]) {
+        return Err(PathError::Reserved(path.to_string()));
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Validate a directory name used as a single folder under the storage root
+pub fn validate_directory_name(name: &str) -> Result<(), PathError> {
+    if name.is_empty() {
+        return Err(PathError::Empty);
+    }
+    if name.contains('\0') {
+        return Err(PathError::NulByte(name.replace('\0', "\\0")));
+    }
+    if name == "." || name == ".." {
+        return Err(PathError::ParentTraversal(name.to_string()));
+    }
+    // Names starting with '.' are kept for server internals
+    if name.contains('/') || name.contains('\\') || name.starts_with('.') || name.len() > 255 {
+        return Err(PathError::InvalidDirectoryName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Resolve a client-supplied relative path against `root`.
+///
+/// Returns the full path together with the normalized relative path. Any
+/// symlink that already exists along the way must resolve to a location
+/// inside `root`.
+pub fn resolve_within(root: &Path, relative: &str) -> Result<(PathBuf, String), PathError> {
+    let normalized = normalize_relative_path(relative)?;
+    let full_path = root.join(&normalized);
+
+    // Nothing exists on disk yet, so nothing can escape
+    let canonical_root = match root.canonicalize() {
+        Ok(path) => path,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((full_path, normalized)),
+        Err(_) => return Err(PathError::Unresolvable(normalized)),
+    };
+
+    let mut current = root.to_path_buf();
+    for component in normalized.split('/') {
+        current.push(component);
+        match std::fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                // Dangling links are rejected too, since writing through them creates the target
+                let target = current
+                    .canonicalize()
+                    .map_err(|_| PathError::SymlinkEscape(normalized.clone()))?;
+                if !target.starts_with(&canonical_root) {
+                    return Err(PathError::SymlinkEscape(normalized));
+                }
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => break,
+            Err(_) => return Err(PathError::Unresolvable(normalized)),
+        }
+    }
+
+    Ok((full_path, normalized))
+}
+
+fn is_server_state_file(name: &str) -> bool {
+    match name.strip_prefix(SERVER_STATE_FILE) {
+        Some(suffix) => matches!(suffix, "" | "-wal" | "-shm" | "-journal"),
+        None => false,
+    }
+}
+
+fn has_drive_prefix(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
@@ -0,0 +1,521 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::channel::{mpsc, oneshot};
+use futures::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::io::Read;
+
+use crate::storage::{check_key, join_key, not_found, ObjectInfo, StorageBackend};
+use crate::types::S3Config;
+
+/// Size of the parts content is streamed to the object store in
+const UPLOAD_PART_SIZE: usize = 1024 * 1024; // 1 MB
+
+/// Parts buffered between the server and a transfer in progress
+const TRANSFER_BUFFER_PARTS: usize = 4;
+
+/// Payloads aren't signed, so content can be streamed without hashing it first
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Objects in a bucket of an S3-compatible object store, such as AWS S3 or MinIO,
+/// addressed path-style (`<endpoint>/<bucket>/<key>`) with Signature Version 4.
+///
+/// The backend interface is blocking, like the file system, while HTTP requests
+/// are async, so requests run on a runtime of their own and callers wait for
+/// them. Transfers stream through bounded channels in parts. Async callers must
+/// call it from a blocking thread, such as one of `tokio::task::spawn_blocking`,
+/// since waiting on a runtime worker holds up everything else scheduled there.
+pub struct S3Storage {
+    config: S3Config,
+    // Endpoint without a trailing slash
+    endpoint: String,
+    client: reqwest::Client,
+    // Taken when the storage is dropped
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config) -> Result<Self> {
+        let endpoint = config.endpoint.trim_end_matches('/').to_string();
+        let url = reqwest::Url::parse(&endpoint)?;
+        if url.host_str().is_none() {
+            return Err(anyhow::anyhow!("Invalid S3 endpoint: {}", config.endpoint));
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("syncpair-s3")
+            .enable_all()
+            .build()?;
+        Ok(Self {
+            config: S3Config {
+                prefix: config.prefix.trim_matches('/').to_string(),
+                ..config.clone()
+            },
+            endpoint,
+            client: reqwest::Client::new(),
+            runtime: Some(runtime),
+        })
+    }
+
+    /// Run `request` on the storage's runtime and wait for it
+    fn run<T: Send + 'static>(
+        &self,
+        request: impl Future<Output = Result<T>> + Send + 'static,
+    ) -> Result<T> {
+        let runtime = self.runtime.as_ref().expect("runtime lives until drop");
+        futures::executor::block_on(runtime.spawn(request))?
+    }
+
+    /// A signed request for the object under `key`, or for the bucket itself
+    fn request(
+        &self,
+        method: Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+    ) -> Result<reqwest::RequestBuilder> {
+        let mut path = format!("/{}", uri_encode(&self.config.bucket, false));
+        if let Some(key) = key {
+            check_key(key)?;
+            path.push('/');
+            path.push_str(&uri_encode(&self.object_key(key), true));
+        }
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, false), uri_encode(value, false)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+        let mut url = format!("{}{}", self.endpoint, path);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, query);
+        }
+        let url = reqwest::Url::parse(&url)?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let now = Utc::now();
+        let mut signed_headers: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+            .chain([
+                ("host".to_string(), host),
+                (
+                    "x-amz-content-sha256".to_string(),
+                    UNSIGNED_PAYLOAD.to_string(),
+                ),
+                (
+                    "x-amz-date".to_string(),
+                    now.format("%Y%m%dT%H%M%SZ").to_string(),
+                ),
+            ])
+            .collect();
+        signed_headers.sort();
+        let authorization = sign(
+            &self.config,
+            method.as_str(),
+            url.path(),
+            &query,
+            &signed_headers,
+            now,
+        );
+
+        let mut builder = self.client.request(method, url);
+        for (name, value) in signed_headers
+            .into_iter()
+            .filter(|(name, _)| name != "host")
+        {
+            builder = builder.header(name, value);
+        }
+        Ok(builder.header("authorization", authorization))
+    }
+
+    /// The key of the object under `key` in the bucket
+    fn object_key(&self, key: &str) -> String {
+        join_key(&self.config.prefix, key)
+    }
+
+    /// The backend key of the object under `object_key` in the bucket
+    fn backend_key<'a>(&self, object_key: &'a str) -> Option<&'a str> {
+        if self.config.prefix.is_empty() {
+            return Some(object_key);
+        }
+        object_key
+            .strip_prefix(self.config.prefix.as_str())
+            .and_then(|key| key.strip_prefix('/'))
+    }
+}
+
+impl Drop for S3Storage {
+    fn drop(&mut self) {
+        // Dropping a runtime blocks, which an async caller can't do
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl StorageBackend for S3Storage {
+    fn put(&self, key: &str, content: &mut dyn Read, size: u64) -> Result<()> {
+        let (mut sender, receiver) =
+            mpsc::channel::<std::io::Result<Vec<u8>>>(TRANSFER_BUFFER_PARTS);
+        let request = self
+            .request(
+                Method::PUT,
+                Some(key),
+                &[],
+                &[("content-length", size.to_string())],
+            )?
+            .body(reqwest::Body::wrap_stream(receiver));
+        let key = key.to_string();
+        let runtime = self.runtime.as_ref().expect("runtime lives until drop");
+        let response = runtime.spawn(async move {
+            let response = request.send().await?;
+            check_status(response, &key).await.map(|_| ())
+        });
+
+        let mut content = content.take(size);
+        let mut sent = 0u64;
+        loop {
+            let mut part = vec![0u8; UPLOAD_PART_SIZE];
+            let read = match content.read(&mut part) {
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    // Abort the request rather than store a partial object
+                    let message = e.to_string();
+                    let _ = futures::executor::block_on(sender.send(Err(e)));
+                    drop(sender);
+                    let _ = futures::executor::block_on(response);
+                    return Err(anyhow::anyhow!("Failed to read content: {}", message));
+                }
+            };
+            if read == 0 {
+                break;
+            }
+            part.truncate(read);
+            sent += read as u64;
+            if futures::executor::block_on(sender.send(Ok(part))).is_err() {
+                // The request ended early; its result says why
+                break;
+            }
+        }
+        if sent < size {
+            let _ = futures::executor::block_on(sender.send(Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "content ended early",
+            ))));
+        }
+        drop(sender);
+        futures::executor::block_on(response)??;
+        if sent < size {
+            return Err(anyhow::anyhow!(
+                "Content ended after {} of {} bytes",
+                sent,
+                size
+            ));
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &str, range: Option<(u64, u64)>) -> Result<Box<dyn Read + Send>> {
+        let mut headers = Vec::new();
+        if let Some((start, length)) = range {
+            if length == 0 {
+                // No byte range can ask for nothing
+                return Ok(Box::new(std::io::empty()));
+            }
+            headers.push(("range", format!("bytes={}-{}", start, start + length - 1)));
+        }
+        let request = self.request(Method::GET, Some(key), &[], &headers)?;
+
+        let (status_sender, status) = oneshot::channel::<Result<()>>();
+        let (mut sender, receiver) = mpsc::channel(TRANSFER_BUFFER_PARTS);
+        let key = key.to_string();
+        let runtime = self.runtime.as_ref().expect("runtime lives until drop");
+        runtime.spawn(async move {
+            let response = match request.send().await {
+                Ok(response) => check_status(response, &key).await,
+                Err(e) => Err(e.into()),
+            };
+            let mut response = match response {
+                Ok(response) => {
+                    let _ = status_sender.send(Ok(()));
+                    response
+                }
+                Err(e) => {
+                    let _ = status_sender.send(Err(e));
+                    return;
+                }
+            };
+            loop {
+                let part = match response.chunk().await {
+                    Ok(Some(part)) => Ok(part.to_vec()),
+                    Ok(None) => break,
+                    Err(e) => Err(std::io::Error::other(e)),
+                };
+                let failed = part.is_err();
+                // Stop once the reader is dropped
+                if sender.send(part).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        futures::executor::block_on(status)??;
+        Ok(Box::new(PartReader {
+            receiver,
+            part: Vec::new(),
+            position: 0,
+        }))
+    }
+
+    fn stat(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        let request = self.request(Method::HEAD, Some(key), &[], &[])?;
+        let key = key.to_string();
+        self.run(async move {
+            let response = request.send().await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let response = check_status(response, &key).await?;
+            let header = |name: &str| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .ok_or_else(|| anyhow::anyhow!("S3 response for {} lacks {}", key, name))
+            };
+            let size = header("content-length")?.parse()?;
+            let modified = DateTime::parse_from_rfc2822(header("last-modified")?)?.into();
+            Ok(Some(ObjectInfo {
+                key: key.clone(),
+                size,
+                modified,
+            }))
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let request = self.request(Method::DELETE, Some(key), &[], &[])?;
+        let key = key.to_string();
+        self.run(async move {
+            let response = request.send().await?;
+            if response.status() != StatusCode::NOT_FOUND {
+                check_status(response, &key).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectInfo>> {
+        let object_prefix = if self.config.prefix.is_empty() {
+            prefix.to_string()
+        } else {
+            format!("{}/{}", self.config.prefix, prefix)
+        };
+        let mut objects = Vec::new();
+        let mut continuation_token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", object_prefix.as_str())];
+            if let Some(token) = &continuation_token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let request = self.request(Method::GET, None, &query, &[])?;
+            let page: ListBucketResult = self.run(async move {
+                let response = check_status(request.send().await?, "bucket listing").await?;
+                Ok(quick_xml::de::from_str(&response.text().await?)?)
+            })?;
+
+            for object in page.contents {
+                let Some(key) = self.backend_key(&object.key) else {
+                    continue;
+                };
+                objects.push(ObjectInfo {
+                    key: key.to_string(),
+                    size: object.size,
+                    modified: DateTime::parse_from_rfc3339(&object.last_modified)?.into(),
+                });
+            }
+            match page.next_continuation_token {
+                Some(token) if page.is_truncated => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        check_key(from)?;
+        // Object stores can't rename, so copy the object and delete the original
+        let source = format!(
+            "/{}/{}",
+            uri_encode(&self.config.bucket, false),
+            uri_encode(&self.object_key(from), true)
+        );
+        let request = self.request(Method::PUT, Some(to), &[], &[("x-amz-copy-source", source)])?;
+        let source_key = from.to_string();
+        self.run(async move {
+            let response = request.send().await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Err(not_found(&source_key));
+            }
+            // A copy can fail after the response started, with an error in a 200 response
+            let body = check_status(response, &source_key).await?.text().await?;
+            if body.contains("<Error>") {
+                return Err(anyhow::anyhow!(
+                    "S3 copy of {} failed: {}",
+                    source_key,
+                    body
+                ));
+            }
+            Ok(())
+        })?;
+        self.delete(from)
+    }
+}
+
+/// Reads the parts of an object as a transfer delivers them
+struct PartReader {
+    receiver: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+    part: Vec<u8>,
+    position: usize,
+}
+
+impl Read for PartReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.part.len() {
+            match futures::executor::block_on(self.receiver.next()) {
+                Some(part) => {
+                    self.part = part?;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.part.len() - self.position);
+        buf[..read].copy_from_slice(&self.part[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListBucketResult {
+    #[serde(default)]
+    contents: Vec<ListedObject>,
+    #[serde(default)]
+    is_truncated: bool,
+    next_continuation_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ListedObject {
+    key: String,
+    size: u64,
+    last_modified: String,
+}
+
+/// `response` if it succeeded; a missing object is a `NotFound` I/O error
+async fn check_status(response: reqwest::Response, key: &str) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    if status == StatusCode::NOT_FOUND {
+        return Err(not_found(key));
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(anyhow::anyhow!(
+        "S3 request for {} failed with {}: {}",
+        key,
+        status,
+        body.trim()
+    ))
+}
+
+/// The `Authorization` header of an AWS Signature Version 4 request.
+/// `headers` are the signed headers, lowercase and sorted by name.
+fn sign(
+    config: &S3Config,
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &[(String, String)],
+    now: DateTime<Utc>,
+) -> String {
+    let date = now.format("%Y%m%d").to_string();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method, path, query, canonical_headers, signed_headers, UNSIGNED_PAYLOAD
+    );
+
+    let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+        amz_date,
+        scope,
+        Sha256::digest(canonical_request.as_bytes())
+    );
+    let key = [config.region.as_str(), "s3", "aws4_request"].iter().fold(
+        hmac(
+            format!("AWS4{}", config.secret_access_key).as_bytes(),
+            date.as_bytes(),
+        ),
+        |key, part| hmac(&key, part.as_bytes()),
+    );
+    let signature: String = hmac(&key, string_to_sign.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        config.access_key_id, scope, signed_headers, signature
+    )
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode `value` the way AWS signatures expect: everything but
+/// unreserved characters, and `/` too unless `keep_slashes`
+fn uri_encode(value: &str, keep_slashes: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if keep_slashes => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
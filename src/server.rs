@@ -1,53 +1,258 @@
 use anyhow::Result;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::broadcast;
 use tracing::{debug, error, info, warn};
-use warp::Filter;
+use warp::http::header::{
+    HeaderMap, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, RANGE,
+};
+use warp::http::StatusCode;
+use warp::hyper::body::Buf;
+use warp::{Filter, Reply};
+
+use crate::auth::{authorize, Authenticator, Identity};
+use crate::chunk_store::{
+    delete_content, manifest_key, read_manifest, stat_content, write_manifest, ChunkStore,
+    ContentReader, Manifest,
+};
+use crate::history::VersionHistory;
+use crate::journal::ChangeJournal;
+use crate::paths::{
+    normalize_relative_path, resolve_within, validate_directory_name, SERVER_STATE_FILE,
+};
+use crate::storage::{
+    is_not_found, not_found, open_storage, LocalStorage, ObjectInfo, ObjectReader, StorageBackend,
+};
+use crate::tls::PeerCertificate;
+use crate::trash::Trash;
+use crate::types::error::{AuthError, PathError, RequestError, WriteConflict};
 
-use crate::types::ClientState;
 use crate::types::{
-    BlockUploadRequest, BlockUploadResponse, DeleteRequest, DeleteResponse, DeltaCompleteRequest,
-    DeltaCompleteResponse, DeltaInitRequest, DeltaInitResponse, DownloadResponse, FileConflict,
-    FileInfo, SyncRequest, SyncResponse, UploadRequest, UploadResponse,
+    AccessLevel, AuthConfig, ChangeEvent, ChangeKind, ClientState, DedupConfig, HistoryConfig,
+    ServerConfig, StorageConfig, TrashConfig,
+};
+use crate::types::{
+    BlockMsg, BlockUploadRequest, BlockUploadResponse, ByteRange, ChangesRequest, DeleteRequest,
+    DeleteResponse, DeltaCompleteRequest, DeltaCompleteResponse, DeltaDownloadRequest,
+    DeltaDownloadResponse, DeltaInitRequest, DeltaInitResponse, DownloadResponse, ErrorResponse,
+    FileChange, FileConflict, FileInfo, RestoreRequest, RestoreResponse, SyncRequest, SyncResponse,
+    TrashRequest, TrashResponse, UploadRequest, UploadResponse, UploadSessionRequest,
+    UploadSessionResponse, VersionListResponse, VersionOrder, VersionVector, WriteConflictResponse,
+};
+use crate::types::{
+    BASE_HASH_HEADER, CLIENT_ID_HEADER, HASH_HEADER, MODIFIED_HEADER, OFFSET_HEADER, PATH_HEADER,
+    VERSION_HEADER,
 };
 use crate::utils::{
-    calculate_block_hashes, calculate_file_hash, get_file_info, init_state_database,
-    load_client_state_db, patch_file, save_client_state_db,
+    add_byte_range, calculate_file_hash, calculate_reader_hash, check_block_layout, copy_range,
+    decode_version_header, encode_version_header, init_state_database, load_client_state_db,
+    patch_file, read_block_hashes, received_prefix, remove_temp_files, save_client_state_db,
+    TempFile,
 };
 
+/// How long deletion records and change journal entries are kept
+const DELETION_RETENTION_DAYS: i64 = 7;
+
+/// Change notifications buffered per subscriber before it is told it lagged behind
+const EVENT_BUFFER_SIZE: usize = 256;
+
+/// How often trashed files past the retention window are purged
+const TRASH_PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// How often file versions the history retention rules no longer keep are dropped
+const HISTORY_PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Delta sessions without a block or completion for this long are dropped
+const DELTA_SESSION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// Resumable upload sessions without a chunk for this long are dropped
+const UPLOAD_SESSION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Size of the parts downloads are streamed from storage in
+const STREAM_PART_SIZE: usize = 64 * 1024;
+
+/// Parts of a download read ahead from storage
+const STREAM_BUFFER_PARTS: usize = 16;
+
+/// How often chunks that no file references any more are collected
+const CHUNK_GC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Largest average block size a delta accepts; blocks can be four times as large
+const MAX_DELTA_BLOCK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Key of the writes the server makes itself, such as restores, in version vectors
+const SERVER_REPLICA_ID: &str = "syncpair:server";
+
+/// Files, deletion times and deletion versions of each directory
 type DirectoryStorage = HashMap<
     String,
     (
         HashMap<String, FileInfo>,
         HashMap<String, chrono::DateTime<chrono::Utc>>,
+        HashMap<String, VersionVector>,
     ),
 >;
 
+/// A delta upload in progress. The blocks the stored file shares with the new
+/// version are copied into a staging file, the client uploads the others, and
+/// the staging file replaces the stored file only once the session completes.
+struct DeltaSession {
+    directory: String,
+    // The authenticated caller that opened the session, the only one who may use it
+    owner: Option<String>,
+    // The version being uploaded, by normalized path
+    file_info: FileInfo,
+    // Blocks of the new version
+    blocks: Vec<BlockMsg>,
+    base_hash: Option<String>,
+    staging: TempFile,
+    last_active: std::time::Instant,
+}
+
+/// A resumable upload in progress. Chunks are written into a staging file of
+/// the file's full size, which replaces the stored file once every byte arrived.
+struct UploadSession {
+    directory: String,
+    // The authenticated caller that opened the session, the only one who may use it
+    owner: Option<String>,
+    // The version being uploaded, by normalized path
+    file_info: FileInfo,
+    base_hash: Option<String>,
+    client_id: Option<String>,
+    staging: TempFile,
+    // Parts of the staging file written so far
+    received: Vec<ByteRange>,
+    last_active: std::time::Instant,
+}
+
+/// Files with a write in progress, by directory and path. A write holds its file
+/// from checking the version it was based on until the new version is recorded,
+/// so two writes from the same base can't both pass the check.
+#[derive(Default)]
+struct WriteLocks {
+    writing: Mutex<HashSet<(String, String)>>,
+    released: Condvar,
+}
+
+impl WriteLocks {
+    /// Wait for the write in progress to `path`, if any, then hold the file
+    fn lock(&self, directory_name: &str, path: &str) -> WriteLock<'_> {
+        let file = (directory_name.to_string(), path.to_string());
+        let mut writing = self.writing.lock().unwrap();
+        while writing.contains(&file) {
+            writing = self.released.wait(writing).unwrap();
+        }
+        writing.insert(file.clone());
+        WriteLock { locks: self, file }
+    }
+
+    /// Hold the file at `path` unless a write to it is in progress. Callers that
+    /// hold the directory state lock use this, since a write in progress waits
+    /// for that lock to record its version.
+    fn try_lock(&self, directory_name: &str, path: &str) -> Option<WriteLock<'_>> {
+        let file = (directory_name.to_string(), path.to_string());
+        if !self.writing.lock().unwrap().insert(file.clone()) {
+            return None;
+        }
+        Some(WriteLock { locks: self, file })
+    }
+}
+
+/// A file held by a write until dropped
+struct WriteLock<'a> {
+    locks: &'a WriteLocks,
+    file: (String, String),
+}
+
+impl Drop for WriteLock<'_> {
+    fn drop(&mut self) {
+        self.locks.writing.lock().unwrap().remove(&self.file);
+        self.locks.released.notify_all();
+    }
+}
+
+/// Credentials presented with a request
+#[derive(Debug, Clone, Default)]
+struct Credentials {
+    authorization: Option<String>,
+    // Verified client certificate of the TLS connection
+    peer: Option<PeerCertificate>,
+}
+
+fn credentials() -> impl Filter<Extract = (Credentials,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::ext::optional::<PeerCertificate>())
+        .map(|authorization, peer| Credentials {
+            authorization,
+            peer,
+        })
+}
+
 #[derive(Clone)]
 pub struct SimpleServer {
+    // Directory states, and files being uploaded
     base_storage_dir: PathBuf,
+    // Content of stored files, versions, trashed files and chunks
+    storage: Arc<dyn StorageBackend>,
     // Directory-based shared storage: directory_name -> (files, deleted_files)
     directory_storage: Arc<Mutex<DirectoryStorage>>,
+    // None when the server accepts anonymous requests
+    authenticator: Option<Arc<Authenticator>>,
+    // None when serving plain HTTP
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    // Change notification channels of directories with subscribers
+    subscribers: Arc<Mutex<HashMap<String, broadcast::Sender<ChangeEvent>>>>,
+    // Retention of replaced and deleted file versions
+    history: Arc<HistoryConfig>,
+    // Retention of deleted files in the trash
+    trash: Arc<TrashConfig>,
+    // Open delta uploads by session ID
+    delta_sessions: Arc<Mutex<HashMap<String, DeltaSession>>>,
+    // Open resumable uploads by session ID
+    upload_sessions: Arc<Mutex<HashMap<String, UploadSession>>>,
+    // Whether new content is stored as chunks
+    dedup: Arc<DedupConfig>,
+    // None when no stored file can be a manifest
+    chunk_store: Option<Arc<ChunkStore>>,
+    // Chunks the manifests of each directory reference, by directory name
+    chunk_index: Arc<Mutex<HashMap<String, DirectoryChunks>>>,
+    // Files with a write in progress
+    write_locks: Arc<WriteLocks>,
 }
 
 impl SimpleServer {
     pub fn new(storage_dir: PathBuf) -> Result<Self> {
         let mut directory_storage = HashMap::new();
 
+        // Writes interrupted by a crash left their temporary files behind
+        if storage_dir.exists() {
+            match remove_temp_files(&storage_dir) {
+                Ok(0) => {}
+                Ok(removed) => info!("Removed {} leftover temporary files", removed),
+                Err(e) => warn!("Failed to remove leftover temporary files: {}", e),
+            }
+        }
+
         // Load existing directory states from subdirectories
         if let Ok(entries) = std::fs::read_dir(&storage_dir) {
             for entry in entries.flatten() {
                 if entry.file_type().is_ok_and(|ft| ft.is_dir()) {
                     if let Some(directory_name) = entry.file_name().to_str() {
                         let dir_path = entry.path();
-                        let state_db = dir_path.join("server_state.db");
+                        let state_db = dir_path.join(SERVER_STATE_FILE);
                         if state_db.exists() {
-                            match load_client_state_db(&state_db) {
+                            match create_directory_schema(&state_db)
+                                .and_then(|_| load_client_state_db(&state_db))
+                            {
                                 Ok(state) => {
                                     directory_storage.insert(
                                         directory_name.to_string(),
-                                        (state.files, state.deleted_files),
+                                        (state.files, state.deleted_files, state.deleted_versions),
                                     );
                                     info!("Loaded state for directory: {}", directory_name);
                                 }
@@ -64,12 +269,135 @@ impl SimpleServer {
             }
         }
 
+        let storage: Arc<dyn StorageBackend> = Arc::new(LocalStorage::new(&storage_dir));
+        let dedup = DedupConfig::default();
+        let chunk_store = existing_chunk_store(&storage, &dedup)?;
+
         Ok(Self {
             base_storage_dir: storage_dir,
+            storage,
             directory_storage: Arc::new(Mutex::new(directory_storage)),
+            authenticator: None,
+            tls_config: None,
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
+            history: Arc::new(HistoryConfig::default()),
+            trash: Arc::new(TrashConfig::default()),
+            delta_sessions: Arc::new(Mutex::new(HashMap::new())),
+            upload_sessions: Arc::new(Mutex::new(HashMap::new())),
+            dedup: Arc::new(dedup),
+            chunk_store,
+            chunk_index: Arc::new(Mutex::new(HashMap::new())),
+            write_locks: Arc::new(WriteLocks::default()),
         })
     }
 
+    /// Create a server with the settings from a server configuration file
+    pub fn from_config(storage_dir: PathBuf, config: &ServerConfig) -> Result<Self> {
+        let storage = open_storage(&config.storage, &storage_dir)?;
+        let mut server = Self::new(storage_dir)?.with_storage(storage)?;
+        if let StorageConfig::S3(ref s3) = config.storage {
+            info!(
+                "Storing file content in bucket '{}' at {}",
+                s3.bucket, s3.endpoint
+            );
+        }
+        if let Some(ref auth) = config.auth {
+            server = server.with_auth(auth)?;
+            info!(
+                "Token authentication enabled for {} identities",
+                auth.identities.len()
+            );
+        } else {
+            warn!("No authentication configured: every client can access every directory");
+        }
+        server = server.with_history(&config.history);
+        if !config.history.enabled {
+            warn!("File version history disabled: replaced and deleted files are not kept");
+        }
+        server = server.with_trash(&config.trash);
+        if !config.trash.enabled {
+            warn!("Trash disabled: deleted files go straight to the version history, if any");
+        }
+        server = server.with_dedup(&config.dedup)?;
+        if config.dedup.enabled {
+            info!(
+                "Deduplication enabled: file content is stored as chunks of {} bytes on average",
+                config.dedup.average_chunk_size
+            );
+        }
+        Ok(server)
+    }
+
+    /// Keep replaced and deleted file versions as long as `config` says
+    pub fn with_history(mut self, config: &HistoryConfig) -> Self {
+        self.history = Arc::new(config.clone());
+        self
+    }
+
+    /// Move deleted files to the trash and purge them as `config` says
+    pub fn with_trash(mut self, config: &TrashConfig) -> Self {
+        self.trash = Arc::new(config.clone());
+        self
+    }
+
+    /// Store new file content as deduplicated chunks when `config` enables it
+    pub fn with_dedup(mut self, config: &DedupConfig) -> Result<Self> {
+        if config.enabled {
+            if config.average_chunk_size == 0 || config.average_chunk_size > MAX_DELTA_BLOCK_SIZE {
+                return Err(anyhow::anyhow!(
+                    "dedup.average_chunk_size must be between 1 and {}",
+                    MAX_DELTA_BLOCK_SIZE
+                ));
+            }
+            self.chunk_store = Some(Arc::new(ChunkStore::open(
+                self.storage.clone(),
+                config.average_chunk_size,
+            )?));
+        }
+        self.dedup = Arc::new(config.clone());
+        Ok(self)
+    }
+
+    /// Keep file content in `storage` instead of the storage directory, which
+    /// still holds the directory states and files being uploaded
+    pub fn with_storage(mut self, storage: Arc<dyn StorageBackend>) -> Result<Self> {
+        self.chunk_store = existing_chunk_store(&storage, &self.dedup)?;
+        self.storage = storage;
+        let dedup = self.dedup.clone();
+        self.with_dedup(&dedup)
+    }
+
+    /// Require a bearer token on every route and enforce per-directory permissions
+    pub fn with_auth(mut self, auth: &AuthConfig) -> Result<Self> {
+        self.authenticator = Some(Arc::new(Authenticator::new(auth)?));
+        Ok(self)
+    }
+
+    /// Serve HTTPS using the given PEM certificate chain and private key.
+    /// With `client_ca`, clients can authenticate with a certificate issued by that CA;
+    /// without an `auth` section they then must, and only own their private directories.
+    pub fn with_tls(
+        mut self,
+        cert_path: &Path,
+        key_path: &Path,
+        client_ca: Option<&Path>,
+    ) -> Result<Self> {
+        let config = crate::tls::server_tls_config(cert_path, key_path, client_ca)?;
+        if let Some(cert) = crate::tls::load_certificates(cert_path)?.first() {
+            info!(
+                "TLS certificate SHA-256 fingerprint: {}",
+                crate::tls::certificate_fingerprint(cert)
+            );
+        }
+        self.tls_config = Some(Arc::new(config));
+        // Certificate identities are only checked by an authenticator, even one
+        // without any identity configured
+        if client_ca.is_some() && self.authenticator.is_none() {
+            self.authenticator = Some(Arc::new(Authenticator::new(&AuthConfig::default())?));
+        }
+        Ok(self)
+    }
+
     pub async fn start(&self, port: u16) -> Result<()> {
         let server = self.clone();
         let server_for_sync = self.clone();
@@ -77,22 +405,32 @@ impl SimpleServer {
         let server_for_delete = self.clone();
         let server_for_delta_init = self.clone();
         let server_for_block_upload = self.clone();
+        let server_for_raw_block_upload = self.clone();
         let server_for_delta_complete = self.clone();
 
         let upload_route = warp::path("upload")
             .and(warp::post())
+            .and(credentials())
             .and(warp::body::json())
-            .and_then(move |upload_req: UploadRequest| {
+            .and_then(move |credentials: Credentials, upload_req: UploadRequest| {
                 let server = server.clone();
                 async move {
-                    match server.handle_upload(upload_req).await {
-                        Ok(response) => Ok::<_, warp::Rejection>(warp::reply::json(&response)),
+                    match server
+                        .run_blocking(move |server| server.handle_upload(credentials, upload_req))
+                        .await
+                    {
+                        Ok(response) => {
+                            Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                        }
                         Err(e) => {
+                            if let Some(reply) = conflict_reply(&e) {
+                                return Ok(reply);
+                            }
                             let error_response = UploadResponse {
                                 success: false,
                                 message: format!("Upload failed: {}", e),
                             };
-                            Ok(warp::reply::json(&error_response))
+                            Ok(json_reply(&error_response, error_status(&e)))
                         }
                     }
                 }
@@ -100,54 +438,409 @@ impl SimpleServer {
 
         let sync_route = warp::path("sync")
             .and(warp::post())
+            .and(credentials())
             .and(warp::body::json())
-            .and_then(move |sync_req: SyncRequest| {
+            .and_then(move |credentials: Credentials, sync_req: SyncRequest| {
                 let server = server_for_sync.clone();
                 async move {
-                    match server.handle_sync(sync_req).await {
-                        Ok(response) => Ok::<_, warp::Rejection>(warp::reply::json(&response)),
+                    match server
+                        .run_blocking(move |server| server.handle_sync(credentials, sync_req))
+                        .await
+                    {
+                        Ok(response) => {
+                            Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                        }
                         Err(e) => {
-                            let error_response = SyncResponse {
-                                files_to_upload: vec![],
-                                files_to_download: vec![],
-                                files_to_delete: vec![],
-                                conflicts: vec![],
-                            };
+                            let error_response = SyncResponse::default();
                             error!("Sync error: {}", e);
-                            Ok(warp::reply::json(&error_response))
+                            Ok(json_reply(&error_response, error_status(&e)))
                         }
                     }
                 }
             });
 
+        let server_for_changes = self.clone();
+        let changes_route = warp::path!("sync" / "changes")
+            .and(warp::post())
+            .and(credentials())
+            .and(warp::body::json())
+            .and_then(
+                move |credentials: Credentials, changes_req: ChangesRequest| {
+                    let server = server_for_changes.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_changes(credentials, changes_req)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                error!("Incremental sync error: {}", e);
+                                Ok(json_reply(&SyncResponse::default(), error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_events = self.clone();
+        let events_route = warp::path("events")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(credentials())
+            .and(directory_query())
+            .and_then(
+                move |credentials: Credentials, directory_name: Option<String>| {
+                    let server = server_for_events.clone();
+                    async move {
+                        match server.handle_events(credentials, directory_name) {
+                            Ok(receiver) => Ok::<_, warp::Rejection>(
+                                warp::sse::reply(
+                                    warp::sse::keep_alive().stream(event_stream(receiver)),
+                                )
+                                .into_response(),
+                            ),
+                            Err(e) => {
+                                let error_response = ErrorResponse {
+                                    success: false,
+                                    message: format!("Subscription failed: {}", e),
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)).into_response())
+                            }
+                        }
+                    }
+                },
+            );
+
         let download_route = warp::path!("download" / String)
             .and(warp::get())
+            .and(credentials())
             .and(warp::query::<HashMap<String, String>>())
-            .and_then(move |file_path: String, query: HashMap<String, String>| {
-                let server = server_for_download.clone();
+            .and_then(
+                move |file_path: String,
+                      credentials: Credentials,
+                      query: HashMap<String, String>| {
+                    let server = server_for_download.clone();
+                    async move {
+                        let directory_name = match query.get("directory") {
+                            Some(dir) => dir.clone(),
+                            None => {
+                                let error_response = DownloadResponse {
+                                    success: false,
+                                    file_info: None,
+                                    content: None,
+                                    message: "Missing required 'directory' parameter".to_string(),
+                                };
+                                return Ok::<_, warp::Rejection>(json_reply(
+                                    &error_response,
+                                    StatusCode::BAD_REQUEST,
+                                ));
+                            }
+                        };
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_download(credentials, file_path, directory_name)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                let error_response = DownloadResponse {
+                                    success: false,
+                                    file_info: None,
+                                    content: None,
+                                    message: format!("Download failed: {}", e),
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_stream_upload = self.clone();
+        let stream_upload_route = warp::path!("files" / String)
+            .and(warp::put())
+            .and(credentials())
+            .and(directory_query())
+            .and(warp::header::headers_cloned())
+            .and(warp::body::stream())
+            .and_then(
+                move |file_path: String,
+                      credentials: Credentials,
+                      directory_name: Option<String>,
+                      headers: HeaderMap,
+                      body| {
+                    let server = server_for_stream_upload.clone();
+                    async move {
+                        match server
+                            .handle_stream_upload(
+                                credentials,
+                                file_path,
+                                directory_name,
+                                headers,
+                                body,
+                            )
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                if let Some(reply) = conflict_reply(&e) {
+                                    return Ok(reply);
+                                }
+                                let error_response = UploadResponse {
+                                    success: false,
+                                    message: format!("Upload failed: {}", e),
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_stream_download = self.clone();
+        let stream_download_route = warp::path!("files" / String)
+            .and(warp::get())
+            .and(credentials())
+            .and(directory_query())
+            .and(warp::header::headers_cloned())
+            .and_then(
+                move |file_path: String,
+                      credentials: Credentials,
+                      directory_name: Option<String>,
+                      headers: HeaderMap| {
+                    let server = server_for_stream_download.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_stream_download(
+                                    credentials,
+                                    file_path,
+                                    directory_name,
+                                )
+                            })
+                            .await
+                        {
+                            Ok((file_info, file)) => Ok::<_, warp::Rejection>(
+                                stream_reply(file_info, file, &headers).await,
+                            ),
+                            Err(e) => {
+                                let error_response = DownloadResponse {
+                                    success: false,
+                                    file_info: None,
+                                    content: None,
+                                    message: format!("Download failed: {}", e),
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)).into_response())
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_versions = self.clone();
+        let versions_route = warp::path!("versions" / String)
+            .and(warp::get())
+            .and(credentials())
+            .and(directory_query())
+            .and_then(
+                move |file_path: String,
+                      credentials: Credentials,
+                      directory_name: Option<String>| {
+                    let server = server_for_versions.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_list_versions(credentials, file_path, directory_name)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                let error_response = VersionListResponse {
+                                    success: false,
+                                    message: format!("Listing versions failed: {}", e),
+                                    versions: vec![],
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_version_download = self.clone();
+        let version_download_route = warp::path!("versions" / String / u64)
+            .and(warp::get())
+            .and(credentials())
+            .and(directory_query())
+            .and(warp::header::headers_cloned())
+            .and_then(
+                move |file_path: String,
+                      version_id: u64,
+                      credentials: Credentials,
+                      directory_name: Option<String>,
+                      headers: HeaderMap| {
+                    let server = server_for_version_download.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_version_download(
+                                    credentials,
+                                    file_path,
+                                    version_id,
+                                    directory_name,
+                                )
+                            })
+                            .await
+                        {
+                            Ok((file_info, file)) => Ok::<_, warp::Rejection>(
+                                stream_reply(file_info, file, &headers).await,
+                            ),
+                            Err(e) => {
+                                let error_response = DownloadResponse {
+                                    success: false,
+                                    file_info: None,
+                                    content: None,
+                                    message: format!("Download failed: {}", e),
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)).into_response())
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_restore = self.clone();
+        let restore_route = warp::path("restore")
+            .and(warp::post())
+            .and(credentials())
+            .and(warp::body::json())
+            .and_then(
+                move |credentials: Credentials, restore_req: RestoreRequest| {
+                    let server = server_for_restore.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_restore(credentials, restore_req)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                error!("Restore error: {}", e);
+                                let error_response = RestoreResponse {
+                                    success: false,
+                                    message: format!("Restore failed: {}", e),
+                                    ..RestoreResponse::default()
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_trash = self.clone();
+        let trash_route = warp::path("trash")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(credentials())
+            .and(directory_query())
+            .and_then(
+                move |credentials: Credentials, directory_name: Option<String>| {
+                    let server = server_for_trash.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_list_trash(credentials, directory_name)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                let error_response = TrashResponse {
+                                    success: false,
+                                    message: format!("Listing the trash failed: {}", e),
+                                    items: vec![],
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_trash_restore = self.clone();
+        let trash_restore_route = warp::path!("trash" / "restore")
+            .and(warp::post())
+            .and(credentials())
+            .and(warp::body::json())
+            .and_then(move |credentials: Credentials, trash_req: TrashRequest| {
+                let server = server_for_trash_restore.clone();
                 async move {
-                    let directory_name = match query.get("directory") {
-                        Some(dir) => dir.clone(),
-                        None => {
-                            let error_response = DownloadResponse {
+                    match server
+                        .run_blocking(move |server| {
+                            server.handle_trash_restore(credentials, trash_req)
+                        })
+                        .await
+                    {
+                        Ok(response) => {
+                            Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                        }
+                        Err(e) => {
+                            if let Some(reply) = conflict_reply(&e) {
+                                return Ok(reply);
+                            }
+                            let error_response = TrashResponse {
                                 success: false,
-                                file_info: None,
-                                content: None,
-                                message: "Missing required 'directory' parameter".to_string(),
+                                message: format!("Restoring from the trash failed: {}", e),
+                                items: vec![],
                             };
-                            return Ok::<_, warp::Rejection>(warp::reply::json(&error_response));
+                            Ok(json_reply(&error_response, error_status(&e)))
+                        }
+                    }
+                }
+            });
+
+        let server_for_trash_purge = self.clone();
+        let trash_purge_route = warp::path!("trash" / "purge")
+            .and(warp::post())
+            .and(credentials())
+            .and(warp::body::json())
+            .and_then(move |credentials: Credentials, trash_req: TrashRequest| {
+                let server = server_for_trash_purge.clone();
+                async move {
+                    match server
+                        .run_blocking(move |server| {
+                            server.handle_trash_purge(credentials, trash_req)
+                        })
+                        .await
+                    {
+                        Ok(response) => {
+                            Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
                         }
-                    };
-                    match server.handle_download(file_path, directory_name).await {
-                        Ok(response) => Ok::<_, warp::Rejection>(warp::reply::json(&response)),
                         Err(e) => {
-                            let error_response = DownloadResponse {
+                            let error_response = TrashResponse {
                                 success: false,
-                                file_info: None,
-                                content: None,
-                                message: format!("Download failed: {}", e),
+                                message: format!("Purging the trash failed: {}", e),
+                                items: vec![],
                             };
-                            Ok(warp::reply::json(&error_response))
+                            Ok(json_reply(&error_response, error_status(&e)))
                         }
                     }
                 }
@@ -155,18 +848,27 @@ impl SimpleServer {
 
         let delete_route = warp::path("delete")
             .and(warp::post())
+            .and(credentials())
             .and(warp::body::json())
-            .and_then(move |delete_req: DeleteRequest| {
+            .and_then(move |credentials: Credentials, delete_req: DeleteRequest| {
                 let server = server_for_delete.clone();
                 async move {
-                    match server.handle_delete(delete_req).await {
-                        Ok(response) => Ok::<_, warp::Rejection>(warp::reply::json(&response)),
+                    match server
+                        .run_blocking(move |server| server.handle_delete(credentials, delete_req))
+                        .await
+                    {
+                        Ok(response) => {
+                            Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                        }
                         Err(e) => {
+                            if let Some(reply) = conflict_reply(&e) {
+                                return Ok(reply);
+                            }
                             let error_response = DeleteResponse {
                                 success: false,
                                 message: format!("Delete failed: {}", e),
                             };
-                            Ok(warp::reply::json(&error_response))
+                            Ok(json_reply(&error_response, error_status(&e)))
                         }
                     }
                 }
@@ -174,92 +876,414 @@ impl SimpleServer {
 
         let delta_init_route = warp::path!("delta" / "init")
             .and(warp::post())
+            .and(credentials())
             .and(warp::body::json())
-            .and_then(move |init_req: DeltaInitRequest| {
-                let server = server_for_delta_init.clone();
-                async move {
-                    match server.handle_delta_init(init_req).await {
-                        Ok(response) => Ok::<_, warp::Rejection>(warp::reply::json(&response)),
-                        Err(e) => {
-                            // On error, default to full upload recommendation or basic error
-                            error!("Delta init error: {}", e);
-                            let response = DeltaInitResponse {
-                                missing_block_indices: vec![],
-                                should_full_upload: true,
-                            };
-                            Ok(warp::reply::json(&response))
+            .and_then(
+                move |credentials: Credentials, init_req: DeltaInitRequest| {
+                    let server = server_for_delta_init.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_delta_init(credentials, init_req)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                if let Some(reply) = conflict_reply(&e) {
+                                    return Ok(reply);
+                                }
+                                // On error, default to full upload recommendation or basic error
+                                error!("Delta init error: {}", e);
+                                let response = DeltaInitResponse {
+                                    missing_block_indices: vec![],
+                                    should_full_upload: true,
+                                    session_id: None,
+                                };
+                                Ok(json_reply(&response, error_status(&e)))
+                            }
                         }
                     }
-                }
-            });
+                },
+            );
 
         let delta_upload_route = warp::path!("delta" / "upload")
             .and(warp::post())
+            .and(credentials())
             .and(warp::body::json())
-            .and_then(move |upload_req: BlockUploadRequest| {
-                let server = server_for_block_upload.clone();
-                async move {
-                    match server.handle_block_upload(upload_req).await {
-                        Ok(response) => Ok::<_, warp::Rejection>(warp::reply::json(&response)),
-                        Err(e) => {
-                            let error_response = BlockUploadResponse {
-                                success: false,
-                                message: format!("Block upload failed: {}", e),
-                            };
-                            Ok(warp::reply::json(&error_response))
+            .and_then(
+                move |credentials: Credentials, upload_req: BlockUploadRequest| {
+                    let server = server_for_block_upload.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_block_upload(credentials, upload_req)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                let error_response = BlockUploadResponse {
+                                    success: false,
+                                    message: format!("Block upload failed: {}", e),
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
                         }
                     }
-                }
-            });
+                },
+            );
+
+        let raw_block_upload_route = warp::path!("delta" / String / "blocks" / u64)
+            .and(warp::put())
+            .and(credentials())
+            .and(directory_query())
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .and_then(
+                move |session_id: String,
+                      index: u64,
+                      credentials: Credentials,
+                      directory_name: Option<String>,
+                      headers: HeaderMap,
+                      content: warp::hyper::body::Bytes| {
+                    let server = server_for_raw_block_upload.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_raw_block_upload(
+                                    credentials,
+                                    session_id,
+                                    index,
+                                    directory_name,
+                                    headers,
+                                    &content,
+                                )
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                let error_response = BlockUploadResponse {
+                                    success: false,
+                                    message: format!("Block upload failed: {}", e),
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
 
         let delta_complete_route = warp::path!("delta" / "complete")
             .and(warp::post())
+            .and(credentials())
             .and(warp::body::json())
-            .and_then(move |complete_req: DeltaCompleteRequest| {
-                let server = server_for_delta_complete.clone();
-                async move {
-                    match server.handle_delta_complete(complete_req).await {
-                        Ok(response) => Ok::<_, warp::Rejection>(warp::reply::json(&response)),
-                        Err(e) => {
-                            let error_response = DeltaCompleteResponse {
-                                success: false,
-                                message: format!("Delta completion failed: {}", e),
-                            };
-                            Ok(warp::reply::json(&error_response))
+            .and_then(
+                move |credentials: Credentials, complete_req: DeltaCompleteRequest| {
+                    let server = server_for_delta_complete.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_delta_complete(credentials, complete_req)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                if let Some(reply) = conflict_reply(&e) {
+                                    return Ok(reply);
+                                }
+                                let error_response = DeltaCompleteResponse {
+                                    success: false,
+                                    message: format!("Delta completion failed: {}", e),
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
                         }
                     }
-                }
-            });
+                },
+            );
+
+        let server_for_delta_download = self.clone();
+        let delta_download_route = warp::path!("delta" / "download")
+            .and(warp::post())
+            .and(credentials())
+            .and(warp::body::json())
+            .and_then(
+                move |credentials: Credentials, download_req: DeltaDownloadRequest| {
+                    let server = server_for_delta_download.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_delta_download(credentials, download_req)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                let error_response = DeltaDownloadResponse {
+                                    success: false,
+                                    message: format!("Delta download failed: {}", e),
+                                    file_info: None,
+                                    missing_block_indices: vec![],
+                                    should_full_download: true,
+                                    blocks: vec![],
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_upload_session = self.clone();
+        let upload_session_route = warp::path("uploads")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(credentials())
+            .and(warp::body::json())
+            .and_then(
+                move |credentials: Credentials, session_req: UploadSessionRequest| {
+                    let server = server_for_upload_session.clone();
+                    async move {
+                        match server
+                            .run_blocking(move |server| {
+                                server.handle_open_upload_session(credentials, session_req)
+                            })
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                if let Some(reply) = conflict_reply(&e) {
+                                    return Ok(reply);
+                                }
+                                let error_response = UploadSessionResponse {
+                                    message: format!("Opening upload session failed: {}", e),
+                                    ..UploadSessionResponse::default()
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_upload_status = self.clone();
+        let upload_status_route = warp::path!("uploads" / String)
+            .and(warp::get())
+            .and(credentials())
+            .and(directory_query())
+            .and_then(
+                move |session_id: String,
+                      credentials: Credentials,
+                      directory_name: Option<String>| {
+                    let server = server_for_upload_status.clone();
+                    async move {
+                        match server.handle_upload_status(credentials, session_id, directory_name) {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                let error_response = UploadSessionResponse {
+                                    message: format!("Upload session lookup failed: {}", e),
+                                    ..UploadSessionResponse::default()
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
+
+        let server_for_upload_chunk = self.clone();
+        let upload_chunk_route = warp::path!("uploads" / String)
+            .and(warp::put())
+            .and(credentials())
+            .and(directory_query())
+            .and(warp::header::headers_cloned())
+            .and(warp::body::stream())
+            .and_then(
+                move |session_id: String,
+                      credentials: Credentials,
+                      directory_name: Option<String>,
+                      headers: HeaderMap,
+                      body| {
+                    let server = server_for_upload_chunk.clone();
+                    async move {
+                        match server
+                            .handle_upload_chunk(
+                                credentials,
+                                session_id,
+                                directory_name,
+                                headers,
+                                body,
+                            )
+                            .await
+                        {
+                            Ok(response) => {
+                                Ok::<_, warp::Rejection>(json_reply(&response, StatusCode::OK))
+                            }
+                            Err(e) => {
+                                if let Some(reply) = conflict_reply(&e) {
+                                    return Ok(reply);
+                                }
+                                let error_response = UploadSessionResponse {
+                                    message: format!("Upload failed: {}", e),
+                                    ..UploadSessionResponse::default()
+                                };
+                                Ok(json_reply(&error_response, error_status(&e)))
+                            }
+                        }
+                    }
+                },
+            );
 
         let routes = upload_route
+            .or(changes_route)
             .or(sync_route)
             .or(download_route)
+            .or(stream_upload_route)
+            .or(stream_download_route)
             .or(delete_route)
             .or(delta_init_route)
             .or(delta_upload_route)
+            .or(raw_block_upload_route)
             .or(delta_complete_route)
+            .or(delta_download_route)
+            .or(upload_session_route)
+            .or(upload_status_route)
+            .or(upload_chunk_route)
+            .or(events_route)
+            .or(versions_route)
+            .or(version_download_route)
+            .or(restore_route)
+            .or(trash_route)
+            .or(trash_restore_route)
+            .or(trash_purge_route)
             .with(
                 warp::cors()
                     .allow_any_origin()
-                    .allow_methods(vec!["GET", "POST", "DELETE"])
-                    .allow_headers(vec!["content-type"]),
+                    .allow_methods(vec!["GET", "POST", "PUT", "DELETE"])
+                    .allow_headers(vec![
+                        "content-type",
+                        "authorization",
+                        "range",
+                        "if-range",
+                        HASH_HEADER,
+                        MODIFIED_HEADER,
+                        PATH_HEADER,
+                        CLIENT_ID_HEADER,
+                        BASE_HASH_HEADER,
+                        VERSION_HEADER,
+                        OFFSET_HEADER,
+                    ])
+                    // File metadata comes back in headers on raw downloads
+                    .expose_headers(vec![
+                        HASH_HEADER,
+                        MODIFIED_HEADER,
+                        PATH_HEADER,
+                        VERSION_HEADER,
+                        "etag",
+                        "content-range",
+                    ]),
             );
 
-        info!("Server starting on http://0.0.0.0:{}", port);
+        // Purge expired trash in the background while the server runs
+        let server_for_trash_purge = self.clone();
+        let trash_purge_task = tokio::spawn(async move {
+            let mut purge_timer = tokio::time::interval(TRASH_PURGE_INTERVAL);
+            loop {
+                purge_timer.tick().await;
+                let purge = server_for_trash_purge.run_blocking(|server| server.expire_trash());
+                if let Err(e) = purge.await {
+                    warn!("Failed to purge expired trash: {}", e);
+                }
+            }
+        });
+
+        // Thin out the version history in the background while the server runs
+        let server_for_history_prune = self.clone();
+        let history_prune_task = tokio::spawn(async move {
+            let mut prune_timer = tokio::time::interval(HISTORY_PRUNE_INTERVAL);
+            loop {
+                prune_timer.tick().await;
+                let prune =
+                    server_for_history_prune.run_blocking(|server| server.expire_versions());
+                if let Err(e) = prune.await {
+                    warn!("Failed to prune the version history: {}", e);
+                }
+            }
+        });
+
+        // Collect unreferenced chunks in the background while the server runs
+        let server_for_chunk_gc = self.clone();
+        let chunk_gc_task = tokio::spawn(async move {
+            let mut gc_timer = tokio::time::interval(CHUNK_GC_INTERVAL);
+            loop {
+                gc_timer.tick().await;
+                let collection =
+                    server_for_chunk_gc.run_blocking(|server| server.collect_chunk_garbage());
+                if let Err(e) = collection.await {
+                    warn!("Failed to collect unreferenced chunks: {}", e);
+                }
+            }
+        });
+
+        // Drop abandoned delta and upload sessions with their staging files
+        let server_for_session_expiry = self.clone();
+        let session_expiry_task = tokio::spawn(async move {
+            let mut expiry_timer = tokio::time::interval(DELTA_SESSION_TIMEOUT);
+            loop {
+                expiry_timer.tick().await;
+                server_for_session_expiry.expire_delta_sessions();
+                server_for_session_expiry.expire_upload_sessions();
+            }
+        });
 
         // Create a graceful shutdown future
-        let shutdown = async {
+        let subscribers = self.subscribers.clone();
+        let shutdown = async move {
             tokio::signal::ctrl_c()
                 .await
                 .expect("Failed to install CTRL+C signal handler");
             info!("\nShutdown signal received, stopping server...");
+            // End the open event streams, which would otherwise hold up the shutdown
+            subscribers.lock().unwrap().clear();
         };
 
         // Start server with graceful shutdown
-        let (_, server_future) =
-            warp::serve(routes).bind_with_graceful_shutdown(([0, 0, 0, 0], port), shutdown);
-
-        server_future.await;
+        if let Some(ref tls_config) = self.tls_config {
+            info!("Server starting on https://0.0.0.0:{}", port);
+            crate::tls::serve(
+                warp::service(routes),
+                ([0, 0, 0, 0], port).into(),
+                tls_config.clone(),
+                shutdown,
+            )
+            .await?;
+        } else {
+            info!("Server starting on http://0.0.0.0:{}", port);
+            let (_, server_future) =
+                warp::serve(routes).bind_with_graceful_shutdown(([0, 0, 0, 0], port), shutdown);
+            server_future.await;
+        }
+        trash_purge_task.abort();
+        history_prune_task.abort();
+        chunk_gc_task.abort();
+        session_expiry_task.abort();
 
         // Save final state before shutdown
         if let Err(e) = self.save_all_states() {
@@ -273,43 +1297,557 @@ impl SimpleServer {
         Ok(())
     }
 
-    fn get_directory_storage_dir(&self, directory_name: &str) -> PathBuf {
-        self.base_storage_dir.join(directory_name)
+    fn get_directory_storage_dir(&self, directory_name: &str) -> Result<PathBuf> {
+        validate_directory_name(directory_name)?;
+        Ok(self.base_storage_dir.join(directory_name))
     }
 
-    fn ensure_directory_exists(&self, directory_name: &str) -> Result<()> {
-        let dir_path = self.get_directory_storage_dir(directory_name);
-        std::fs::create_dir_all(&dir_path)?;
+    /// Resolve a client-supplied file path inside a directory's storage.
+    /// Returns the full path and the normalized relative path.
+    fn resolve_file_path(
+        &self,
+        directory_name: &str,
+        file_path: &str,
+    ) -> Result<(PathBuf, String)> {
+        let directory_storage_dir = self.get_directory_storage_dir(directory_name)?;
+        Ok(resolve_within(&directory_storage_dir, file_path)?)
+    }
 
-        // Initialize directory storage if it doesn't exist
-        let mut directory_storage = self.directory_storage.lock().unwrap();
-        if !directory_storage.contains_key(directory_name) {
-            directory_storage.insert(directory_name.to_string(), (HashMap::new(), HashMap::new()));
-            info!("Created new shared directory: {}", directory_name);
-        }
+    /// Authenticate the caller and check its access to `directory_name`.
+    /// A verified client certificate takes precedence over a bearer token.
+    /// Returns the caller's identity, or `None` when authentication is disabled.
+    fn authorize(
+        &self,
+        credentials: &Credentials,
+        directory_name: &str,
+        access: AccessLevel,
+    ) -> Result<Option<Arc<Identity>>> {
+        let Some(authenticator) = &self.authenticator else {
+            return Ok(None);
+        };
 
-        Ok(())
+        let identity = match credentials.peer {
+            Some(ref peer) => authenticator.authenticate_certificate(peer),
+            None => authenticator.authenticate(credentials.authorization.as_deref())?,
+        };
+        authorize(&identity, directory_name, access)?;
+        Ok(Some(identity))
     }
 
-    async fn handle_upload(&self, upload_req: UploadRequest) -> Result<UploadResponse> {
-        let directory_name = upload_req.directory.ok_or_else(|| {
-            anyhow::anyhow!("Missing required 'directory' field in upload request")
-        })?;
-        self.ensure_directory_exists(&directory_name)?;
+    /// The client ID to record for a request: the authenticated identity when
+    /// there is one, so callers can't claim to be someone else.
+    fn caller_id(
+        credentials: &Credentials,
+        identity: Option<&Identity>,
+        claimed: Option<String>,
+    ) -> Option<String> {
+        match (identity, &credentials.peer) {
+            (Some(identity), _) => Some(identity.name.clone()),
+            (None, Some(peer)) => Some(peer.name().to_string()),
+            (None, None) => claimed,
+        }
+    }
 
-        let directory_storage_dir = self.get_directory_storage_dir(&directory_name);
-        let file_path = directory_storage_dir.join(&upload_req.file_info.path);
+    /// The change journal of a directory whose storage directory exists
+    fn journal(&self, directory_name: &str) -> Result<ChangeJournal> {
+        ChangeJournal::open(
+            &self
+                .get_directory_storage_dir(directory_name)?
+                .join(SERVER_STATE_FILE),
+        )
+    }
+
+    /// The version history of a directory whose storage directory exists, or None
+    /// when the server keeps no history
+    fn version_history(&self, directory_name: &str) -> Result<Option<VersionHistory>> {
+        if !self.history.enabled {
+            return Ok(None);
+        }
+        let directory_storage_dir = self.get_directory_storage_dir(directory_name)?;
+        Ok(Some(VersionHistory::open(
+            &directory_storage_dir,
+            self.storage.clone(),
+            directory_name,
+        )?))
+    }
+
+    /// The trash of a directory whose storage directory exists, or None when the
+    /// server has no trash
+    fn trash(&self, directory_name: &str) -> Result<Option<Trash>> {
+        if !self.trash.enabled {
+            return Ok(None);
+        }
+        let directory_storage_dir = self.get_directory_storage_dir(directory_name)?;
+        Ok(Some(Trash::open(
+            &directory_storage_dir,
+            self.storage.clone(),
+            directory_name,
+        )?))
+    }
+
+    /// Purge the trashed files of every directory that are past the retention
+    /// window. They move on to the version history when that is enabled.
+    fn expire_trash(&self) -> Result<()> {
+        let directory_names: Vec<String> = self
+            .directory_storage
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        for directory_name in directory_names {
+            if !self.get_directory_storage_dir(&directory_name)?.is_dir() {
+                continue;
+            }
+            if let Some(trash) = self.trash(&directory_name)? {
+                let history = self.version_history(&directory_name)?;
+                let expired = trash.expire(&self.trash, |item, content_key| {
+                    if let Some(history) = &history {
+                        history.archive_deleted(content_key, &item.file_info, item.deleted_at)?;
+                    }
+                    Ok(())
+                })?;
+                if let (Some(history), false) = (&history, expired.is_empty()) {
+                    history.prune(&self.history, None)?;
+                }
+                if !expired.is_empty() {
+                    info!(
+                        "🗑️  Purged {} expired files from the trash of directory '{}'",
+                        expired.len(),
+                        directory_name
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop the file versions of every directory that the retention rules no longer keep
+    fn expire_versions(&self) -> Result<()> {
+        let directory_names: Vec<String> = self
+            .directory_storage
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        for directory_name in directory_names {
+            if !self.get_directory_storage_dir(&directory_name)?.is_dir() {
+                continue;
+            }
+            if let Some(history) = self.version_history(&directory_name)? {
+                let pruned = history.prune(&self.history, None)?;
+                if pruned > 0 {
+                    info!(
+                        "🧹 Dropped {} old file versions of directory '{}'",
+                        pruned, directory_name
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The directory's recorded version of `path`
+    fn recorded_file(&self, directory_name: &str, path: &str) -> Option<FileInfo> {
+        let directory_storage = self.directory_storage.lock().unwrap();
+        directory_storage
+            .get(directory_name)
+            .and_then(|(directory_files, _, _)| directory_files.get(path))
+            .cloned()
+    }
+
+    /// Run `work` with the server on a blocking thread. Storage backends block
+    /// while they wait for their I/O, which must not hold up a runtime worker.
+    async fn run_blocking<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&SimpleServer) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let server = self.clone();
+        tokio::task::spawn_blocking(move || work(&server)).await?
+    }
+
+    /// Take the file at `relative_path` out of the directory before it is replaced
+    /// or deleted. Deleted files go to the trash, or with the trash disabled to the
+    /// version history like replaced ones, when that is enabled.
+    /// `recorded` is the directory state's entry for the file, if any.
+    fn retire_file(
+        &self,
+        directory_name: &str,
+        relative_path: &str,
+        recorded: Option<&FileInfo>,
+        replaced_by: ChangeKind,
+    ) -> Result<()> {
+        let key = file_key(directory_name, relative_path);
+        let Some(stored) = self.stat_stored(&key)? else {
+            return Ok(());
+        };
+        if replaced_by == ChangeKind::Deleted {
+            if let Some(trash) = self.trash(directory_name)? {
+                let file_info = self.history_entry(&key, &stored, relative_path, recorded)?;
+                trash.put(&key, &file_info)?;
+                return Ok(());
+            }
+        }
+        let Some(history) = self.version_history(directory_name)? else {
+            if replaced_by == ChangeKind::Deleted {
+                delete_content(self.storage.as_ref(), &key)?;
+            }
+            return Ok(());
+        };
+
+        let file_info = self.history_entry(&key, &stored, relative_path, recorded)?;
+        history.archive(&key, &file_info, replaced_by)?;
+        history.prune(&self.history, Some(relative_path))?;
+        Ok(())
+    }
+
+    /// What to record in the version history for the file stored under `key` in
+    /// `stored`: the directory state's entry when it still describes the file,
+    /// otherwise the file as found in storage
+    fn history_entry(
+        &self,
+        key: &str,
+        stored: &ObjectInfo,
+        relative_path: &str,
+        recorded: Option<&FileInfo>,
+    ) -> Result<FileInfo> {
+        let size = match self.stored_manifest(key)? {
+            Some(manifest) => manifest.size,
+            None => stored.size,
+        };
+        match recorded {
+            Some(recorded) if recorded.size == size => Ok(recorded.clone()),
+            _ => self.stored_file_info(key, relative_path),
+        }
+    }
+
+    /// With deduplication, swap the verified new content in `temp` for a manifest
+    /// of its chunks, to commit over `target` in its place
+    fn stage_content(&self, temp: TempFile, target: &Path) -> Result<StagedContent> {
+        match &self.chunk_store {
+            Some(chunk_store) if self.dedup.enabled => {
+                let manifest = chunk_store.store(temp.path())?;
+                let temp = write_manifest(target, &manifest)?;
+                Ok(StagedContent::Manifest(temp, manifest))
+            }
+            _ => Ok(StagedContent::Content(temp)),
+        }
+    }
+
+    /// Store the verified content staged in `staged` as the file at `relative_path`
+    /// in the directory, replacing the content there whether it was stored as
+    /// itself or as a manifest
+    fn commit_content(
+        &self,
+        staged: StagedContent,
+        directory_name: &str,
+        relative_path: &str,
+    ) -> Result<()> {
+        let key = &file_key(directory_name, relative_path);
+        match staged {
+            StagedContent::Manifest(temp, manifest) => {
+                self.storage.put_file(&manifest_key(key), temp.path())?;
+                self.chunk_index
+                    .lock()
+                    .unwrap()
+                    .entry(directory_name.to_string())
+                    .or_default()
+                    .chunks
+                    .extend(manifest.chunks.into_iter().map(|chunk| chunk.hash));
+                self.storage.delete(key)
+            }
+            StagedContent::Content(temp) => {
+                self.storage.put_file(key, temp.path())?;
+                // Only servers that deduplicated content have manifests to replace
+                match self.chunk_store {
+                    Some(_) => self.storage.delete(&manifest_key(key)),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// The object holding the content stored under `key`, if there is any
+    fn stat_stored(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        match self.chunk_store {
+            Some(_) => stat_content(self.storage.as_ref(), key),
+            None => self.storage.stat(key),
+        }
+    }
+
+    /// The manifest of the content stored under `key`, when it is stored as one
+    fn stored_manifest(&self, key: &str) -> Result<Option<Manifest>> {
+        match self.chunk_store {
+            Some(_) => read_manifest(self.storage.as_ref(), key),
+            None => Ok(None),
+        }
+    }
+
+    /// The content stored under `key`, as found in storage
+    fn stored_file_info(&self, key: &str, relative_path: &str) -> Result<FileInfo> {
+        let stored = self.stat_stored(key)?.ok_or_else(|| not_found(key))?;
+        let (hash, size) = match self.stored_manifest(key)? {
+            Some(manifest) => (manifest.hash, manifest.size),
+            None => (
+                calculate_reader_hash(self.storage.get(key, None)?)?,
+                stored.size,
+            ),
+        };
+        Ok(FileInfo {
+            path: relative_path.to_string(),
+            hash,
+            size,
+            modified: stored.modified,
+            version: VersionVector::default(),
+        })
+    }
+
+    /// Open the content stored under `key` for reading
+    fn open_stored(&self, key: &str) -> Result<ContentReader> {
+        match (&self.chunk_store, self.stored_manifest(key)?) {
+            (Some(chunk_store), Some(manifest)) => {
+                Ok(ContentReader::Chunks(chunk_store.reader(&manifest)?))
+            }
+            _ => {
+                let stored = self.storage.stat(key)?.ok_or_else(|| not_found(key))?;
+                Ok(ContentReader::Object(ObjectReader::new(
+                    self.storage.clone(),
+                    key,
+                    stored.size,
+                )))
+            }
+        }
+    }
+
+    /// Blocks of the content stored under `key`, split as `calculate_block_hashes` does
+    fn stored_block_hashes(&self, key: &str, average_size: u64) -> Result<Vec<BlockMsg>> {
+        read_block_hashes(self.open_stored(key)?, average_size)
+    }
+
+    /// The content stored under `key`, to stream in a download
+    fn stored_content(&self, key: &str) -> Result<StoredContent> {
+        let manifest = self.stored_manifest(key)?;
+        Ok(StoredContent {
+            storage: self.storage.clone(),
+            parts: match manifest {
+                Some(manifest) => StoredParts::Chunks(manifest),
+                None => StoredParts::Object(key.to_string()),
+            },
+        })
+    }
+
+    /// Read the chunks the manifests of a directory's files, versions and trashed
+    /// files list into the chunk index, unless they were already. Commits add
+    /// their chunks from then on, so the manifests are read once per directory
+    /// between two garbage collections.
+    fn index_directory_chunks(&self, directory_name: &str) -> Result<()> {
+        let indexed = self
+            .chunk_index
+            .lock()
+            .unwrap()
+            .get(directory_name)
+            .is_some_and(|index| index.complete);
+        if indexed {
+            return Ok(());
+        }
+
+        // Commits while the manifests are read add to the index themselves
+        let keys = ChunkStore::manifest_keys(self.storage.as_ref(), &file_key(directory_name, ""))?;
+        let chunks = ChunkStore::reference_counts(self.storage.as_ref(), &keys)?;
+        let mut chunk_index = self.chunk_index.lock().unwrap();
+        let index = chunk_index.entry(directory_name.to_string()).or_default();
+        index.chunks.extend(chunks.into_keys());
+        index.complete = true;
+        Ok(())
+    }
+
+    /// Whether the chunk index lists the chunk `hash` for the directory
+    fn directory_has_chunk(&self, directory_name: &str, hash: &str) -> bool {
+        self.chunk_index
+            .lock()
+            .unwrap()
+            .get(directory_name)
+            .is_some_and(|index| index.chunks.contains(hash))
+    }
+
+    /// Remove the chunks no stored file, version or trashed file references.
+    /// Returns how many were removed.
+    pub fn collect_chunk_garbage(&self) -> Result<usize> {
+        let Some(chunk_store) = &self.chunk_store else {
+            return Ok(0);
+        };
+        let removed = chunk_store.collect_garbage()?;
+        // Files retired since the index was read may have taken chunks out of their
+        // directory; those are read again when next needed
+        self.chunk_index.lock().unwrap().clear();
+        if removed > 0 {
+            info!("🧹 Removed {} unreferenced chunks", removed);
+        }
+        Ok(removed)
+    }
+
+    /// Reject a write whose `base_hash` is not the directory's current version of
+    /// `path`. A write that leaves the file as it already is (`new_hash`, or no
+    /// file for a deletion) is never a conflict, so retries are harmless.
+    fn check_base_hash(
+        &self,
+        directory_name: &str,
+        path: &str,
+        base_hash: Option<&str>,
+        new_hash: Option<&str>,
+    ) -> Result<()> {
+        let Some(base_hash) = base_hash else {
+            return Ok(());
+        };
+
+        let directory_storage = self.directory_storage.lock().unwrap();
+        let current = directory_storage
+            .get(directory_name)
+            .and_then(|(directory_files, _, _)| directory_files.get(path));
+        let current_hash = current.map(|file_info| file_info.hash.as_str());
+        if current_hash == new_hash || current_hash.unwrap_or_default() == base_hash {
+            return Ok(());
+        }
+
+        Err(WriteConflict {
+            path: path.to_string(),
+            current: current.cloned(),
+        }
+        .into())
+    }
+
+    /// The recorded version of a file, if the recorded content is what's on disk
+    fn stored_version(&self, directory_name: &str, path: &str, hash: &str) -> VersionVector {
+        let directory_storage = self.directory_storage.lock().unwrap();
+        directory_storage
+            .get(directory_name)
+            .and_then(|(directory_files, _, _)| directory_files.get(path))
+            .filter(|file_info| file_info.hash == hash)
+            .map(|file_info| file_info.version.clone())
+            .unwrap_or_default()
+    }
+
+    /// Record a change in the journal and push it to the directory's subscribers
+    fn record_change(
+        &self,
+        journal: &ChangeJournal,
+        directory_name: &str,
+        path: &str,
+        kind: ChangeKind,
+    ) -> Result<()> {
+        let cursor = journal.record(path, kind)?;
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(sender) = subscribers.get(directory_name) {
+            let event = ChangeEvent {
+                path: path.to_string(),
+                kind,
+                cursor,
+            };
+            if sender.send(event).is_err() {
+                // Every subscriber is gone
+                subscribers.remove(directory_name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Subscribe to the changes committed in a directory
+    fn handle_events(
+        &self,
+        credentials: Credentials,
+        directory_name: Option<String>,
+    ) -> Result<broadcast::Receiver<ChangeEvent>> {
+        let directory_name = required_directory(directory_name)?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+        self.get_directory_storage_dir(&directory_name)?;
+
+        info!(
+            "🔔 {} subscribed to changes in directory '{}'",
+            Self::caller_id(&credentials, identity.as_deref(), None)
+                .as_deref()
+                .unwrap_or("unknown client"),
+            directory_name
+        );
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let sender = subscribers
+            .entry(directory_name)
+            .or_insert_with(|| broadcast::channel(EVENT_BUFFER_SIZE).0);
+        Ok(sender.subscribe())
+    }
+
+    fn ensure_directory_exists(&self, directory_name: &str) -> Result<()> {
+        if self
+            .directory_storage
+            .lock()
+            .unwrap()
+            .contains_key(directory_name)
+        {
+            return Ok(());
+        }
+
+        let dir_path = self.get_directory_storage_dir(directory_name)?;
+        std::fs::create_dir_all(&dir_path)?;
+        create_directory_schema(&dir_path.join(SERVER_STATE_FILE))?;
+
+        // Initialize directory storage if it doesn't exist
+        let created = {
+            let mut directory_storage = self.directory_storage.lock().unwrap();
+            if directory_storage.contains_key(directory_name) {
+                false
+            } else {
+                directory_storage.insert(
+                    directory_name.to_string(),
+                    (HashMap::new(), HashMap::new(), HashMap::new()),
+                );
+                info!("Created new shared directory: {}", directory_name);
+                true
+            }
+        };
+
+        // The state database now exists, so it needs a state to load on restart
+        if created {
+            self.atomic_save_directory_state(directory_name)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_upload(
+        &self,
+        credentials: Credentials,
+        mut upload_req: UploadRequest,
+    ) -> Result<UploadResponse> {
+        let directory_name = upload_req.directory.take().ok_or_else(|| {
+            anyhow::anyhow!("Missing required 'directory' field in upload request")
+        })?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        upload_req.client_id = Self::caller_id(
+            &credentials,
+            identity.as_deref(),
+            upload_req.client_id.take(),
+        );
+        let (file_path, relative_path) =
+            self.resolve_file_path(&directory_name, &upload_req.file_info.path)?;
+        upload_req.file_info.path = relative_path;
+        self.ensure_directory_exists(&directory_name)?;
+        self.check_base_hash(
+            &directory_name,
+            &upload_req.file_info.path,
+            upload_req.base_hash.as_deref(),
+            Some(&upload_req.file_info.hash),
+        )?;
 
         // Create parent directories if they don't exist
         if let Some(parent) = file_path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        // Write file content
-        std::fs::write(&file_path, &upload_req.content)?;
-
-        // Verify file hash
-        let calculated_hash = calculate_file_hash(&file_path)?;
+        // Write file content next to the stored version, which stays intact until
+        // the new one is verified
+        let temp = TempFile::write(&file_path, &upload_req.content)?;
+        let calculated_hash = calculate_file_hash(temp.path())?;
         if calculated_hash != upload_req.file_info.hash {
             return Ok(UploadResponse {
                 success: false,
@@ -320,20 +1858,39 @@ impl SimpleServer {
             });
         }
 
-        // Update directory state
+        self.install_staged_file(
+            &directory_name,
+            &file_path,
+            temp,
+            upload_req.file_info,
+            upload_req.base_hash.as_deref(),
+            upload_req.client_id.as_deref(),
+        )
+    }
+
+    /// Record a file whose content was written and verified in the directory state
+    fn record_upload(
+        &self,
+        directory_name: &str,
+        file_info: FileInfo,
+        client_id: Option<&str>,
+    ) -> Result<UploadResponse> {
+        let journal = self.journal(directory_name)?;
         let state_modified = {
             let mut directory_storage = self.directory_storage.lock().unwrap();
-            let (directory_files, _) = directory_storage.get_mut(&directory_name).unwrap();
+            let (directory_files, _, _) = directory_storage.get_mut(directory_name).unwrap();
 
-            let old_file = directory_files.get(&upload_req.file_info.path);
-            let is_new_or_changed =
-                old_file.is_none_or(|old| old.hash != upload_req.file_info.hash);
+            let old_file = directory_files.get(&file_info.path);
+            let is_new_or_changed = old_file.is_none_or(|old| old.hash != file_info.hash);
 
             if is_new_or_changed {
-                directory_files.insert(
-                    upload_req.file_info.path.clone(),
-                    upload_req.file_info.clone(),
-                );
+                directory_files.insert(file_info.path.clone(), file_info.clone());
+                self.record_change(
+                    &journal,
+                    directory_name,
+                    &file_info.path,
+                    ChangeKind::Modified,
+                )?;
                 true
             } else {
                 false
@@ -341,10 +1898,12 @@ impl SimpleServer {
         };
 
         if state_modified {
-            self.atomic_save_directory_state(&directory_name)?;
+            self.atomic_save_directory_state(directory_name)?;
             info!(
-                "📁 Uploaded to directory '{}': {}",
-                directory_name, upload_req.file_info.path
+                "📁 Uploaded to directory '{}': {} (by {})",
+                directory_name,
+                file_info.path,
+                client_id.unwrap_or("unknown client")
             );
         }
 
@@ -357,34 +1916,187 @@ impl SimpleServer {
         })
     }
 
-    async fn handle_sync(&self, sync_req: SyncRequest) -> Result<SyncResponse> {
+    /// Stream a raw request body to disk, hashing it on the way
+    async fn handle_stream_upload<S, B>(
+        &self,
+        credentials: Credentials,
+        file_path: String,
+        directory_name: Option<String>,
+        headers: HeaderMap,
+        body: S,
+    ) -> Result<UploadResponse>
+    where
+        S: Stream<Item = Result<B, warp::Error>>,
+        B: Buf,
+    {
+        let directory_name = required_directory(directory_name)?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        let client_id = Self::caller_id(
+            &credentials,
+            identity.as_deref(),
+            optional_header(&headers, CLIENT_ID_HEADER)?,
+        );
+        let expected_hash = required_header(&headers, HASH_HEADER)?;
+        let base_hash = optional_header(&headers, BASE_HASH_HEADER)?;
+        let modified = parse_modified_header(&headers)?;
+        let version = parse_version_header(&headers)?;
+
+        let decoded_file_path = urlencoding::decode(&file_path)
+            .map_err(|e| anyhow::anyhow!("Failed to decode file path '{}': {}", file_path, e))?
+            .into_owned();
+        let (full_file_path, relative_path) =
+            self.resolve_file_path(&directory_name, &decoded_file_path)?;
+
+        // Refuse a conflicting upload before its body is read
+        {
+            let directory_name = directory_name.clone();
+            let relative_path = relative_path.clone();
+            let base_hash = base_hash.clone();
+            let expected_hash = expected_hash.clone();
+            self.run_blocking(move |server| {
+                server.ensure_directory_exists(&directory_name)?;
+                server.check_base_hash(
+                    &directory_name,
+                    &relative_path,
+                    base_hash.as_deref(),
+                    Some(&expected_hash),
+                )
+            })
+            .await?;
+        }
+
+        if let Some(parent) = full_file_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Stream to a temporary file; the stored version stays intact until the
+        // new one is verified
+        let temp = TempFile::new(&full_file_path);
+        let mut file = tokio::fs::File::create(temp.path()).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        futures::pin_mut!(body);
+        while let Some(chunk) = body.next().await {
+            let mut chunk = chunk?;
+            let bytes = chunk.copy_to_bytes(chunk.remaining());
+            hasher.update(&bytes);
+            file.write_all(&bytes).await?;
+            size += bytes.len() as u64;
+        }
+        file.flush().await?;
+        file.sync_all().await?;
+        drop(file);
+
+        let calculated_hash = format!("{:x}", hasher.finalize());
+        if calculated_hash != expected_hash {
+            return Ok(UploadResponse {
+                success: false,
+                message: format!(
+                    "Hash mismatch: expected {}, got {}",
+                    expected_hash, calculated_hash
+                ),
+            });
+        }
+
+        let file_info = FileInfo {
+            path: relative_path,
+            hash: calculated_hash,
+            size,
+            modified,
+            version,
+        };
+        self.run_blocking(move |server| {
+            server.install_staged_file(
+                &directory_name,
+                &full_file_path,
+                temp,
+                file_info,
+                base_hash.as_deref(),
+                client_id.as_deref(),
+            )
+        })
+        .await
+    }
+
+    fn handle_sync(&self, credentials: Credentials, sync_req: SyncRequest) -> Result<SyncResponse> {
         let directory_name = sync_req
             .directory
             .ok_or_else(|| anyhow::anyhow!("Missing required 'directory' field in sync request"))?;
+        // Read access is enough to pull changes; pushing them requires write access
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+        let client_id = Self::caller_id(&credentials, identity.as_deref(), sync_req.client_id);
+        let can_write = identity
+            .as_ref()
+            .is_none_or(|identity| identity.allows(&directory_name, AccessLevel::Write));
 
-        // Create directory on filesystem first
-        let directory_storage_dir = self.get_directory_storage_dir(&directory_name);
-        std::fs::create_dir_all(&directory_storage_dir)?;
+        let directory_storage_dir = self.get_directory_storage_dir(&directory_name)?;
+        let mut rejected_paths = Vec::new();
+        let client_files = normalize_file_map(sync_req.files, &mut rejected_paths);
+        let mut client_deleted_files =
+            normalize_deletion_map(sync_req.deleted_files, &mut rejected_paths);
+        let client_deleted_versions =
+            normalize_deletion_map(sync_req.deleted_versions, &mut rejected_paths);
+        let client_deleted_base_hashes =
+            normalize_deletion_map(sync_req.deleted_base_hashes, &mut rejected_paths);
+        rejected_paths.sort();
+        rejected_paths.dedup();
+        if !can_write {
+            client_deleted_files.clear();
+        }
 
-        // Use a single, scoped lock to ensure atomicity and avoid deadlock
-        let (files_to_upload, files_to_download, files_to_delete, conflicts) = {
-            let mut directory_storage = self.directory_storage.lock().unwrap();
+        self.ensure_directory_exists(&directory_name)?;
+        let journal = self.journal(&directory_name)?;
 
-            // Initialize directory storage if it doesn't exist (within the same lock)
-            if !directory_storage.contains_key(&directory_name) {
-                directory_storage.insert(directory_name.clone(), (HashMap::new(), HashMap::new()));
-                info!("Created new shared directory: {}", directory_name);
-            }
+        // Files the client's deletions win over are retired before the directory
+        // state lock is taken, and held against writes until the deletions are recorded
+        let (winning_deletions, _writes): (Vec<_>, Vec<_>) = {
+            let directory_storage = self.directory_storage.lock().unwrap();
+            let directory_files = directory_storage
+                .get(&directory_name)
+                .map(|(directory_files, _, _)| directory_files);
+            client_deleted_files
+                .iter()
+                .filter_map(|(deleted_path, deletion_time)| {
+                    let directory_file = directory_files?.get(deleted_path)?;
+                    let deletion_version = client_deleted_versions
+                        .get(deleted_path)
+                        .cloned()
+                        .unwrap_or_default();
+                    let order = order_versions(
+                        &deletion_version,
+                        *deletion_time,
+                        &directory_file.version,
+                        directory_file.modified,
+                    );
+                    // A deletion of another version than the server's never removes it
+                    let stale = client_deleted_base_hashes
+                        .get(deleted_path)
+                        .is_some_and(|base_hash| *base_hash != directory_file.hash);
+                    if order != VersionOrder::Dominates || stale {
+                        return None;
+                    }
+                    // A write to the file still in progress is newer than the deletion
+                    let write = self.write_locks.try_lock(&directory_name, deleted_path)?;
+                    Some(((deleted_path.clone(), directory_file.clone()), write))
+                })
+                .unzip()
+        };
+        let retired =
+            self.retire_deleted_files(&directory_name, &directory_storage_dir, &winning_deletions);
 
-            let (directory_files, directory_deleted_files) =
+        // Use a single, scoped lock to ensure atomicity and avoid deadlock
+        let (files_to_upload, files_to_download, files_to_delete, conflicts, cursor) = {
+            let mut directory_storage = self.directory_storage.lock().unwrap();
+            let (directory_files, directory_deleted_files, deletion_versions) =
                 directory_storage.get_mut(&directory_name).unwrap();
 
-            // Clean up old deletion records (older than 7 days) to prevent unlimited growth
-            let cutoff_time = chrono::Utc::now() - chrono::Duration::days(7);
+            // Clean up old deletion records (older than 7 days) to prevent unlimited growth.
+            // Journal entries go with them, since cursors older than that can no longer
+            // tell which deletions they missed.
+            let cutoff_time = chrono::Utc::now() - chrono::Duration::days(DELETION_RETENTION_DAYS);
             directory_deleted_files.retain(|_, deletion_time| *deletion_time > cutoff_time);
-
-            let client_files = sync_req.files;
-            let client_deleted_files = sync_req.deleted_files;
+            deletion_versions.retain(|path, _| directory_deleted_files.contains_key(path));
+            journal.prune(cutoff_time)?;
 
             let mut files_to_upload = Vec::new();
             let mut files_to_download = Vec::new();
@@ -392,39 +2104,52 @@ impl SimpleServer {
             let mut conflicts = Vec::new();
             let mut state_modified = false;
 
-            // Handle files that the client has deleted with timestamp comparison
+            // Handle files that the client has deleted
             for (deleted_path, deletion_time) in &client_deleted_files {
+                let deletion_version = client_deleted_versions
+                    .get(deleted_path)
+                    .cloned()
+                    .unwrap_or_default();
                 if let Some(directory_file) = directory_files.get(deleted_path) {
-                    // Compare deletion time with directory file modification time
-                    if *deletion_time > directory_file.modified {
+                    if retired.contains(deleted_path) {
                         info!(
                             "📁 Client deleted file from directory '{}' (newer than server): {}",
                             directory_name, deleted_path
                         );
 
-                        // Delete the file from directory storage
-                        let directory_file_path = directory_storage_dir.join(deleted_path);
-                        if directory_file_path.exists() {
-                            if let Err(e) = std::fs::remove_file(&directory_file_path) {
-                                error!(
-                                    "Failed to delete {} from directory '{}' storage: {}",
-                                    deleted_path, directory_name, e
-                                );
-                                continue;
-                            } else {
-                                debug!(
-                                    "✓ Deleted from directory '{}' storage: {}",
-                                    directory_name, deleted_path
-                                );
-                            }
-                        }
-
                         // Remove from directory state and add to deleted files with timestamp
                         directory_files.remove(deleted_path);
                         directory_deleted_files.insert(deleted_path.clone(), *deletion_time);
+                        deletion_versions.insert(deleted_path.clone(), deletion_version);
+                        self.record_change(
+                            &journal,
+                            &directory_name,
+                            deleted_path,
+                            ChangeKind::Deleted,
+                        )?;
                         state_modified = true;
+                    } else if winning_deletions
+                        .iter()
+                        .any(|(winning_path, _)| winning_path == deleted_path)
+                    {
+                        // Retiring the file failed, which was logged; the deletion waits
+                        // for the next sync
+                        continue;
                     } else {
-                        warn!("⚠️  Client deletion ignored in directory '{}': server file {} is newer", directory_name, deleted_path);
+                        let order = order_versions(
+                            &deletion_version,
+                            *deletion_time,
+                            &directory_file.version,
+                            directory_file.modified,
+                        );
+                        if order == VersionOrder::Concurrent {
+                            warn!(
+                                "⚠️  Conflict in directory '{}': {} was deleted on the client and edited on the server, keeping the edit",
+                                directory_name, deleted_path
+                            );
+                        } else {
+                            warn!("⚠️  Client deletion ignored in directory '{}': server file {} is newer", directory_name, deleted_path);
+                        }
                         // Directory file is newer, client should get the update
                         files_to_download.push(directory_file.clone());
                     }
@@ -436,9 +2161,16 @@ impl SimpleServer {
                         directory_deleted_files.insert(deleted_path.clone(), *deletion_time);
                         state_modified = true;
                     }
+                    let known_version = deletion_versions.entry(deleted_path.clone()).or_default();
+                    let merged_version = known_version.merged(&deletion_version);
+                    if merged_version != *known_version {
+                        *known_version = merged_version;
+                        state_modified = true;
+                    }
                 } else {
                     // File doesn't exist in directory, just record the deletion
                     directory_deleted_files.insert(deleted_path.clone(), *deletion_time);
+                    deletion_versions.insert(deleted_path.clone(), deletion_version);
                     state_modified = true;
                 }
             }
@@ -447,18 +2179,33 @@ impl SimpleServer {
             let mut deletions_to_remove = Vec::new();
             for (deleted_path, deletion_time) in directory_deleted_files.iter() {
                 if let Some(client_file) = client_files.get(deleted_path) {
-                    // Compare deletion time with client file modification time
-                    if *deletion_time > client_file.modified {
+                    let order = order_versions(
+                        &client_file.version,
+                        client_file.modified,
+                        &deletion_versions
+                            .get(deleted_path)
+                            .cloned()
+                            .unwrap_or_default(),
+                        *deletion_time,
+                    );
+                    if order == VersionOrder::Dominated {
                         files_to_delete.push(deleted_path.clone());
                         debug!(
                             "📁 Client should delete (directory deleted it): {}",
                             deleted_path
                         );
-                    } else {
-                        warn!(
-                            "⚠️  Directory '{}' deletion ignored: client file {} is newer",
-                            directory_name, deleted_path
-                        );
+                    } else if can_write {
+                        if order == VersionOrder::Concurrent {
+                            warn!(
+                                "⚠️  Conflict in directory '{}': {} was edited on the client and deleted on the server, keeping the edit",
+                                directory_name, deleted_path
+                            );
+                        } else {
+                            warn!(
+                                "⚠️  Directory '{}' deletion ignored: client file {} is newer",
+                                directory_name, deleted_path
+                            );
+                        }
                         // Client file is newer, should be uploaded
                         files_to_upload.push(deleted_path.clone());
                         // Mark for removal from deleted files since client has newer version
@@ -471,6 +2218,7 @@ impl SimpleServer {
             // Remove obsolete deletions
             for path in deletions_to_remove {
                 directory_deleted_files.remove(&path);
+                deletion_versions.remove(&path);
             }
 
             // Compare client files with directory files
@@ -482,23 +2230,38 @@ impl SimpleServer {
 
                 if let Some(directory_file) = directory_files.get(file_path) {
                     // File exists in both client and directory - check for conflicts
-                    if client_file.hash != directory_file.hash {
-                        if client_file.modified > directory_file.modified {
+                    if client_file.hash == directory_file.hash {
+                        if client_file.version != directory_file.version {
+                            // Same content reached both sides; the client adopts the
+                            // server's history so later edits on either side compare
+                            files_to_download.push(directory_file.clone());
+                        }
+                        continue;
+                    }
+                    match order_versions(
+                        &client_file.version,
+                        client_file.modified,
+                        &directory_file.version,
+                        directory_file.modified,
+                    ) {
+                        VersionOrder::Dominates => {
                             // Client file is newer
                             files_to_upload.push(file_path.clone());
                             debug!(
                                 "📁 Client file is newer in directory '{}': {}",
                                 directory_name, file_path
                             );
-                        } else if directory_file.modified > client_file.modified {
+                        }
+                        VersionOrder::Dominated => {
                             // Directory file is newer
                             files_to_download.push(directory_file.clone());
                             debug!(
                                 "📁 Directory '{}' file is newer: {}",
                                 directory_name, file_path
                             );
-                        } else {
-                            // Same timestamp but different content - conflict
+                        }
+                        VersionOrder::Equal | VersionOrder::Concurrent => {
+                            // Edited on both sides without seeing each other - conflict
                             conflicts.push(FileConflict {
                                 path: file_path.clone(),
                                 client_file: client_file.clone(),
@@ -534,12 +2297,18 @@ impl SimpleServer {
                 }
             }
 
+            // Read-only callers can't act on upload requests
+            if !can_write {
+                files_to_upload.clear();
+            }
+
             // Save state if modified (while still holding the lock)
             if state_modified {
                 if let Err(e) = self.save_directory_state_with_lock(
                     &directory_name,
                     directory_files,
                     directory_deleted_files,
+                    deletion_versions,
                 ) {
                     error!(
                         "Failed to save directory state for '{}': {}",
@@ -553,38 +2322,397 @@ impl SimpleServer {
                 files_to_download,
                 files_to_delete,
                 conflicts,
+                journal.cursor()?,
             )
         };
 
-        info!("📁 Sync completed for directory '{}': {} to upload, {} to download, {} to delete, {} conflicts", 
-              directory_name, files_to_upload.len(), files_to_download.len(), files_to_delete.len(), conflicts.len());
+        info!("📁 Sync completed for directory '{}' (by {}): {} to upload, {} to download, {} to delete, {} conflicts",
+              directory_name, client_id.as_deref().unwrap_or("unknown client"), files_to_upload.len(), files_to_download.len(), files_to_delete.len(), conflicts.len());
 
         Ok(SyncResponse {
             files_to_upload,
             files_to_download,
             files_to_delete,
             conflicts,
+            cursor: Some(cursor),
+            cursor_expired: false,
+            rejected_paths,
         })
     }
 
-    async fn handle_download(
+    /// Retire the files at the paths of `deletions` for the deletions to be recorded,
+    /// outside the directory state lock. `deletions` pairs each path with the
+    /// file recorded there. Returns the paths whose files were retired; the
+    /// others failed, which is logged.
+    fn retire_deleted_files(
         &self,
-        file_path: String,
-        directory_name: String,
-    ) -> Result<DownloadResponse> {
-        // URL decode the file path since the client URL-encodes it
-        let decoded_file_path = urlencoding::decode(&file_path)
-            .map_err(|e| anyhow::anyhow!("Failed to decode file path '{}': {}", file_path, e))?
-            .into_owned();
+        directory_name: &str,
+        directory_storage_dir: &Path,
+        deletions: &[(String, FileInfo)],
+    ) -> HashSet<String> {
+        let mut retired = HashSet::new();
+        for (path, recorded) in deletions {
+            if let Err(e) = resolve_within(directory_storage_dir, path) {
+                error!(
+                    "Refusing to delete {} from directory '{}': {}",
+                    path, directory_name, e
+                );
+                continue;
+            }
+            match self.retire_file(directory_name, path, Some(recorded), ChangeKind::Deleted) {
+                Ok(()) => {
+                    debug!(
+                        "✓ Deleted from directory '{}' storage: {}",
+                        directory_name, path
+                    );
+                    retired.insert(path.clone());
+                }
+                Err(e) => error!(
+                    "Failed to delete {} from directory '{}' storage: {}",
+                    path, directory_name, e
+                ),
+            }
+        }
+        retired
+    }
 
-        let directory_storage_dir = self.get_directory_storage_dir(&directory_name);
-        let full_file_path = directory_storage_dir.join(&decoded_file_path);
+    /// Incremental sync: apply the client's changes since its cursor and return
+    /// only the paths that changed on the server since then
+    fn handle_changes(
+        &self,
+        credentials: Credentials,
+        changes_req: ChangesRequest,
+    ) -> Result<SyncResponse> {
+        let directory_name = changes_req.directory.ok_or_else(|| {
+            anyhow::anyhow!("Missing required 'directory' field in changes request")
+        })?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+        let client_id = Self::caller_id(&credentials, identity.as_deref(), changes_req.client_id);
+        let can_write = identity
+            .as_ref()
+            .is_none_or(|identity| identity.allows(&directory_name, AccessLevel::Write));
 
-        if !full_file_path.exists() {
-            return Ok(DownloadResponse {
-                success: false,
-                file_info: None,
-                content: None,
+        let mut local_changes = Vec::with_capacity(changes_req.changes.len());
+        let mut rejected_paths = Vec::new();
+        for mut change in changes_req.changes {
+            let path = match change {
+                FileChange::Modified(ref mut file_info) => &mut file_info.path,
+                FileChange::Deleted { ref mut path, .. } => path,
+            };
+            match normalize_relative_path(path) {
+                Ok(normalized) => *path = normalized,
+                Err(e) => {
+                    warn!("Skipping change to invalid path {:?}: {}", path, e);
+                    rejected_paths.push(path.clone());
+                    continue;
+                }
+            }
+            local_changes.push(change);
+        }
+        if !can_write {
+            local_changes.clear();
+        }
+
+        let directory_storage_dir = self.get_directory_storage_dir(&directory_name)?;
+        self.ensure_directory_exists(&directory_name)?;
+        let journal = self.journal(&directory_name)?;
+
+        // Files the client's deletions win over are retired before the directory
+        // state lock is taken for the changes, and held against writes until the
+        // deletions are recorded
+        let (winning_deletions, _writes): (Vec<_>, Vec<_>) = {
+            let mut directory_storage = self.directory_storage.lock().unwrap();
+            let (directory_files, directory_deleted_files, deletion_versions) =
+                directory_storage.get_mut(&directory_name).unwrap();
+
+            let cutoff_time = chrono::Utc::now() - chrono::Duration::days(DELETION_RETENTION_DAYS);
+            directory_deleted_files.retain(|_, deletion_time| *deletion_time > cutoff_time);
+            deletion_versions.retain(|path, _| directory_deleted_files.contains_key(path));
+            journal.prune(cutoff_time)?;
+
+            let Some(remote_paths) = journal.changed_since(&changes_req.cursor)? else {
+                info!(
+                    "📁 Cursor of {} for directory '{}' expired, full sync required",
+                    client_id.as_deref().unwrap_or("unknown client"),
+                    directory_name
+                );
+                return Ok(SyncResponse {
+                    cursor_expired: true,
+                    rejected_paths,
+                    ..SyncResponse::default()
+                });
+            };
+
+            local_changes
+                .iter()
+                .filter_map(|change| {
+                    let FileChange::Deleted {
+                        path,
+                        deleted_at,
+                        version,
+                        base_hash,
+                    } = change
+                    else {
+                        return None;
+                    };
+                    let directory_file = directory_files.get(path)?;
+                    // A deletion of another version than the server's never removes it
+                    let stale = base_hash
+                        .as_ref()
+                        .is_some_and(|base_hash| *base_hash != directory_file.hash);
+                    let order = if !remote_paths.contains(path)
+                        && version.compare(&directory_file.version) == VersionOrder::Equal
+                    {
+                        VersionOrder::Dominates
+                    } else {
+                        order_versions(
+                            version,
+                            *deleted_at,
+                            &directory_file.version,
+                            directory_file.modified,
+                        )
+                    };
+                    if order != VersionOrder::Dominates || stale {
+                        return None;
+                    }
+                    // A write to the file still in progress is newer than the deletion
+                    let write = self.write_locks.try_lock(&directory_name, path)?;
+                    Some(((path.clone(), directory_file.clone()), write))
+                })
+                .unzip()
+        };
+        let retired =
+            self.retire_deleted_files(&directory_name, &directory_storage_dir, &winning_deletions);
+
+        let mut directory_storage = self.directory_storage.lock().unwrap();
+        let (directory_files, directory_deleted_files, deletion_versions) =
+            directory_storage.get_mut(&directory_name).unwrap();
+
+        // Should another sync have expired the cursor in the meantime, the changes
+        // are still applied, and the client syncs in full
+        let remote_changes = journal.changed_since(&changes_req.cursor)?;
+        let cursor_expired = remote_changes.is_none();
+        let mut remote_paths = remote_changes.unwrap_or_default();
+
+        let mut files_to_upload = Vec::new();
+        let mut conflicts = Vec::new();
+        let mut state_modified = false;
+
+        for change in &local_changes {
+            let path = change.path();
+            let changed_remotely = remote_paths.iter().any(|remote| remote == path);
+
+            // Without version history on either side, a change the server hasn't
+            // seen since the client's cursor is the client's to make
+            let trust_client = |client_version: &VersionVector, server_version: &VersionVector| {
+                !changed_remotely && client_version.compare(server_version) == VersionOrder::Equal
+            };
+
+            match change {
+                FileChange::Modified(client_file) => {
+                    let client_wins = if let Some(directory_file) = directory_files.get(path) {
+                        if client_file.hash == directory_file.hash {
+                            false
+                        } else if trust_client(&client_file.version, &directory_file.version) {
+                            true
+                        } else {
+                            match order_versions(
+                                &client_file.version,
+                                client_file.modified,
+                                &directory_file.version,
+                                directory_file.modified,
+                            ) {
+                                VersionOrder::Dominates => true,
+                                VersionOrder::Dominated => false,
+                                VersionOrder::Equal | VersionOrder::Concurrent => {
+                                    // Edited on both sides without seeing each other - conflict
+                                    conflicts.push(FileConflict {
+                                        path: path.to_string(),
+                                        client_file: client_file.clone(),
+                                        server_file: directory_file.clone(),
+                                    });
+                                    warn!(
+                                        "⚠️  Conflict in directory '{}' resolved (server wins): {}",
+                                        directory_name, path
+                                    );
+                                    false
+                                }
+                            }
+                        }
+                    } else {
+                        match directory_deleted_files.get(path) {
+                            Some(deletion_time) => {
+                                let deletion_version =
+                                    deletion_versions.get(path).cloned().unwrap_or_default();
+                                let order = if trust_client(&client_file.version, &deletion_version)
+                                {
+                                    VersionOrder::Dominates
+                                } else {
+                                    order_versions(
+                                        &client_file.version,
+                                        client_file.modified,
+                                        &deletion_version,
+                                        *deletion_time,
+                                    )
+                                };
+                                if order == VersionOrder::Concurrent {
+                                    warn!(
+                                        "⚠️  Conflict in directory '{}': {} was edited on the client and deleted on the server, keeping the edit",
+                                        directory_name, path
+                                    );
+                                }
+                                let deleted_later = order == VersionOrder::Dominated;
+                                if !deleted_later {
+                                    // The client's version replaces the deletion
+                                    directory_deleted_files.remove(path);
+                                    deletion_versions.remove(path);
+                                    state_modified = true;
+                                }
+                                !deleted_later
+                            }
+                            None => true,
+                        }
+                    };
+
+                    if client_wins {
+                        files_to_upload.push(path.to_string());
+                        remote_paths.retain(|remote| remote != path);
+                    } else if !changed_remotely
+                        && directory_files.get(path).is_none_or(|file| {
+                            file.hash != client_file.hash || file.version != client_file.version
+                        })
+                    {
+                        // The client is behind on this path, whatever its cursor says; for
+                        // content both sides share, it adopts the server's history
+                        remote_paths.push(path.to_string());
+                    }
+                }
+                FileChange::Deleted {
+                    deleted_at,
+                    version,
+                    ..
+                } => {
+                    let Some(directory_file) = directory_files.get(path) else {
+                        if !directory_deleted_files.contains_key(path) {
+                            directory_deleted_files.insert(path.to_string(), *deleted_at);
+                            deletion_versions.insert(path.to_string(), version.clone());
+                            state_modified = true;
+                        }
+                        continue;
+                    };
+                    if !retired.contains(path) {
+                        if winning_deletions
+                            .iter()
+                            .any(|(winning_path, _)| winning_path == path)
+                        {
+                            // Retiring the file failed, which was logged; the deletion
+                            // waits for the next sync
+                            continue;
+                        }
+                        let order = if trust_client(version, &directory_file.version) {
+                            VersionOrder::Dominates
+                        } else {
+                            order_versions(
+                                version,
+                                *deleted_at,
+                                &directory_file.version,
+                                directory_file.modified,
+                            )
+                        };
+                        if order == VersionOrder::Concurrent {
+                            warn!(
+                                "⚠️  Conflict in directory '{}': {} was deleted on the client and edited on the server, keeping the edit",
+                                directory_name, path
+                            );
+                        } else {
+                            warn!(
+                                "⚠️  Client deletion ignored in directory '{}': server file {} is newer",
+                                directory_name, path
+                            );
+                        }
+                        if !changed_remotely {
+                            remote_paths.push(path.to_string());
+                        }
+                        continue;
+                    }
+
+                    info!(
+                        "📁 Client deleted file from directory '{}': {}",
+                        directory_name, path
+                    );
+
+                    directory_files.remove(path);
+                    directory_deleted_files.insert(path.to_string(), *deleted_at);
+                    deletion_versions.insert(path.to_string(), version.clone());
+                    self.record_change(&journal, &directory_name, path, ChangeKind::Deleted)?;
+                    remote_paths.retain(|remote| remote != path);
+                    state_modified = true;
+                }
+            }
+        }
+
+        // Whatever else changed remotely is pulled as it is now
+        let mut files_to_download = Vec::new();
+        let mut files_to_delete = Vec::new();
+        for path in remote_paths {
+            if let Some(directory_file) = directory_files.get(&path) {
+                files_to_download.push(directory_file.clone());
+            } else if directory_deleted_files.contains_key(&path) {
+                files_to_delete.push(path);
+            }
+        }
+
+        if state_modified {
+            if let Err(e) = self.save_directory_state_with_lock(
+                &directory_name,
+                directory_files,
+                directory_deleted_files,
+                deletion_versions,
+            ) {
+                error!(
+                    "Failed to save directory state for '{}': {}",
+                    directory_name, e
+                );
+            }
+        }
+        let cursor = journal.cursor()?;
+        drop(directory_storage);
+
+        info!("📁 Incremental sync for directory '{}' (by {}): {} local changes, {} to upload, {} to download, {} to delete, {} conflicts",
+              directory_name, client_id.as_deref().unwrap_or("unknown client"), local_changes.len(), files_to_upload.len(), files_to_download.len(), files_to_delete.len(), conflicts.len());
+
+        Ok(SyncResponse {
+            files_to_upload,
+            files_to_download,
+            files_to_delete,
+            conflicts,
+            cursor: Some(cursor),
+            cursor_expired,
+            rejected_paths,
+        })
+    }
+
+    fn handle_download(
+        &self,
+        credentials: Credentials,
+        file_path: String,
+        directory_name: String,
+    ) -> Result<DownloadResponse> {
+        self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+
+        // URL decode the file path since the client URL-encodes it
+        let decoded_file_path = urlencoding::decode(&file_path)
+            .map_err(|e| anyhow::anyhow!("Failed to decode file path '{}': {}", file_path, e))?
+            .into_owned();
+
+        let (_, decoded_file_path) = self.resolve_file_path(&directory_name, &decoded_file_path)?;
+        let key = file_key(&directory_name, &decoded_file_path);
+
+        if self.stat_stored(&key)?.is_none() {
+            return Ok(DownloadResponse {
+                success: false,
+                file_info: None,
+                content: None,
                 message: format!(
                     "File not found in directory '{}': {}",
                     directory_name, decoded_file_path
@@ -592,17 +2720,11 @@ impl SimpleServer {
             });
         }
 
-        let content = std::fs::read(&full_file_path)?;
-        let file_hash = calculate_file_hash(&full_file_path)?;
-        let metadata = std::fs::metadata(&full_file_path)?;
-        let modified = metadata.modified()?.into();
-
-        let file_info = FileInfo {
-            path: decoded_file_path.clone(),
-            hash: file_hash,
-            size: metadata.len(),
-            modified,
-        };
+        let mut content = Vec::new();
+        self.open_stored(&key)?.read_to_end(&mut content)?;
+        let mut file_info = self.stored_file_info(&key, &decoded_file_path)?;
+        file_info.version =
+            self.stored_version(&directory_name, &decoded_file_path, &file_info.hash);
 
         info!(
             "📁 Downloaded from directory '{}': {}",
@@ -620,28 +2742,586 @@ impl SimpleServer {
         })
     }
 
-    async fn handle_delete(&self, delete_req: DeleteRequest) -> Result<DeleteResponse> {
-        let directory_name = delete_req.directory.ok_or_else(|| {
-            anyhow::anyhow!("Missing required 'directory' field in delete request")
+    /// Open a file for a raw download, with the metadata sent in the response headers
+    fn handle_stream_download(
+        &self,
+        credentials: Credentials,
+        file_path: String,
+        directory_name: Option<String>,
+    ) -> Result<(FileInfo, StoredContent)> {
+        let directory_name = required_directory(directory_name)?;
+        self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+
+        let decoded_file_path = urlencoding::decode(&file_path)
+            .map_err(|e| anyhow::anyhow!("Failed to decode file path '{}': {}", file_path, e))?
+            .into_owned();
+        let (_, relative_path) = self.resolve_file_path(&directory_name, &decoded_file_path)?;
+        let key = file_key(&directory_name, &relative_path);
+
+        let mut file_info = match self.stored_file_info(&key, &relative_path) {
+            Ok(file_info) => file_info,
+            Err(e) if is_not_found(&e) => {
+                return Err(RequestError::NotFound(format!(
+                    "File not found in directory '{}': {}",
+                    directory_name, relative_path
+                ))
+                .into());
+            }
+            Err(e) => return Err(e),
+        };
+        file_info.version = self.stored_version(&directory_name, &relative_path, &file_info.hash);
+        info!(
+            "📁 Streaming download from directory '{}': {}",
+            directory_name, relative_path
+        );
+        self.stored_content(&key)
+            .map(|content| (file_info, content))
+    }
+
+    /// List the prior versions of a file kept in the directory's history
+    fn handle_list_versions(
+        &self,
+        credentials: Credentials,
+        file_path: String,
+        directory_name: Option<String>,
+    ) -> Result<VersionListResponse> {
+        let directory_name = required_directory(directory_name)?;
+        self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+
+        let decoded_file_path = urlencoding::decode(&file_path)
+            .map_err(|e| anyhow::anyhow!("Failed to decode file path '{}': {}", file_path, e))?
+            .into_owned();
+        let (_, relative_path) = self.resolve_file_path(&directory_name, &decoded_file_path)?;
+
+        let versions = if self.get_directory_storage_dir(&directory_name)?.is_dir() {
+            match self.version_history(&directory_name)? {
+                Some(history) => history.list(&relative_path)?,
+                None => Vec::new(),
+            }
+        } else {
+            Vec::new()
+        };
+
+        Ok(VersionListResponse {
+            success: true,
+            message: format!(
+                "{} versions of {} in directory '{}'",
+                versions.len(),
+                relative_path,
+                directory_name
+            ),
+            versions,
+        })
+    }
+
+    /// Open a prior version of a file for a raw download
+    fn handle_version_download(
+        &self,
+        credentials: Credentials,
+        file_path: String,
+        version_id: u64,
+        directory_name: Option<String>,
+    ) -> Result<(FileInfo, StoredContent)> {
+        let directory_name = required_directory(directory_name)?;
+        self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+
+        let decoded_file_path = urlencoding::decode(&file_path)
+            .map_err(|e| anyhow::anyhow!("Failed to decode file path '{}': {}", file_path, e))?
+            .into_owned();
+        let (_, relative_path) = self.resolve_file_path(&directory_name, &decoded_file_path)?;
+        let not_found = || {
+            RequestError::NotFound(format!(
+                "Version {} of {} not found in directory '{}'",
+                version_id, relative_path, directory_name
+            ))
+        };
+
+        if !self.get_directory_storage_dir(&directory_name)?.is_dir() {
+            return Err(not_found().into());
+        }
+        let Some(history) = self.version_history(&directory_name)? else {
+            return Err(not_found().into());
+        };
+        let Some((version, content_key)) = history.get(&relative_path, version_id)? else {
+            return Err(not_found().into());
+        };
+        if self.stat_stored(&content_key)?.is_none() {
+            return Err(not_found().into());
+        }
+
+        info!(
+            "📁 Streaming version {} of {} from directory '{}'",
+            version_id, relative_path, directory_name
+        );
+        self.stored_content(&content_key)
+            .map(|content| (version.file_info, content))
+    }
+
+    /// Roll files back to how they were at a point in time. The content each file
+    /// had then is written as a new version, and files created since are deleted,
+    /// so the restore reaches every client through normal sync.
+    fn handle_restore(
+        &self,
+        credentials: Credentials,
+        restore_req: RestoreRequest,
+    ) -> Result<RestoreResponse> {
+        let directory_name = restore_req.directory.ok_or_else(|| {
+            anyhow::anyhow!("Missing required 'directory' field in restore request")
+        })?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        let client_id = Self::caller_id(&credentials, identity.as_deref(), restore_req.client_id);
+        let scope = restore_req
+            .path
+            .as_deref()
+            .map(normalize_relative_path)
+            .transpose()?;
+        let at = restore_req.at;
+
+        let directory_storage_dir = self.get_directory_storage_dir(&directory_name)?;
+        if !directory_storage_dir.is_dir() {
+            return Err(RequestError::NotFound(format!(
+                "Directory '{}' not found",
+                directory_name
+            ))
+            .into());
+        }
+        let history = self.version_history(&directory_name)?;
+        let trash = self.trash(&directory_name)?;
+        if history.is_none() && trash.is_none() {
+            return Err(anyhow::anyhow!(
+                "Version history and trash are disabled, so there is nothing to restore from"
+            ));
+        }
+        self.ensure_directory_exists(&directory_name)?;
+        let journal = self.journal(&directory_name)?;
+
+        let in_scope = |path: &String| {
+            scope.as_deref().is_none_or(|scope| {
+                path == scope
+                    || path
+                        .strip_prefix(scope)
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+        };
+        let mut paths: Vec<String> = {
+            let directory_storage = self.directory_storage.lock().unwrap();
+            let (directory_files, directory_deleted_files, _) = &directory_storage[&directory_name];
+            directory_files
+                .keys()
+                .chain(directory_deleted_files.keys())
+                .filter(|path| in_scope(path))
+                .cloned()
+                .collect()
+        };
+        if let Some(history) = &history {
+            paths.extend(history.paths()?.into_iter().filter(in_scope));
+        }
+        if let Some(trash) = &trash {
+            paths.extend(trash.paths()?.into_iter().filter(in_scope));
+        }
+        paths.sort();
+        paths.dedup();
+
+        // Old content is copied and stored while holding only the file's write lock;
+        // the directory state is locked just to record each result
+        let restore_path = |path: &str, file_path: &Path| -> Result<RestoreOutcome> {
+            let (current, deleted_at, deletion_version) = {
+                let directory_storage = self.directory_storage.lock().unwrap();
+                let (directory_files, directory_deleted_files, deletion_versions) =
+                    &directory_storage[&directory_name];
+                (
+                    directory_files.get(path).cloned(),
+                    directory_deleted_files.get(path).copied(),
+                    deletion_versions.get(path).cloned(),
+                )
+            };
+            let key = file_key(&directory_name, path);
+            if current.is_some() {
+                // The current content was stored when the file was last written
+                let Some(stored) = self.stat_stored(&key)? else {
+                    return Ok(RestoreOutcome::Unchanged);
+                };
+                if stored.modified <= at {
+                    return Ok(RestoreOutcome::Unchanged);
+                }
+            }
+
+            // Deleted content is in the trash, or with the trash disabled in the history
+            let mut old_version = match &history {
+                Some(history) => history
+                    .version_at(path, at)?
+                    .map(|(version, content_key)| (version.file_info, content_key)),
+                None => None,
+            };
+            if old_version.is_none() {
+                if let Some(trash) = &trash {
+                    old_version = trash
+                        .version_at(path, at)?
+                        .map(|(item, content_key)| (item.file_info, content_key));
+                }
+            }
+
+            let Some((old_file_info, content_key)) = old_version else {
+                let Some(current) = current else {
+                    if deleted_at.is_some_and(|deleted_at| deleted_at > at) {
+                        return Ok(RestoreOutcome::Unavailable);
+                    }
+                    return Ok(RestoreOutcome::Unchanged);
+                };
+
+                // Nothing was stored at this path yet at that time
+                self.retire_file(&directory_name, path, Some(&current), ChangeKind::Deleted)?;
+
+                let mut directory_storage = self.directory_storage.lock().unwrap();
+                let (directory_files, directory_deleted_files, deletion_versions) =
+                    directory_storage.get_mut(&directory_name).unwrap();
+                directory_files.remove(path);
+                directory_deleted_files.insert(path.to_string(), chrono::Utc::now());
+                deletion_versions.insert(
+                    path.to_string(),
+                    current.version.incremented(SERVER_REPLICA_ID),
+                );
+                self.record_change(&journal, &directory_name, path, ChangeKind::Deleted)?;
+                return Ok(RestoreOutcome::Deleted);
+            };
+            if current
+                .as_ref()
+                .is_some_and(|current| current.hash == old_file_info.hash)
+            {
+                return Ok(RestoreOutcome::Unchanged);
+            }
+
+            // A new version, derived from both the current one and the old one
+            let known = match &current {
+                Some(current) => current.version.clone(),
+                None => deletion_version.unwrap_or_default(),
+            };
+            let file_info = FileInfo {
+                modified: chrono::Utc::now(),
+                version: known
+                    .merged(&old_file_info.version)
+                    .incremented(SERVER_REPLICA_ID),
+                ..old_file_info
+            };
+
+            if let Some(parent) = file_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let temp = TempFile::new(file_path);
+            let mut file = std::fs::File::create(temp.path())?;
+            std::io::copy(&mut self.open_stored(&content_key)?, &mut file)?;
+            file.sync_all()?;
+            let temp = self.stage_content(temp, file_path)?;
+            self.retire_file(
+                &directory_name,
+                path,
+                current.as_ref(),
+                ChangeKind::Modified,
+            )?;
+            self.commit_content(temp, &directory_name, path)?;
+
+            let mut directory_storage = self.directory_storage.lock().unwrap();
+            let (directory_files, directory_deleted_files, deletion_versions) =
+                directory_storage.get_mut(&directory_name).unwrap();
+            directory_files.insert(path.to_string(), file_info);
+            directory_deleted_files.remove(path);
+            deletion_versions.remove(path);
+            self.record_change(&journal, &directory_name, path, ChangeKind::Modified)?;
+            Ok(RestoreOutcome::Restored)
+        };
+
+        let mut restored = Vec::new();
+        let mut deleted = Vec::new();
+        let mut unavailable = Vec::new();
+        for path in paths {
+            let file_path = match resolve_within(&directory_storage_dir, &path) {
+                Ok((file_path, _)) => file_path,
+                Err(e) => {
+                    error!(
+                        "Refusing to restore {} in directory '{}': {}",
+                        path, directory_name, e
+                    );
+                    continue;
+                }
+            };
+            // A file being written is left as the write leaves it
+            let Some(_write) = self.write_locks.try_lock(&directory_name, &path) else {
+                warn!(
+                    "Not restoring {} in directory '{}': it is being written",
+                    path, directory_name
+                );
+                continue;
+            };
+            match restore_path(&path, &file_path) {
+                Ok(RestoreOutcome::Restored) => restored.push(path),
+                Ok(RestoreOutcome::Deleted) => deleted.push(path),
+                Ok(RestoreOutcome::Unavailable) => unavailable.push(path),
+                Ok(RestoreOutcome::Unchanged) => {}
+                Err(e) => {
+                    // The files restored so far stay restored, and are reported
+                    error!(
+                        "Failed to restore {} in directory '{}': {}",
+                        path, directory_name, e
+                    );
+                    unavailable.push(path);
+                }
+            }
+        }
+
+        if !restored.is_empty() || !deleted.is_empty() {
+            self.atomic_save_directory_state(&directory_name)?;
+        }
+
+        info!(
+            "📁 Restored directory '{}'{} to {} (by {}): {} restored, {} deleted, {} unavailable",
+            directory_name,
+            scope
+                .as_deref()
+                .map(|scope| format!(" at {}", scope))
+                .unwrap_or_default(),
+            at,
+            client_id.as_deref().unwrap_or("unknown client"),
+            restored.len(),
+            deleted.len(),
+            unavailable.len()
+        );
+
+        Ok(RestoreResponse {
+            success: true,
+            message: format!(
+                "Restored {} files and deleted {} in directory '{}'",
+                restored.len(),
+                deleted.len(),
+                directory_name
+            ),
+            restored,
+            deleted,
+            unavailable,
+        })
+    }
+
+    /// List the deleted files in a directory's trash
+    fn handle_list_trash(
+        &self,
+        credentials: Credentials,
+        directory_name: Option<String>,
+    ) -> Result<TrashResponse> {
+        let directory_name = required_directory(directory_name)?;
+        self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+
+        let items = if self.get_directory_storage_dir(&directory_name)?.is_dir() {
+            match self.trash(&directory_name)? {
+                Some(trash) => trash.list()?,
+                None => Vec::new(),
+            }
+        } else {
+            Vec::new()
+        };
+
+        Ok(TrashResponse {
+            success: true,
+            message: format!(
+                "{} files in the trash of directory '{}'",
+                items.len(),
+                directory_name
+            ),
+            items,
+        })
+    }
+
+    /// Put a trashed file back where it was deleted from, as a new version that
+    /// reaches every client through normal sync
+    fn handle_trash_restore(
+        &self,
+        credentials: Credentials,
+        trash_req: TrashRequest,
+    ) -> Result<TrashResponse> {
+        let directory_name = trash_req.directory.ok_or_else(|| {
+            anyhow::anyhow!("Missing required 'directory' field in trash restore request")
         })?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        let client_id = Self::caller_id(&credentials, identity.as_deref(), trash_req.client_id);
+        let id = trash_req
+            .id
+            .ok_or_else(|| RequestError::MissingParameter("id".to_string()))?;
+        let not_found = || {
+            RequestError::NotFound(format!(
+                "No file {} in the trash of directory '{}'",
+                id, directory_name
+            ))
+        };
+
+        if !self.get_directory_storage_dir(&directory_name)?.is_dir() {
+            return Err(not_found().into());
+        }
+        let Some(trash) = self.trash(&directory_name)? else {
+            return Err(not_found().into());
+        };
+        let Some((item, _)) = trash.get(id)? else {
+            return Err(not_found().into());
+        };
+        let (_, relative_path) = self.resolve_file_path(&directory_name, &item.file_info.path)?;
         self.ensure_directory_exists(&directory_name)?;
+        let journal = self.journal(&directory_name)?;
+
+        // Never overwrite a file created at the path since, or being written
+        let write = self.write_locks.try_lock(&directory_name, &relative_path);
+        let current = self.recorded_file(&directory_name, &relative_path);
+        if current.is_some() || write.is_none() {
+            return Err(WriteConflict {
+                path: relative_path,
+                current,
+            }
+            .into());
+        }
+
+        let Some(item) = trash.take(id, &file_key(&directory_name, &relative_path))? else {
+            return Err(not_found().into());
+        };
+
+        let mut directory_storage = self.directory_storage.lock().unwrap();
+        let (directory_files, directory_deleted_files, deletion_versions) =
+            directory_storage.get_mut(&directory_name).unwrap();
+        let file_info = FileInfo {
+            modified: chrono::Utc::now(),
+            version: deletion_versions
+                .remove(&relative_path)
+                .unwrap_or_default()
+                .merged(&item.file_info.version)
+                .incremented(SERVER_REPLICA_ID),
+            ..item.file_info.clone()
+        };
+        directory_files.insert(relative_path.clone(), file_info);
+        directory_deleted_files.remove(&relative_path);
+        self.record_change(
+            &journal,
+            &directory_name,
+            &relative_path,
+            ChangeKind::Modified,
+        )?;
+        self.save_directory_state_with_lock(
+            &directory_name,
+            directory_files,
+            directory_deleted_files,
+            deletion_versions,
+        )?;
+        drop(directory_storage);
+
+        info!(
+            "📁 Restored from the trash of directory '{}': {} (by {})",
+            directory_name,
+            relative_path,
+            client_id.as_deref().unwrap_or("unknown client")
+        );
+
+        Ok(TrashResponse {
+            success: true,
+            message: format!(
+                "Restored {} in directory '{}'",
+                relative_path, directory_name
+            ),
+            items: vec![item],
+        })
+    }
 
-        let directory_storage_dir = self.get_directory_storage_dir(&directory_name);
-        let file_path = directory_storage_dir.join(&delete_req.path);
+    /// Permanently delete one trashed file, or everything in a directory's trash
+    fn handle_trash_purge(
+        &self,
+        credentials: Credentials,
+        trash_req: TrashRequest,
+    ) -> Result<TrashResponse> {
+        let directory_name = trash_req.directory.ok_or_else(|| {
+            anyhow::anyhow!("Missing required 'directory' field in trash purge request")
+        })?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Admin)?;
+        let client_id = Self::caller_id(&credentials, identity.as_deref(), trash_req.client_id);
 
-        // Delete the physical file if it exists
-        if file_path.exists() {
-            std::fs::remove_file(&file_path)?;
+        let items = if self.get_directory_storage_dir(&directory_name)?.is_dir() {
+            match self.trash(&directory_name)? {
+                Some(trash) => trash.purge(trash_req.id)?,
+                None => Vec::new(),
+            }
+        } else {
+            Vec::new()
+        };
+        if let (Some(id), true) = (trash_req.id, items.is_empty()) {
+            return Err(RequestError::NotFound(format!(
+                "No file {} in the trash of directory '{}'",
+                id, directory_name
+            ))
+            .into());
         }
 
+        info!(
+            "🗑️  Purged {} files from the trash of directory '{}' (by {})",
+            items.len(),
+            directory_name,
+            client_id.as_deref().unwrap_or("unknown client")
+        );
+
+        Ok(TrashResponse {
+            success: true,
+            message: format!(
+                "Purged {} files from the trash of directory '{}'",
+                items.len(),
+                directory_name
+            ),
+            items,
+        })
+    }
+
+    fn handle_delete(
+        &self,
+        credentials: Credentials,
+        mut delete_req: DeleteRequest,
+    ) -> Result<DeleteResponse> {
+        let directory_name = delete_req.directory.take().ok_or_else(|| {
+            anyhow::anyhow!("Missing required 'directory' field in delete request")
+        })?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        delete_req.client_id = Self::caller_id(
+            &credentials,
+            identity.as_deref(),
+            delete_req.client_id.take(),
+        );
+        let (_, relative_path) = self.resolve_file_path(&directory_name, &delete_req.path)?;
+        delete_req.path = relative_path;
+        self.ensure_directory_exists(&directory_name)?;
+        let _write = self.write_locks.lock(&directory_name, &delete_req.path);
+        self.check_base_hash(
+            &directory_name,
+            &delete_req.path,
+            delete_req.base_hash.as_deref(),
+            None,
+        )?;
+
+        // Delete the physical file if it exists, keeping it in the version history
+        let recorded = self.recorded_file(&directory_name, &delete_req.path);
+        self.retire_file(
+            &directory_name,
+            &delete_req.path,
+            recorded.as_ref(),
+            ChangeKind::Deleted,
+        )?;
+
         // Update directory state
+        let journal = self.journal(&directory_name)?;
         let state_modified = {
             let mut directory_storage = self.directory_storage.lock().unwrap();
-            let (directory_files, directory_deleted_files) =
+            let (directory_files, directory_deleted_files, deletion_versions) =
                 directory_storage.get_mut(&directory_name).unwrap();
 
             let was_present = directory_files.remove(&delete_req.path).is_some();
             directory_deleted_files.insert(delete_req.path.clone(), chrono::Utc::now());
+            deletion_versions.insert(delete_req.path.clone(), delete_req.version.clone());
+            if was_present {
+                self.record_change(
+                    &journal,
+                    &directory_name,
+                    &delete_req.path,
+                    ChangeKind::Deleted,
+                )?;
+            }
 
             was_present
         };
@@ -649,8 +3329,10 @@ impl SimpleServer {
         if state_modified {
             self.atomic_save_directory_state(&directory_name)?;
             info!(
-                "📁 Deleted from directory '{}': {}",
-                directory_name, delete_req.path
+                "📁 Deleted from directory '{}': {} (by {})",
+                directory_name,
+                delete_req.path,
+                delete_req.client_id.as_deref().unwrap_or("unknown client")
             );
         }
 
@@ -664,8 +3346,8 @@ impl SimpleServer {
     }
 
     fn atomic_save_directory_state(&self, directory_name: &str) -> Result<()> {
-        let directory_storage_dir = self.get_directory_storage_dir(directory_name);
-        let state_db = directory_storage_dir.join("server_state.db");
+        let directory_storage_dir = self.get_directory_storage_dir(directory_name)?;
+        let state_db = directory_storage_dir.join(SERVER_STATE_FILE);
 
         // Initialize the database if it doesn't exist
         if !state_db.exists() {
@@ -673,13 +3355,14 @@ impl SimpleServer {
         }
 
         let directory_storage = self.directory_storage.lock().unwrap();
-        if let Some((directory_files, directory_deleted_files)) =
+        if let Some((directory_files, directory_deleted_files, deletion_versions)) =
             directory_storage.get(directory_name)
         {
             let state = ClientState {
                 files: directory_files.clone(),
                 deleted_files: directory_deleted_files.clone(),
                 last_sync: chrono::Utc::now(),
+                deleted_versions: deletion_versions.clone(),
             };
 
             save_client_state_db(&state, &state_db)?;
@@ -693,9 +3376,10 @@ impl SimpleServer {
         directory_name: &str,
         directory_files: &HashMap<String, FileInfo>,
         directory_deleted_files: &HashMap<String, chrono::DateTime<chrono::Utc>>,
+        deletion_versions: &HashMap<String, VersionVector>,
     ) -> Result<()> {
-        let directory_storage_dir = self.get_directory_storage_dir(directory_name);
-        let state_db = directory_storage_dir.join("server_state.db");
+        let directory_storage_dir = self.get_directory_storage_dir(directory_name)?;
+        let state_db = directory_storage_dir.join(SERVER_STATE_FILE);
 
         // Initialize the database if it doesn't exist
         if !state_db.exists() {
@@ -706,6 +3390,7 @@ impl SimpleServer {
             files: directory_files.clone(),
             deleted_files: directory_deleted_files.clone(),
             last_sync: chrono::Utc::now(),
+            deleted_versions: deletion_versions.clone(),
         };
 
         save_client_state_db(&state, &state_db)?;
@@ -715,12 +3400,14 @@ impl SimpleServer {
     // Helper methods for directory storage management
     fn save_all_states(&self) -> Result<()> {
         let directory_storage = self.directory_storage.lock().unwrap();
-        for (directory_name, (directory_files, directory_deleted_files)) in directory_storage.iter()
+        for (directory_name, (directory_files, directory_deleted_files, deletion_versions)) in
+            directory_storage.iter()
         {
             if let Err(e) = self.save_directory_state_with_lock(
                 directory_name,
                 directory_files,
                 directory_deleted_files,
+                deletion_versions,
             ) {
                 warn!(
                     "Failed to save state for directory {}: {}",
@@ -731,25 +3418,76 @@ impl SimpleServer {
         Ok(())
     }
 
-    async fn handle_delta_init(&self, init_req: DeltaInitRequest) -> Result<DeltaInitResponse> {
+    fn handle_delta_init(
+        &self,
+        credentials: Credentials,
+        init_req: DeltaInitRequest,
+    ) -> Result<DeltaInitResponse> {
         let directory_name = init_req
             .directory
             .ok_or_else(|| anyhow::anyhow!("Missing required 'directory' field"))?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        let owner = Self::caller_id(&credentials, identity.as_deref(), None);
 
-        let directory_storage_dir = self.get_directory_storage_dir(&directory_name);
-        let file_path = directory_storage_dir.join(&init_req.file_info.path);
+        let (file_path, relative_path) =
+            self.resolve_file_path(&directory_name, &init_req.file_info.path)?;
+        self.check_base_hash(
+            &directory_name,
+            &relative_path,
+            init_req.base_hash.as_deref(),
+            Some(&init_req.file_info.hash),
+        )?;
+        check_block_size(init_req.block_size)?;
+        if !check_block_layout(&init_req.block_hashes, init_req.file_info.size) {
+            return Err(RequestError::InvalidParameter(
+                "block_hashes".to_string(),
+                "blocks must cover the file in order".to_string(),
+            )
+            .into());
+        }
 
-        // If file doesn't exist, recommend full upload
-        if !file_path.exists() {
+        // If file doesn't exist, and none of its blocks is a chunk the directory
+        // already has, recommend full upload. Chunks only other directories have
+        // are never reused, so their content can't be had, or probed for, by hash.
+        let indexed = std::cell::OnceCell::new();
+        let stored_chunk = |block: &BlockMsg| {
+            let chunk_store = self.chunk_store.as_ref()?;
+            indexed.get_or_init(|| {
+                if let Err(e) = self.index_directory_chunks(&directory_name) {
+                    warn!(
+                        "Failed to list the chunks of directory '{}': {}",
+                        directory_name, e
+                    );
+                }
+            });
+            if !self.directory_has_chunk(&directory_name, &block.hash) {
+                return None;
+            }
+            let key = chunk_store.find(&block.hash, block.length).ok()??;
+            Some(ObjectReader::new(self.storage.clone(), &key, block.length))
+        };
+        let key = file_key(&directory_name, &relative_path);
+        let exists = self.stat_stored(&key)?.is_some();
+        if !exists
+            && (self.chunk_store.is_none()
+                || !init_req
+                    .block_hashes
+                    .iter()
+                    .any(|block| stored_chunk(block).is_some()))
+        {
             return Ok(DeltaInitResponse {
                 missing_block_indices: vec![],
                 should_full_upload: true,
+                session_id: None,
             });
         }
 
-        // Calculate server's block hashes
-        let server_hashes = match calculate_block_hashes(&file_path, init_req.block_size) {
-            Ok(hashes) => hashes,
+        // Blocks of the stored file, found by content wherever they moved to
+        let server_blocks = match exists
+            .then(|| self.stored_block_hashes(&key, init_req.block_size))
+            .transpose()
+        {
+            Ok(blocks) => blocks.unwrap_or_default(),
             Err(e) => {
                 warn!(
                     "Failed to calculate block hashes for {}: {}",
@@ -758,110 +3496,172 @@ impl SimpleServer {
                 return Ok(DeltaInitResponse {
                     missing_block_indices: vec![],
                     should_full_upload: true,
+                    session_id: None,
                 });
             }
         };
-
-        // Compare hashes
-        let mut missing_indices = Vec::new();
-        let server_map: HashMap<u64, String> = server_hashes
-            .into_iter()
-            .map(|b| (b.index, b.hash))
+        let server_map: HashMap<&str, &BlockMsg> = server_blocks
+            .iter()
+            .map(|block| (block.hash.as_str(), block))
             .collect();
 
-        for client_block in init_req.block_hashes {
-            match server_map.get(&client_block.index) {
-                Some(server_hash) => {
-                    if *server_hash != client_block.hash {
-                        missing_indices.push(client_block.index);
-                    }
-                }
-                None => {
-                    // Block doesn't exist on server (file grew)
-                    missing_indices.push(client_block.index);
-                }
+        // Stage the new version with the blocks the stored file or the directory's
+        // chunks already have; the stored file stays intact until the session completes
+        self.expire_delta_sessions();
+        if !exists {
+            self.ensure_directory_exists(&directory_name)?;
+            if let Some(parent) = file_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let staging = TempFile::new(&file_path);
+        let mut staged = std::fs::File::create(staging.path())?;
+        staged.set_len(init_req.file_info.size)?;
+        let mut stored = if exists {
+            Some(self.open_stored(&key)?)
+        } else {
+            None
+        };
+        let mut missing_indices = Vec::new();
+        for block in &init_req.block_hashes {
+            let known = server_map
+                .get(block.hash.as_str())
+                .filter(|known| known.length == block.length);
+            if let (Some(known), Some(stored)) = (known, stored.as_mut()) {
+                copy_range(
+                    stored,
+                    known.offset,
+                    &mut staged,
+                    block.offset,
+                    block.length,
+                )?;
+            } else if let Some(mut chunk) = stored_chunk(block) {
+                copy_range(&mut chunk, 0, &mut staged, block.offset, block.length)?;
+            } else {
+                missing_indices.push(block.index);
             }
         }
+        drop(staged);
 
         debug!(
-            "Delta init for {}: {} missing blocks",
+            "Delta init for {}: {} of {} blocks missing",
             init_req.file_info.path,
-            missing_indices.len()
+            missing_indices.len(),
+            init_req.block_hashes.len()
         );
 
+        let session_id = new_session_id();
+        let session = DeltaSession {
+            directory: directory_name,
+            owner,
+            file_info: FileInfo {
+                path: relative_path,
+                ..init_req.file_info
+            },
+            blocks: init_req.block_hashes,
+            base_hash: init_req.base_hash,
+            staging,
+            last_active: std::time::Instant::now(),
+        };
+        self.delta_sessions
+            .lock()
+            .unwrap()
+            .insert(session_id.clone(), session);
+
         Ok(DeltaInitResponse {
             missing_block_indices: missing_indices,
             should_full_upload: false,
+            session_id: Some(session_id),
         })
     }
 
-    async fn handle_block_upload(
+    fn handle_block_upload(
         &self,
+        credentials: Credentials,
         upload_req: BlockUploadRequest,
     ) -> Result<BlockUploadResponse> {
-        self.ensure_directory_exists(&upload_req.directory)?;
-
-        let directory_storage_dir = self.get_directory_storage_dir(&upload_req.directory);
-        let file_path = directory_storage_dir.join(&upload_req.path);
+        self.stage_block(
+            &credentials,
+            upload_req.session_id.as_deref(),
+            &upload_req.directory,
+            &upload_req.path,
+            upload_req.index,
+            &upload_req.content,
+        )
+    }
 
-        if !file_path.exists() {
-            // Should verify creation in delta init, but safe to create parent if needed
-            if let Some(parent) = file_path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
-            // Create empty file if not exists processing first block?
-            // Better to fail if not init_req flow, but simple patch might work if we write zeros?
-            // For now, rely on file existing or being created by full upload fallback
-            // But if it's a new file growing, we need to handle it.
-            if !file_path.exists() {
-                std::fs::File::create(&file_path)?;
-            }
-        }
-
-        // Block size hardcoded 1MB on client side plan?
-        // We receive content, length is implicit block size for this block.
-        // We need the index to know offset.
-        // We don't strictly know the block size constant here, but we can assume standard or client sends it?
-        // Ah, client doesn't send block size in UploadRequest, but it sends index.
-        // Wait, to calculate offset I need block size.
-        // I should have added block size to BlockUploadRequest.
-        // Let's assume 1MB (1024*1024) as per plan, OR deduce from content length?
-        // Deduce from content length is risky if it's the last block (short).
-        // I MUST have block size or implicit knowledge.
-        // The plan said "Block-based hashing (1MB blocks)".
-        // I will hardcode 1024*1024 for now or infer?
-        // Actually, if we assume fixed block size, 1MB is fine.
-        let block_size = 1024 * 1024;
-        let offset = upload_req.index * block_size;
-
-        patch_file(&file_path, offset, &upload_req.content)?;
-
-        // Optimizing: Do NOT recalculate hash and update state after every block
-        // Just patch the file and return success.
-        // The client must call /delta/complete to finalize.
-
-        /*
-        // Update state
-        // Recalculating hash... potentially expensive but necessary for consistency
-        let file_info = crate::utils::get_file_info(&file_path, &upload_req.path)?;
+    fn handle_raw_block_upload(
+        &self,
+        credentials: Credentials,
+        session_id: String,
+        index: u64,
+        directory_name: Option<String>,
+        headers: HeaderMap,
+        content: &[u8],
+    ) -> Result<BlockUploadResponse> {
+        let directory_name = required_directory(directory_name)?;
+        let encoded_path = required_header(&headers, PATH_HEADER)?;
+        let file_path = urlencoding::decode(&encoded_path)
+            .map_err(|_| {
+                RequestError::InvalidHeader(PATH_HEADER.to_string(), encoded_path.clone())
+            })?
+            .into_owned();
+        self.stage_block(
+            &credentials,
+            Some(&session_id),
+            &directory_name,
+            &file_path,
+            index,
+            content,
+        )
+    }
 
-        let state_modified = {
-            let mut directory_storage = self.directory_storage.lock().unwrap();
-            let (directory_files, _) = directory_storage.get_mut(&upload_req.directory).unwrap();
+    /// Write a block into a delta session's staging copy
+    fn stage_block(
+        &self,
+        credentials: &Credentials,
+        session_id: Option<&str>,
+        directory_name: &str,
+        file_path: &str,
+        index: u64,
+        content: &[u8],
+    ) -> Result<BlockUploadResponse> {
+        let identity = self.authorize(credentials, directory_name, AccessLevel::Write)?;
+        let owner = Self::caller_id(credentials, identity.as_deref(), None);
+        let (_, relative_path) = self.resolve_file_path(directory_name, file_path)?;
 
-            directory_files.insert(upload_req.path.clone(), file_info);
-            true
+        let (staging_path, block) = {
+            let mut sessions = self.delta_sessions.lock().unwrap();
+            let session = delta_session(
+                &mut sessions,
+                session_id,
+                directory_name,
+                &relative_path,
+                owner.as_deref(),
+            )?;
+            session.last_active = std::time::Instant::now();
+            (
+                session.staging.path().to_path_buf(),
+                session.blocks.get(index as usize).cloned(),
+            )
         };
 
-        if state_modified {
-             self.atomic_save_directory_state(&upload_req.directory)?;
-        }
-        */
+        // Blocks must match the layout the session was opened with
+        let block = block
+            .filter(|block| block.length == content.len() as u64)
+            .ok_or_else(|| {
+                RequestError::InvalidParameter(
+                    "index".to_string(),
+                    format!(
+                        "block {} is not a block of the file with {} bytes",
+                        index,
+                        content.len()
+                    ),
+                )
+            })?;
+        patch_file(&staging_path, block.offset, content)?;
 
-        debug!(
-            "✓ Patched block {} for {}",
-            upload_req.index, upload_req.path
-        );
+        debug!("✓ Staged block {} for {}", index, file_path);
 
         Ok(BlockUploadResponse {
             success: true,
@@ -869,62 +3669,896 @@ impl SimpleServer {
         })
     }
 
-    async fn handle_delta_complete(
+    fn handle_delta_complete(
         &self,
+        credentials: Credentials,
         complete_req: DeltaCompleteRequest,
     ) -> Result<DeltaCompleteResponse> {
         let directory_name = complete_req
             .directory
             .ok_or_else(|| anyhow::anyhow!("Missing 'directory'"))?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        let client_id = Self::caller_id(&credentials, identity.as_deref(), complete_req.client_id);
 
-        let directory_storage_dir = self.get_directory_storage_dir(&directory_name);
-        let file_path = directory_storage_dir.join(&complete_req.path);
+        let (file_path, relative_path) =
+            self.resolve_file_path(&directory_name, &complete_req.path)?;
+
+        // The session ends here either way, and its staging copy with it
+        let session = {
+            let mut sessions = self.delta_sessions.lock().unwrap();
+            let session_id = complete_req.session_id.as_deref();
+            let owner = Self::caller_id(&credentials, identity.as_deref(), None);
+            delta_session(
+                &mut sessions,
+                session_id,
+                &directory_name,
+                &relative_path,
+                owner.as_deref(),
+            )?;
+            sessions.remove(session_id.unwrap()).unwrap()
+        };
 
-        if !file_path.exists() {
+        let calculated_hash = calculate_file_hash(session.staging.path())?;
+        if calculated_hash != complete_req.expected_hash
+            || calculated_hash != session.file_info.hash
+        {
             return Ok(DeltaCompleteResponse {
                 success: false,
-                message: "File not found for completion".to_string(),
+                message: format!(
+                    "Hash mismatch after patch: expected {}, got {}",
+                    complete_req.expected_hash, calculated_hash
+                ),
             });
         }
+        // The file may have been replaced since the session started, which
+        // installing it checks
+        self.ensure_directory_exists(&directory_name)?;
+        let file_info = FileInfo {
+            version: complete_req.version,
+            ..session.file_info
+        };
+        self.install_staged_file(
+            &directory_name,
+            &file_path,
+            session.staging,
+            file_info,
+            session.base_hash.as_deref(),
+            client_id.as_deref(),
+        )?;
+
+        Ok(DeltaCompleteResponse {
+            success: true,
+            message: "Delta sync finalized".to_string(),
+        })
+    }
+
+    /// Compare a client's copy of a file with the stored version block by block,
+    /// listing the blocks the client needs to fetch
+    fn handle_delta_download(
+        &self,
+        credentials: Credentials,
+        download_req: DeltaDownloadRequest,
+    ) -> Result<DeltaDownloadResponse> {
+        let directory_name = required_directory(download_req.directory)?;
+        self.authorize(&credentials, &directory_name, AccessLevel::Read)?;
+        let (_, relative_path) = self.resolve_file_path(&directory_name, &download_req.path)?;
+        check_block_size(download_req.block_size)?;
+        let key = file_key(&directory_name, &relative_path);
+        if self.stat_stored(&key)?.is_none() {
+            return Err(RequestError::NotFound(format!(
+                "File not found in directory '{}': {}",
+                directory_name, relative_path
+            ))
+            .into());
+        }
 
-        // Calculate hash ONCE
-        let file_info = get_file_info(&file_path, &complete_req.path)?;
+        let mut file_info = self.stored_file_info(&key, &relative_path)?;
+        file_info.version = self.stored_version(&directory_name, &relative_path, &file_info.hash);
+        let client_hashes: std::collections::HashSet<String> = download_req
+            .block_hashes
+            .into_iter()
+            .map(|block| block.hash)
+            .collect();
+        let blocks = self.stored_block_hashes(&key, download_req.block_size)?;
+        let block_count = blocks.len();
+        let missing_indices: Vec<u64> = blocks
+            .iter()
+            .filter(|block| !client_hashes.contains(&block.hash))
+            .map(|block| block.index)
+            .collect();
 
-        // Verify against expected hash if provided?
-        // The client provided expected_hash. Let's verify it matches what we calculated.
-        if file_info.hash != complete_req.expected_hash {
-            return Ok(DeltaCompleteResponse {
+        debug!(
+            "Delta download of {}: {} of {} blocks differ",
+            relative_path,
+            missing_indices.len(),
+            block_count
+        );
+        Ok(DeltaDownloadResponse {
+            success: true,
+            message: format!("{} of {} blocks differ", missing_indices.len(), block_count),
+            file_info: Some(file_info),
+            // Nothing of the client's copy can be reused
+            should_full_download: block_count > 0 && missing_indices.len() == block_count,
+            missing_block_indices: missing_indices,
+            blocks,
+        })
+    }
+
+    /// Swap a verified staging file in for the stored file at `file_path` as its
+    /// new version `file_info`, keeping the version it replaces. The file must
+    /// still be at `base_hash`, which is checked again while no other write to it
+    /// can come in between.
+    fn install_staged_file(
+        &self,
+        directory_name: &str,
+        file_path: &Path,
+        staging: TempFile,
+        file_info: FileInfo,
+        base_hash: Option<&str>,
+        client_id: Option<&str>,
+    ) -> Result<UploadResponse> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(staging.path())?
+            .sync_all()?;
+        let staging = self.stage_content(staging, file_path)?;
+
+        let _write = self.write_locks.lock(directory_name, &file_info.path);
+        self.check_base_hash(
+            directory_name,
+            &file_info.path,
+            base_hash,
+            Some(&file_info.hash),
+        )?;
+        let recorded = self.recorded_file(directory_name, &file_info.path);
+        if recorded
+            .as_ref()
+            .is_none_or(|recorded| recorded.hash != file_info.hash)
+        {
+            self.retire_file(
+                directory_name,
+                &file_info.path,
+                recorded.as_ref(),
+                ChangeKind::Modified,
+            )?;
+        }
+        self.commit_content(staging, directory_name, &file_info.path)?;
+        self.record_upload(directory_name, file_info, client_id)
+    }
+
+    /// Drop delta sessions idle for longer than `DELTA_SESSION_TIMEOUT`, removing
+    /// their staging copies
+    fn expire_delta_sessions(&self) {
+        let mut sessions = self.delta_sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| session.last_active.elapsed() < DELTA_SESSION_TIMEOUT);
+        let expired = before - sessions.len();
+        if expired > 0 {
+            debug!("Expired {} abandoned delta sessions", expired);
+        }
+    }
+
+    /// Open a resumable upload session, with a staging file of the full size
+    fn handle_open_upload_session(
+        &self,
+        credentials: Credentials,
+        session_req: UploadSessionRequest,
+    ) -> Result<UploadSessionResponse> {
+        let directory_name = required_directory(session_req.directory)?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        let client_id = Self::caller_id(&credentials, identity.as_deref(), session_req.client_id);
+
+        let (file_path, relative_path) =
+            self.resolve_file_path(&directory_name, &session_req.file_info.path)?;
+        self.ensure_directory_exists(&directory_name)?;
+        self.check_base_hash(
+            &directory_name,
+            &relative_path,
+            session_req.base_hash.as_deref(),
+            Some(&session_req.file_info.hash),
+        )?;
+
+        if let Some(parent) = file_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        self.expire_upload_sessions();
+        let staging = TempFile::new(&file_path);
+        std::fs::File::create(staging.path())?.set_len(session_req.file_info.size)?;
+
+        let session_id = new_session_id();
+        info!(
+            "📁 Opened upload session for '{}' in directory '{}' ({} bytes)",
+            relative_path, directory_name, session_req.file_info.size
+        );
+        let session = UploadSession {
+            directory: directory_name,
+            owner: Self::caller_id(&credentials, identity.as_deref(), None),
+            file_info: FileInfo {
+                path: relative_path,
+                ..session_req.file_info
+            },
+            base_hash: session_req.base_hash,
+            client_id,
+            staging,
+            received: Vec::new(),
+            last_active: std::time::Instant::now(),
+        };
+        self.upload_sessions
+            .lock()
+            .unwrap()
+            .insert(session_id.clone(), session);
+
+        Ok(UploadSessionResponse {
+            success: true,
+            message: "Upload session opened".to_string(),
+            session_id: Some(session_id),
+            received: vec![],
+            completed: false,
+        })
+    }
+
+    /// Which parts of a file an upload session has received
+    fn handle_upload_status(
+        &self,
+        credentials: Credentials,
+        session_id: String,
+        directory_name: Option<String>,
+    ) -> Result<UploadSessionResponse> {
+        let directory_name = required_directory(directory_name)?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        let owner = Self::caller_id(&credentials, identity.as_deref(), None);
+
+        let mut sessions = self.upload_sessions.lock().unwrap();
+        let session = upload_session(
+            &mut sessions,
+            &session_id,
+            &directory_name,
+            owner.as_deref(),
+        )?;
+        Ok(UploadSessionResponse {
+            success: true,
+            message: format!(
+                "{} of {} bytes received",
+                received_prefix(&session.received),
+                session.file_info.size
+            ),
+            session_id: Some(session_id),
+            received: session.received.clone(),
+            completed: false,
+        })
+    }
+
+    /// Write a chunk of an upload session at the offset in its `OFFSET_HEADER`.
+    /// The bytes that arrived count even when the request breaks off, and the
+    /// chunk that completes the file stores it.
+    async fn handle_upload_chunk<S, B>(
+        &self,
+        credentials: Credentials,
+        session_id: String,
+        directory_name: Option<String>,
+        headers: HeaderMap,
+        body: S,
+    ) -> Result<UploadSessionResponse>
+    where
+        S: Stream<Item = Result<B, warp::Error>>,
+        B: Buf,
+    {
+        let directory_name = required_directory(directory_name)?;
+        let identity = self.authorize(&credentials, &directory_name, AccessLevel::Write)?;
+        let owner = Self::caller_id(&credentials, identity.as_deref(), None);
+        let offset_value = required_header(&headers, OFFSET_HEADER)?;
+        let offset: u64 = offset_value
+            .parse()
+            .map_err(|_| RequestError::InvalidHeader(OFFSET_HEADER.to_string(), offset_value))?;
+
+        let (staging_path, size) = {
+            let mut sessions = self.upload_sessions.lock().unwrap();
+            let session = upload_session(
+                &mut sessions,
+                &session_id,
+                &directory_name,
+                owner.as_deref(),
+            )?;
+            session.last_active = std::time::Instant::now();
+            (session.staging.path().to_path_buf(), session.file_info.size)
+        };
+        if offset > size {
+            return Err(RequestError::InvalidParameter(
+                OFFSET_HEADER.to_string(),
+                format!("{} is past the end of the file", offset),
+            )
+            .into());
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&staging_path)
+            .await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut position = offset;
+        let mut outcome: Result<()> = Ok(());
+        futures::pin_mut!(body);
+        while let Some(chunk) = body.next().await {
+            let bytes = match chunk {
+                Ok(mut chunk) => chunk.copy_to_bytes(chunk.remaining()),
+                Err(e) => {
+                    outcome = Err(e.into());
+                    break;
+                }
+            };
+            if position + bytes.len() as u64 > size {
+                outcome = Err(RequestError::InvalidParameter(
+                    OFFSET_HEADER.to_string(),
+                    "the chunk runs past the end of the file".to_string(),
+                )
+                .into());
+                break;
+            }
+            if let Err(e) = file.write_all(&bytes).await {
+                outcome = Err(e.into());
+                break;
+            }
+            position += bytes.len() as u64;
+        }
+        // Bytes are only acknowledged once they are on disk
+        file.flush().await?;
+        file.sync_data().await?;
+        drop(file);
+
+        let response = {
+            let mut sessions = self.upload_sessions.lock().unwrap();
+            let session = upload_session(
+                &mut sessions,
+                &session_id,
+                &directory_name,
+                owner.as_deref(),
+            )?;
+            add_byte_range(
+                &mut session.received,
+                ByteRange {
+                    start: offset,
+                    end: position,
+                },
+            );
+            session.last_active = std::time::Instant::now();
+            UploadSessionResponse {
+                success: true,
+                message: format!(
+                    "{} of {} bytes received",
+                    received_prefix(&session.received),
+                    size
+                ),
+                session_id: Some(session_id.clone()),
+                received: session.received.clone(),
+                completed: false,
+            }
+        };
+        outcome?;
+
+        if received_prefix(&response.received) < size {
+            return Ok(response);
+        }
+        self.run_blocking(move |server| server.complete_upload_session(&session_id))
+            .await
+    }
+
+    /// Verify the file an upload session received in full and store it. The
+    /// session ends here either way.
+    fn complete_upload_session(&self, session_id: &str) -> Result<UploadSessionResponse> {
+        let Some(session) = self.upload_sessions.lock().unwrap().remove(session_id) else {
+            // Another request completed it first
+            return Err(RequestError::NotFound(format!(
+                "Unknown or expired upload session '{}'",
+                session_id
+            ))
+            .into());
+        };
+        let directory_name = session.directory;
+        let (file_path, relative_path) =
+            self.resolve_file_path(&directory_name, &session.file_info.path)?;
+
+        let calculated_hash = calculate_file_hash(session.staging.path())?;
+        if calculated_hash != session.file_info.hash {
+            return Ok(UploadSessionResponse {
                 success: false,
                 message: format!(
-                    "Hash mismatch after patch: expected {}, got {}",
-                    complete_req.expected_hash, file_info.hash
+                    "Hash mismatch: expected {}, got {}",
+                    session.file_info.hash, calculated_hash
                 ),
+                session_id: Some(session_id.to_string()),
+                ..UploadSessionResponse::default()
             });
         }
+        // The file may have been replaced since the session was opened, which
+        // installing it checks
+        self.ensure_directory_exists(&directory_name)?;
+        let size = session.file_info.size;
+        let response = self.install_staged_file(
+            &directory_name,
+            &file_path,
+            session.staging,
+            FileInfo {
+                path: relative_path,
+                ..session.file_info
+            },
+            session.base_hash.as_deref(),
+            session.client_id.as_deref(),
+        )?;
+        Ok(UploadSessionResponse {
+            success: true,
+            message: response.message,
+            session_id: Some(session_id.to_string()),
+            received: vec![ByteRange {
+                start: 0,
+                end: size,
+            }],
+            completed: true,
+        })
+    }
+
+    /// Drop upload sessions idle for longer than `UPLOAD_SESSION_TIMEOUT`,
+    /// removing their staging files
+    fn expire_upload_sessions(&self) {
+        let mut sessions = self.upload_sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| session.last_active.elapsed() < UPLOAD_SESSION_TIMEOUT);
+        let expired = before - sessions.len();
+        if expired > 0 {
+            debug!("Expired {} abandoned upload sessions", expired);
+        }
+    }
+}
 
-        // Update state
-        let state_modified = {
-            let mut directory_storage = self.directory_storage.lock().unwrap();
-            // Ensure directory exists in map (should be there from ensure_directory_exists called previous steps, or init)
-            if !directory_storage.contains_key(&directory_name) {
-                // Should ideally not happen if delta init/upload called first, but safe to guard
-                directory_storage.insert(directory_name.clone(), (HashMap::new(), HashMap::new()));
+/// The open delta session `session_id`, which must be for `path` in `directory_name`
+/// and opened by `owner`
+fn delta_session<'a>(
+    sessions: &'a mut HashMap<String, DeltaSession>,
+    session_id: Option<&str>,
+    directory_name: &str,
+    path: &str,
+    owner: Option<&str>,
+) -> Result<&'a mut DeltaSession> {
+    // Clients from before delta sessions send blocks without one
+    let session_id =
+        session_id.ok_or_else(|| RequestError::MissingParameter("session_id".to_string()))?;
+    sessions
+        .get_mut(session_id)
+        .filter(|session| {
+            session.directory == directory_name
+                && session.file_info.path == path
+                && session.owner.as_deref() == owner
+        })
+        .ok_or_else(|| {
+            RequestError::NotFound(format!("Unknown or expired delta session '{}'", session_id))
+                .into()
+        })
+}
+
+/// Average block sizes a delta may use
+fn check_block_size(block_size: u64) -> Result<()> {
+    if block_size == 0 || block_size > MAX_DELTA_BLOCK_SIZE {
+        return Err(RequestError::InvalidParameter(
+            "block_size".to_string(),
+            format!("must be between 1 and {}", MAX_DELTA_BLOCK_SIZE),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The open upload session `session_id`, which must be for a file in `directory_name`
+/// and opened by `owner`
+fn upload_session<'a>(
+    sessions: &'a mut HashMap<String, UploadSession>,
+    session_id: &str,
+    directory_name: &str,
+    owner: Option<&str>,
+) -> Result<&'a mut UploadSession> {
+    sessions
+        .get_mut(session_id)
+        .filter(|session| session.directory == directory_name && session.owner.as_deref() == owner)
+        .ok_or_else(|| {
+            RequestError::NotFound(format!(
+                "Unknown or expired upload session '{}'",
+                session_id
+            ))
+            .into()
+        })
+}
+
+/// A new delta or upload session ID. Whoever knows it can use the session, so
+/// it comes from a cryptographically secure random number generator.
+fn new_session_id() -> String {
+    rand::random::<[u8; 16]>()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Server-sent events for a change subscription; a subscriber that fell behind
+/// gets a `lagged` event and should do a full pull
+fn event_stream(
+    receiver: broadcast::Receiver<ChangeEvent>,
+) -> impl Stream<Item = Result<warp::sse::Event, std::convert::Infallible>> {
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        let event = match receiver.recv().await {
+            Ok(change) => warp::sse::Event::default()
+                .event("change")
+                .json_data(&change)
+                .unwrap_or_default(),
+            Err(broadcast::error::RecvError::Lagged(missed)) => warp::sse::Event::default()
+                .event("lagged")
+                .data(missed.to_string()),
+            Err(broadcast::error::RecvError::Closed) => return None,
+        };
+        Some((Ok(event), receiver))
+    })
+}
+
+/// The `directory` query parameter
+/// Create the tables of a directory's journal, version history and trash in its
+/// state database at `state_db`
+fn create_directory_schema(state_db: &Path) -> Result<()> {
+    let conn = rusqlite::Connection::open(state_db)?;
+    ChangeJournal::create_schema(&conn, state_db)?;
+    VersionHistory::create_schema(&conn)?;
+    Trash::create_schema(&conn)?;
+    Ok(())
+}
+
+fn directory_query() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .map(|mut query: HashMap<String, String>| query.remove("directory"))
+}
+
+fn required_directory(directory_name: Option<String>) -> Result<String> {
+    directory_name.ok_or_else(|| RequestError::MissingParameter("directory".to_string()).into())
+}
+
+/// The chunk store in `storage`, if it has one. Files stored with deduplication
+/// stay manifests after it is turned off.
+fn existing_chunk_store(
+    storage: &Arc<dyn StorageBackend>,
+    dedup: &DedupConfig,
+) -> Result<Option<Arc<ChunkStore>>> {
+    if !ChunkStore::exists(storage.as_ref())? {
+        return Ok(None);
+    }
+    Ok(Some(Arc::new(ChunkStore::open(
+        storage.clone(),
+        dedup.average_chunk_size,
+    )?)))
+}
+
+/// The key of the file at `relative_path` of a directory in the server's storage
+fn file_key(directory_name: &str, relative_path: &str) -> String {
+    format!("{}/{}", directory_name, relative_path)
+}
+
+/// What restoring a path to an earlier time did
+enum RestoreOutcome {
+    Restored,
+    Deleted,
+    // Deleted since, with no version left to restore
+    Unavailable,
+    Unchanged,
+}
+
+/// Verified content ready to commit under a key
+enum StagedContent {
+    /// The content itself
+    Content(TempFile),
+    /// A manifest of the content's chunks, which are already in the chunk store
+    Manifest(TempFile, Manifest),
+}
+
+/// The chunks a directory's manifests reference, as far as the chunk index knows.
+/// May still list chunks of files retired since the manifests were read.
+#[derive(Default)]
+struct DirectoryChunks {
+    chunks: HashSet<String>,
+    // Whether the directory's stored manifests were read in, beyond the chunks
+    // of the commits since the index was last cleared
+    complete: bool,
+}
+
+/// Stored content to stream in a download
+struct StoredContent {
+    storage: Arc<dyn StorageBackend>,
+    parts: StoredParts,
+}
+
+/// Where stored content is: in an object of its own, or in the chunks a manifest lists
+enum StoredParts {
+    Object(String),
+    Chunks(Manifest),
+}
+
+impl StoredContent {
+    /// The `length` bytes at `start`, read from storage on a blocking thread
+    fn range_stream(
+        self,
+        start: u64,
+        length: u64,
+    ) -> Result<futures::stream::BoxStream<'static, std::io::Result<warp::hyper::body::Bytes>>>
+    {
+        let ranges = match self.parts {
+            StoredParts::Object(key) => vec![(key, start, length)],
+            StoredParts::Chunks(manifest) => ChunkStore::chunk_ranges(&manifest, start, length)?,
+        };
+        let storage = self.storage;
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_BUFFER_PARTS);
+        tokio::task::spawn_blocking(move || {
+            for (key, offset, length) in ranges {
+                let mut reader = match storage.get(&key, Some((offset, length))) {
+                    Ok(reader) => reader,
+                    Err(e) => {
+                        let _ = sender.blocking_send(Err(std::io::Error::other(e)));
+                        return;
+                    }
+                };
+                loop {
+                    let mut part = vec![0u8; STREAM_PART_SIZE];
+                    match reader.read(&mut part) {
+                        Ok(0) => break,
+                        Ok(read) => {
+                            part.truncate(read);
+                            // Stop once the download is gone
+                            if sender.blocking_send(Ok(part.into())).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            let _ = sender.blocking_send(Err(e));
+                            return;
+                        }
+                    }
+                }
             }
+        });
+        Ok(
+            futures::stream::unfold(receiver, |mut receiver| async move {
+                receiver.recv().await.map(|part| (part, receiver))
+            })
+            .boxed(),
+        )
+    }
+}
 
-            let (directory_files, _) = directory_storage.get_mut(&directory_name).unwrap();
-            directory_files.insert(complete_req.path.clone(), file_info);
-            true
+/// Reply with `content`, or with the part of it a `Range: bytes=<start>-[<end>]`
+/// request header asks for
+async fn stream_reply(
+    file_info: FileInfo,
+    content: StoredContent,
+    headers: &HeaderMap,
+) -> warp::reply::Response {
+    let failed = |message: String, status: StatusCode| {
+        let response = DownloadResponse {
+            success: false,
+            file_info: None,
+            content: None,
+            message,
         };
+        json_reply(&response, status).into_response()
+    };
 
-        if state_modified {
-            self.atomic_save_directory_state(&directory_name)?;
+    let mut builder = warp::http::Response::builder()
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .header(ACCEPT_RANGES, "bytes")
+        .header(ETAG, entity_tag(&file_info.hash))
+        .header(PATH_HEADER, urlencoding::encode(&file_info.path).as_ref())
+        .header(HASH_HEADER, &file_info.hash)
+        .header(MODIFIED_HEADER, file_info.modified.to_rfc3339())
+        .header(VERSION_HEADER, encode_version_header(&file_info.version));
+    let mut start = 0;
+    let mut length = file_info.size;
+    match requested_range(headers, &file_info) {
+        Some((start, _)) if start >= file_info.size => {
+            let mut response = failed(
+                format!("Range starts past the end of the file: {}", start),
+                StatusCode::RANGE_NOT_SATISFIABLE,
+            );
+            if let Ok(value) = format!("bytes */{}", file_info.size).parse() {
+                response.headers_mut().insert(CONTENT_RANGE, value);
+            }
+            return response;
         }
+        Some((range_start, end)) => {
+            let end = end.map_or(file_info.size - 1, |end| end.min(file_info.size - 1));
+            start = range_start;
+            length = end - start + 1;
+            builder = builder.status(StatusCode::PARTIAL_CONTENT).header(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, file_info.size),
+            );
+        }
+        None => {}
+    }
 
-        info!("✓ Delta sync complete for {}", complete_req.path);
+    let stream = match content.range_stream(start, length) {
+        Ok(stream) => stream,
+        Err(e) => {
+            return failed(
+                format!("Download failed: {}", e),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        }
+    };
+    let body = warp::hyper::Body::wrap_stream(stream);
+    builder = builder.header(CONTENT_LENGTH, length);
+    builder.body(body).unwrap_or_else(|e| {
+        failed(
+            format!("Download failed: {}", e),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+    })
+}
 
-        Ok(DeltaCompleteResponse {
-            success: true,
-            message: "Delta sync finalized".to_string(),
-        })
+/// Entity tag of file content with `hash`, for `ETag` and `If-Range`
+fn entity_tag(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+/// First and, if given, last byte of the range a download asks for with
+/// `Range: bytes=<start>-[<end>]`. Other range forms, and ranges of a version
+/// other than the `If-Range` one, are ignored so the whole file is sent.
+fn requested_range(headers: &HeaderMap, file_info: &FileInfo) -> Option<(u64, Option<u64>)> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    if header(IF_RANGE).is_some_and(|tag| tag != entity_tag(&file_info.hash)) {
+        return None;
+    }
+    let (start, end) = header(RANGE)?.strip_prefix("bytes=")?.split_once('-')?;
+    let start = start.parse().ok()?;
+    if end.is_empty() {
+        return Some((start, None));
+    }
+    let end = end.parse().ok().filter(|end| *end >= start)?;
+    Some((start, Some(end)))
+}
+
+fn optional_header(headers: &HeaderMap, name: &str) -> Result<Option<String>> {
+    match headers.get(name) {
+        Some(value) => {
+            let value = value.to_str().map_err(|_| {
+                RequestError::InvalidHeader(name.to_string(), format!("{:?}", value))
+            })?;
+            Ok(Some(value.to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+fn required_header(headers: &HeaderMap, name: &str) -> Result<String> {
+    optional_header(headers, name)?
+        .ok_or_else(|| RequestError::MissingHeader(name.to_string()).into())
+}
+
+fn parse_modified_header(headers: &HeaderMap) -> Result<chrono::DateTime<chrono::Utc>> {
+    let value = required_header(headers, MODIFIED_HEADER)?;
+    let modified = chrono::DateTime::parse_from_rfc3339(&value)
+        .map_err(|_| RequestError::InvalidHeader(MODIFIED_HEADER.to_string(), value))?;
+    Ok(modified.with_timezone(&chrono::Utc))
+}
+
+fn parse_version_header(headers: &HeaderMap) -> Result<VersionVector> {
+    let Some(value) = optional_header(headers, VERSION_HEADER)? else {
+        return Ok(VersionVector::default());
+    };
+    decode_version_header(&value)
+        .ok_or_else(|| RequestError::InvalidHeader(VERSION_HEADER.to_string(), value).into())
+}
+
+fn json_reply<T: serde::Serialize>(
+    body: &T,
+    status: StatusCode,
+) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(body), status)
+}
+
+/// 409 Conflict reply for a write based on a version that is no longer current
+fn conflict_reply(error: &anyhow::Error) -> Option<warp::reply::WithStatus<warp::reply::Json>> {
+    let conflict = error.downcast_ref::<WriteConflict>()?;
+    let response = WriteConflictResponse {
+        success: false,
+        message: conflict.to_string(),
+        path: conflict.path.clone(),
+        current: conflict.current.clone(),
+    };
+    Some(json_reply(&response, StatusCode::CONFLICT))
+}
+
+/// HTTP status for a failed request. Authentication failures, invalid paths and
+/// malformed requests get their own status codes; other failures keep reporting `success: false` in the body.
+fn error_status(error: &anyhow::Error) -> StatusCode {
+    if let Some(auth_error) = error.downcast_ref::<AuthError>() {
+        return match auth_error {
+            AuthError::MissingCredentials | AuthError::InvalidCredentials => {
+                StatusCode::UNAUTHORIZED
+            }
+            AuthError::Forbidden { .. } => StatusCode::FORBIDDEN,
+        };
+    }
+
+    if let Some(request_error) = error.downcast_ref::<RequestError>() {
+        return match request_error {
+            RequestError::NotFound(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::BAD_REQUEST,
+        };
+    }
+
+    if error.downcast_ref::<PathError>().is_some() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::OK
+    }
+}
+
+/// Order a client's version of a path against the server's. Version vectors decide
+/// whenever they differ; versions recorded without any history (by older clients)
+/// fall back to their timestamps, and equal timestamps count as concurrent. The
+/// same goes for a deletion against a file: an edit and a deletion that never saw
+/// each other are concurrent, and the edit is kept.
+fn order_versions(
+    client_version: &VersionVector,
+    client_time: chrono::DateTime<chrono::Utc>,
+    server_version: &VersionVector,
+    server_time: chrono::DateTime<chrono::Utc>,
+) -> VersionOrder {
+    match client_version.compare(server_version) {
+        VersionOrder::Equal => order_by_time(client_time, server_time),
+        order => order,
+    }
+}
+
+fn order_by_time(
+    client_time: chrono::DateTime<chrono::Utc>,
+    server_time: chrono::DateTime<chrono::Utc>,
+) -> VersionOrder {
+    match client_time.cmp(&server_time) {
+        std::cmp::Ordering::Greater => VersionOrder::Dominates,
+        std::cmp::Ordering::Less => VersionOrder::Dominated,
+        std::cmp::Ordering::Equal => VersionOrder::Concurrent,
+    }
+}
+
+/// Normalize the paths of a client's file list, skipping invalid ones into `rejected`
+fn normalize_file_map(
+    files: HashMap<String, FileInfo>,
+    rejected: &mut Vec<String>,
+) -> HashMap<String, FileInfo> {
+    let mut normalized = HashMap::with_capacity(files.len());
+    for (path, mut file_info) in files {
+        let path = match normalize_relative_path(&path) {
+            Ok(normalized) => normalized,
+            Err(e) => {
+                warn!("Skipping invalid path {:?}: {}", path, e);
+                rejected.push(path);
+                continue;
+            }
+        };
+        file_info.path = path.clone();
+        normalized.insert(path, file_info);
+    }
+    normalized
+}
+
+/// Normalize the paths of a client's deletions, skipping invalid ones into `rejected`
+fn normalize_deletion_map<T>(
+    deleted_files: HashMap<String, T>,
+    rejected: &mut Vec<String>,
+) -> HashMap<String, T> {
+    let mut normalized = HashMap::with_capacity(deleted_files.len());
+    for (path, deleted_at) in deleted_files {
+        match normalize_relative_path(&path) {
+            Ok(path) => {
+                normalized.insert(path, deleted_at);
+            }
+            Err(e) => {
+                warn!("Skipping deletion of invalid path {:?}: {}", path, e);
+                rejected.push(path);
+            }
+        }
     }
+    normalized
 }
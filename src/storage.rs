@@ -0,0 +1,415 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use walkdir::WalkDir;
+
+use crate::paths::TEMP_FILE_PREFIX;
+use crate::types::StorageConfig;
+use crate::utils::{replace_file, TempFile};
+
+/// An object in a storage backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    /// When the object's content was stored
+    pub modified: DateTime<Utc>,
+}
+
+/// Where the server keeps the content of stored files, their prior versions,
+/// trashed files and deduplicated chunks.
+///
+/// Objects are addressed by keys made of `/`-separated names, such as
+/// `docs/notes/a.txt` or `docs/.syncpair/versions/12`. Content is streamed in
+/// and out, so objects can be larger than memory. Reading or renaming a key
+/// that has no object fails with an I/O error of kind `NotFound`; deleting one
+/// succeeds.
+pub trait StorageBackend: Send + Sync {
+    /// Store the `size` bytes `content` reads under `key`, replacing the object
+    /// there. Readers see either the old or the new content, never a mix.
+    fn put(&self, key: &str, content: &mut dyn Read, size: u64) -> Result<()>;
+
+    /// Read the object under `key`, or the `(start, length)` part of it
+    fn get(&self, key: &str, range: Option<(u64, u64)>) -> Result<Box<dyn Read + Send>>;
+
+    /// The object under `key`, or None when there is none
+    fn stat(&self, key: &str) -> Result<Option<ObjectInfo>>;
+
+    /// Remove the object under `key`
+    fn delete(&self, key: &str) -> Result<()>;
+
+    /// Every object whose key starts with `prefix`, ordered by key
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectInfo>>;
+
+    /// Move the object under `from` to `to`, replacing the object there
+    fn rename(&self, from: &str, to: &str) -> Result<()>;
+
+    /// Store the content of the local file at `source` under `key`. The file
+    /// may be moved into place rather than copied.
+    fn put_file(&self, key: &str, source: &Path) -> Result<()> {
+        let mut file = File::open(source)?;
+        let size = file.metadata()?.len();
+        self.put(key, &mut file, size)
+    }
+}
+
+/// Open the backend `config` selects. Local storage lives in `storage_dir`.
+pub fn open_storage(config: &StorageConfig, storage_dir: &Path) -> Result<Arc<dyn StorageBackend>> {
+    Ok(match config {
+        StorageConfig::Local => Arc::new(LocalStorage::new(storage_dir)),
+        StorageConfig::S3(s3) => Arc::new(crate::s3::S3Storage::new(s3)?),
+    })
+}
+
+/// `name` under the key `prefix`, which is empty for the storage root
+pub fn join_key(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Reject keys that aren't `/`-separated relative names, which could reach
+/// outside the storage root of a backend that maps them to paths
+pub fn check_key(key: &str) -> Result<()> {
+    let valid = !key.is_empty()
+        && !key.contains(['\\', '\0'])
+        && key
+            .split('/')
+            .all(|name| !name.is_empty() && name != "." && name != "..");
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Invalid storage key: {:?}", key))
+    }
+}
+
+/// The error for reading a key that has no object
+pub fn not_found(key: &str) -> anyhow::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("No object stored under {}", key),
+    )
+    .into()
+}
+
+/// Whether `error` says an object was not found
+pub fn is_not_found(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<std::io::Error>()
+        .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+}
+
+/// Reads an object from any backend, seeking by reading the rest of the object
+/// from the new position
+pub struct ObjectReader {
+    storage: Arc<dyn StorageBackend>,
+    key: String,
+    size: u64,
+    position: u64,
+    current: Option<Box<dyn Read + Send>>,
+}
+
+impl ObjectReader {
+    /// A reader of the `size` bytes stored under `key`
+    pub fn new(storage: Arc<dyn StorageBackend>, key: &str, size: u64) -> Self {
+        Self {
+            storage,
+            key: key.to_string(),
+            size,
+            position: 0,
+            current: None,
+        }
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let reader = match &mut self.current {
+            Some(reader) => reader,
+            current => current.insert(
+                self.storage
+                    .get(&self.key, Some((self.position, self.size - self.position)))
+                    .map_err(std::io::Error::other)?,
+            ),
+        };
+        let read = reader.read(buf)?;
+        if read == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("Object {} is shorter than expected", self.key),
+            ));
+        }
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ObjectReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek to a negative position",
+            )
+        })?;
+        if position != self.position {
+            self.position = position;
+            self.current = None;
+        }
+        Ok(position)
+    }
+}
+
+/// Objects as files under a root directory, with keys as their relative paths.
+/// Temporary files of writes in progress are not objects.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+        }
+    }
+
+    /// The file holding the object under `key`
+    pub fn path(&self, key: &str) -> Result<PathBuf> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+
+    fn create_parent(path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(())
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn put(&self, key: &str, content: &mut dyn Read, size: u64) -> Result<()> {
+        let path = self.path(key)?;
+        Self::create_parent(&path)?;
+        let temp = TempFile::new(&path);
+        let mut file = File::create(temp.path())?;
+        let copied = std::io::copy(&mut content.take(size), &mut file)?;
+        if copied != size {
+            return Err(anyhow::anyhow!(
+                "Content for {} ended after {} of {} bytes",
+                key,
+                copied,
+                size
+            ));
+        }
+        file.sync_all()?;
+        temp.commit()
+    }
+
+    fn get(&self, key: &str, range: Option<(u64, u64)>) -> Result<Box<dyn Read + Send>> {
+        let mut file = File::open(self.path(key)?)?;
+        Ok(match range {
+            Some((start, length)) => {
+                file.seek(SeekFrom::Start(start))?;
+                Box::new(file.take(length))
+            }
+            None => Box::new(file),
+        })
+    }
+
+    fn stat(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        match std::fs::metadata(self.path(key)?) {
+            Ok(metadata) if metadata.is_file() => Ok(Some(ObjectInfo {
+                key: key.to_string(),
+                size: metadata.len(),
+                modified: metadata.modified()?.into(),
+            })),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match std::fs::remove_file(self.path(key)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectInfo>> {
+        // Only the directory the prefix ends in can hold matching files
+        let dir = match prefix.rsplit_once('/') {
+            Some((dir, _)) => {
+                check_key(dir)?;
+                self.root.join(dir)
+            }
+            None => self.root.clone(),
+        };
+        let mut objects = Vec::new();
+        for entry in WalkDir::new(dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file())
+        {
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(TEMP_FILE_PREFIX)
+            {
+                continue;
+            }
+            let Ok(relative) = entry.path().strip_prefix(&self.root) else {
+                continue;
+            };
+            let Some(key) = relative
+                .components()
+                .map(|component| component.as_os_str().to_str())
+                .collect::<Option<Vec<_>>>()
+                .map(|names| names.join("/"))
+            else {
+                continue;
+            };
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            objects.push(ObjectInfo {
+                key,
+                size: metadata.len(),
+                modified: metadata.modified()?.into(),
+            });
+        }
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        let from = self.path(from)?;
+        let to = self.path(to)?;
+        Self::create_parent(&to)?;
+        replace_file(&from, &to)
+    }
+
+    fn put_file(&self, key: &str, source: &Path) -> Result<()> {
+        let path = self.path(key)?;
+        Self::create_parent(&path)?;
+        // A file staged on another file system can't be renamed into place
+        if replace_file(source, &path).is_ok() {
+            return Ok(());
+        }
+        let mut file = File::open(source)?;
+        let size = file.metadata()?.len();
+        self.put(key, &mut file, size)
+    }
+}
+
+/// An object's content and when it was stored
+type MemoryObject = (Arc<[u8]>, DateTime<Utc>);
+
+/// Objects held in memory, for tests
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: Mutex<BTreeMap<String, MemoryObject>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn put(&self, key: &str, content: &mut dyn Read, size: u64) -> Result<()> {
+        check_key(key)?;
+        let mut bytes = Vec::new();
+        content.take(size).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != size {
+            return Err(anyhow::anyhow!(
+                "Content for {} ended after {} of {} bytes",
+                key,
+                bytes.len(),
+                size
+            ));
+        }
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (bytes.into(), Utc::now()));
+        Ok(())
+    }
+
+    fn get(&self, key: &str, range: Option<(u64, u64)>) -> Result<Box<dyn Read + Send>> {
+        let content = self
+            .objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|(content, _)| content.clone())
+            .ok_or_else(|| not_found(key))?;
+        let mut reader = Cursor::new(content);
+        Ok(match range {
+            Some((start, length)) => {
+                reader.set_position(start);
+                Box::new(reader.take(length))
+            }
+            None => Box::new(reader),
+        })
+    }
+
+    fn stat(&self, key: &str) -> Result<Option<ObjectInfo>> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|(content, modified)| ObjectInfo {
+                key: key.to_string(),
+                size: content.len() as u64,
+                modified: *modified,
+            }))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<ObjectInfo>> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, (content, modified))| ObjectInfo {
+                key: key.clone(),
+                size: content.len() as u64,
+                modified: *modified,
+            })
+            .collect())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<()> {
+        check_key(to)?;
+        let mut objects = self.objects.lock().unwrap();
+        let object = objects.remove(from).ok_or_else(|| not_found(from))?;
+        objects.insert(to.to_string(), object);
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+use anyhow::{Context, Result};
+use hyper::server::conn::Http;
+use hyper::service::{service_fn, Service};
+use hyper::{Body, Request, Response};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::future::Future;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, warn};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::{
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerConfig,
+    ServerName,
+};
+
+/// Load a PEM certificate chain
+pub fn load_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open certificate file {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to parse certificates in {}", path.display()))?;
+
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No certificates found in {}",
+            path.display()
+        ));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Load the first PKCS#8, PKCS#1 or SEC1 private key from a PEM file
+pub fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open private key file {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+
+    loop {
+        match rustls_pemfile::read_one(&mut reader)
+            .with_context(|| format!("Failed to parse private key in {}", path.display()))?
+        {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => {
+                return Err(anyhow::anyhow!(
+                    "No private key found in {}",
+                    path.display()
+                ))
+            }
+        }
+    }
+}
+
+/// Hex-encoded SHA-256 fingerprint of a DER certificate, as used by `pinned_cert_sha256`
+pub fn certificate_fingerprint(certificate: &Certificate) -> String {
+    format!("{:x}", Sha256::digest(&certificate.0))
+}
+
+/// Build the server-side TLS configuration from a certificate chain and private key.
+///
+/// With `client_ca`, clients may present a certificate issued by that CA to
+/// authenticate; clients without a certificate are still accepted.
+pub fn server_tls_config(
+    cert_path: &Path,
+    key_path: &Path,
+    client_ca: Option<&Path>,
+) -> Result<ServerConfig> {
+    let certs = load_certificates(cert_path)?;
+    let key = load_private_key(key_path)?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match client_ca {
+        Some(path) => builder.with_client_cert_verifier(
+            AllowAnyAnonymousOrAuthenticatedClient::new(load_root_store(path)?).boxed(),
+        ),
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .context("Invalid TLS certificate or private key")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+/// Client-side TLS settings
+#[derive(Debug, Clone, Default)]
+pub struct ClientTlsOptions {
+    /// PEM CA certificate(s) the server certificate must chain to, instead of the public roots
+    pub ca_cert: Option<PathBuf>,
+    /// Expected SHA-256 fingerprint of the server certificate
+    pub pinned_cert_sha256: Option<String>,
+    /// PEM certificate chain presented to the server
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key for `client_cert`
+    pub client_key: Option<PathBuf>,
+}
+
+/// Build the client-side TLS configuration.
+///
+/// With `ca_cert`, the server certificate must chain to one of the certificates
+/// in that file instead of the public roots. With `pinned_cert_sha256`, the
+/// server must present exactly the certificate with that fingerprint; when
+/// both are set, both checks apply.
+pub fn client_tls_config(options: &ClientTlsOptions) -> Result<ClientConfig> {
+    let roots = match options.ca_cert {
+        Some(ref path) => Some(load_root_store(path)?),
+        None => None,
+    };
+    let pinned = options
+        .pinned_cert_sha256
+        .as_deref()
+        .map(parse_fingerprint)
+        .transpose()?;
+
+    let verifier: Arc<dyn ServerCertVerifier> = match (roots, pinned) {
+        (roots, Some(fingerprint)) => Arc::new(PinnedCertVerifier {
+            fingerprint,
+            webpki: roots.map(|roots| WebPkiVerifier::new(roots, None)),
+        }),
+        (Some(roots), None) => Arc::new(WebPkiVerifier::new(roots, None)),
+        (None, None) => Arc::new(WebPkiVerifier::new(public_root_store(), None)),
+    };
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(verifier);
+
+    match (&options.client_cert, &options.client_key) {
+        (Some(cert_path), Some(key_path)) => builder
+            .with_client_auth_cert(load_certificates(cert_path)?, load_private_key(key_path)?)
+            .context("Invalid client certificate or private key"),
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => Err(anyhow::anyhow!(
+            "A client certificate and its private key must be configured together"
+        )),
+    }
+}
+
+fn load_root_store(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certificates(path)? {
+        roots
+            .add(&cert)
+            .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+fn public_root_store() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    roots
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Result<String> {
+    // Accept both "ab12..." and the "AB:12:..." form printed by openssl
+    let normalized: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>()
+        .to_lowercase();
+
+    if normalized.len() != 64 || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow::anyhow!(
+            "Invalid SHA-256 certificate fingerprint: {}",
+            fingerprint
+        ));
+    }
+    Ok(normalized)
+}
+
+/// Accepts only the server certificate with a known fingerprint
+struct PinnedCertVerifier {
+    fingerprint: String,
+    // Chain and hostname checks when a CA is configured as well
+    webpki: Option<WebPkiVerifier>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if certificate_fingerprint(end_entity) != self.fingerprint {
+            return Err(rustls::Error::General(
+                "Server certificate does not match the pinned fingerprint".to_string(),
+            ));
+        }
+
+        match &self.webpki {
+            Some(webpki) => webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                scts,
+                ocsp_response,
+                now,
+            ),
+            None => Ok(ServerCertVerified::assertion()),
+        }
+    }
+}
+
+/// Names taken from a verified client certificate: the subject CN, then DNS and e-mail SANs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCertificate {
+    pub names: Vec<String>,
+}
+
+impl PeerCertificate {
+    /// The primary name, used as the identity when no configured identity matches
+    pub fn name(&self) -> &str {
+        &self.names[0]
+    }
+
+    pub fn from_der(certificate: &Certificate) -> Result<Self> {
+        let (_, cert) = X509Certificate::from_der(&certificate.0)
+            .map_err(|e| anyhow::anyhow!("Failed to parse client certificate: {}", e))?;
+
+        let mut candidates: Vec<&str> = cert
+            .subject()
+            .iter_common_name()
+            .filter_map(|cn| cn.as_str().ok())
+            .collect();
+
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) | GeneralName::RFC822Name(name) => {
+                        candidates.push(name)
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut names: Vec<String> = Vec::new();
+        for name in candidates {
+            if !name.is_empty() && !names.iter().any(|known| known == name) {
+                names.push(name.to_string());
+            }
+        }
+        if names.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Client certificate has neither a common name nor a subject alternative name"
+            ));
+        }
+        Ok(Self { names })
+    }
+}
+
+/// Serve `service` over TLS until `shutdown` resolves.
+///
+/// Requests on connections that presented a client certificate carry a
+/// `PeerCertificate` extension.
+pub(crate) async fn serve<S>(
+    service: S,
+    addr: SocketAddr,
+    config: Arc<ServerConfig>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let listener = TcpListener::bind(addr).await?;
+    let acceptor = TlsAcceptor::from(config);
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, peer_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let service = service.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", peer_addr, e);
+                    return;
+                }
+            };
+
+            // The certificate chain was already verified during the handshake
+            let peer = match stream.get_ref().1.peer_certificates() {
+                Some([certificate, ..]) => match PeerCertificate::from_der(certificate) {
+                    Ok(peer) => Some(peer),
+                    Err(e) => {
+                        debug!("Rejecting connection from {}: {}", peer_addr, e);
+                        return;
+                    }
+                },
+                _ => None,
+            };
+
+            let service = service_fn(move |mut request: Request<Body>| {
+                if let Some(ref peer) = peer {
+                    request.extensions_mut().insert(peer.clone());
+                }
+                service.clone().call(request)
+            });
+
+            if let Err(e) = Http::new().serve_connection(stream, service).await {
+                debug!("Connection with {} closed with error: {}", peer_addr, e);
+            }
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,234 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::chunk_store::{delete_content, rename_content, stat_content};
+use crate::paths::{RESERVED_DIR_NAME, SERVER_STATE_FILE};
+use crate::storage::{join_key, not_found, StorageBackend};
+use crate::types::{FileInfo, TrashConfig, TrashedFile};
+
+const SELECT_TRASHED: &str =
+    "SELECT id, file_path, file_hash, file_size, modified_at, version, stored_at, deleted_at
+     FROM trashed_files";
+
+/// Per-directory trash holding deleted files until they are restored or purged.
+///
+/// Trashed files are kept under `.syncpair/trash/` in the directory's storage,
+/// next to the version history, and their metadata in the directory's state
+/// database.
+pub struct Trash {
+    conn: Connection,
+    storage: Arc<dyn StorageBackend>,
+    trash_prefix: String,
+}
+
+impl Trash {
+    /// Create the trash's table in a directory's state database, once, when the
+    /// directory state is first loaded or created
+    pub fn create_schema(conn: &Connection) -> Result<()> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS trashed_files (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                file_path TEXT NOT NULL,
+                file_hash TEXT NOT NULL,
+                file_size INTEGER NOT NULL,
+                modified_at TEXT NOT NULL,
+                version TEXT NOT NULL,
+                stored_at INTEGER NOT NULL,
+                deleted_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Open the trash of the directory whose state is at `directory_dir` and
+    /// whose content is under `directory_key` in `storage`. Its schema must
+    /// have been created with `create_schema`.
+    pub fn open(
+        directory_dir: &Path,
+        storage: Arc<dyn StorageBackend>,
+        directory_key: &str,
+    ) -> Result<Self> {
+        Ok(Self {
+            conn: Connection::open(directory_dir.join(SERVER_STATE_FILE))?,
+            storage,
+            trash_prefix: join_key(directory_key, &format!("{}/trash", RESERVED_DIR_NAME)),
+        })
+    }
+
+    /// Move the deleted file stored under `file_key`, described by `file_info`,
+    /// into the trash
+    pub fn put(&self, file_key: &str, file_info: &FileInfo) -> Result<u64> {
+        let stored_at = stat_content(self.storage.as_ref(), file_key)?
+            .ok_or_else(|| not_found(file_key))?
+            .modified;
+
+        self.conn.execute(
+            "INSERT INTO trashed_files (file_path, file_hash, file_size, modified_at, version, stored_at, deleted_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                file_info.path,
+                file_info.hash,
+                file_info.size,
+                file_info.modified.to_rfc3339(),
+                serde_json::to_string(&file_info.version)?,
+                stored_at.timestamp_millis(),
+                Utc::now().timestamp_millis()
+            ],
+        )?;
+        let id = self.conn.last_insert_rowid() as u64;
+
+        if let Err(e) = rename_content(self.storage.as_ref(), file_key, &self.content_key(id)) {
+            self.conn
+                .execute("DELETE FROM trashed_files WHERE id = ?", params![id as i64])?;
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// The trashed files, most recently deleted first
+    pub fn list(&self) -> Result<Vec<TrashedFile>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "{} ORDER BY deleted_at DESC, id DESC",
+            SELECT_TRASHED
+        ))?;
+        let items = stmt
+            .query_map([], read_trashed)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(items)
+    }
+
+    /// A trashed file and the key of its content
+    pub fn get(&self, id: u64) -> Result<Option<(TrashedFile, String)>> {
+        let item = self
+            .conn
+            .query_row(
+                &format!("{} WHERE id = ?", SELECT_TRASHED),
+                params![id as i64],
+                read_trashed,
+            )
+            .optional()?;
+        Ok(item.map(|item| {
+            let content_key = self.content_key(item.id);
+            (item, content_key)
+        }))
+    }
+
+    /// The content `path` had at `at`, if it was deleted since and is still in
+    /// the trash, and the key of that content
+    pub fn version_at(
+        &self,
+        path: &str,
+        at: DateTime<Utc>,
+    ) -> Result<Option<(TrashedFile, String)>> {
+        let at = at.timestamp_millis();
+        let item = self
+            .conn
+            .query_row(
+                &format!(
+                    "{} WHERE file_path = ? AND stored_at <= ? AND deleted_at > ?
+                     ORDER BY stored_at DESC, id DESC LIMIT 1",
+                    SELECT_TRASHED
+                ),
+                params![path, at, at],
+                read_trashed,
+            )
+            .optional()?;
+        Ok(item.map(|item| {
+            let content_key = self.content_key(item.id);
+            (item, content_key)
+        }))
+    }
+
+    /// Every path with files in the trash
+    pub fn paths(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT DISTINCT file_path FROM trashed_files ORDER BY file_path")?;
+        let paths = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(paths)
+    }
+
+    /// Move the content of trashed file `id` to the key `destination` and take
+    /// it out of the trash
+    pub fn take(&self, id: u64, destination: &str) -> Result<Option<TrashedFile>> {
+        let Some((item, content_key)) = self.get(id)? else {
+            return Ok(None);
+        };
+        rename_content(self.storage.as_ref(), &content_key, destination)?;
+        self.conn
+            .execute("DELETE FROM trashed_files WHERE id = ?", params![id as i64])?;
+        Ok(Some(item))
+    }
+
+    /// Permanently delete trashed file `id`, or every trashed file
+    pub fn purge(&self, id: Option<u64>) -> Result<Vec<TrashedFile>> {
+        let items = match id {
+            Some(id) => self.get(id)?.into_iter().map(|(item, _)| item).collect(),
+            None => self.list()?,
+        };
+        self.remove(&items)?;
+        Ok(items)
+    }
+
+    /// Take the trashed files `policy` no longer keeps out of the trash. `retire`
+    /// gets each one with the key of its content first, and may move it.
+    pub fn expire(
+        &self,
+        policy: &TrashConfig,
+        mut retire: impl FnMut(&TrashedFile, &str) -> Result<()>,
+    ) -> Result<Vec<TrashedFile>> {
+        let cutoff = Utc::now() - Duration::days(i64::from(policy.retention_days));
+        let items: Vec<TrashedFile> = self
+            .list()?
+            .into_iter()
+            .filter(|item| item.deleted_at <= cutoff)
+            .collect();
+        for item in &items {
+            retire(item, &self.content_key(item.id))?;
+        }
+        self.remove(&items)?;
+        Ok(items)
+    }
+
+    fn remove(&self, items: &[TrashedFile]) -> Result<()> {
+        for item in items {
+            self.conn.execute(
+                "DELETE FROM trashed_files WHERE id = ?",
+                params![item.id as i64],
+            )?;
+            delete_content(self.storage.as_ref(), &self.content_key(item.id))?;
+        }
+        Ok(())
+    }
+
+    fn content_key(&self, id: u64) -> String {
+        format!("{}/{}", self.trash_prefix, id)
+    }
+}
+
+fn read_trashed(row: &Row) -> rusqlite::Result<TrashedFile> {
+    let modified = DateTime::parse_from_rfc3339(&row.get::<_, String>(4)?)
+        .map_err(|_| rusqlite::Error::InvalidColumnIndex(4))?
+        .with_timezone(&Utc);
+    let version = serde_json::from_str(&row.get::<_, String>(5)?)
+        .map_err(|_| rusqlite::Error::InvalidColumnIndex(5))?;
+
+    Ok(TrashedFile {
+        id: row.get::<_, i64>(0)? as u64,
+        file_info: FileInfo {
+            path: row.get(1)?,
+            hash: row.get(2)?,
+            size: row.get(3)?,
+            modified,
+            version,
+        },
+        stored_at: DateTime::from_timestamp_millis(row.get(6)?).unwrap_or_default(),
+        deleted_at: DateTime::from_timestamp_millis(row.get(7)?).unwrap_or_default(),
+    })
+}
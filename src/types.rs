@@ -1,6 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -9,6 +9,115 @@ pub struct FileInfo {
     pub hash: String,
     pub size: u64,
     pub modified: DateTime<Utc>,
+    /// Causal history of this version; empty for records from before version vectors
+    #[serde(default, skip_serializing_if = "VersionVector::is_empty")]
+    pub version: VersionVector,
+}
+
+/// Number of edits each client has made to a file, keyed by client identity.
+/// Unlike modification times, these don't depend on anyone's clock.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+/// How one version of a file relates to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionOrder {
+    Equal,
+    /// Derived from the other version, so it supersedes it
+    Dominates,
+    /// The other version was derived from this one
+    Dominated,
+    /// Neither has seen the other: both are real edits
+    Concurrent,
+}
+
+impl VersionVector {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, client: &str) -> u64 {
+        self.0.get(client).copied().unwrap_or_default()
+    }
+
+    /// The version after `client` edits this one
+    pub fn incremented(&self, client: &str) -> Self {
+        let mut version = self.clone();
+        *version.0.entry(client.to_string()).or_default() += 1;
+        version
+    }
+
+    /// The least version that has seen both this one and `other`
+    pub fn merged(&self, other: &Self) -> Self {
+        let mut version = self.clone();
+        for (client, &count) in &other.0 {
+            let entry = version.0.entry(client.clone()).or_default();
+            *entry = (*entry).max(count);
+        }
+        version
+    }
+
+    pub fn compare(&self, other: &Self) -> VersionOrder {
+        let clients = self.0.keys().chain(other.0.keys());
+        let (mut ahead, mut behind) = (false, false);
+        for client in clients {
+            match self.get(client).cmp(&other.get(client)) {
+                std::cmp::Ordering::Greater => ahead = true,
+                std::cmp::Ordering::Less => behind = true,
+                std::cmp::Ordering::Equal => {}
+            }
+        }
+
+        match (ahead, behind) {
+            (false, false) => VersionOrder::Equal,
+            (true, false) => VersionOrder::Dominates,
+            (false, true) => VersionOrder::Dominated,
+            (true, true) => VersionOrder::Concurrent,
+        }
+    }
+}
+
+/// A conflict recorded in the client state, with the copy that holds the losing version
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConflictRecord {
+    pub path: String,
+    pub copy_path: String,
+    pub detected_at: DateTime<Utc>,
+    /// Set once the conflict copy is gone, i.e. the user has dealt with it
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+/// A directory whose sync stopped because too many local files disappeared at once
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeletionPause {
+    /// Files deleted locally since the last sync
+    pub deleted: usize,
+    /// Files the last sync knew about
+    pub tracked: usize,
+    pub paused_at: DateTime<Utc>,
+    /// Set once a user confirmed that the deletions are intended
+    pub confirmed: bool,
+}
+
+/// An upload session left open by an interrupted upload, so it can resume
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadProgress {
+    pub session_id: String,
+    /// Hash of the content being uploaded; other content needs a new session
+    pub hash: String,
+    /// Bytes from the start of the file the server acknowledged
+    pub received: u64,
+}
+
+/// A download interrupted partway, whose first bytes are kept in the
+/// reserved folder (see `utils::partial_download_path`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    /// Hash of the version being downloaded
+    pub hash: String,
+    /// Bytes of it written and flushed to the partial file
+    pub received: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +131,9 @@ pub struct ClientState {
     pub files: HashMap<String, FileInfo>,
     pub deleted_files: HashMap<String, DateTime<Utc>>, // Files deleted with timestamp
     pub last_sync: DateTime<Utc>,
+    /// Version of each deletion in `deleted_files`: the deleted version plus the deletion
+    #[serde(default)]
+    pub deleted_versions: HashMap<String, VersionVector>,
 }
 
 // Configuration types for multi-directory support
@@ -29,6 +141,28 @@ pub struct ClientState {
 pub struct ClientConfig {
     pub client_id: String,
     pub server: String,
+    /// Bearer token sent with every request
+    #[serde(default)]
+    pub token: Option<String>,
+    /// File containing the bearer token (used when `token` is not set)
+    #[serde(default)]
+    pub token_file: Option<PathBuf>,
+    /// CA certificate (PEM) that the `https://` server certificate must chain to
+    #[serde(default)]
+    pub ca_cert: Option<PathBuf>,
+    /// Expected SHA-256 fingerprint of the server certificate
+    #[serde(default)]
+    pub pinned_cert_sha256: Option<String>,
+    /// Client certificate (PEM) presented to servers that accept mutual TLS
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    /// Private key (PEM) for `client_cert`
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+    /// Directory for client state kept outside the synced folders (default: the
+    /// platform's local data directory, e.g. `~/.local/share/syncpair`)
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
     #[serde(default)]
     pub default: Option<DefaultSettings>,
     pub directories: Vec<DirectoryConfig>,
@@ -46,6 +180,17 @@ pub struct DefaultSettings {
     pub ignore_patterns: Vec<String>,
     #[serde(default)]
     pub shared: Option<bool>,
+    #[serde(default)]
+    pub conflict_policy: Option<ConflictPolicy>,
+    /// Days that files removed or overwritten by sync stay in the local trash
+    #[serde(default)]
+    pub trash_retention_days: Option<u32>,
+    /// Pause when a sync would delete more than this share of the files, in percent
+    #[serde(default)]
+    pub deletion_guard_percent: Option<u32>,
+    /// Pause when a sync would delete more than this many files
+    #[serde(default)]
+    pub deletion_guard_files: Option<usize>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,6 +213,28 @@ pub struct DirectorySettings {
     pub ignore_patterns: Vec<String>,
     #[serde(default)]
     pub shared: Option<bool>,
+    #[serde(default)]
+    pub conflict_policy: Option<ConflictPolicy>,
+    /// Days that files removed or overwritten by sync stay in the local trash
+    #[serde(default)]
+    pub trash_retention_days: Option<u32>,
+    /// Pause when a sync would delete more than this share of the files, in percent
+    #[serde(default)]
+    pub deletion_guard_percent: Option<u32>,
+    /// Pause when a sync would delete more than this many files
+    #[serde(default)]
+    pub deletion_guard_files: Option<usize>,
+}
+
+/// What to do when a file changed on both sides since the last sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Keep the losing version next to the winner as a conflict copy
+    #[default]
+    KeepBoth,
+    /// Overwrite the losing version
+    NewestWins,
 }
 
 impl DirectorySettings {
@@ -103,6 +270,15 @@ impl DirectorySettings {
 
             // Apply default shared only if current is None
             shared: self.shared.or(defaults.shared),
+
+            conflict_policy: self.conflict_policy.or(defaults.conflict_policy),
+
+            trash_retention_days: self.trash_retention_days.or(defaults.trash_retention_days),
+
+            deletion_guard_percent: self
+                .deletion_guard_percent
+                .or(defaults.deletion_guard_percent),
+            deletion_guard_files: self.deletion_guard_files.or(defaults.deletion_guard_files),
         }
     }
 
@@ -116,6 +292,16 @@ impl DirectorySettings {
             enabled: self.enabled.unwrap_or(default_true()),
             ignore_patterns: self.ignore_patterns.clone(),
             shared: self.shared.unwrap_or(false),
+            conflict_policy: self.conflict_policy.unwrap_or_default(),
+            trash_retention_days: self
+                .trash_retention_days
+                .unwrap_or(default_trash_retention_days()),
+            deletion_guard_percent: self
+                .deletion_guard_percent
+                .unwrap_or(default_deletion_guard_percent()),
+            deletion_guard_files: self
+                .deletion_guard_files
+                .unwrap_or(default_deletion_guard_files()),
         }
     }
 }
@@ -128,16 +314,191 @@ pub struct EffectiveDirectorySettings {
     pub enabled: bool,
     pub ignore_patterns: Vec<String>,
     pub shared: bool,
+    pub conflict_policy: ConflictPolicy,
+    pub trash_retention_days: u32,
+    pub deletion_guard_percent: u32,
+    pub deletion_guard_files: usize,
 }
 
 fn default_sync_interval() -> u64 {
     30
 }
 
+fn default_trash_retention_days() -> u32 {
+    30
+}
+
+fn default_deletion_guard_percent() -> u32 {
+    50
+}
+
+fn default_deletion_guard_files() -> usize {
+    500
+}
+
 fn default_true() -> bool {
     true
 }
 
+// Server configuration
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ServerConfig {
+    /// Authentication settings; when absent the server accepts anonymous requests
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// Which replaced and deleted versions of files the server keeps
+    #[serde(default)]
+    pub history: HistoryConfig,
+    /// How long deleted files stay in each directory's trash
+    #[serde(default)]
+    pub trash: TrashConfig,
+    /// Whether file content is stored once in a deduplicated chunk store
+    #[serde(default)]
+    pub dedup: DedupConfig,
+    /// Where file content is stored
+    #[serde(default)]
+    pub storage: StorageConfig,
+}
+
+/// Backend holding the content of stored files, versions, trashed files and
+/// chunks. Directory state and files being uploaded stay in the storage directory.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Files in the storage directory
+    #[default]
+    Local,
+    /// Objects in a bucket of an S3-compatible object store
+    S3(S3Config),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    /// Base URL of the object store, e.g. `https://s3.eu-west-1.amazonaws.com`
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Prefix of every key in the bucket, so servers can share a bucket
+    #[serde(default)]
+    pub prefix: String,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// Retention of prior file versions. A version is kept while any rule keeps it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    /// Number of most recent versions of each file that are always kept
+    pub keep_versions: usize,
+    /// Every version replaced within this many days is kept
+    pub keep_all_days: u32,
+    /// Then the latest version of each day is kept for this many days
+    pub daily_days: u32,
+    /// Then the latest version of each week is kept for this many weeks
+    pub weekly_weeks: u32,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            keep_versions: 10,
+            keep_all_days: 1,
+            daily_days: 30,
+            weekly_weeks: 52,
+        }
+    }
+}
+
+/// Deleted files are moved to the trash of their directory instead of being removed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrashConfig {
+    pub enabled: bool,
+    /// Trashed files are purged this many days after they were deleted
+    pub retention_days: u32,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 30,
+        }
+    }
+}
+
+/// File content is split into chunks kept once in a store shared by every
+/// directory, and stored files, versions and trashed files list their chunks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DedupConfig {
+    pub enabled: bool,
+    /// Average chunk size in bytes; chunks are content-defined, like delta blocks
+    pub average_chunk_size: u64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            average_chunk_size: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub identities: Vec<IdentityConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityConfig {
+    pub name: String,
+    /// API tokens that authenticate as this identity
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    #[serde(default)]
+    pub permissions: Vec<PermissionRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionRule {
+    /// Directory name or glob pattern (e.g. `team_*`, `alice:*`)
+    pub directory: String,
+    pub access: AccessLevel,
+}
+
+/// Rights on a directory; each level includes the ones before it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLevel {
+    Read,
+    Write,
+    Admin,
+}
+
+// Headers carrying file metadata on the raw `/files/{path}` endpoints
+pub const HASH_HEADER: &str = "x-syncpair-hash";
+pub const MODIFIED_HEADER: &str = "x-syncpair-modified";
+pub const PATH_HEADER: &str = "x-syncpair-path";
+pub const CLIENT_ID_HEADER: &str = "x-syncpair-client-id";
+/// Hash of the version an upload replaces, see `UploadRequest::base_hash`
+pub const BASE_HASH_HEADER: &str = "x-syncpair-base-hash";
+/// URL-encoded JSON `VersionVector` of the file being transferred
+pub const VERSION_HEADER: &str = "x-syncpair-version";
+/// Position in the file of the bytes sent to a resumable upload session
+pub const OFFSET_HEADER: &str = "x-syncpair-offset";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadRequest {
     pub file_info: FileInfo,
@@ -146,6 +507,10 @@ pub struct UploadRequest {
     pub client_id: Option<String>,
     #[serde(default)]
     pub directory: Option<String>,
+    /// Hash of the version the client last synced, or an empty string for a
+    /// file it expects not to exist. Without a base the write is unconditional.
+    #[serde(default)]
+    pub base_hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -163,6 +528,72 @@ pub struct SyncRequest {
     pub client_id: Option<String>,
     #[serde(default)]
     pub directory: Option<String>,
+    #[serde(default)]
+    pub deleted_versions: HashMap<String, VersionVector>,
+    /// Hash of the version each new deletion removed (see `DeleteRequest::base_hash`)
+    #[serde(default)]
+    pub deleted_base_hashes: HashMap<String, String>,
+}
+
+/// Position in a directory's change journal, as returned by the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncCursor {
+    pub journal_id: String,
+    pub seq: u64,
+}
+
+/// What happened to a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Modified,
+    Deleted,
+}
+
+/// A change committed on the server, pushed to the directory's subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub path: String,
+    pub kind: ChangeKind,
+    /// Journal position right after the change
+    pub cursor: SyncCursor,
+}
+
+/// A local change since the client's last sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileChange {
+    Modified(FileInfo),
+    Deleted {
+        path: String,
+        deleted_at: DateTime<Utc>,
+        #[serde(default)]
+        version: VersionVector,
+        /// Hash of the version the client deleted (see `DeleteRequest::base_hash`)
+        #[serde(default)]
+        base_hash: Option<String>,
+    },
+}
+
+impl FileChange {
+    pub fn path(&self) -> &str {
+        match self {
+            FileChange::Modified(file_info) => &file_info.path,
+            FileChange::Deleted { path, .. } => path,
+        }
+    }
+}
+
+/// Incremental sync: the local changes since `cursor`, answered with the
+/// remote changes since `cursor`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangesRequest {
+    pub cursor: SyncCursor,
+    pub changes: Vec<FileChange>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub directory: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -172,6 +603,19 @@ pub struct DeleteRequest {
     pub client_id: Option<String>,
     #[serde(default)]
     pub directory: Option<String>,
+    /// Hash of the version the client deleted (see `UploadRequest::base_hash`)
+    #[serde(default)]
+    pub base_hash: Option<String>,
+    /// Version of the deletion (see `ClientState::deleted_versions`)
+    #[serde(default)]
+    pub version: VersionVector,
+}
+
+/// Body of failed requests on routes without a response type of their own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub success: bool,
+    pub message: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -180,12 +624,32 @@ pub struct DeleteResponse {
     pub message: String,
 }
 
+/// Body of a write rejected with 409 Conflict because its base is not the
+/// current version of the file
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteConflictResponse {
+    pub success: bool,
+    pub message: String,
+    pub path: String,
+    /// The server's current version, or None when the file is deleted
+    pub current: Option<FileInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SyncResponse {
     pub files_to_upload: Vec<String>,
     pub files_to_download: Vec<FileInfo>,
     pub files_to_delete: Vec<String>,
     pub conflicts: Vec<FileConflict>,
+    /// Journal position covered by this response, for the next incremental sync
+    #[serde(default)]
+    pub cursor: Option<SyncCursor>,
+    /// The incremental sync cursor has expired; the client must do a full sync
+    #[serde(default)]
+    pub cursor_expired: bool,
+    /// Paths in the request that were skipped because they aren't valid
+    #[serde(default)]
+    pub rejected_paths: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -210,6 +674,82 @@ pub struct DownloadResponse {
     pub message: String,
 }
 
+/// A prior version of a file kept in the server's version history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileVersion {
+    pub id: u64,
+    pub file_info: FileInfo,
+    /// When the server stored this content
+    pub stored_at: DateTime<Utc>,
+    /// When it stopped being the current version
+    pub archived_at: DateTime<Utc>,
+    /// Whether a newer version replaced it or the file was deleted
+    pub replaced_by: ChangeKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionListResponse {
+    pub success: bool,
+    pub message: String,
+    /// Newest first
+    pub versions: Vec<FileVersion>,
+}
+
+/// A deleted file waiting in the trash of its directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedFile {
+    pub id: u64,
+    pub file_info: FileInfo,
+    /// When the server stored this content
+    pub stored_at: DateTime<Utc>,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// Restore or purge the trashed file `id`; a purge without an ID empties the trash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashRequest {
+    #[serde(default)]
+    pub directory: Option<String>,
+    #[serde(default)]
+    pub id: Option<u64>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrashResponse {
+    pub success: bool,
+    pub message: String,
+    /// The trashed files listed, restored or purged, most recently deleted first
+    pub items: Vec<TrashedFile>,
+}
+
+/// Roll a directory, or the files at and below `path`, back to how they were at `at`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreRequest {
+    #[serde(default)]
+    pub directory: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
+    pub at: DateTime<Utc>,
+    #[serde(default)]
+    pub client_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RestoreResponse {
+    pub success: bool,
+    pub message: String,
+    /// Paths that got back the content they had at the requested time
+    pub restored: Vec<String>,
+    /// Paths deleted because they did not exist at the requested time
+    pub deleted: Vec<String>,
+    /// Deleted paths with no version in the history or trash for the requested time, either
+    /// because they did not exist then or because retention dropped that version, and
+    /// paths that failed to restore
+    pub unavailable: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileChangeEvent {
     pub path: String,
@@ -224,6 +764,7 @@ pub enum ChangeType {
 }
 
 pub mod error {
+    use super::FileInfo;
     use thiserror::Error;
 
     #[derive(Error, Debug)]
@@ -246,35 +787,141 @@ pub mod error {
         #[error("Watch error: {0}")]
         Watch(String),
     }
+
+    #[derive(Error, Debug, Clone, PartialEq, Eq)]
+    pub enum PathError {
+        #[error("Path is empty")]
+        Empty,
+
+        #[error("Path contains a NUL byte: {0}")]
+        NulByte(String),
+
+        #[error("Absolute paths are not allowed: {0}")]
+        Absolute(String),
+
+        #[error("Parent directory references are not allowed: {0}")]
+        ParentTraversal(String),
+
+        #[error("Path uses a reserved name: {0}")]
+        Reserved(String),
+
+        #[error("Invalid directory name: {0}")]
+        InvalidDirectoryName(String),
+
+        #[error("Path escapes the storage root through a symlink: {0}")]
+        SymlinkEscape(String),
+
+        #[error("Path could not be resolved: {0}")]
+        Unresolvable(String),
+    }
+
+    #[derive(Error, Debug, Clone, PartialEq, Eq)]
+    pub enum RequestError {
+        #[error("Missing required header '{0}'")]
+        MissingHeader(String),
+
+        #[error("Invalid value for header '{0}': {1}")]
+        InvalidHeader(String, String),
+
+        #[error("Missing required '{0}' parameter")]
+        MissingParameter(String),
+
+        #[error("Invalid value for '{0}': {1}")]
+        InvalidParameter(String, String),
+
+        #[error("{0}")]
+        NotFound(String),
+    }
+
+    /// A write based on a version of the file that is no longer current
+    #[derive(Error, Debug, Clone, PartialEq)]
+    #[error("'{path}' changed on the server since the version this write is based on")]
+    pub struct WriteConflict {
+        pub path: String,
+        pub current: Option<FileInfo>,
+    }
+
+    /// The synced folder is not the one the client state was recorded for
+    #[derive(Error, Debug, Clone, PartialEq, Eq)]
+    pub enum FolderIdentityError {
+        #[error("{0} has sync state but no folder marker; it may not be the synced folder, e.g. an unmounted disk")]
+        MissingMarker(String),
+
+        #[error("{0} was synced before but no longer exists; it may be an unmounted disk")]
+        MissingFolder(String),
+
+        #[error(
+            "{path} is marked as folder {found}, but its sync state belongs to folder {expected}"
+        )]
+        Mismatch {
+            path: String,
+            expected: String,
+            found: String,
+        },
+    }
+
+    #[derive(Error, Debug, Clone, PartialEq, Eq)]
+    pub enum AuthError {
+        #[error("Missing credentials")]
+        MissingCredentials,
+
+        #[error("Invalid credentials")]
+        InvalidCredentials,
+
+        #[error("'{identity}' lacks {access} access to directory '{directory}'")]
+        Forbidden {
+            identity: String,
+            directory: String,
+            access: String,
+        },
+    }
 }
 
 // Delta Sync Types
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A content-defined block of a file (see `utils::calculate_block_hashes`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BlockMsg {
     pub index: u64,
     pub hash: String,
+    /// Position of the block in its file
+    #[serde(default)]
+    pub offset: u64,
+    #[serde(default)]
+    pub length: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeltaInitRequest {
     pub file_info: FileInfo,
+    /// Blocks of the new version, covering it in order
     pub block_hashes: Vec<BlockMsg>,
+    /// Average block size
     pub block_size: u64,
     #[serde(default)]
     pub client_id: Option<String>,
     #[serde(default)]
     pub directory: Option<String>,
+    /// Hash of the version the blocks patch (see `UploadRequest::base_hash`)
+    #[serde(default)]
+    pub base_hash: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeltaInitResponse {
     pub missing_block_indices: Vec<u64>,
     pub should_full_upload: bool,
+    /// Session the blocks and the completion belong to; None when a full upload
+    /// is recommended
+    #[serde(default)]
+    pub session_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockUploadRequest {
+    /// Delta session opened by `/delta/init`; older clients don't send one
+    #[serde(default)]
+    pub session_id: Option<String>,
     pub path: String,
     pub directory: String,
     pub index: u64,
@@ -291,10 +938,14 @@ pub struct BlockUploadResponse {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeltaCompleteRequest {
+    #[serde(default)]
+    pub session_id: Option<String>,
     pub path: String,
     pub directory: Option<String>,
     pub client_id: Option<String>,
     pub expected_hash: String,
+    #[serde(default)]
+    pub version: VersionVector,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -302,3 +953,68 @@ pub struct DeltaCompleteResponse {
     pub success: bool,
     pub message: String,
 }
+
+/// Ask which blocks of the server's version of a file differ from the client's copy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaDownloadRequest {
+    pub path: String,
+    #[serde(default)]
+    pub directory: Option<String>,
+    /// Hashes of the blocks of the client's copy
+    pub block_hashes: Vec<BlockMsg>,
+    /// Average block size
+    pub block_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaDownloadResponse {
+    pub success: bool,
+    pub message: String,
+    /// The server's version, which the client patches its copy into
+    pub file_info: Option<FileInfo>,
+    /// Blocks of the server's version the client's copy lacks, to be fetched with
+    /// `Range` requests on `GET /files/{path}`
+    pub missing_block_indices: Vec<u64>,
+    pub should_full_download: bool,
+    /// Blocks of the server's version; the client copies the ones it has from
+    /// wherever they are in its copy
+    #[serde(default)]
+    pub blocks: Vec<BlockMsg>,
+}
+
+// Resumable Upload Types
+
+/// Bytes `start..end` of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// Open a resumable upload session for a large file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSessionRequest {
+    pub file_info: FileInfo,
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub directory: Option<String>,
+    /// Hash of the version the upload replaces (see `UploadRequest::base_hash`)
+    #[serde(default)]
+    pub base_hash: Option<String>,
+}
+
+/// State of a resumable upload session
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UploadSessionResponse {
+    pub success: bool,
+    pub message: String,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Parts of the file the server has received, in order and not overlapping
+    #[serde(default)]
+    pub received: Vec<ByteRange>,
+    /// Set once the whole file was received, verified and stored, which closes the session
+    #[serde(default)]
+    pub completed: bool,
+}
@@ -1,48 +1,247 @@
-use crate::types::{BlockMsg, ClientState, FileInfo};
+use crate::paths::{FOLDER_ID_FILE, RESERVED_DIR_NAME, TEMP_FILE_PREFIX};
+use crate::types::{
+    BlockMsg, ByteRange, ClientState, ConflictRecord, DeletionPause, DownloadProgress, FileInfo,
+    SyncCursor, UploadProgress, VersionVector,
+};
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
 use glob::Pattern;
-use rusqlite::{params, Connection};
+use memmap2::Mmap;
+use rusqlite::{params, Connection, OptionalExtension};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::fs::{self, File, Metadata};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::{debug, warn};
 use walkdir::WalkDir;
 
+/// Size of the buffer files are streamed through while hashing
+const HASH_BUFFER_SIZE: usize = 1024 * 1024; // 1 MB
+
+/// Files modified less than this long before a scan are rehashed by the next one
+const RACY_WINDOW_NANOS: i64 = 2_000_000_000; // 2 seconds
+
+/// How file contents are read for hashing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashMethod {
+    /// Read through a fixed-size buffer
+    #[default]
+    Buffered,
+    /// Read through a memory map of the whole file
+    Mmap,
+}
+
+/// SHA-256 of a file, streamed through a fixed-size buffer so memory use
+/// doesn't depend on the file size
 pub fn calculate_file_hash(path: &Path) -> Result<String> {
-    let contents = fs::read(path)?;
+    calculate_file_hash_with(path, HashMethod::Buffered)
+}
+
+/// SHA-256 of a file; every method produces the same hash
+pub fn calculate_file_hash_with(path: &Path, method: HashMethod) -> Result<String> {
+    let file = File::open(path)?;
+
+    match method {
+        HashMethod::Buffered => calculate_reader_hash(file),
+        HashMethod::Mmap => {
+            let mut hasher = Sha256::new();
+            // Mapping an empty file fails on some platforms
+            if file.metadata()?.len() > 0 {
+                // SAFETY: the map is only read, and only while hashing. A file
+                // truncated concurrently can still fault, which is why this
+                // method is opt-in.
+                let map = unsafe { Mmap::map(&file)? };
+                for chunk in map.chunks(HASH_BUFFER_SIZE) {
+                    hasher.update(chunk);
+                }
+            }
+            Ok(format!("{:x}", hasher.finalize()))
+        }
+    }
+}
+
+/// SHA-256 of the content `reader` reads, streamed through a fixed-size buffer
+pub fn calculate_reader_hash(mut reader: impl Read) -> Result<String> {
     let mut hasher = Sha256::new();
-    hasher.update(&contents);
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(bytes_read) => hasher.update(&buffer[..bytes_read]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
     Ok(format!("{:x}", hasher.finalize()))
 }
 
-pub fn calculate_block_hashes(path: &Path, block_size: u64) -> Result<Vec<BlockMsg>> {
-    let mut file = File::open(path)?;
-    let metadata = file.metadata()?;
-    let len = metadata.len();
-    let mut block_hashes = Vec::new();
-    let mut buffer = vec![0u8; block_size as usize];
+/// Random values the gear hash of content-defined chunking adds per byte
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // SplitMix64, so the table is the same on every build
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
 
-    // Calculate number of blocks
-    let num_blocks = len.div_ceil(block_size);
+/// Block size limits of content-defined chunking with a given average block size
+struct Chunker {
+    min_size: usize,
+    average_size: usize,
+    max_size: usize,
+    // Cut-point masks before and after the average size is reached; the stricter
+    // first one keeps block sizes close to the average
+    strict_mask: u64,
+    loose_mask: u64,
+}
+
+impl Chunker {
+    fn new(average_size: u64) -> Self {
+        let average_size = average_size.max(1) as usize;
+        let bits = average_size.ilog2();
+        // The high bits of the gear hash depend on the last 64 bytes
+        let mask = |bits: u32| match bits {
+            0 => 0,
+            bits => u64::MAX << (64 - bits.min(64)),
+        };
+        Self {
+            min_size: (average_size / 4).max(1),
+            average_size,
+            max_size: average_size * 4,
+            strict_mask: mask(bits + 1),
+            loose_mask: mask(bits.saturating_sub(1)),
+        }
+    }
 
-    for i in 0..num_blocks {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
+    /// Length of the block at the start of `data`
+    fn cut_point(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let average = self.average_size.min(end);
+        let mut hash: u64 = 0;
+        for (i, &byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < average {
+                self.strict_mask
+            } else {
+                self.loose_mask
+            };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+}
+
+/// Split a file into content-defined blocks averaging `average_size` bytes
+/// (FastCDC), with their SHA-256 hashes. Block boundaries depend only on the
+/// bytes around them, so an insert or a deletion changes the blocks it touches
+/// and leaves the rest of the file's blocks as they were, just shifted.
+pub fn calculate_block_hashes(path: &Path, average_size: u64) -> Result<Vec<BlockMsg>> {
+    read_block_hashes(File::open(path)?, average_size)
+}
+
+/// Like `calculate_block_hashes`, for the content `reader` reads
+pub fn read_block_hashes(reader: impl Read, average_size: u64) -> Result<Vec<BlockMsg>> {
+    let mut blocks = Vec::new();
+    split_blocks(reader, average_size, |block, _| {
+        blocks.push(block);
+        Ok(())
+    })?;
+    Ok(blocks)
+}
+
+/// Split the content `reader` reads into the blocks of `calculate_block_hashes`
+/// and pass each block to `visit` with its bytes
+pub fn split_blocks(
+    mut reader: impl Read,
+    average_size: u64,
+    mut visit: impl FnMut(BlockMsg, &[u8]) -> Result<()>,
+) -> Result<()> {
+    let chunker = Chunker::new(average_size);
+    let mut read_buffer = vec![0u8; HASH_BUFFER_SIZE];
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut end_of_file = false;
+    let mut index = 0u64;
+    let mut offset = 0u64;
+
+    loop {
+        // Keep a whole block of the largest size buffered
+        while !end_of_file && buffer.len() < chunker.max_size {
+            let bytes_read = reader.read(&mut read_buffer)?;
+            if bytes_read == 0 {
+                end_of_file = true;
+            }
+            buffer.extend_from_slice(&read_buffer[..bytes_read]);
+        }
+        if buffer.is_empty() {
             break;
         }
 
-        // Hash the actual bytes read (slice of buffer)
-        let mut hasher = Sha256::new();
-        hasher.update(&buffer[0..bytes_read]);
-        let hash = format!("{:x}", hasher.finalize());
+        let length = chunker.cut_point(&buffer);
+        let block = BlockMsg {
+            index,
+            hash: format!("{:x}", Sha256::digest(&buffer[..length])),
+            offset,
+            length: length as u64,
+        };
+        visit(block, &buffer[..length])?;
+        index += 1;
+        offset += length as u64;
+        buffer.drain(..length);
+    }
 
-        block_hashes.push(BlockMsg { index: i, hash });
+    Ok(())
+}
+
+/// Check that `blocks` cover a file of `size` bytes in order, without gaps or overlaps
+pub fn check_block_layout(blocks: &[BlockMsg], size: u64) -> bool {
+    let mut offset = 0;
+    for (index, block) in blocks.iter().enumerate() {
+        if block.index != index as u64 || block.offset != offset || block.length == 0 {
+            return false;
+        }
+        offset += block.length;
     }
+    offset == size
+}
 
-    Ok(block_hashes)
+/// Copy `length` bytes at `source_offset` of `source` to `target_offset` of `target`
+pub fn copy_range(
+    source: &mut (impl Read + Seek),
+    source_offset: u64,
+    target: &mut File,
+    target_offset: u64,
+    length: u64,
+) -> Result<()> {
+    source.seek(SeekFrom::Start(source_offset))?;
+    target.seek(SeekFrom::Start(target_offset))?;
+    let copied = std::io::copy(&mut source.take(length), target)?;
+    if copied != length {
+        return Err(anyhow::anyhow!(
+            "Expected {} bytes at offset {}, found {}",
+            length,
+            source_offset,
+            copied
+        ));
+    }
+    Ok(())
 }
 
 pub fn patch_file(path: &Path, offset: u64, content: &[u8]) -> Result<()> {
@@ -56,6 +255,102 @@ pub fn patch_file(path: &Path, offset: u64, content: &[u8]) -> Result<()> {
     Ok(())
 }
 
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A temporary file next to the file a sync write targets. The new content is
+/// written and flushed here, then renamed over the target in one step, so the
+/// target never holds a partial file. The temporary file is removed when
+/// dropped without being committed.
+pub struct TempFile {
+    path: PathBuf,
+    target: PathBuf,
+}
+
+impl TempFile {
+    /// A temporary file for writing the new content of `target`
+    pub fn new(target: &Path) -> Self {
+        let name = format!(
+            "{}{}-{}",
+            TEMP_FILE_PREFIX,
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        Self {
+            path: target.with_file_name(name),
+            target: target.to_path_buf(),
+        }
+    }
+
+    /// A temporary file for `target` holding `content`, flushed to disk
+    pub fn write(target: &Path, content: &[u8]) -> Result<Self> {
+        let temp = Self::new(target);
+        let mut file = File::create(&temp.path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        Ok(temp)
+    }
+
+    /// A temporary file for `target` holding a copy of `source`, flushed to disk
+    pub fn copy(target: &Path, source: &Path) -> Result<Self> {
+        let temp = Self::new(target);
+        fs::copy(source, &temp.path)?;
+        File::open(&temp.path)?.sync_all()?;
+        Ok(temp)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Rename the fully written temporary file over its target and make the
+    /// rename durable
+    pub fn commit(self) -> Result<()> {
+        replace_file(&self.path, &self.target)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Already gone once committed
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Rename `source` over `target` and make the rename durable
+pub fn replace_file(source: &Path, target: &Path) -> Result<()> {
+    fs::rename(source, target)?;
+    sync_parent_dir(target)
+}
+
+/// Flush the directory entry of `path`, so a rename to it survives a crash
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Remove the temporary files that interrupted sync writes left under `dir`.
+/// Returns how many were removed.
+pub fn remove_temp_files(dir: &Path) -> Result<usize> {
+    let mut removed = 0;
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file()
+            && entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(TEMP_FILE_PREFIX)
+        {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
 pub fn get_file_info(path: &Path, relative_path: &str) -> Result<FileInfo> {
     let metadata = fs::metadata(path)?;
     let hash = calculate_file_hash(path)?;
@@ -65,9 +360,73 @@ pub fn get_file_info(path: &Path, relative_path: &str) -> Result<FileInfo> {
         hash,
         size: metadata.len(),
         modified: metadata.modified()?.into(),
+        version: VersionVector::default(),
     })
 }
 
+/// The file metadata a cached hash is keyed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub size: u64,
+    pub mtime_ns: i64,
+    pub inode: u64,
+    pub ctime_ns: i64,
+}
+
+impl FileStat {
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+
+        Self {
+            size: metadata.len(),
+            mtime_ns: metadata.mtime() * 1_000_000_000 + metadata.mtime_nsec(),
+            inode: metadata.ino(),
+            ctime_ns: metadata.ctime() * 1_000_000_000 + metadata.ctime_nsec(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        // No inode or change time here; the creation time stands in for the latter
+        Self {
+            size: metadata.len(),
+            mtime_ns: metadata.modified().map(system_time_nanos).unwrap_or(0),
+            inode: 0,
+            ctime_ns: metadata.created().map(system_time_nanos).unwrap_or(0),
+        }
+    }
+}
+
+fn system_time_nanos(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_nanos() as i64,
+        Err(e) => -(e.duration().as_nanos() as i64),
+    }
+}
+
+fn load_hash_cache(conn: &Connection) -> Result<HashMap<String, (FileStat, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT file_path, file_size, mtime_ns, inode, ctime_ns, file_hash FROM file_hash_cache",
+    )?;
+    let entries = stmt.query_map([], |row| {
+        let stat = FileStat {
+            size: row.get(1)?,
+            mtime_ns: row.get(2)?,
+            inode: row.get::<_, i64>(3)? as u64,
+            ctime_ns: row.get(4)?,
+        };
+        Ok((row.get::<_, String>(0)?, (stat, row.get::<_, String>(5)?)))
+    })?;
+
+    let mut cache = HashMap::new();
+    for entry in entries {
+        let (path, cached) = entry?;
+        cache.insert(path, cached);
+    }
+    Ok(cache)
+}
+
 pub fn scan_directory(dir_path: &Path) -> Result<Vec<FileInfo>> {
     scan_directory_with_patterns(dir_path, &[])
 }
@@ -76,6 +435,111 @@ pub fn scan_directory_with_patterns(
     dir_path: &Path,
     exclude_patterns: &[String],
 ) -> Result<Vec<FileInfo>> {
+    collect_files(dir_path, exclude_patterns)?
+        .iter()
+        .map(|(path, relative_path)| get_file_info(path, relative_path))
+        .collect()
+}
+
+/// Like `scan_directory_with_patterns`, but reuses hashes cached in the state
+/// database at `db_path` for files whose size, mtime, inode and ctime are
+/// unchanged since they were last hashed. With `full_verification`, every file
+/// is rehashed and the cache is rebuilt.
+pub fn scan_directory_cached(
+    dir_path: &Path,
+    exclude_patterns: &[String],
+    db_path: &Path,
+    full_verification: bool,
+) -> Result<Vec<FileInfo>> {
+    let mut conn = init_state_database(db_path)?;
+    let mut cache = load_hash_cache(&conn)?;
+
+    // Files modified within the mtime granularity of the scan could change again
+    // without a visible stat change, so their hashes are not cached
+    let racy_after = system_time_nanos(SystemTime::now()) - RACY_WINDOW_NANOS;
+
+    let mut files = Vec::new();
+    let mut updated = Vec::new();
+    let mut rehashed = 0;
+    for (path, relative_path) in collect_files(dir_path, exclude_patterns)? {
+        let metadata = fs::metadata(&path)?;
+        let stat = FileStat::from_metadata(&metadata);
+        let cached = cache.remove(&relative_path);
+
+        let hash = match cached {
+            Some((cached_stat, hash)) if cached_stat == stat && !full_verification => hash,
+            cached => {
+                let hash = calculate_file_hash(&path)?;
+                rehashed += 1;
+                if let Some((cached_stat, cached_hash)) = cached {
+                    if cached_stat == stat && cached_hash != hash {
+                        warn!(
+                            "Cached hash of {} was stale although its metadata is unchanged",
+                            relative_path
+                        );
+                    }
+                }
+                let cacheable = stat.mtime_ns < racy_after;
+                updated.push((
+                    relative_path.clone(),
+                    cacheable.then_some(stat),
+                    hash.clone(),
+                ));
+                hash
+            }
+        };
+
+        files.push(FileInfo {
+            path: relative_path,
+            hash,
+            size: metadata.len(),
+            modified: metadata.modified()?.into(),
+            version: VersionVector::default(),
+        });
+    }
+
+    // Whatever is left in the cache no longer exists or is now excluded
+    let tx = conn.transaction()?;
+    for relative_path in cache.keys() {
+        tx.execute(
+            "DELETE FROM file_hash_cache WHERE file_path = ?",
+            params![relative_path],
+        )?;
+    }
+    for (relative_path, stat, hash) in &updated {
+        match stat {
+            Some(stat) => tx.execute(
+                "INSERT OR REPLACE INTO file_hash_cache
+                 (file_path, file_size, mtime_ns, inode, ctime_ns, file_hash)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+                params![
+                    relative_path,
+                    stat.size,
+                    stat.mtime_ns,
+                    stat.inode as i64,
+                    stat.ctime_ns,
+                    hash
+                ],
+            )?,
+            None => tx.execute(
+                "DELETE FROM file_hash_cache WHERE file_path = ?",
+                params![relative_path],
+            )?,
+        };
+    }
+    tx.commit()?;
+
+    debug!(
+        "Scanned {} files in {}, rehashed {}",
+        files.len(),
+        dir_path.display(),
+        rehashed
+    );
+    Ok(files)
+}
+
+/// Regular files under `dir_path` that aren't hidden or excluded, with their relative paths
+fn collect_files(dir_path: &Path, exclude_patterns: &[String]) -> Result<Vec<(PathBuf, String)>> {
     let mut files = Vec::new();
 
     // Compile exclude patterns once for efficiency
@@ -92,7 +556,13 @@ pub fn scan_directory_with_patterns(
         }
     };
 
-    for entry in WalkDir::new(dir_path).into_iter().filter_map(|e| e.ok()) {
+    let reserved_dir = dir_path.join(RESERVED_DIR_NAME);
+    for entry in WalkDir::new(dir_path)
+        .into_iter()
+        // Syncpair's own folder, with the local trash, is never synced
+        .filter_entry(|entry| entry.path() != reserved_dir)
+        .filter_map(|e| e.ok())
+    {
         if entry.file_type().is_file() {
             // Skip hidden files (files starting with .)
             if let Some(file_name) = entry.path().file_name() {
@@ -136,8 +606,7 @@ pub fn scan_directory_with_patterns(
                 continue;
             }
 
-            let file_info = get_file_info(entry.path(), &relative_path_str)?;
-            files.push(file_info);
+            files.push((entry.path().to_path_buf(), relative_path_str));
         }
     }
 
@@ -150,6 +619,7 @@ pub fn load_client_state(state_path: &Path) -> Result<ClientState> {
             files: std::collections::HashMap::new(),
             deleted_files: HashMap::new(),
             last_sync: Utc::now(),
+            deleted_versions: HashMap::new(),
         });
     }
 
@@ -160,6 +630,7 @@ pub fn load_client_state(state_path: &Path) -> Result<ClientState> {
             files: std::collections::HashMap::new(),
             deleted_files: HashMap::new(),
             last_sync: Utc::now(),
+            deleted_versions: HashMap::new(),
         }
     });
 
@@ -209,6 +680,102 @@ pub fn init_state_database(db_path: &Path) -> Result<Connection> {
         [],
     )?;
 
+    // Version vectors (JSON) of files and deletions, missing from older databases
+    add_column_if_missing(
+        &conn,
+        "file_states",
+        "version",
+        "TEXT NOT NULL DEFAULT '{}'",
+    )?;
+    add_column_if_missing(
+        &conn,
+        "deleted_files",
+        "version",
+        "TEXT NOT NULL DEFAULT '{}'",
+    )?;
+
+    // Identity of this replica in version vectors when it has no client ID
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS replica (
+            replica_id TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // ID of the folder this state belongs to, also kept in the folder's marker file
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS folder_identity (
+            folder_id TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Set while the mass-deletion guard holds the directory
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS deletion_pause (
+            deleted INTEGER NOT NULL,
+            tracked INTEGER NOT NULL,
+            paused_at TEXT NOT NULL,
+            confirmed INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Position in the server's change journal after the last sync
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sync_cursor (
+            journal_id TEXT NOT NULL,
+            seq INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Upload sessions of interrupted uploads, resumed by the next upload of the same content
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS upload_progress (
+            file_path TEXT PRIMARY KEY,
+            file_hash TEXT NOT NULL,
+            session_id TEXT NOT NULL,
+            received INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Interrupted downloads, whose partial files are kept in the reserved folder
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS download_progress (
+            file_path TEXT PRIMARY KEY,
+            file_hash TEXT NOT NULL,
+            received INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Conflicts whose losing version was kept as a conflict copy
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS conflicts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_path TEXT NOT NULL,
+            copy_path TEXT NOT NULL,
+            detected_at TEXT NOT NULL,
+            resolved_at TEXT
+        )",
+        [],
+    )?;
+
+    // Hashes of local files keyed on their metadata (see scan_directory_cached)
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS file_hash_cache (
+            file_path TEXT PRIMARY KEY,
+            file_size INTEGER NOT NULL,
+            mtime_ns INTEGER NOT NULL,
+            inode INTEGER NOT NULL,
+            ctime_ns INTEGER NOT NULL,
+            file_hash TEXT NOT NULL
+        )",
+        [],
+    )?;
+
     // Initialize sync_state if empty
     let count: i64 = conn.query_row("SELECT COUNT(*) FROM sync_state", [], |row| row.get(0))?;
 
@@ -222,6 +789,154 @@ pub fn init_state_database(db_path: &Path) -> Result<Connection> {
     Ok(conn)
 }
 
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if !columns.iter().any(|name| name == column) {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn parse_version(json: &str, column: usize) -> rusqlite::Result<VersionVector> {
+    serde_json::from_str(json).map_err(|_| rusqlite::Error::InvalidColumnIndex(column))
+}
+
+/// A version vector as the value of a `VERSION_HEADER`
+pub fn encode_version_header(version: &VersionVector) -> String {
+    let json = serde_json::to_string(version).unwrap_or_default();
+    urlencoding::encode(&json).into_owned()
+}
+
+pub fn decode_version_header(value: &str) -> Option<VersionVector> {
+    let json = urlencoding::decode(value).ok()?;
+    serde_json::from_str(&json).ok()
+}
+
+/// This replica's identity in version vectors, created on first use
+pub fn load_replica_id(db_path: &Path) -> Result<String> {
+    let conn = init_state_database(db_path)?;
+    let existing = conn
+        .query_row("SELECT replica_id FROM replica LIMIT 1", [], |row| {
+            row.get::<_, String>(0)
+        })
+        .optional()?;
+    if let Some(replica_id) = existing {
+        return Ok(replica_id);
+    }
+
+    let seed = format!(
+        "{}:{}:{}",
+        db_path.display(),
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    );
+    let replica_id = format!("replica-{}", &format!("{:x}", Sha256::digest(seed))[..16]);
+    conn.execute(
+        "INSERT INTO replica (replica_id) VALUES (?)",
+        params![replica_id],
+    )?;
+    Ok(replica_id)
+}
+
+/// The ID of the folder the state database belongs to, if one was recorded
+pub fn load_folder_id(db_path: &Path) -> Result<Option<String>> {
+    let conn = init_state_database(db_path)?;
+    let folder_id = conn
+        .query_row("SELECT folder_id FROM folder_identity LIMIT 1", [], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    Ok(folder_id)
+}
+
+pub fn save_folder_id(db_path: &Path, folder_id: &str) -> Result<()> {
+    let mut conn = init_state_database(db_path)?;
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM folder_identity", [])?;
+    tx.execute(
+        "INSERT INTO folder_identity (folder_id) VALUES (?)",
+        params![folder_id],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// The folder ID in the marker file of `dir`, if it has one
+pub fn read_folder_marker(dir: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(dir.join(RESERVED_DIR_NAME).join(FOLDER_ID_FILE)) {
+        Ok(folder_id) => Ok(Some(folder_id.trim().to_string())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Mark `dir` with a new folder ID and return it
+pub fn write_folder_marker(dir: &Path) -> Result<String> {
+    let seed = format!(
+        "{}:{}:{}",
+        dir.display(),
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    );
+    let folder_id = format!("folder-{}", &format!("{:x}", Sha256::digest(seed))[..16]);
+
+    let marker_dir = dir.join(RESERVED_DIR_NAME);
+    fs::create_dir_all(&marker_dir)?;
+    fs::write(marker_dir.join(FOLDER_ID_FILE), format!("{}\n", folder_id))?;
+    Ok(folder_id)
+}
+
+/// The database of folders synced before, kept outside them, so a folder that
+/// goes missing with its state database (an unmounted disk) is still noticed
+fn init_folder_registry(db_path: &Path) -> Result<Connection> {
+    if let Some(parent) = db_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let conn = Connection::open(db_path)?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS known_folders (
+            local_path TEXT PRIMARY KEY,
+            folder_id TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(conn)
+}
+
+/// The ID recorded in the folder registry at `db_path` for the folder at `dir`,
+/// if it was synced before
+pub fn load_known_folder(db_path: &Path, dir: &Path) -> Result<Option<String>> {
+    let conn = init_folder_registry(db_path)?;
+    let folder_id = conn
+        .query_row(
+            "SELECT folder_id FROM known_folders WHERE local_path = ?",
+            params![std::path::absolute(dir)?.to_string_lossy()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(folder_id)
+}
+
+pub fn save_known_folder(db_path: &Path, dir: &Path, folder_id: &str) -> Result<()> {
+    let conn = init_folder_registry(db_path)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO known_folders (local_path, folder_id) VALUES (?, ?)",
+        params![std::path::absolute(dir)?.to_string_lossy(), folder_id],
+    )?;
+    Ok(())
+}
+
 pub fn load_client_state_db(db_path: &Path) -> Result<ClientState> {
     let conn = init_state_database(db_path)?;
 
@@ -234,8 +949,8 @@ pub fn load_client_state_db(db_path: &Path) -> Result<ClientState> {
 
     // Load files
     let mut files = HashMap::new();
-    let mut stmt =
-        conn.prepare("SELECT file_path, file_hash, file_size, modified_at FROM file_states")?;
+    let mut stmt = conn
+        .prepare("SELECT file_path, file_hash, file_size, modified_at, version FROM file_states")?;
     let file_iter = stmt.query_map([], |row| {
         let modified_str: String = row.get(3)?;
         let modified = match DateTime::parse_from_rfc3339(&modified_str) {
@@ -248,6 +963,7 @@ pub fn load_client_state_db(db_path: &Path) -> Result<ClientState> {
             hash: row.get(1)?,
             size: row.get(2)?,
             modified,
+            version: parse_version(&row.get::<_, String>(4)?, 4)?,
         })
     })?;
 
@@ -258,7 +974,8 @@ pub fn load_client_state_db(db_path: &Path) -> Result<ClientState> {
 
     // Load deleted files
     let mut deleted_files = HashMap::new();
-    let mut stmt = conn.prepare("SELECT file_path, deleted_at FROM deleted_files")?;
+    let mut deleted_versions = HashMap::new();
+    let mut stmt = conn.prepare("SELECT file_path, deleted_at, version FROM deleted_files")?;
     let deleted_iter = stmt.query_map([], |row| {
         let deleted_at_str: String = row.get(1)?;
         let deleted_at = match DateTime::parse_from_rfc3339(&deleted_at_str) {
@@ -266,11 +983,15 @@ pub fn load_client_state_db(db_path: &Path) -> Result<ClientState> {
             Err(_) => return Err(rusqlite::Error::InvalidColumnIndex(1)),
         };
 
-        Ok((row.get::<_, String>(0)?, deleted_at))
+        let version = parse_version(&row.get::<_, String>(2)?, 2)?;
+        Ok((row.get::<_, String>(0)?, deleted_at, version))
     })?;
 
     for deleted_result in deleted_iter {
-        let (path, deleted_at) = deleted_result?;
+        let (path, deleted_at, version) = deleted_result?;
+        if !version.is_empty() {
+            deleted_versions.insert(path.clone(), version);
+        }
         deleted_files.insert(path, deleted_at);
     }
 
@@ -278,6 +999,7 @@ pub fn load_client_state_db(db_path: &Path) -> Result<ClientState> {
         files,
         deleted_files,
         last_sync,
+        deleted_versions,
     })
 }
 
@@ -299,8 +1021,14 @@ pub fn save_client_state_db(state: &ClientState, db_path: &Path) -> Result<()> {
     // Insert current file states
     for file_info in state.files.values() {
         tx.execute(
-            "INSERT INTO file_states (file_path, file_hash, file_size, modified_at) VALUES (?, ?, ?, ?)",
-            params![file_info.path, file_info.hash, file_info.size, file_info.modified.to_rfc3339()],
+            "INSERT INTO file_states (file_path, file_hash, file_size, modified_at, version) VALUES (?, ?, ?, ?, ?)",
+            params![
+                file_info.path,
+                file_info.hash,
+                file_info.size,
+                file_info.modified.to_rfc3339(),
+                serde_json::to_string(&file_info.version)?
+            ],
         )?;
     }
 
@@ -309,9 +1037,18 @@ pub fn save_client_state_db(state: &ClientState, db_path: &Path) -> Result<()> {
 
     // Insert current deleted files
     for (path, deleted_at) in &state.deleted_files {
+        let version = state
+            .deleted_versions
+            .get(path)
+            .cloned()
+            .unwrap_or_default();
         tx.execute(
-            "INSERT INTO deleted_files (file_path, deleted_at) VALUES (?, ?)",
-            params![path, deleted_at.to_rfc3339()],
+            "INSERT INTO deleted_files (file_path, deleted_at, version) VALUES (?, ?, ?)",
+            params![
+                path,
+                deleted_at.to_rfc3339(),
+                serde_json::to_string(&version)?
+            ],
         )?;
     }
 
@@ -320,3 +1057,428 @@ pub fn save_client_state_db(state: &ClientState, db_path: &Path) -> Result<()> {
 
     Ok(())
 }
+
+pub fn load_sync_cursor(db_path: &Path) -> Result<Option<SyncCursor>> {
+    let conn = init_state_database(db_path)?;
+    let cursor = conn
+        .query_row(
+            "SELECT journal_id, seq FROM sync_cursor LIMIT 1",
+            [],
+            |row| {
+                Ok(SyncCursor {
+                    journal_id: row.get(0)?,
+                    seq: row.get::<_, i64>(1)? as u64,
+                })
+            },
+        )
+        .optional()?;
+    Ok(cursor)
+}
+
+/// Store the cursor for the next incremental sync; None forces a full sync
+pub fn save_sync_cursor(db_path: &Path, cursor: Option<&SyncCursor>) -> Result<()> {
+    let mut conn = init_state_database(db_path)?;
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM sync_cursor", [])?;
+    if let Some(cursor) = cursor {
+        tx.execute(
+            "INSERT INTO sync_cursor (journal_id, seq) VALUES (?, ?)",
+            params![cursor.journal_id, cursor.seq as i64],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn load_deletion_pause(db_path: &Path) -> Result<Option<DeletionPause>> {
+    let conn = init_state_database(db_path)?;
+    let pause = conn
+        .query_row(
+            "SELECT deleted, tracked, paused_at, confirmed FROM deletion_pause LIMIT 1",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)? as usize,
+                    row.get::<_, i64>(1)? as usize,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, bool>(3)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    match pause {
+        Some((deleted, tracked, paused_at, confirmed)) => Ok(Some(DeletionPause {
+            deleted,
+            tracked,
+            paused_at: DateTime::parse_from_rfc3339(&paused_at)?.with_timezone(&Utc),
+            confirmed,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Store the mass-deletion pause of the directory; None resumes it
+pub fn save_deletion_pause(db_path: &Path, pause: Option<&DeletionPause>) -> Result<()> {
+    let mut conn = init_state_database(db_path)?;
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM deletion_pause", [])?;
+    if let Some(pause) = pause {
+        tx.execute(
+            "INSERT INTO deletion_pause (deleted, tracked, paused_at, confirmed) VALUES (?, ?, ?, ?)",
+            params![
+                pause.deleted as i64,
+                pause.tracked as i64,
+                pause.paused_at.to_rfc3339(),
+                pause.confirmed
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn load_upload_progress(db_path: &Path) -> Result<HashMap<String, UploadProgress>> {
+    let conn = init_state_database(db_path)?;
+    let mut stmt =
+        conn.prepare("SELECT file_path, file_hash, session_id, received FROM upload_progress")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            UploadProgress {
+                hash: row.get(1)?,
+                session_id: row.get(2)?,
+                received: row.get::<_, i64>(3)? as u64,
+            },
+        ))
+    })?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// Store the open upload session of `path`; None forgets it
+pub fn save_upload_progress(
+    db_path: &Path,
+    path: &str,
+    progress: Option<&UploadProgress>,
+) -> Result<()> {
+    let mut conn = init_state_database(db_path)?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM upload_progress WHERE file_path = ?",
+        params![path],
+    )?;
+    if let Some(progress) = progress {
+        tx.execute(
+            "INSERT INTO upload_progress (file_path, file_hash, session_id, received) VALUES (?, ?, ?, ?)",
+            params![
+                path,
+                progress.hash,
+                progress.session_id,
+                progress.received as i64
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn load_download_progress(db_path: &Path) -> Result<HashMap<String, DownloadProgress>> {
+    let conn = init_state_database(db_path)?;
+    let mut stmt = conn.prepare("SELECT file_path, file_hash, received FROM download_progress")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            DownloadProgress {
+                hash: row.get(1)?,
+                received: row.get::<_, i64>(2)? as u64,
+            },
+        ))
+    })?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+/// Store how far the download of `path` got; None forgets it
+pub fn save_download_progress(
+    db_path: &Path,
+    path: &str,
+    progress: Option<&DownloadProgress>,
+) -> Result<()> {
+    let mut conn = init_state_database(db_path)?;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM download_progress WHERE file_path = ?",
+        params![path],
+    )?;
+    if let Some(progress) = progress {
+        tx.execute(
+            "INSERT INTO download_progress (file_path, file_hash, received) VALUES (?, ?, ?)",
+            params![path, progress.hash, progress.received as i64],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Where the download of `path` into `dir` is written until it is complete. The
+/// partial file stays there between attempts, so an interrupted download can resume.
+pub fn partial_download_path(dir: &Path, path: &str) -> PathBuf {
+    let name = format!("{:x}", Sha256::digest(path.as_bytes()));
+    dir.join(RESERVED_DIR_NAME)
+        .join("downloads")
+        .join(&name[..32])
+}
+
+/// Remove the partial downloads under `dir` that no download progress in the
+/// state database refers to. Returns how many were removed.
+pub fn remove_stale_downloads(dir: &Path, db_path: &Path) -> Result<usize> {
+    let downloads_dir = dir.join(RESERVED_DIR_NAME).join("downloads");
+    let entries = match fs::read_dir(&downloads_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    let in_progress: Vec<PathBuf> = load_download_progress(db_path)?
+        .keys()
+        .map(|path| partial_download_path(dir, path))
+        .collect();
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if !in_progress.contains(&path) {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Add `range` to the sorted, non-overlapping `ranges`, merging it with the
+/// ranges it overlaps or touches
+pub fn add_byte_range(ranges: &mut Vec<ByteRange>, range: ByteRange) {
+    if range.start >= range.end {
+        return;
+    }
+    let mut merged = range;
+    ranges.retain(|existing| {
+        let joins = existing.start <= merged.end && merged.start <= existing.end;
+        if joins {
+            merged.start = merged.start.min(existing.start);
+            merged.end = merged.end.max(existing.end);
+        }
+        !joins
+    });
+    let position = ranges
+        .iter()
+        .position(|existing| existing.start > merged.start)
+        .unwrap_or(ranges.len());
+    ranges.insert(position, merged);
+}
+
+/// Bytes from the start of a file covered by `ranges`, where a transfer resumes
+pub fn received_prefix(ranges: &[ByteRange]) -> u64 {
+    ranges
+        .first()
+        .filter(|range| range.start == 0)
+        .map_or(0, |range| range.end)
+}
+
+/// Unused sibling path under `dir` for the losing version of `path` in a
+/// conflict, e.g. `docs/report (conflict from alice 2026-10-16 1412).docx`
+pub fn conflict_copy_path(dir: &Path, path: &str, origin: &str, when: DateTime<Local>) -> String {
+    // Client IDs look like "alice:docs"; only the first part names the device
+    let origin: String = origin
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '@' | ' '))
+        .collect();
+    let origin = if origin.trim().is_empty() {
+        "unknown"
+    } else {
+        origin.trim()
+    };
+
+    let (parent, file_name) = match path.rsplit_once('/') {
+        Some((parent, file_name)) => (Some(parent), file_name),
+        None => (None, path),
+    };
+    // Dotfiles like ".env" have no extension
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, Some(extension)),
+        _ => (file_name, None),
+    };
+
+    let label = format!("conflict from {} {}", origin, when.format("%Y-%m-%d %H%M"));
+    let mut attempt = 1;
+    loop {
+        // Several conflicts of the same file within a minute get numbered copies
+        let mut copy_name = match attempt {
+            1 => format!("{} ({})", stem, label),
+            n => format!("{} ({} {})", stem, label, n),
+        };
+        if let Some(extension) = extension {
+            copy_name.push('.');
+            copy_name.push_str(extension);
+        }
+        let copy_path = match parent {
+            Some(parent) => format!("{}/{}", parent, copy_name),
+            None => copy_name,
+        };
+        if !dir.join(&copy_path).exists() {
+            return copy_path;
+        }
+        attempt += 1;
+    }
+}
+
+/// Format of the `.syncpair/trash/<timestamp>` folders on clients
+const LOCAL_TRASH_TIMESTAMP: &str = "%Y-%m-%dT%H%M%SZ";
+
+/// Move the file or folder at `local_path`, known to sync as `path`, into the
+/// local trash of `dir` under a folder named after `when`. Returns where it went.
+pub fn move_to_local_trash(
+    dir: &Path,
+    local_path: &Path,
+    path: &str,
+    when: DateTime<Utc>,
+) -> Result<PathBuf> {
+    let trash_path = local_trash_path(dir, path, when)?;
+    fs::rename(local_path, &trash_path)?;
+    Ok(trash_path)
+}
+
+/// Like `move_to_local_trash` for a file, but leave it in place, so that it can
+/// be replaced in one rename afterwards. The trash gets a hard link to it, or a
+/// copy where the file system can't link.
+pub fn copy_to_local_trash(
+    dir: &Path,
+    local_path: &Path,
+    path: &str,
+    when: DateTime<Utc>,
+) -> Result<PathBuf> {
+    let trash_path = local_trash_path(dir, path, when)?;
+    if fs::hard_link(local_path, &trash_path).is_err() {
+        fs::copy(local_path, &trash_path)?;
+    }
+    Ok(trash_path)
+}
+
+/// A free place for `path` in the local trash of `dir`, in a folder named after `when`
+fn local_trash_path(dir: &Path, path: &str, when: DateTime<Utc>) -> Result<PathBuf> {
+    let trash_dir = dir.join(RESERVED_DIR_NAME).join("trash");
+    let timestamp = when.format(LOCAL_TRASH_TIMESTAMP).to_string();
+    let mut attempt = 1;
+    loop {
+        // The same path trashed twice within a second goes to a numbered folder
+        let folder = match attempt {
+            1 => timestamp.clone(),
+            n => format!("{}.{}", timestamp, n),
+        };
+        let trash_path = trash_dir.join(folder).join(path);
+        if !trash_path.exists() {
+            if let Some(parent) = trash_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            return Ok(trash_path);
+        }
+        attempt += 1;
+    }
+}
+
+/// Delete the local trash folders of `dir` older than `retention_days`.
+/// Returns how many were deleted.
+pub fn purge_local_trash(dir: &Path, retention_days: u32, now: DateTime<Utc>) -> Result<usize> {
+    let trash_dir = dir.join(RESERVED_DIR_NAME).join("trash");
+    let entries = match fs::read_dir(&trash_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    let cutoff = now - chrono::Duration::days(i64::from(retention_days));
+    let mut purged = 0;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Numbered folders share the timestamp of the first one
+        let timestamp = name.split('.').next().unwrap_or_default();
+        let Ok(trashed_at) = NaiveDateTime::parse_from_str(timestamp, LOCAL_TRASH_TIMESTAMP) else {
+            warn!("Ignoring unknown folder in local trash: {}", name);
+            continue;
+        };
+        if trashed_at.and_utc() < cutoff {
+            fs::remove_dir_all(entry.path())?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+pub fn record_conflict(db_path: &Path, conflict: &ConflictRecord) -> Result<()> {
+    let conn = init_state_database(db_path)?;
+    conn.execute(
+        "INSERT INTO conflicts (file_path, copy_path, detected_at, resolved_at) VALUES (?, ?, ?, ?)",
+        params![
+            conflict.path,
+            conflict.copy_path,
+            conflict.detected_at.to_rfc3339(),
+            conflict.resolved_at.map(|at| at.to_rfc3339())
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn load_conflicts(db_path: &Path) -> Result<Vec<ConflictRecord>> {
+    let conn = init_state_database(db_path)?;
+    let mut stmt = conn.prepare(
+        "SELECT file_path, copy_path, detected_at, resolved_at FROM conflicts ORDER BY id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, Option<String>>(3)?,
+        ))
+    })?;
+
+    let mut conflicts = Vec::new();
+    for row in rows {
+        let (path, copy_path, detected_at, resolved_at) = row?;
+        conflicts.push(ConflictRecord {
+            path,
+            copy_path,
+            detected_at: DateTime::parse_from_rfc3339(&detected_at)?.with_timezone(&Utc),
+            resolved_at: match resolved_at {
+                Some(at) => Some(DateTime::parse_from_rfc3339(&at)?.with_timezone(&Utc)),
+                None => None,
+            },
+        });
+    }
+    Ok(conflicts)
+}
+
+/// Mark conflicts whose copy no longer exists under `dir_path` as resolved
+pub fn resolve_removed_conflicts(db_path: &Path, dir_path: &Path) -> Result<usize> {
+    let conn = init_state_database(db_path)?;
+    let mut stmt = conn.prepare("SELECT id, copy_path FROM conflicts WHERE resolved_at IS NULL")?;
+    let open = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut resolved = 0;
+    for (id, copy_path) in open {
+        if !dir_path.join(&copy_path).exists() {
+            conn.execute(
+                "UPDATE conflicts SET resolved_at = ? WHERE id = ?",
+                params![Utc::now().to_rfc3339(), id],
+            )?;
+            resolved += 1;
+        }
+    }
+    Ok(resolved)
+}
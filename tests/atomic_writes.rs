@@ -0,0 +1,116 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use syncpair::server::SimpleServer;
+use syncpair::types::{FileInfo, UploadRequest, UploadResponse};
+use syncpair::utils::TempFile;
+
+#[path = "common/mod.rs"]
+mod common;
+
+/// Temporary files anywhere under `dir`
+fn temp_files(dir: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(".syncpair-tmp-")
+        })
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_failed_uploads_leave_the_stored_file_intact() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9042;
+    common::setup_server(port, storage.clone()).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let upload = |content: &str, claimed_hash: String| {
+        http.put(format!("{}/files/notes%2Fa.txt?directory=docs", base))
+            .header("x-syncpair-hash", claimed_hash)
+            .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+            .body(content.to_string())
+            .send()
+    };
+    let response: UploadResponse = upload("a", common::hash("a")).await?.json().await?;
+    assert!(response.success);
+
+    // A streamed upload that doesn't match its hash
+    let response: UploadResponse = upload("broken", common::hash("b")).await?.json().await?;
+    assert!(!response.success);
+
+    // A JSON upload that doesn't match its hash
+    let response: UploadResponse = http
+        .post(format!("{}/upload", base))
+        .json(&UploadRequest {
+            file_info: FileInfo {
+                path: "notes/a.txt".to_string(),
+                hash: common::hash("b"),
+                size: 6,
+                modified: chrono::Utc::now(),
+                version: Default::default(),
+            },
+            content: b"broken".to_vec(),
+            client_id: None,
+            directory: Some("docs".to_string()),
+            base_hash: None,
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(!response.success);
+
+    let response = http
+        .get(format!("{}/files/notes%2Fa.txt?directory=docs", base))
+        .send()
+        .await?;
+    assert_eq!(response.text().await?, "a");
+    assert!(temp_files(&storage).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_leftover_temp_files_are_removed_at_startup() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    std::fs::create_dir_all(storage.join("docs/notes"))?;
+    std::fs::write(storage.join("docs/notes/a.txt"), "a")?;
+    std::fs::write(storage.join("docs/notes/.syncpair-tmp-1-0"), "partial")?;
+
+    SimpleServer::new(storage.clone())?;
+    assert!(temp_files(&storage).is_empty());
+    assert_eq!(
+        std::fs::read_to_string(storage.join("docs/notes/a.txt"))?,
+        "a"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_temp_files_replace_their_target_only_when_committed() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let target = temp_dir.path().join("a.txt");
+    std::fs::write(&target, "old")?;
+
+    let temp = TempFile::write(&target, b"new")?;
+    assert_eq!(std::fs::read_to_string(&target)?, "old");
+    drop(temp);
+    assert_eq!(std::fs::read_to_string(&target)?, "old");
+    assert!(temp_files(temp_dir.path()).is_empty());
+
+    TempFile::write(&target, b"new")?.commit()?;
+    assert_eq!(std::fs::read_to_string(&target)?, "new");
+    assert!(temp_files(temp_dir.path()).is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,364 @@
+use anyhow::Result;
+use serde_json::json;
+use std::path::PathBuf;
+use syncpair::client::SimpleClient;
+use syncpair::server::SimpleServer;
+use syncpair::types::{DeltaInitResponse, ServerConfig, UploadSessionResponse};
+use syncpair::utils::calculate_block_hashes;
+
+#[path = "common/mod.rs"]
+mod common;
+
+const SERVER_CONFIG: &str = r#"
+auth:
+  identities:
+    - name: alice
+      tokens: ["alice-token"]
+      permissions:
+        - directory: "alice:*"
+          access: admin
+        - directory: "team_*"
+          access: write
+    - name: bob
+      tokens: ["bob-token"]
+      permissions:
+        - directory: "bob:*"
+          access: admin
+        - directory: "team_*"
+          access: read
+    - name: carol
+      tokens: ["carol-token"]
+      permissions:
+        - directory: "team_*"
+          access: write
+"#;
+
+async fn setup_server(port: u16, storage_dir: PathBuf) -> Result<()> {
+    let config: ServerConfig = serde_yaml::from_str(SERVER_CONFIG)?;
+    common::start_server(port, SimpleServer::from_config(storage_dir, &config)?).await
+}
+
+fn upload_body(directory: &str, path: &str, content: &[u8]) -> serde_json::Value {
+    json!({
+        "file_info": {
+            "path": path,
+            "hash": common::hash(content),
+            "size": content.len(),
+            "modified": chrono::Utc::now(),
+        },
+        "content": content.to_vec(),
+        "directory": directory,
+    })
+}
+
+#[tokio::test]
+async fn test_requests_without_valid_token_are_rejected() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9011;
+    setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    for token in [None, Some("wrong-token")] {
+        let with_token = |request: reqwest::RequestBuilder| match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+
+        let response = with_token(http.post(format!("{}/upload", base)))
+            .json(&upload_body("team_docs", "a.txt", b"hello"))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 401);
+
+        let response = with_token(http.post(format!("{}/sync", base)))
+            .json(&json!({
+                "files": {},
+                "deleted_files": {},
+                "last_sync": chrono::Utc::now(),
+                "directory": "team_docs",
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 401);
+
+        let response = with_token(http.get(format!("{}/download/a.txt?directory=team_docs", base)))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 401);
+
+        let response = with_token(http.post(format!("{}/delete", base)))
+            .json(&json!({ "path": "a.txt", "directory": "team_docs" }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 401);
+
+        let response = with_token(http.post(format!("{}/delta/upload", base)))
+            .json(&json!({
+                "session_id": "unknown",
+                "path": "a.txt",
+                "directory": "team_docs",
+                "index": 0,
+                "content": [1, 2, 3],
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 401);
+    }
+
+    // Basic auth is not a bearer token
+    let response = http
+        .post(format!("{}/upload", base))
+        .basic_auth("alice", Some("alice-token"))
+        .json(&upload_body("team_docs", "a.txt", b"hello"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 401);
+
+    assert!(!temp_dir.path().join("storage/team_docs/a.txt").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_directory_permissions_are_enforced() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9012;
+    setup_server(port, storage.clone()).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    // Alice can write to shared and her own private directories
+    for directory in ["team_docs", "alice:notes"] {
+        let response = http
+            .post(format!("{}/upload", base))
+            .bearer_auth("alice-token")
+            .json(&upload_body(directory, "plan.txt", b"alice's plan"))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200, "alice upload to {}", directory);
+    }
+
+    // Bob may read the shared directory but not write to it
+    let response = http
+        .get(format!("{}/download/plan.txt?directory=team_docs", base))
+        .bearer_auth("bob-token")
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+
+    let response = http
+        .post(format!("{}/upload", base))
+        .bearer_auth("bob-token")
+        .json(&upload_body("team_docs", "plan.txt", b"bob's plan"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 403);
+
+    let response = http
+        .post(format!("{}/delete", base))
+        .bearer_auth("bob-token")
+        .json(&json!({ "path": "plan.txt", "directory": "team_docs" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 403);
+
+    // A read-only sync can't delete anything on the server
+    let response = http
+        .post(format!("{}/sync", base))
+        .bearer_auth("bob-token")
+        .json(&json!({
+            "files": {},
+            "deleted_files": { "plan.txt": chrono::Utc::now() },
+            "last_sync": chrono::Utc::now(),
+            "directory": "team_docs",
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+
+    // Bob can't touch Alice's private namespace at all
+    let response = http
+        .get(format!(
+            "{}/download/plan.txt?directory={}",
+            base,
+            urlencoding::encode("alice:notes")
+        ))
+        .bearer_auth("bob-token")
+        .send()
+        .await?;
+    assert_eq!(response.status(), 403);
+
+    let response = http
+        .post(format!("{}/upload", base))
+        .bearer_auth("bob-token")
+        .json(&upload_body("alice:notes", "plan.txt", b"overwritten"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 403);
+
+    assert_eq!(
+        std::fs::read_to_string(storage.join("team_docs/plan.txt"))?,
+        "alice's plan"
+    );
+    assert_eq!(
+        std::fs::read_to_string(storage.join("alice:notes/plan.txt"))?,
+        "alice's plan"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_sends_token() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9013;
+    setup_server(port, storage).await?;
+    let server_url = format!("http://localhost:{}", port);
+
+    let alice = SimpleClient::new(server_url.clone(), client_a_dir.clone())
+        .with_directory("team_docs".to_string())
+        .with_token("alice-token".to_string());
+    let bob = SimpleClient::new(server_url.clone(), client_b_dir.clone())
+        .with_directory("team_docs".to_string())
+        .with_token("bob-token".to_string());
+    let anonymous =
+        SimpleClient::new(server_url, client_b_dir.clone()).with_directory("team_docs".to_string());
+
+    std::fs::write(client_a_dir.join("notes.txt"), "from alice")?;
+    alice.initial_sync().await?;
+
+    let error = anonymous
+        .initial_sync()
+        .await
+        .expect_err("anonymous sync must fail");
+    assert!(error.to_string().contains("401"), "{}", error);
+
+    bob.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("notes.txt"))?,
+        "from alice"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sessions_belong_to_the_identity_that_opened_them() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9073;
+    setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+    // Alice opens an upload session in a directory carol can write to as well
+    let content = b"alice's content";
+    let session: UploadSessionResponse = http
+        .post(format!("{}/uploads", base))
+        .bearer_auth("alice-token")
+        .json(&json!({
+            "file_info": {
+                "path": "a.txt",
+                "hash": common::hash(content),
+                "size": content.len(),
+                "modified": chrono::Utc::now(),
+            },
+            "directory": "team_docs",
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let session_id = session.session_id.expect("a session should be opened");
+    let session_url = format!("{}/uploads/{}?directory=team_docs", base, session_id);
+
+    let response = http
+        .put(&session_url)
+        .bearer_auth("carol-token")
+        .header("x-syncpair-offset", 0)
+        .body(b"carol's content".to_vec())
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+    let response = http
+        .get(&session_url)
+        .bearer_auth("carol-token")
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+
+    let response: UploadSessionResponse = http
+        .put(&session_url)
+        .bearer_auth("alice-token")
+        .header("x-syncpair-offset", 0)
+        .body(content.to_vec())
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(response.completed, "{}", response.message);
+
+    // Likewise for delta sessions
+    let new_content = b"alice's new content";
+    let local_file = temp_dir.path().join("a.txt");
+    std::fs::write(&local_file, new_content)?;
+    let init: DeltaInitResponse = http
+        .post(format!("{}/delta/init", base))
+        .bearer_auth("alice-token")
+        .json(&json!({
+            "file_info": {
+                "path": "a.txt",
+                "hash": common::hash(new_content),
+                "size": new_content.len(),
+                "modified": chrono::Utc::now(),
+            },
+            "block_hashes": calculate_block_hashes(&local_file, 1024)?,
+            "block_size": 1024,
+            "directory": "team_docs",
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let session_id = init.session_id.expect("delta init should open a session");
+
+    let response = http
+        .post(format!("{}/delta/upload", base))
+        .bearer_auth("carol-token")
+        .json(&json!({
+            "session_id": session_id,
+            "path": "a.txt",
+            "directory": "team_docs",
+            "index": 0,
+            "content": new_content.to_vec(),
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+    let response = http
+        .post(format!("{}/delta/complete", base))
+        .bearer_auth("carol-token")
+        .json(&json!({
+            "session_id": session_id,
+            "path": "a.txt",
+            "directory": "team_docs",
+            "expected_hash": common::hash(new_content),
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+    assert_eq!(
+        std::fs::read(temp_dir.path().join("storage/team_docs/a.txt"))?,
+        content
+    );
+
+    Ok(())
+}
@@ -1,7 +1,13 @@
+#![allow(dead_code)]
+
 use anyhow::Result;
+use sha2::{Digest, Sha256};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use syncpair::server::SimpleServer;
 use tempfile::TempDir;
+use tokio::time::sleep;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 /// Create a temporary directory for testing
@@ -17,6 +23,62 @@ pub fn init_test_logging() {
         .try_init();
 }
 
+/// Run `server` on `port` in the background, giving it a moment to start listening
+pub async fn start_server(port: u16, server: SimpleServer) -> Result<()> {
+    tokio::spawn(async move {
+        if let Err(e) = server.start(port).await {
+            eprintln!("Server error: {}", e);
+        }
+    });
+
+    sleep(Duration::from_millis(100)).await;
+    Ok(())
+}
+
+/// Run a server with the default settings storing its files in `storage_dir`
+pub async fn setup_server(port: u16, storage_dir: PathBuf) -> Result<()> {
+    start_server(port, SimpleServer::new(storage_dir)?).await
+}
+
+/// SHA-256 of `content` as hex, the form file hashes take on the wire
+pub fn hash(content: impl AsRef<[u8]>) -> String {
+    format!("{:x}", Sha256::digest(content.as_ref()))
+}
+
+/// Store `content` at `path` in `directory` through the raw upload endpoint
+pub async fn upload(
+    http: &reqwest::Client,
+    base: &str,
+    directory: &str,
+    path: &str,
+    content: impl AsRef<[u8]>,
+) -> Result<()> {
+    let content = content.as_ref().to_vec();
+    let response = http
+        .put(format!("{}/files/{}?directory={}", base, path, directory))
+        .header("x-syncpair-hash", hash(&content))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(content)
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200, "upload of {} failed", path);
+    Ok(())
+}
+
+/// Pseudo-random bytes, so content-defined chunk boundaries fall as they would
+/// in real files
+pub fn random_content(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
 /// Create test directory with sample files for exclude pattern testing
 pub fn create_exclude_pattern_test_dir(base_path: &Path) -> Result<()> {
     let test_dir = base_path.join("test_exclude_demo");
@@ -0,0 +1,451 @@
+use anyhow::Result;
+use chrono::TimeZone;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+use syncpair::client::SimpleClient;
+use syncpair::types::{
+    ConflictPolicy, DeleteRequest, DeltaInitRequest, FileInfo, SyncRequest, SyncResponse,
+    VersionVector, WriteConflictResponse,
+};
+use syncpair::utils::conflict_copy_path;
+use tokio::time::sleep;
+
+#[path = "common/mod.rs"]
+mod common;
+
+fn client(port: u16, dir: &Path, name: &str) -> SimpleClient {
+    SimpleClient::new(format!("http://localhost:{}", port), dir.to_path_buf())
+        .with_directory("docs".to_string())
+        .with_client_id(format!("{}:docs", name))
+}
+
+/// Files in `dir` whose name marks them as conflict copies
+fn conflict_copies(dir: &Path) -> Result<Vec<String>> {
+    let mut copies = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.contains("(conflict from ") {
+            copies.push(name);
+        }
+    }
+    copies.sort();
+    Ok(copies)
+}
+
+#[test]
+fn test_conflict_copy_names() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let when = chrono::Local
+        .with_ymd_and_hms(2026, 10, 16, 14, 12, 30)
+        .unwrap();
+
+    assert_eq!(
+        conflict_copy_path(temp_dir.path(), "report.docx", "alice", when),
+        "report (conflict from alice 2026-10-16 1412).docx"
+    );
+    // Only the device part of a client ID is used, and never as a path
+    assert_eq!(
+        conflict_copy_path(temp_dir.path(), "a/b/notes.tar.gz", "al/ice:docs", when),
+        "a/b/notes.tar (conflict from alice 2026-10-16 1412).gz"
+    );
+    assert_eq!(
+        conflict_copy_path(temp_dir.path(), ".env", "", when),
+        ".env (conflict from unknown 2026-10-16 1412)"
+    );
+
+    // Existing copies are never overwritten
+    std::fs::write(
+        temp_dir
+            .path()
+            .join("report (conflict from alice 2026-10-16 1412).docx"),
+        "first",
+    )?;
+    assert_eq!(
+        conflict_copy_path(temp_dir.path(), "report.docx", "alice", when),
+        "report (conflict from alice 2026-10-16 1412 2).docx"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_losing_version_is_kept_as_conflict_copy() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9025;
+    common::setup_server(port, storage.clone()).await?;
+    let client_a = client(port, &client_a_dir, "alice");
+    let client_b = client(port, &client_b_dir, "bob");
+
+    std::fs::write(client_a_dir.join("notes.txt"), "v1")?;
+    std::fs::write(client_a_dir.join("todo.txt"), "todo")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    // Both edit the same file; alice's later edit wins
+    std::fs::write(client_b_dir.join("notes.txt"), "bob's edit")?;
+    sleep(Duration::from_millis(20)).await;
+    std::fs::write(client_a_dir.join("notes.txt"), "alice's edit")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("notes.txt"))?,
+        "alice's edit"
+    );
+    let copies = conflict_copies(&client_b_dir)?;
+    assert_eq!(copies.len(), 1);
+    assert!(copies[0].starts_with("notes (conflict from bob "));
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join(&copies[0]))?,
+        "bob's edit"
+    );
+
+    // The copy reaches the server and the other client
+    assert!(storage.join("docs").join(&copies[0]).exists());
+    client_a.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(client_a_dir.join(&copies[0]))?,
+        "bob's edit"
+    );
+
+    // An edit of a file deleted elsewhere is kept the same way
+    std::fs::write(client_b_dir.join("todo.txt"), "more todo")?;
+    sleep(Duration::from_millis(20)).await;
+    std::fs::remove_file(client_a_dir.join("todo.txt"))?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert!(!client_b_dir.join("todo.txt").exists());
+    let copies = conflict_copies(&client_b_dir)?;
+    let todo_copy = copies
+        .iter()
+        .find(|name| name.starts_with("todo (conflict from bob "))
+        .expect("conflict copy of todo.txt");
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join(todo_copy))?,
+        "more todo"
+    );
+
+    // Conflicts are recorded until their copy is removed
+    let conflicts = client_b.conflicts()?;
+    assert_eq!(conflicts.len(), 2);
+    assert_eq!(conflicts[0].path, "notes.txt");
+    assert_eq!(conflicts[1].path, "todo.txt");
+    assert_eq!(&conflicts[1].copy_path, todo_copy);
+    assert!(conflicts.iter().all(|c| c.resolved_at.is_none()));
+
+    std::fs::remove_file(client_b_dir.join(&conflicts[0].copy_path))?;
+    client_b.initial_sync().await?;
+    let conflicts = client_b.conflicts()?;
+    assert!(conflicts[0].resolved_at.is_some());
+    assert!(conflicts[1].resolved_at.is_none());
+    assert!(client_a.conflicts()?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_newest_wins_policy_discards_losing_version() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9026;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let client_a = client(port, &client_a_dir, "alice");
+    let client_b =
+        client(port, &client_b_dir, "bob").with_conflict_policy(ConflictPolicy::NewestWins);
+
+    std::fs::write(client_a_dir.join("notes.txt"), "v1")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    std::fs::write(client_b_dir.join("notes.txt"), "bob's edit")?;
+    sleep(Duration::from_millis(20)).await;
+    std::fs::write(client_a_dir.join("notes.txt"), "alice's edit")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("notes.txt"))?,
+        "alice's edit"
+    );
+    assert!(conflict_copies(&client_b_dir)?.is_empty());
+    assert!(client_b.conflicts()?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deletion_of_remotely_edited_file_is_settled() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9069;
+    common::setup_server(port, storage.clone()).await?;
+    let client_a = client(port, &client_a_dir, "alice");
+    let client_b = client(port, &client_b_dir, "bob");
+
+    std::fs::write(client_a_dir.join("notes.txt"), "v1")?;
+    std::fs::write(client_a_dir.join("todo.txt"), "todo")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    // Alice deletes the version bob has since replaced, so his edit comes back
+    std::fs::write(client_b_dir.join("notes.txt"), "bob's edit")?;
+    client_b.initial_sync().await?;
+    std::fs::remove_file(client_a_dir.join("notes.txt"))?;
+    client_a.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(storage.join("docs/notes.txt"))?,
+        "bob's edit"
+    );
+    assert_eq!(
+        std::fs::read_to_string(client_a_dir.join("notes.txt"))?,
+        "bob's edit"
+    );
+    client_b.initial_sync().await?;
+    assert!(client_b_dir.join("notes.txt").exists());
+
+    // Under newest-wins the deletion, being the latest change, goes through
+    let client_a = client_a.with_conflict_policy(ConflictPolicy::NewestWins);
+    std::fs::write(client_b_dir.join("todo.txt"), "bob's todo")?;
+    client_b.initial_sync().await?;
+    std::fs::remove_file(client_a_dir.join("todo.txt"))?;
+    client_a.initial_sync().await?;
+    assert!(!storage.join("docs/todo.txt").exists());
+    assert!(!client_a_dir.join("todo.txt").exists());
+    client_b.initial_sync().await?;
+    assert!(!client_b_dir.join("todo.txt").exists());
+
+    // A deletion carried by a sync request is checked the same way, even with a
+    // version that has seen every edit so far
+    let version: VersionVector =
+        serde_json::from_str(r#"{"alice:docs": 100, "bob:docs": 100, "carol:docs": 1}"#)?;
+    let http = reqwest::Client::new();
+    let response: SyncResponse = http
+        .post(format!("http://localhost:{}/sync", port))
+        .json(&SyncRequest {
+            files: Default::default(),
+            deleted_files: HashMap::from([("notes.txt".to_string(), chrono::Utc::now())]),
+            deleted_versions: HashMap::from([("notes.txt".to_string(), version)]),
+            deleted_base_hashes: HashMap::from([("notes.txt".to_string(), common::hash("v1"))]),
+            last_sync: chrono::Utc::now(),
+            client_id: Some("carol:docs".to_string()),
+            directory: Some("docs".to_string()),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(storage.join("docs/notes.txt").exists());
+    assert!(response
+        .files_to_download
+        .iter()
+        .any(|file| file.path == "notes.txt"));
+
+    Ok(())
+}
+
+async fn upload(
+    http: &reqwest::Client,
+    base: &str,
+    content: &str,
+    base_hash: Option<&str>,
+) -> Result<reqwest::Response> {
+    let mut request = http
+        .put(format!("{}/files/a.txt?directory=docs", base))
+        .header("x-syncpair-hash", common::hash(content))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(content.to_string());
+    if let Some(base_hash) = base_hash {
+        request = request.header("x-syncpair-base-hash", base_hash);
+    }
+    Ok(request.send().await?)
+}
+
+async fn delete(
+    http: &reqwest::Client,
+    base: &str,
+    base_hash: Option<&str>,
+) -> Result<reqwest::Response> {
+    let request = DeleteRequest {
+        path: "a.txt".to_string(),
+        client_id: None,
+        directory: Some("docs".to_string()),
+        base_hash: base_hash.map(str::to_string),
+        version: Default::default(),
+    };
+    Ok(http
+        .post(format!("{}/delete", base))
+        .json(&request)
+        .send()
+        .await?)
+}
+
+#[tokio::test]
+async fn test_writes_with_stale_base_are_rejected() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9027;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    // An empty base creates a file that must not exist yet
+    assert_eq!(upload(&http, &base, "one", Some("")).await?.status(), 200);
+    let response = upload(&http, &base, "two", Some("")).await?;
+    assert_eq!(response.status(), 409);
+    let conflict: WriteConflictResponse = response.json().await?;
+    assert!(!conflict.success);
+    assert_eq!(conflict.path, "a.txt");
+    assert_eq!(conflict.current.unwrap().hash, common::hash("one"));
+
+    assert_eq!(
+        upload(&http, &base, "two", Some(&common::hash("one")))
+            .await?
+            .status(),
+        200
+    );
+    // Repeating a write that already happened is not a conflict
+    assert_eq!(
+        upload(&http, &base, "two", Some(&common::hash("one")))
+            .await?
+            .status(),
+        200
+    );
+
+    // Delta uploads are checked before any block is patched
+    let response = http
+        .post(format!("{}/delta/init", base))
+        .json(&DeltaInitRequest {
+            file_info: FileInfo {
+                path: "a.txt".to_string(),
+                hash: common::hash("three"),
+                size: 5,
+                modified: chrono::Utc::now(),
+                version: Default::default(),
+            },
+            block_hashes: vec![],
+            block_size: 1024 * 1024,
+            client_id: None,
+            directory: Some("docs".to_string()),
+            base_hash: Some(common::hash("one")),
+        })
+        .send()
+        .await?;
+    assert_eq!(response.status(), 409);
+
+    assert_eq!(
+        delete(&http, &base, Some(&common::hash("one")))
+            .await?
+            .status(),
+        409
+    );
+    assert_eq!(
+        delete(&http, &base, Some(&common::hash("two")))
+            .await?
+            .status(),
+        200
+    );
+
+    // Writes without a base stay unconditional
+    assert_eq!(upload(&http, &base, "four", None).await?.status(), 200);
+    assert_eq!(upload(&http, &base, "five", None).await?.status(), 200);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_concurrent_writes_from_the_same_base_conflict() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9066;
+    common::setup_server(port, storage.clone()).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+    assert_eq!(upload(&http, &base, "one", None).await?.status(), 200);
+
+    // Large enough for the two uploads to be in flight at once
+    let first = format!("{}first", "x".repeat(8 * 1024 * 1024));
+    let second = format!("{}second", "x".repeat(8 * 1024 * 1024));
+    let base_hash = common::hash("one");
+    let (first_response, second_response) = tokio::join!(
+        upload(&http, &base, &first, Some(&base_hash)),
+        upload(&http, &base, &second, Some(&base_hash)),
+    );
+    let mut statuses = vec![
+        first_response?.status().as_u16(),
+        second_response?.status().as_u16(),
+    ];
+    statuses.sort();
+    assert_eq!(statuses, vec![200, 409]);
+
+    // The write that won is the stored file
+    let stored = std::fs::read_to_string(storage.join("docs/a.txt"))?;
+    assert!(stored == first || stored == second);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upload_from_stale_base_keeps_conflict_copy() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9028;
+    common::setup_server(port, storage.clone()).await?;
+    let client_a = client(port, &client_a_dir, "alice");
+    let client_b = client(port, &client_b_dir, "bob");
+
+    std::fs::write(client_a_dir.join("notes.txt"), "v1")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    // Bob's edit is newer, but alice's reached the server first
+    std::fs::write(client_a_dir.join("notes.txt"), "alice's edit")?;
+    sleep(Duration::from_millis(20)).await;
+    std::fs::write(client_b_dir.join("notes.txt"), "bob's edit")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    assert_eq!(
+        std::fs::read_to_string(storage.join("docs/notes.txt"))?,
+        "alice's edit"
+    );
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("notes.txt"))?,
+        "alice's edit"
+    );
+    let copies = conflict_copies(&client_b_dir)?;
+    assert_eq!(copies.len(), 1);
+    assert!(copies[0].starts_with("notes (conflict from bob "));
+    assert_eq!(
+        std::fs::read_to_string(storage.join("docs").join(&copies[0]))?,
+        "bob's edit"
+    );
+    assert_eq!(client_b.conflicts()?[0].copy_path, copies[0]);
+
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use syncpair::chunk_store::ChunkStore;
+use syncpair::server::SimpleServer;
+use syncpair::storage::LocalStorage;
+use syncpair::types::{
+    DedupConfig, DeleteRequest, DeltaCompleteRequest, DeltaCompleteResponse, DeltaInitRequest,
+    DeltaInitResponse, FileInfo, HistoryConfig, ServerConfig, TrashConfig, VersionListResponse,
+};
+use syncpair::utils::calculate_block_hashes;
+
+#[path = "common/mod.rs"]
+mod common;
+
+const DEDUP_SERVER_CONFIG: &str = r#"
+dedup:
+  enabled: true
+"#;
+
+/// The chunk files in the store of the server storage at `storage`
+fn chunk_files(storage: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(storage.join(".syncpair/chunks"))
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Start a delta upload of the file at `local_file` to `path` in `directory`
+async fn delta_init(
+    http: &reqwest::Client,
+    base: &str,
+    directory: &str,
+    path: &str,
+    local_file: &Path,
+) -> Result<DeltaInitResponse> {
+    let content = std::fs::read(local_file)?;
+    Ok(http
+        .post(format!("{}/delta/init", base))
+        .json(&DeltaInitRequest {
+            file_info: FileInfo {
+                path: path.to_string(),
+                hash: common::hash(&content),
+                size: content.len() as u64,
+                modified: chrono::Utc::now(),
+                version: Default::default(),
+            },
+            block_hashes: calculate_block_hashes(local_file, 1024 * 1024)?,
+            block_size: 1024 * 1024,
+            client_id: None,
+            directory: Some(directory.to_string()),
+            base_hash: None,
+        })
+        .send()
+        .await?
+        .json()
+        .await?)
+}
+
+async fn delete(http: &reqwest::Client, base: &str, path: &str) -> Result<()> {
+    let response = http
+        .post(format!("{}/delete", base))
+        .json(&DeleteRequest {
+            path: path.to_string(),
+            client_id: None,
+            directory: Some("docs".to_string()),
+            base_hash: None,
+            version: Default::default(),
+        })
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_identical_content_is_stored_once() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let config: ServerConfig = serde_yaml::from_str(DEDUP_SERVER_CONFIG)?;
+    let port = 9050;
+    common::start_server(port, SimpleServer::from_config(storage.clone(), &config)?).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let content = common::random_content(3 * 1024 * 1024, 1);
+    common::upload(&http, &base, "docs", "a.bin", &content).await?;
+    let chunks = chunk_files(&storage).len();
+    assert!(chunks > 1, "{} chunks", chunks);
+    // The stored file is only a manifest listing its chunks
+    assert!(!storage.join("docs/a.bin").exists());
+    assert!(std::fs::metadata(storage.join(".syncpair/manifests/docs/a.bin"))?.len() < 4096);
+
+    let response = http
+        .get(format!("{}/files/a.bin?directory=docs", base))
+        .send()
+        .await?;
+    assert_eq!(response.bytes().await?, content);
+    // Ranges span chunks
+    let response = http
+        .get(format!("{}/files/a.bin?directory=docs", base))
+        .header("range", "bytes=1000000-2500000")
+        .send()
+        .await?;
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.bytes().await?, &content[1000000..=2500000]);
+
+    // Only chunks the directory itself has are reused: another directory has to
+    // send the content, which is then still stored once
+    let local_file = temp_dir.path().join("b.bin");
+    std::fs::write(&local_file, &content)?;
+    let init = delta_init(&http, &base, "photos", "copies/b.bin", &local_file).await?;
+    assert!(init.should_full_upload);
+    assert!(init.session_id.is_none());
+    common::upload(&http, &base, "photos", "b.bin", &content).await?;
+    assert_eq!(chunk_files(&storage).len(), chunks);
+
+    // Content stored after the directory's chunks were looked up is found too
+    let init = delta_init(&http, &base, "photos", "copies/b.bin", &local_file).await?;
+    assert!(!init.should_full_upload);
+    assert!(init.missing_block_indices.is_empty());
+
+    // Uploading the same content within the directory transfers no block
+    let init = delta_init(&http, &base, "docs", "copies/b.bin", &local_file).await?;
+    assert!(!init.should_full_upload);
+    assert!(init.missing_block_indices.is_empty());
+    let response: DeltaCompleteResponse = http
+        .post(format!("{}/delta/complete", base))
+        .json(&DeltaCompleteRequest {
+            session_id: Some(init.session_id.expect("delta init should open a session")),
+            path: "copies/b.bin".to_string(),
+            directory: Some("docs".to_string()),
+            client_id: None,
+            expected_hash: common::hash(&content),
+            version: Default::default(),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(response.success, "{}", response.message);
+
+    assert_eq!(chunk_files(&storage).len(), chunks);
+    let response = http
+        .get(format!("{}/files/copies%2Fb.bin?directory=docs", base))
+        .send()
+        .await?;
+    assert_eq!(response.bytes().await?, content);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unreferenced_chunks_are_collected() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let server = SimpleServer::new(storage.clone())?
+        .with_history(&HistoryConfig {
+            enabled: false,
+            ..Default::default()
+        })
+        .with_trash(&TrashConfig {
+            enabled: false,
+            ..Default::default()
+        })
+        .with_dedup(&DedupConfig {
+            enabled: true,
+            average_chunk_size: 4096,
+        })?;
+    let port = 9051;
+    common::start_server(port, server.clone()).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let content = common::random_content(64 * 1024, 2);
+    common::upload(&http, &base, "docs", "a.bin", &content).await?;
+    common::upload(&http, &base, "docs", "b.bin", &content).await?;
+    let chunks = chunk_files(&storage).len();
+    let counts = ChunkStore::reference_counts(
+        &LocalStorage::new(&storage),
+        &["docs/a.bin".to_string(), "docs/b.bin".to_string()],
+    )?;
+    assert_eq!(counts.len(), chunks);
+    assert!(counts.values().all(|count| *count == 2));
+
+    // Chunks another file still references stay
+    delete(&http, &base, "a.bin").await?;
+    assert_eq!(server.collect_chunk_garbage()?, 0);
+    assert_eq!(server.collect_chunk_garbage()?, 0);
+    assert_eq!(chunk_files(&storage).len(), chunks);
+
+    // Unreferenced chunks go once a second collection still finds them unreferenced
+    delete(&http, &base, "b.bin").await?;
+    assert_eq!(server.collect_chunk_garbage()?, 0);
+    assert_eq!(server.collect_chunk_garbage()?, chunks);
+    assert!(chunk_files(&storage).is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deduplicated_files_stay_readable_with_dedup_off() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let config: ServerConfig = serde_yaml::from_str(DEDUP_SERVER_CONFIG)?;
+    let port = 9052;
+    common::start_server(port, SimpleServer::from_config(storage.clone(), &config)?).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+    common::upload(&http, &base, "docs", "a.txt", b"first").await?;
+    common::upload(&http, &base, "docs", "a.txt", b"second").await?;
+
+    // The same storage served without deduplication
+    let port = 9053;
+    common::start_server(port, SimpleServer::new(storage.clone())?).await?;
+    let base = format!("http://localhost:{}", port);
+    common::upload(&http, &base, "docs", "b.txt", b"plain").await?;
+    assert_eq!(std::fs::read(storage.join("docs/b.txt"))?, b"plain");
+
+    let response = http
+        .get(format!("{}/files/a.txt?directory=docs", base))
+        .send()
+        .await?;
+    assert_eq!(response.bytes().await?, &b"second"[..]);
+    let versions: VersionListResponse = http
+        .get(format!("{}/versions/a.txt?directory=docs", base))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let response = http
+        .get(format!(
+            "{}/versions/a.txt/{}?directory=docs",
+            base, versions.versions[0].id
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.bytes().await?, &b"first"[..]);
+
+    // An uploaded file that looks like a manifest is only ever its own content
+    let crafted = serde_json::to_vec(&serde_json::json!({
+        "hash": common::hash(b"second"),
+        "size": 6,
+        "chunks": [{ "hash": common::hash(b"second"), "length": 6 }],
+    }))?;
+    common::upload(&http, &base, "other", "c.txt", &crafted).await?;
+    let response = http
+        .get(format!("{}/files/c.txt?directory=other", base))
+        .send()
+        .await?;
+    assert_eq!(response.bytes().await?, crafted);
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use syncpair::client::SimpleClient;
+
+#[path = "common/mod.rs"]
+mod common;
+
+fn file_count(dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        if name.to_string_lossy().starts_with("file") {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[tokio::test]
+async fn test_mass_deletion_pauses_until_confirmed() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9039;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client = |dir: &PathBuf, id: &str| {
+        SimpleClient::new(server_url.clone(), dir.clone())
+            .with_directory("docs".to_string())
+            .with_client_id(format!("{}:docs", id))
+            .with_trash_retention_days(0)
+    };
+    let client_a = client(&client_a_dir, "alice");
+    let client_b = client(&client_b_dir, "bob");
+
+    for i in 0..20 {
+        std::fs::write(client_a_dir.join(format!("file{}.txt", i)), i.to_string())?;
+    }
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert_eq!(file_count(&client_b_dir)?, 20);
+
+    // A few deletions sync as usual
+    std::fs::remove_file(client_a_dir.join("file0.txt"))?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert_eq!(file_count(&client_b_dir)?, 19);
+    assert!(client_a.deletion_pause()?.is_none());
+
+    // Deleting most files pauses the directory
+    for i in 1..16 {
+        std::fs::remove_file(client_a_dir.join(format!("file{}.txt", i)))?;
+    }
+    client_a.initial_sync().await?;
+    let pause = client_a
+        .deletion_pause()?
+        .expect("directory should be paused");
+    assert_eq!((pause.deleted, pause.tracked), (15, 19));
+    assert!(!pause.confirmed);
+    client_b.initial_sync().await?;
+    assert_eq!(file_count(&client_b_dir)?, 19);
+
+    // Putting the files back resumes sync
+    for i in 1..16 {
+        std::fs::write(client_a_dir.join(format!("file{}.txt", i)), i.to_string())?;
+    }
+    client_a.initial_sync().await?;
+    assert!(client_a.deletion_pause()?.is_none());
+
+    // Confirmed deletions go through on the next sync
+    for i in 1..16 {
+        std::fs::remove_file(client_a_dir.join(format!("file{}.txt", i)))?;
+    }
+    client_a.initial_sync().await?;
+    assert!(client_a.deletion_pause()?.is_some());
+    assert!(client_a
+        .confirm_deletions()?
+        .is_some_and(|pause| pause.confirmed));
+    client_a.initial_sync().await?;
+    assert!(client_a.deletion_pause()?.is_none());
+    client_b.initial_sync().await?;
+    assert_eq!(file_count(&client_b_dir)?, 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deletion_guard_file_limit() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_dir = temp_dir.path().join("client");
+    std::fs::create_dir_all(&client_dir)?;
+
+    let port = 9040;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let client = SimpleClient::new(format!("http://localhost:{}", port), client_dir.clone())
+        .with_directory("docs".to_string())
+        .with_client_id("alice:docs".to_string())
+        .with_deletion_guard(0, 3);
+
+    for i in 0..10 {
+        std::fs::write(client_dir.join(format!("file{}.txt", i)), i.to_string())?;
+    }
+    client.initial_sync().await?;
+
+    for i in 0..3 {
+        std::fs::remove_file(client_dir.join(format!("file{}.txt", i)))?;
+    }
+    client.initial_sync().await?;
+    assert!(client.deletion_pause()?.is_none());
+
+    for i in 3..7 {
+        std::fs::remove_file(client_dir.join(format!("file{}.txt", i)))?;
+    }
+    client.initial_sync().await?;
+    let pause = client
+        .deletion_pause()?
+        .expect("directory should be paused");
+    assert_eq!((pause.deleted, pause.tracked), (4, 7));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_confirmed_deletions_keep_remote_edits() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9068;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client = |dir: &PathBuf, id: &str| {
+        SimpleClient::new(server_url.clone(), dir.clone())
+            .with_directory("docs".to_string())
+            .with_client_id(format!("{}:docs", id))
+            .with_trash_retention_days(0)
+    };
+    let client_a = client(&client_a_dir, "alice");
+    let client_b = client(&client_b_dir, "bob");
+
+    for i in 0..20 {
+        std::fs::write(client_a_dir.join(format!("file{}.txt", i)), i.to_string())?;
+    }
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    // Alice deletes most files, and bob edits one of them while she is paused
+    for i in 0..15 {
+        std::fs::remove_file(client_a_dir.join(format!("file{}.txt", i)))?;
+    }
+    client_a.initial_sync().await?;
+    assert!(client_a.deletion_pause()?.is_some());
+    std::fs::write(client_b_dir.join("file3.txt"), "bob's edit")?;
+    client_b.initial_sync().await?;
+
+    // The confirmed deletions were based on the old version, so bob's edit stays
+    client_a.confirm_deletions()?;
+    client_a.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(client_a_dir.join("file3.txt"))?,
+        "bob's edit"
+    );
+    client_b.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("file3.txt"))?,
+        "bob's edit"
+    );
+    assert_eq!(file_count(&client_b_dir)?, 6);
+
+    Ok(())
+}
@@ -0,0 +1,235 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use syncpair::client::SimpleClient;
+use syncpair::types::{
+    BlockUploadRequest, BlockUploadResponse, DeltaCompleteRequest, DeltaCompleteResponse,
+    DeltaDownloadRequest, DeltaDownloadResponse, DeltaInitRequest, DeltaInitResponse, FileInfo,
+};
+use syncpair::utils::calculate_block_hashes;
+
+#[path = "common/mod.rs"]
+mod common;
+
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+#[tokio::test]
+async fn test_clients_download_only_changed_blocks() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9047;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client = |dir: &PathBuf, id: &str| {
+        SimpleClient::new(server_url.clone(), dir.clone())
+            .with_directory("docs".to_string())
+            .with_client_id(format!("{}:docs", id))
+    };
+    let client_a = client(&client_a_dir, "alice");
+    let client_b = client(&client_b_dir, "bob");
+
+    let mut content = common::random_content(8 * BLOCK_SIZE, 1);
+    std::fs::write(client_a_dir.join("big.bin"), &content)?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert_eq!(std::fs::read(client_b_dir.join("big.bin"))?, content);
+
+    // Change a byte in the middle and grow the file by half a block
+    content[4 * BLOCK_SIZE + 10] ^= 0xff;
+    content.extend(common::random_content(BLOCK_SIZE / 2, 2));
+    std::fs::write(client_a_dir.join("big.bin"), &content)?;
+    client_a.initial_sync().await?;
+
+    // The server lists what bob's copy lacks
+    let response: DeltaDownloadResponse = reqwest::Client::new()
+        .post(format!("{}/delta/download", server_url))
+        .json(&DeltaDownloadRequest {
+            path: "big.bin".to_string(),
+            directory: Some("docs".to_string()),
+            block_hashes: calculate_block_hashes(&client_b_dir.join("big.bin"), BLOCK_SIZE as u64)?,
+            block_size: BLOCK_SIZE as u64,
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(response.success, "{}", response.message);
+    assert!(!response.should_full_download);
+    let missing = response.missing_block_indices.len();
+    assert!(missing > 0 && missing <= 4, "{} blocks missing", missing);
+    assert_eq!(
+        response.file_info.map(|file_info| file_info.size),
+        Some(content.len() as u64)
+    );
+
+    client_b.initial_sync().await?;
+    assert_eq!(std::fs::read(client_b_dir.join("big.bin"))?, content);
+
+    // Shrinking works the same way
+    content.truncate(6 * BLOCK_SIZE + 100);
+    std::fs::write(client_a_dir.join("big.bin"), &content)?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert_eq!(std::fs::read(client_b_dir.join("big.bin"))?, content);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_downloads_honor_bounded_range_requests() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9048;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let content = "0123456789";
+    http.put(format!("{}/files/a.txt?directory=docs", base))
+        .header("x-syncpair-hash", common::hash(content))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(content)
+        .send()
+        .await?;
+
+    let ranged = |range: &'static str| {
+        http.get(format!("{}/files/a.txt?directory=docs", base))
+            .header("range", range)
+            .send()
+    };
+    let response = ranged("bytes=2-4").await?;
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.headers()["content-range"], "bytes 2-4/10");
+    assert_eq!(response.text().await?, "234");
+
+    // The end is capped at the end of the file
+    let response = ranged("bytes=8-20").await?;
+    assert_eq!(response.headers()["content-range"], "bytes 8-9/10");
+    assert_eq!(response.text().await?, "89");
+
+    // Malformed ranges are ignored
+    let response = ranged("bytes=4-2").await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await?, content);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shifted_content_reuses_blocks() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9049;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+    let block_size = 4096;
+
+    let old_content = common::random_content(512 * 1024, 3);
+    http.put(format!("{}/files/a.bin?directory=docs", base))
+        .header("x-syncpair-hash", common::hash(&old_content))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(old_content.clone())
+        .send()
+        .await?;
+
+    // A byte inserted near the start shifts everything after it
+    let mut new_content = old_content.clone();
+    new_content.insert(100, 0x42);
+    let new_file = temp_dir.path().join("new.bin");
+    std::fs::write(&new_file, &new_content)?;
+    let blocks = calculate_block_hashes(&new_file, block_size)?;
+
+    // Uploading the shifted version sends only the blocks around the insert
+    let init: DeltaInitResponse = http
+        .post(format!("{}/delta/init", base))
+        .json(&DeltaInitRequest {
+            file_info: FileInfo {
+                path: "a.bin".to_string(),
+                hash: common::hash(&new_content),
+                size: new_content.len() as u64,
+                modified: chrono::Utc::now(),
+                version: Default::default(),
+            },
+            block_hashes: blocks.clone(),
+            block_size,
+            client_id: None,
+            directory: Some("docs".to_string()),
+            base_hash: Some(common::hash(&old_content)),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    let session_id = init.session_id.expect("delta init should open a session");
+    assert!(
+        init.missing_block_indices.len() <= 2,
+        "{} of {} blocks missing",
+        init.missing_block_indices.len(),
+        blocks.len()
+    );
+    for index in init.missing_block_indices {
+        let block = &blocks[index as usize];
+        let response: BlockUploadResponse = http
+            .post(format!("{}/delta/upload", base))
+            .json(&BlockUploadRequest {
+                session_id: Some(session_id.clone()),
+                path: "a.bin".to_string(),
+                directory: "docs".to_string(),
+                index,
+                content: new_content[block.offset as usize..(block.offset + block.length) as usize]
+                    .to_vec(),
+                client_id: None,
+            })
+            .send()
+            .await?
+            .json()
+            .await?;
+        assert!(response.success, "{}", response.message);
+    }
+    let response: DeltaCompleteResponse = http
+        .post(format!("{}/delta/complete", base))
+        .json(&DeltaCompleteRequest {
+            session_id: Some(session_id),
+            path: "a.bin".to_string(),
+            directory: Some("docs".to_string()),
+            client_id: None,
+            expected_hash: common::hash(&new_content),
+            version: Default::default(),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(response.success, "{}", response.message);
+
+    // A client with the old version needs only the blocks around the insert too
+    let old_file = temp_dir.path().join("old.bin");
+    std::fs::write(&old_file, &old_content)?;
+    let response: DeltaDownloadResponse = http
+        .post(format!("{}/delta/download", base))
+        .json(&DeltaDownloadRequest {
+            path: "a.bin".to_string(),
+            directory: Some("docs".to_string()),
+            block_hashes: calculate_block_hashes(&old_file, block_size)?,
+            block_size,
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(response.success, "{}", response.message);
+    assert_eq!(response.blocks, blocks);
+    assert!(
+        response.missing_block_indices.len() <= 2,
+        "{} of {} blocks missing",
+        response.missing_block_indices.len(),
+        blocks.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use anyhow::Result;
+use syncpair::types::{
+    BlockUploadRequest, BlockUploadResponse, DeltaCompleteRequest, DeltaCompleteResponse,
+    DeltaInitRequest, DeltaInitResponse, FileInfo,
+};
+use syncpair::utils::calculate_block_hashes;
+
+#[path = "common/mod.rs"]
+mod common;
+
+async fn download(http: &reqwest::Client, base: &str) -> Result<String> {
+    let response = http
+        .get(format!("{}/files/a.txt?directory=docs", base))
+        .send()
+        .await?;
+    Ok(response.text().await?)
+}
+
+async fn upload_block(
+    http: &reqwest::Client,
+    base: &str,
+    session_id: &str,
+    index: u64,
+    content: &str,
+) -> Result<reqwest::Response> {
+    Ok(http
+        .put(format!(
+            "{}/delta/{}/blocks/{}?directory=docs",
+            base, session_id, index
+        ))
+        .header("content-type", "application/octet-stream")
+        .header("x-syncpair-path", "a.txt")
+        .body(content.to_string())
+        .send()
+        .await?)
+}
+
+/// Upload a block the way clients from before raw block bodies do
+async fn upload_json_block(
+    http: &reqwest::Client,
+    base: &str,
+    session_id: &str,
+    index: u64,
+    content: &str,
+) -> Result<reqwest::Response> {
+    Ok(http
+        .post(format!("{}/delta/upload", base))
+        .json(&BlockUploadRequest {
+            session_id: Some(session_id.to_string()),
+            path: "a.txt".to_string(),
+            directory: "docs".to_string(),
+            index,
+            content: content.as_bytes().to_vec(),
+            client_id: None,
+        })
+        .send()
+        .await?)
+}
+
+async fn complete(
+    http: &reqwest::Client,
+    base: &str,
+    session_id: &str,
+    content: &str,
+) -> Result<reqwest::Response> {
+    Ok(http
+        .post(format!("{}/delta/complete", base))
+        .json(&DeltaCompleteRequest {
+            session_id: Some(session_id.to_string()),
+            path: "a.txt".to_string(),
+            directory: Some("docs".to_string()),
+            client_id: None,
+            expected_hash: common::hash(content),
+            version: Default::default(),
+        })
+        .send()
+        .await?)
+}
+
+#[tokio::test]
+async fn test_delta_blocks_are_staged_until_completion() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9043;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let response = http
+        .put(format!("{}/files/a.txt?directory=docs", base))
+        .header("x-syncpair-hash", common::hash("aaaabbbbcccc"))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body("aaaabbbbcccc")
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+
+    // The new version changes the middle and drops the end
+    let new_content = "aaaaXXXXcc";
+    let new_file = temp_dir.path().join("new.txt");
+    std::fs::write(&new_file, new_content)?;
+    let blocks = calculate_block_hashes(&new_file, 4)?;
+    let init: DeltaInitResponse = http
+        .post(format!("{}/delta/init", base))
+        .json(&DeltaInitRequest {
+            file_info: FileInfo {
+                path: "a.txt".to_string(),
+                hash: common::hash(new_content),
+                size: new_content.len() as u64,
+                modified: chrono::Utc::now(),
+                version: Default::default(),
+            },
+            block_hashes: blocks.clone(),
+            block_size: 4,
+            client_id: None,
+            directory: Some("docs".to_string()),
+            base_hash: Some(common::hash("aaaabbbbcccc")),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(!init.should_full_upload);
+    assert!(!init.missing_block_indices.is_empty());
+    let session_id = init.session_id.expect("delta init should open a session");
+
+    // Blocks past the last one, or of the wrong length, are refused
+    let response = upload_block(&http, &base, &session_id, blocks.len() as u64, "cccc").await?;
+    assert_eq!(response.status(), 400);
+    let too_long = format!("{}X", &new_content[..blocks[0].length as usize]);
+    let response = upload_block(&http, &base, &session_id, 0, &too_long).await?;
+    assert_eq!(response.status(), 400);
+    let response = upload_json_block(&http, &base, &session_id, 0, &too_long).await?;
+    assert_eq!(response.status(), 400);
+
+    // Raw blocks name their file in a header
+    let response = http
+        .put(format!(
+            "{}/delta/{}/blocks/0?directory=docs",
+            base, session_id
+        ))
+        .body(new_content[..blocks[0].length as usize].to_string())
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    // Blocks can come as raw bodies or, from older clients, as JSON
+    for (position, index) in init.missing_block_indices.into_iter().enumerate() {
+        let block = &blocks[index as usize];
+        let content = &new_content[block.offset as usize..(block.offset + block.length) as usize];
+        let response = if position == 0 {
+            upload_json_block(&http, &base, &session_id, index, content).await?
+        } else {
+            upload_block(&http, &base, &session_id, index, content).await?
+        };
+        let response: BlockUploadResponse = response.json().await?;
+        assert!(response.success, "{}", response.message);
+    }
+    // Readers keep seeing the stored version until the session completes
+    assert_eq!(download(&http, &base).await?, "aaaabbbbcccc");
+
+    let response: DeltaCompleteResponse = complete(&http, &base, &session_id, new_content)
+        .await?
+        .json()
+        .await?;
+    assert!(response.success, "{}", response.message);
+    assert_eq!(download(&http, &base).await?, new_content);
+
+    // A completed session is closed
+    let response = upload_block(&http, &base, &session_id, 0, "YYYY").await?;
+    assert_eq!(response.status(), 404);
+    let response = complete(&http, &base, &session_id, new_content).await?;
+    assert_eq!(response.status(), 404);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delta_requests_without_a_session_are_rejected() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9071;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    // Clients from before delta sessions upload blocks without one
+    let response = http
+        .post(format!("{}/delta/upload", base))
+        .json(&serde_json::json!({
+            "path": "a.bin",
+            "directory": "docs",
+            "index": 0,
+            "content": [1, 2, 3],
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+    assert!(response.text().await?.contains("session_id"));
+
+    let response = http
+        .post(format!("{}/delta/complete", base))
+        .json(&serde_json::json!({
+            "path": "a.bin",
+            "directory": "docs",
+            "expected_hash": common::hash("abc"),
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+    assert!(response.text().await?.contains("session_id"));
+
+    Ok(())
+}
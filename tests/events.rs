@@ -0,0 +1,129 @@
+use anyhow::Result;
+use futures::StreamExt;
+use std::time::Duration;
+use syncpair::client::SimpleClient;
+use syncpair::types::{ChangeEvent, ChangeKind};
+use tokio::sync::broadcast;
+use tokio::time::{sleep, timeout, Instant};
+
+#[path = "common/mod.rs"]
+mod common;
+
+/// Read `count` change events from a server-sent event stream
+async fn next_changes(
+    body: &mut (impl futures::Stream<Item = reqwest::Result<warp::hyper::body::Bytes>> + Unpin),
+    count: usize,
+) -> Result<Vec<ChangeEvent>> {
+    let mut text = String::new();
+    let mut events = Vec::new();
+    while events.len() < count {
+        let chunk = timeout(Duration::from_secs(5), body.next())
+            .await?
+            .expect("event stream ended")?;
+        text.push_str(std::str::from_utf8(&chunk)?);
+
+        while let Some(end) = text.find("\n\n") {
+            let block: String = text.drain(..end + 2).collect();
+            if block.lines().any(|line| line == "event:change") {
+                let data = block
+                    .lines()
+                    .find_map(|line| line.strip_prefix("data:"))
+                    .unwrap();
+                events.push(serde_json::from_str(data)?);
+            }
+        }
+    }
+    Ok(events)
+}
+
+#[tokio::test]
+async fn test_change_events_stream() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9023;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let response = http
+        .get(format!("{}/events?directory=docs", base))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let mut body = response.bytes_stream();
+
+    // Changes in other directories are not announced
+    for (directory, content) in [("other", "elsewhere"), ("docs", "hello")] {
+        let response = http
+            .put(format!("{}/files/a.txt?directory={}", base, directory))
+            .header("x-syncpair-hash", common::hash(content))
+            .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+            .body(content)
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200);
+    }
+    let response = http
+        .post(format!("{}/delete", base))
+        .json(&serde_json::json!({ "path": "a.txt", "directory": "docs" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+
+    let events = next_changes(&mut body, 2).await?;
+    assert_eq!(events[0].path, "a.txt");
+    assert_eq!(events[0].kind, ChangeKind::Modified);
+    assert_eq!(events[1].path, "a.txt");
+    assert_eq!(events[1].kind, ChangeKind::Deleted);
+    assert_eq!(events[1].cursor.journal_id, events[0].cursor.journal_id);
+    assert!(events[1].cursor.seq > events[0].cursor.seq);
+
+    // A directory is required
+    let response = http.get(format!("{}/events", base)).send().await?;
+    assert_eq!(response.status(), 400);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_pulls_on_notification() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9024;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client_a = SimpleClient::new(server_url.clone(), client_a_dir.clone())
+        .with_directory("docs".to_string());
+    // Far too slow for polling to deliver the change within the test
+    let client_b = SimpleClient::new(server_url.clone(), client_b_dir.clone())
+        .with_directory("docs".to_string())
+        .with_sync_interval(Duration::from_secs(300));
+
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let watcher = tokio::spawn(async move {
+        client_b
+            .start_watching_with_shutdown(Some(shutdown_rx))
+            .await
+    });
+    sleep(Duration::from_millis(500)).await;
+
+    std::fs::write(client_a_dir.join("pushed.txt"), "pushed")?;
+    client_a.initial_sync().await?;
+
+    let target = client_b_dir.join("pushed.txt");
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !target.exists() && Instant::now() < deadline {
+        sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(std::fs::read_to_string(&target)?, "pushed");
+
+    shutdown_tx.send(())?;
+    watcher.await??;
+    Ok(())
+}
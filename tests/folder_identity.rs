@@ -0,0 +1,138 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use syncpair::client::SimpleClient;
+use syncpair::types::error::FolderIdentityError;
+
+#[path = "common/mod.rs"]
+mod common;
+
+#[tokio::test]
+async fn test_client_refuses_to_sync_an_unmarked_or_foreign_folder() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9041;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client = |dir: &PathBuf, id: &str| {
+        SimpleClient::new(server_url.clone(), dir.clone())
+            .with_directory("docs".to_string())
+            .with_client_id(format!("{}:docs", id))
+    };
+    let client_a = client(&client_a_dir, "alice");
+    let client_b = client(&client_b_dir, "bob");
+
+    std::fs::write(client_a_dir.join("a.txt"), "a")?;
+    std::fs::write(client_a_dir.join("b.txt"), "b")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    let marker = client_a_dir.join(".syncpair/folder-id");
+    let folder_id = std::fs::read_to_string(&marker)?;
+    assert!(folder_id.starts_with("folder-"));
+    // Every folder gets its own ID
+    assert_ne!(
+        std::fs::read_to_string(client_b_dir.join(".syncpair/folder-id"))?,
+        folder_id
+    );
+
+    // The folder was swapped for another one, leaving the state database behind
+    std::fs::remove_dir_all(client_a_dir.join(".syncpair"))?;
+    std::fs::remove_file(client_a_dir.join("a.txt"))?;
+    std::fs::remove_file(client_a_dir.join("b.txt"))?;
+    let error = client_a.initial_sync().await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<FolderIdentityError>(),
+        Some(FolderIdentityError::MissingMarker(_))
+    ));
+    std::fs::create_dir_all(client_a_dir.join(".syncpair"))?;
+    std::fs::write(&marker, "folder-0000000000000000\n")?;
+    let error = client_a.initial_sync().await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<FolderIdentityError>(),
+        Some(FolderIdentityError::Mismatch { .. })
+    ));
+
+    // Nothing was deleted on the server or the other client
+    client_b.initial_sync().await?;
+    assert!(client_b_dir.join("a.txt").exists());
+    assert!(client_b_dir.join("b.txt").exists());
+
+    // With the right folder back, sync resumes
+    std::fs::write(&marker, &folder_id)?;
+    std::fs::write(client_a_dir.join("a.txt"), "a")?;
+    std::fs::write(client_a_dir.join("b.txt"), "b")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert!(client_b_dir.join("a.txt").exists());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_refuses_to_sync_an_unmounted_folder() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    // The synced folder is on a disk mounted at `mnt`; the registry is elsewhere
+    let mount_point = temp_dir.path().join("mnt");
+    let client_a_dir = mount_point.join("docs");
+    let client_b_dir = temp_dir.path().join("client_b");
+    let registry = temp_dir.path().join("state/known_folders.db");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9065;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client = |dir: &PathBuf, id: &str| {
+        SimpleClient::new(server_url.clone(), dir.clone())
+            .with_directory("docs".to_string())
+            .with_client_id(format!("{}:docs", id))
+            .with_folder_registry(registry.clone())
+    };
+    let client_a = client(&client_a_dir, "alice");
+    let client_b = client(&client_b_dir, "bob");
+
+    std::fs::write(client_a_dir.join("a.txt"), "a")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert!(client_b_dir.join("a.txt").exists());
+
+    // Unmounted, the folder is gone along with its marker and state database
+    let disk = temp_dir.path().join("disk");
+    std::fs::rename(&mount_point, &disk)?;
+    let error = client_a.initial_sync().await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<FolderIdentityError>(),
+        Some(FolderIdentityError::MissingFolder(_))
+    ));
+    let error = client_a.start_watching().await.unwrap_err();
+    assert!(error.is::<FolderIdentityError>());
+    assert!(!client_a_dir.exists());
+
+    // An empty mount point is neither marked nor given a new state database
+    std::fs::create_dir_all(&client_a_dir)?;
+    let error = client_a.initial_sync().await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<FolderIdentityError>(),
+        Some(FolderIdentityError::MissingMarker(_))
+    ));
+    assert_eq!(std::fs::read_dir(&client_a_dir)?.count(), 0);
+
+    // Nothing was deleted on the server or the other client
+    client_b.initial_sync().await?;
+    assert!(client_b_dir.join("a.txt").exists());
+
+    // Mounted again, sync resumes
+    std::fs::remove_dir_all(&mount_point)?;
+    std::fs::rename(&disk, &mount_point)?;
+    std::fs::write(client_a_dir.join("b.txt"), "b")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert!(client_b_dir.join("b.txt").exists());
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use syncpair::types::FileInfo;
+use syncpair::utils::{
+    calculate_file_hash, calculate_file_hash_with, get_file_info, scan_directory_cached, HashMethod,
+};
+
+#[path = "common/mod.rs"]
+mod common;
+
+const MB: u64 = 1024 * 1024;
+const GB: u64 = 1024 * MB;
+
+/// Peak resident set size of this process in KB, where the platform reports it
+fn peak_rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find(|line| line.starts_with("VmHWM:"))?
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+/// Create a sparse file of `size` bytes with short markers written at `markers` offsets
+fn create_sparse_file(path: &Path, size: u64, markers: &[(u64, &[u8])]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    file.set_len(size)?;
+    for (offset, data) in markers {
+        file.seek(SeekFrom::Start(*offset))?;
+        file.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Hash the same layout in memory, one zero-filled chunk at a time
+fn expected_sparse_hash(size: u64, markers: &[(u64, &[u8])]) -> String {
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0u8; MB as usize];
+    let mut offset = 0;
+    while offset < size {
+        let len = MB.min(size - offset);
+        chunk.iter_mut().for_each(|byte| *byte = 0);
+        for (marker_offset, data) in markers {
+            for (i, byte) in data.iter().enumerate() {
+                let position = marker_offset + i as u64;
+                if position >= offset && position < offset + len {
+                    chunk[(position - offset) as usize] = *byte;
+                }
+            }
+        }
+        hasher.update(&chunk[..len as usize]);
+        offset += len;
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[test]
+fn test_hash_methods_match_in_memory_hash() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+
+    // Sizes around the internal 1 MB buffer boundary
+    let sizes = [0, 1, MB - 1, MB, MB + 1, 3 * MB + 12345];
+    for size in sizes {
+        let content: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+        let path = temp_dir.path().join(format!("file_{}", size));
+        File::create(&path)?.write_all(&content)?;
+
+        let expected = format!("{:x}", Sha256::digest(&content));
+        assert_eq!(calculate_file_hash(&path)?, expected, "size {}", size);
+        assert_eq!(
+            calculate_file_hash_with(&path, HashMethod::Mmap)?,
+            expected,
+            "size {}",
+            size
+        );
+    }
+
+    assert!(calculate_file_hash(&temp_dir.path().join("missing")).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_sparse_file_hash() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let path = temp_dir.path().join("disk.img");
+
+    let size = 16 * MB + 123;
+    let markers: [(u64, &[u8]); 3] = [
+        (0, b"BOOT"),
+        (MB - 2, b"across a buffer boundary"),
+        (size - 3, b"end"),
+    ];
+    create_sparse_file(&path, size, &markers)?;
+
+    let expected = expected_sparse_hash(size, &markers);
+    assert_eq!(calculate_file_hash(&path)?, expected);
+    assert_eq!(get_file_info(&path, "disk.img")?.hash, expected);
+    assert_eq!(calculate_file_hash_with(&path, HashMethod::Mmap)?, expected);
+
+    Ok(())
+}
+
+// Hashes over 10 GB in total; run with `cargo test -- --ignored`
+#[test]
+#[ignore = "hashes multi-GB files"]
+fn test_multi_gb_sparse_file_hash() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let path = temp_dir.path().join("disk.img");
+
+    // Larger than 4 GB so offsets don't fit in 32 bits
+    let size = 5 * GB + 123;
+    let markers: [(u64, &[u8]); 4] = [
+        (0, b"BOOT"),
+        (MB - 2, b"across a buffer boundary"),
+        (4 * GB + 7, b"past the 32-bit limit"),
+        (size - 3, b"end"),
+    ];
+    create_sparse_file(&path, size, &markers)?;
+
+    let rss_before = peak_rss_kb();
+    let file_info = get_file_info(&path, "disk.img")?;
+    let buffered = calculate_file_hash(&path)?;
+
+    // Streaming must not pull the file into memory
+    if let (Some(before), Some(after)) = (rss_before, peak_rss_kb()) {
+        assert!(
+            after.saturating_sub(before) < 256 * 1024,
+            "peak RSS grew by {} KB while hashing",
+            after - before
+        );
+    }
+
+    let expected = expected_sparse_hash(size, &markers);
+    assert_eq!(buffered, expected);
+    assert_eq!(file_info.hash, expected);
+    assert_eq!(file_info.size, size);
+    assert_eq!(calculate_file_hash_with(&path, HashMethod::Mmap)?, expected);
+
+    // A single changed byte deep inside the file changes the hash
+    create_sparse_file(&path, size, &[(3 * GB, b"x")])?;
+    assert_ne!(calculate_file_hash(&path)?, expected);
+
+    Ok(())
+}
+
+fn set_mtime(path: &Path, age: Duration) -> Result<()> {
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(SystemTime::now() - age)?;
+    Ok(())
+}
+
+fn cached_hashes(db_path: &Path) -> Result<HashMap<String, String>> {
+    let conn = rusqlite::Connection::open(db_path)?;
+    let mut stmt = conn.prepare("SELECT file_path, file_hash FROM file_hash_cache")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn hashes_by_path(files: Vec<FileInfo>) -> HashMap<String, String> {
+    files
+        .into_iter()
+        .map(|file| (file.path, file.hash))
+        .collect()
+}
+
+#[test]
+fn test_scan_reuses_cached_hashes_for_unchanged_files() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let dir = temp_dir.path().join("watched");
+    let db_path = temp_dir.path().join("state.db");
+    std::fs::create_dir_all(dir.join("sub"))?;
+    std::fs::write(dir.join("a.txt"), "alpha")?;
+    std::fs::write(dir.join("sub/b.txt"), "bravo")?;
+    set_mtime(&dir.join("a.txt"), Duration::from_secs(3600))?;
+    set_mtime(&dir.join("sub/b.txt"), Duration::from_secs(3600))?;
+
+    let files = hashes_by_path(scan_directory_cached(&dir, &[], &db_path, false)?);
+    assert_eq!(files["a.txt"], calculate_file_hash(&dir.join("a.txt"))?);
+    assert_eq!(cached_hashes(&db_path)?, files);
+
+    // A scan trusts the cache while the metadata is unchanged...
+    let conn = rusqlite::Connection::open(&db_path)?;
+    conn.execute(
+        "UPDATE file_hash_cache SET file_hash = 'stale' WHERE file_path = 'a.txt'",
+        [],
+    )?;
+    let rescanned = hashes_by_path(scan_directory_cached(&dir, &[], &db_path, false)?);
+    assert_eq!(rescanned["a.txt"], "stale");
+
+    // ...rehashes files whose metadata changed, even with the same size and mtime...
+    std::fs::write(dir.join("sub/b.txt"), "BRAVO")?;
+    set_mtime(&dir.join("sub/b.txt"), Duration::from_secs(3600))?;
+    let rescanned = hashes_by_path(scan_directory_cached(&dir, &[], &db_path, false)?);
+    assert_eq!(
+        rescanned["sub/b.txt"],
+        calculate_file_hash(&dir.join("sub/b.txt"))?
+    );
+    assert_ne!(rescanned["sub/b.txt"], files["sub/b.txt"]);
+
+    // ...and full verification rehashes everything and repairs the cache
+    let verified = hashes_by_path(scan_directory_cached(&dir, &[], &db_path, true)?);
+    assert_eq!(verified["a.txt"], files["a.txt"]);
+    assert_eq!(cached_hashes(&db_path)?["a.txt"], files["a.txt"]);
+
+    // Deleted and excluded files are dropped from the cache
+    std::fs::remove_file(dir.join("sub/b.txt"))?;
+    scan_directory_cached(&dir, &["a.txt".to_string()], &db_path, false)?;
+    assert!(cached_hashes(&db_path)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_recently_modified_files_are_not_cached() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let dir = temp_dir.path().join("watched");
+    let db_path = temp_dir.path().join("state.db");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("fresh.txt"), "just written")?;
+
+    // Another write within the mtime granularity could go unnoticed
+    let files = hashes_by_path(scan_directory_cached(&dir, &[], &db_path, false)?);
+    assert_eq!(
+        files["fresh.txt"],
+        calculate_file_hash(&dir.join("fresh.txt"))?
+    );
+    assert!(cached_hashes(&db_path)?.is_empty());
+
+    set_mtime(&dir.join("fresh.txt"), Duration::from_secs(60))?;
+    scan_directory_cached(&dir, &[], &db_path, false)?;
+    assert!(cached_hashes(&db_path)?.contains_key("fresh.txt"));
+
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use syncpair::history::VersionHistory;
+use syncpair::server::SimpleServer;
+use syncpair::storage::LocalStorage;
+use syncpair::types::{
+    ChangeKind, DeleteRequest, FileInfo, HistoryConfig, TrashConfig, VersionListResponse,
+    VersionVector,
+};
+
+#[path = "common/mod.rs"]
+mod common;
+
+async fn setup_server(port: u16, storage_dir: PathBuf, history: HistoryConfig) -> Result<()> {
+    // Without a trash, deleted files go straight to the history
+    let server = SimpleServer::new(storage_dir)?
+        .with_history(&history)
+        .with_trash(&TrashConfig {
+            enabled: false,
+            ..TrashConfig::default()
+        });
+    common::start_server(port, server).await
+}
+
+async fn versions(http: &reqwest::Client, base: &str, path: &str) -> Result<VersionListResponse> {
+    let response = http
+        .get(format!("{}/versions/{}?directory=docs", base, path))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    Ok(response.json().await?)
+}
+
+#[tokio::test]
+async fn test_replaced_and_deleted_versions_are_kept() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9031;
+    setup_server(
+        port,
+        temp_dir.path().join("storage"),
+        HistoryConfig::default(),
+    )
+    .await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    common::upload(&http, &base, "docs", "notes%2Fa.txt", "one").await?;
+    assert!(versions(&http, &base, "notes%2Fa.txt")
+        .await?
+        .versions
+        .is_empty());
+
+    common::upload(&http, &base, "docs", "notes%2Fa.txt", "two").await?;
+    // Uploading the current content again replaces nothing
+    common::upload(&http, &base, "docs", "notes%2Fa.txt", "two").await?;
+    common::upload(&http, &base, "docs", "notes%2Fa.txt", "three").await?;
+    let response = http
+        .post(format!("{}/delete", base))
+        .json(&DeleteRequest {
+            path: "notes/a.txt".to_string(),
+            client_id: None,
+            directory: Some("docs".to_string()),
+            base_hash: None,
+            version: Default::default(),
+        })
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+
+    let listed = versions(&http, &base, "notes%2Fa.txt").await?.versions;
+    let hashes: Vec<_> = listed.iter().map(|v| v.file_info.hash.clone()).collect();
+    assert_eq!(
+        hashes,
+        vec![
+            common::hash("three"),
+            common::hash("two"),
+            common::hash("one")
+        ]
+    );
+    assert_eq!(listed[0].replaced_by, ChangeKind::Deleted);
+    assert_eq!(listed[1].replaced_by, ChangeKind::Modified);
+
+    // Each version downloads with its own metadata
+    let response = http
+        .get(format!(
+            "{}/versions/notes%2Fa.txt/{}?directory=docs",
+            base, listed[1].id
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.headers()["x-syncpair-hash"],
+        common::hash("two").as_str()
+    );
+    assert_eq!(response.text().await?, "two");
+
+    // Versions belong to their path
+    let response = http
+        .get(format!(
+            "{}/versions/b.txt/{}?directory=docs",
+            base, listed[1].id
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+
+    // The history is out of reach of client paths
+    let response = http
+        .get(format!(
+            "{}/files/.syncpair%2Fversions%2F1?directory=docs",
+            base
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_history_keeps_configured_number_of_versions() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9032;
+    let history = HistoryConfig {
+        keep_versions: 2,
+        keep_all_days: 0,
+        daily_days: 0,
+        weekly_weeks: 0,
+        ..HistoryConfig::default()
+    };
+    setup_server(port, storage.clone(), history).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    for content in ["one", "two", "three", "four", "five"] {
+        common::upload(&http, &base, "docs", "a.txt", content).await?;
+    }
+
+    let listed = versions(&http, &base, "a.txt").await?.versions;
+    let hashes: Vec<_> = listed.iter().map(|v| v.file_info.hash.clone()).collect();
+    assert_eq!(hashes, vec![common::hash("four"), common::hash("three")]);
+    let stored = std::fs::read_dir(storage.join("docs/.syncpair/versions"))?.count();
+    assert_eq!(stored, 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_retention_thins_out_old_versions() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let storage = Arc::new(LocalStorage::new(temp_dir.path()));
+    let conn = rusqlite::Connection::open(temp_dir.path().join("server_state.db"))?;
+    VersionHistory::create_schema(&conn)?;
+    let history = VersionHistory::open(temp_dir.path(), storage, "")?;
+
+    // One version a day for the last 60 days, and five from today
+    let file_path = temp_dir.path().join("a.txt");
+    let mut ids = Vec::new();
+    for n in 0..65 {
+        std::fs::write(&file_path, n.to_string())?;
+        let file_info = FileInfo {
+            path: "a.txt".to_string(),
+            hash: common::hash(n.to_string()),
+            size: std::fs::metadata(&file_path)?.len(),
+            modified: chrono::Utc::now(),
+            version: VersionVector::default(),
+        };
+        ids.push(history.archive("a.txt", &file_info, ChangeKind::Modified)?);
+    }
+    let conn = rusqlite::Connection::open(temp_dir.path().join("server_state.db"))?;
+    let now = chrono::Utc::now();
+    for (n, id) in ids.iter().take(60).enumerate() {
+        let archived_at = now - chrono::Duration::days(60 - n as i64) - chrono::Duration::hours(1);
+        conn.execute(
+            "UPDATE file_versions SET archived_at = ? WHERE id = ?",
+            rusqlite::params![archived_at.timestamp_millis(), *id as i64],
+        )?;
+    }
+
+    let policy = HistoryConfig {
+        keep_versions: 3,
+        keep_all_days: 1,
+        daily_days: 6,
+        weekly_weeks: 4,
+        ..HistoryConfig::default()
+    };
+    history.prune(&policy, None)?;
+
+    let kept = history.list("a.txt")?;
+    let ages: Vec<i64> = kept
+        .iter()
+        .map(|version| (now - version.archived_at).num_days())
+        .collect();
+    // Today's five, one a day for six days, then one a week for four weeks
+    assert_eq!(&ages[..5], &[0, 0, 0, 0, 0]);
+    assert_eq!(&ages[5..11], &[1, 2, 3, 4, 5, 6]);
+    assert!(ages[11..].iter().all(|age| (7..35).contains(age)));
+    assert!((3..=5).contains(&ages[11..].len()));
+    assert_eq!(
+        std::fs::read_dir(temp_dir.path().join(".syncpair/versions"))?.count(),
+        kept.len()
+    );
+
+    Ok(())
+}
@@ -2,7 +2,7 @@ use anyhow::Result;
 use std::path::PathBuf;
 use std::time::Duration;
 use syncpair::client::SimpleClient;
-use syncpair::server::SimpleServer;
+use syncpair::types::ConflictPolicy;
 
 use tokio::time::sleep;
 
@@ -10,22 +10,6 @@ use tokio::time::sleep;
 mod common;
 
 // Test helpers
-async fn setup_server(port: u16, storage_dir: PathBuf) -> Result<()> {
-    // Spawn server in background
-    tokio::spawn(async move {
-        let server = SimpleServer::new(storage_dir).unwrap();
-        if let Err(e) = server.start(port).await {
-            // It's expected that the server might get cancelled or fail to bind if port is taken
-            // but for tests we want to see why
-            eprintln!("Server error: {}", e);
-        }
-    });
-
-    // Give server a moment to start
-    sleep(Duration::from_millis(100)).await;
-    Ok(())
-}
-
 fn setup_client(server_url: String, watch_dir: PathBuf, dir_name: String) -> SimpleClient {
     SimpleClient::new(server_url, watch_dir)
         .with_directory(dir_name)
@@ -48,7 +32,7 @@ async fn test_basic_sync() -> Result<()> {
 
     // Start server
     let port = 9001; // Use different port than default to avoid conflicts
-    setup_server(port, server_dir).await?;
+    common::setup_server(port, server_dir).await?;
     let server_url = format!("http://localhost:{}", port);
     let shared_dir = "test_project".to_string();
 
@@ -99,14 +83,17 @@ async fn test_conflict_resolution() -> Result<()> {
     std::fs::create_dir_all(&client_b_dir)?;
 
     let port = 9002;
-    setup_server(port, server_dir).await?;
+    common::setup_server(port, server_dir).await?;
     let server_url = format!("http://localhost:{}", port);
     let shared_dir = "conflict_project".to_string();
 
+    // Timestamps decide; the default policy would keep the losing edit as a copy
     let client_a = setup_client(server_url.clone(), client_a_dir.clone(), shared_dir.clone())
-        .with_client_id("client_a".to_string());
+        .with_client_id("client_a".to_string())
+        .with_conflict_policy(ConflictPolicy::NewestWins);
     let client_b = setup_client(server_url.clone(), client_b_dir.clone(), shared_dir.clone())
-        .with_client_id("client_b".to_string());
+        .with_client_id("client_b".to_string())
+        .with_conflict_policy(ConflictPolicy::NewestWins);
 
     // 1. Create initial file
     let file_name = "conflict.txt";
@@ -159,7 +146,7 @@ async fn test_deletion_propagation() -> Result<()> {
     std::fs::create_dir_all(&client_b_dir)?;
 
     let port = 9003;
-    setup_server(port, server_dir).await?;
+    common::setup_server(port, server_dir).await?;
     let server_url = format!("http://localhost:{}", port);
     let shared_dir = "deletion_project".to_string();
 
@@ -206,7 +193,7 @@ async fn test_delta_sync() -> Result<()> {
     std::fs::create_dir_all(&client_a_dir)?;
 
     let port = 9004;
-    setup_server(port, server_dir.clone()).await?;
+    common::setup_server(port, server_dir.clone()).await?;
     let server_url = format!("http://localhost:{}", port);
     let shared_dir = "delta_project".to_string();
 
@@ -219,8 +206,8 @@ async fn test_delta_sync() -> Result<()> {
     let size = 2 * 1024 * 1024 + 100;
     let mut content = vec![0u8; size];
     // Fill with some data
-    for i in 0..size {
-        content[i] = (i % 256) as u8;
+    for (i, byte) in content.iter_mut().enumerate() {
+        *byte = (i % 256) as u8;
     }
 
     std::fs::write(client_a_dir.join(file_name), &content)?;
@@ -0,0 +1,214 @@
+use anyhow::Result;
+use std::time::Duration;
+use syncpair::client::SimpleClient;
+use syncpair::types::{ChangesRequest, FileChange, SyncCursor, SyncRequest, SyncResponse};
+use syncpair::utils::load_sync_cursor;
+use tokio::time::sleep;
+
+#[path = "common/mod.rs"]
+mod common;
+
+async fn changes(
+    http: &reqwest::Client,
+    base: &str,
+    cursor: &SyncCursor,
+    changes: Vec<FileChange>,
+) -> Result<SyncResponse> {
+    let request = ChangesRequest {
+        cursor: cursor.clone(),
+        changes,
+        client_id: None,
+        directory: Some("docs".to_string()),
+    };
+    let response = http
+        .post(format!("{}/sync/changes", base))
+        .json(&request)
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    Ok(response.json().await?)
+}
+
+#[tokio::test]
+async fn test_changes_since_cursor() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9021;
+    common::setup_server(port, storage.clone()).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    // A full sync hands out the starting cursor
+    let full: SyncResponse = http
+        .post(format!("{}/sync", base))
+        .json(&SyncRequest {
+            files: Default::default(),
+            deleted_files: Default::default(),
+            deleted_versions: Default::default(),
+            deleted_base_hashes: Default::default(),
+            last_sync: chrono::Utc::now(),
+            client_id: None,
+            directory: Some("docs".to_string()),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    let start = full.cursor.expect("full sync returns a cursor");
+
+    common::upload(&http, &base, "docs", "a.txt", "first").await?;
+    common::upload(&http, &base, "docs", "a.txt", "second").await?;
+    common::upload(&http, &base, "docs", "b.txt", "bee").await?;
+
+    // Each path is reported once, as it is now
+    let response = changes(&http, &base, &start, vec![]).await?;
+    assert!(!response.cursor_expired);
+    let mut downloads: Vec<_> = response
+        .files_to_download
+        .iter()
+        .map(|file| (file.path.as_str(), file.hash.clone()))
+        .collect();
+    downloads.sort();
+    assert_eq!(
+        downloads,
+        vec![
+            ("a.txt", common::hash("second")),
+            ("b.txt", common::hash("bee")),
+        ]
+    );
+    let current = response.cursor.unwrap();
+    assert!(current.seq > start.seq);
+
+    // Nothing changed since the latest cursor
+    let response = changes(&http, &base, &current, vec![]).await?;
+    assert!(response.files_to_download.is_empty());
+    assert_eq!(response.cursor.as_ref(), Some(&current));
+
+    // A local deletion is applied and reported to clients at older cursors only
+    let response = changes(
+        &http,
+        &base,
+        &current,
+        vec![FileChange::Deleted {
+            path: "a.txt".to_string(),
+            deleted_at: chrono::Utc::now(),
+            version: Default::default(),
+            base_hash: None,
+        }],
+    )
+    .await?;
+    assert!(response.files_to_delete.is_empty());
+    assert!(!storage.join("docs/a.txt").exists());
+    let after_delete = response.cursor.unwrap();
+
+    let response = changes(&http, &base, &current, vec![]).await?;
+    assert_eq!(response.files_to_delete, vec!["a.txt".to_string()]);
+    let response = changes(&http, &base, &after_delete, vec![]).await?;
+    assert!(response.files_to_delete.is_empty());
+
+    // A local modification of a path unchanged on the server is requested for upload
+    let modified = syncpair::types::FileInfo {
+        path: "b.txt".to_string(),
+        hash: common::hash("bee 2"),
+        size: 5,
+        modified: chrono::Utc::now(),
+        version: Default::default(),
+    };
+    let response = changes(
+        &http,
+        &base,
+        &after_delete,
+        vec![FileChange::Modified(modified)],
+    )
+    .await?;
+    assert_eq!(response.files_to_upload, vec!["b.txt".to_string()]);
+
+    // Cursors from another journal or from the future need a full sync
+    for cursor in [
+        SyncCursor {
+            journal_id: "unknown".to_string(),
+            seq: start.seq,
+        },
+        SyncCursor {
+            journal_id: start.journal_id.clone(),
+            seq: after_delete.seq + 100,
+        },
+    ] {
+        let response = changes(&http, &base, &cursor, vec![]).await?;
+        assert!(response.cursor_expired);
+        assert!(response.cursor.is_none());
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_clients_sync_incrementally() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9022;
+    common::setup_server(port, storage.clone()).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client_a = SimpleClient::new(server_url.clone(), client_a_dir.clone())
+        .with_directory("docs".to_string())
+        .with_client_id("client_a".to_string());
+    let client_b = SimpleClient::new(server_url.clone(), client_b_dir.clone())
+        .with_directory("docs".to_string())
+        .with_client_id("client_b".to_string());
+
+    // The first syncs are full syncs and leave a cursor behind
+    std::fs::write(client_a_dir.join("notes.txt"), "v1")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("notes.txt"))?,
+        "v1"
+    );
+    let cursor = load_sync_cursor(&client_b_dir.join(".syncpair_state.db"))?
+        .expect("cursor saved after sync");
+
+    // Later syncs only exchange changes
+    sleep(Duration::from_millis(20)).await;
+    std::fs::write(client_a_dir.join("notes.txt"), "v2")?;
+    std::fs::write(client_a_dir.join("todo.txt"), "todo")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("notes.txt"))?,
+        "v2"
+    );
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("todo.txt"))?,
+        "todo"
+    );
+    let next = load_sync_cursor(&client_b_dir.join(".syncpair_state.db"))?.unwrap();
+    assert_eq!(next.journal_id, cursor.journal_id);
+    assert!(next.seq > cursor.seq);
+
+    std::fs::remove_file(client_b_dir.join("todo.txt"))?;
+    client_b.initial_sync().await?;
+    client_a.initial_sync().await?;
+    assert!(!client_a_dir.join("todo.txt").exists());
+    assert!(!storage.join("docs/todo.txt").exists());
+
+    // Once the journal entries after a cursor are pruned, the client falls back to a full sync
+    std::fs::write(client_a_dir.join("late.txt"), "late")?;
+    client_a.initial_sync().await?;
+    let conn = rusqlite::Connection::open(storage.join("docs/server_state.db"))?;
+    conn.execute("UPDATE change_log SET recorded_at = 0", [])?;
+    drop(conn);
+    client_b.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("late.txt"))?,
+        "late"
+    );
+
+    Ok(())
+}
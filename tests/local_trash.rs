@@ -0,0 +1,146 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use syncpair::client::SimpleClient;
+use syncpair::utils::{copy_to_local_trash, purge_local_trash, replace_file};
+
+#[path = "common/mod.rs"]
+mod common;
+
+/// The contents of the files in the local trash of `dir`, by path within their
+/// timestamp folder
+fn trashed_files(dir: &Path) -> Result<Vec<(String, String)>> {
+    let trash_dir = dir.join(".syncpair/trash");
+    let mut files = Vec::new();
+    if !trash_dir.exists() {
+        return Ok(files);
+    }
+    for entry in walkdir::WalkDir::new(&trash_dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let relative_path = entry.path().strip_prefix(&trash_dir)?;
+            let path: PathBuf = relative_path.components().skip(1).collect();
+            files.push((
+                path.to_string_lossy().to_string(),
+                std::fs::read_to_string(entry.path())?,
+            ));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[tokio::test]
+async fn test_sync_keeps_deleted_and_overwritten_files_in_local_trash() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    let client_c_dir = temp_dir.path().join("client_c");
+    for dir in [&client_a_dir, &client_b_dir, &client_c_dir] {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let port = 9038;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client = |dir: &PathBuf, id: &str| {
+        SimpleClient::new(server_url.clone(), dir.clone())
+            .with_directory("docs".to_string())
+            .with_client_id(format!("{}:docs", id))
+    };
+    let client_a = client(&client_a_dir, "alice");
+    let client_b = client(&client_b_dir, "bob");
+    let client_c = client(&client_c_dir, "carol").with_trash_retention_days(0);
+
+    std::fs::create_dir_all(client_a_dir.join("notes"))?;
+    std::fs::write(client_a_dir.join("notes/a.txt"), "a")?;
+    std::fs::write(client_a_dir.join("b.txt"), "b")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    client_c.initial_sync().await?;
+
+    std::fs::remove_file(client_a_dir.join("notes/a.txt"))?;
+    std::fs::write(client_a_dir.join("b.txt"), "new b")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    client_c.initial_sync().await?;
+
+    assert!(!client_b_dir.join("notes/a.txt").exists());
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("b.txt"))?,
+        "new b"
+    );
+    assert_eq!(
+        trashed_files(&client_b_dir)?,
+        vec![
+            ("b.txt".to_string(), "b".to_string()),
+            ("notes/a.txt".to_string(), "a".to_string()),
+        ]
+    );
+    // The trash stays local
+    client_b.initial_sync().await?;
+    client_a.initial_sync().await?;
+    assert!(!client_a_dir.join(".syncpair/trash").exists());
+
+    // Without a trash, files are deleted right away
+    assert!(!client_c_dir.join("notes/a.txt").exists());
+    assert!(trashed_files(&client_c_dir)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_local_trash_purges_expired_folders() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let trash_dir = temp_dir.path().join(".syncpair/trash");
+    let now = chrono::Utc::now();
+    let recent = (now - chrono::Duration::days(2))
+        .format("%Y-%m-%dT%H%M%SZ")
+        .to_string();
+    for folder in [
+        "2020-01-01T120000Z",
+        "2020-01-01T120000Z.2",
+        recent.as_str(),
+        "not a timestamp",
+    ] {
+        std::fs::create_dir_all(trash_dir.join(folder))?;
+        std::fs::write(trash_dir.join(folder).join("a.txt"), "a")?;
+    }
+
+    assert_eq!(purge_local_trash(temp_dir.path(), 30, now)?, 2);
+    let mut remaining: Vec<String> = std::fs::read_dir(&trash_dir)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+        .collect::<Result<_>>()?;
+    remaining.sort();
+    assert_eq!(remaining, vec![recent, "not a timestamp".to_string()]);
+
+    Ok(())
+}
+
+#[test]
+fn test_overwritten_file_stays_in_place_until_replaced() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let dir = temp_dir.path();
+    std::fs::create_dir_all(dir.join("notes"))?;
+    std::fs::write(dir.join("notes/a.txt"), "old")?;
+
+    // The trash gets its copy first, so the file is never missing
+    let trash_path = copy_to_local_trash(
+        dir,
+        &dir.join("notes/a.txt"),
+        "notes/a.txt",
+        chrono::Utc::now(),
+    )?;
+    assert_eq!(std::fs::read_to_string(dir.join("notes/a.txt"))?, "old");
+    assert_eq!(std::fs::read_to_string(&trash_path)?, "old");
+
+    std::fs::write(dir.join("new.tmp"), "new")?;
+    replace_file(&dir.join("new.tmp"), &dir.join("notes/a.txt"))?;
+    assert_eq!(std::fs::read_to_string(dir.join("notes/a.txt"))?, "new");
+    assert_eq!(
+        trashed_files(dir)?,
+        vec![("notes/a.txt".to_string(), "old".to_string())]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,478 @@
+use anyhow::Result;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use syncpair::paths::{normalize_relative_path, resolve_within, validate_directory_name};
+use syncpair::types::error::PathError;
+use syncpair::types::SyncResponse;
+
+#[path = "common/mod.rs"]
+mod common;
+
+/// Paths that must never be accepted, built around a directory outside the storage root
+fn attack_paths(outside: &Path) -> Vec<String> {
+    vec![
+        "../escape.txt".to_string(),
+        "../../outside/escape.txt".to_string(),
+        "nested/../../../outside/escape.txt".to_string(),
+        "..\\..\\outside\\escape.txt".to_string(),
+        outside.join("escape.txt").to_string_lossy().to_string(),
+        "evil\0.txt".to_string(),
+        ".syncpair/escape.txt".to_string(),
+        "server_state.db".to_string(),
+    ]
+}
+
+fn attack_directories() -> Vec<&'static str> {
+    vec![
+        "..",
+        ".",
+        "../outside",
+        "a/b",
+        "/tmp",
+        ".hidden",
+        "nul\0dir",
+        "",
+    ]
+}
+
+fn assert_outside_untouched(outside: &Path) {
+    assert!(
+        !outside.join("escape.txt").exists(),
+        "File escaped the storage root"
+    );
+    assert!(
+        outside.join("victim.txt").exists(),
+        "File outside the storage root was deleted"
+    );
+}
+
+fn setup_dirs() -> Result<(tempfile::TempDir, PathBuf, PathBuf)> {
+    let temp_dir = common::create_temp_dir()?;
+    let server_dir = temp_dir.path().join("storage");
+    let outside_dir = temp_dir.path().join("outside");
+    std::fs::create_dir_all(server_dir.join("project"))?;
+    std::fs::create_dir_all(&outside_dir)?;
+    std::fs::write(outside_dir.join("victim.txt"), "keep me")?;
+    Ok((temp_dir, server_dir, outside_dir))
+}
+
+fn file_info(path: &str, content: &[u8]) -> serde_json::Value {
+    json!({
+        "path": path,
+        "hash": common::hash(content),
+        "size": content.len(),
+        "modified": chrono::Utc::now(),
+    })
+}
+
+#[test]
+fn test_path_normalization() {
+    assert_eq!(normalize_relative_path("a/b.txt").unwrap(), "a/b.txt");
+    assert_eq!(normalize_relative_path("./a//b.txt").unwrap(), "a/b.txt");
+    assert_eq!(normalize_relative_path("a\\b.txt").unwrap(), "a/b.txt");
+    assert_eq!(
+        normalize_relative_path("notes/server_state.db").unwrap(),
+        "notes/server_state.db"
+    );
+
+    assert!(matches!(
+        normalize_relative_path("../x"),
+        Err(PathError::ParentTraversal(_))
+    ));
+    assert!(matches!(
+        normalize_relative_path("a/../../x"),
+        Err(PathError::ParentTraversal(_))
+    ));
+    assert!(matches!(
+        normalize_relative_path("/etc/passwd"),
+        Err(PathError::Absolute(_))
+    ));
+    assert!(matches!(
+        normalize_relative_path("C:\\Windows"),
+        Err(PathError::Absolute(_))
+    ));
+    assert!(matches!(
+        normalize_relative_path("a\0b"),
+        Err(PathError::NulByte(_))
+    ));
+    assert!(matches!(
+        normalize_relative_path("./"),
+        Err(PathError::Empty)
+    ));
+    for sidecar in [
+        "server_state.db-journal",
+        "server_state.db-wal",
+        "server_state.db-shm",
+    ] {
+        assert!(matches!(
+            normalize_relative_path(sidecar),
+            Err(PathError::Reserved(_))
+        ));
+    }
+    // Only the state file itself is reserved, not names that merely start like it
+    assert_eq!(
+        normalize_relative_path("server_state.db.notes").unwrap(),
+        "server_state.db.notes"
+    );
+    assert_eq!(
+        normalize_relative_path("server_state.dbx").unwrap(),
+        "server_state.dbx"
+    );
+    assert!(matches!(
+        normalize_relative_path("notes/.syncpair-tmp-1-2"),
+        Err(PathError::Reserved(_))
+    ));
+
+    assert!(validate_directory_name("team_project").is_ok());
+    assert!(validate_directory_name("alice:notes").is_ok());
+    for name in attack_directories() {
+        assert!(
+            validate_directory_name(name).is_err(),
+            "{:?} should be rejected",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_resolve_within_rejects_symlink_escape() -> Result<()> {
+    let (_temp_dir, server_dir, outside_dir) = setup_dirs()?;
+    let root = server_dir.join("project");
+    std::fs::create_dir_all(root.join("inner"))?;
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&outside_dir, root.join("escape"))?;
+        std::os::unix::fs::symlink(root.join("inner"), root.join("internal"))?;
+        std::os::unix::fs::symlink(root.join("missing"), root.join("dangling"))?;
+
+        assert!(matches!(
+            resolve_within(&root, "escape/file.txt"),
+            Err(PathError::SymlinkEscape(_))
+        ));
+        assert!(matches!(
+            resolve_within(&root, "dangling"),
+            Err(PathError::SymlinkEscape(_))
+        ));
+        let (resolved, normalized) = resolve_within(&root, "internal/file.txt")?;
+        assert_eq!(resolved, root.join("internal/file.txt"));
+        assert_eq!(normalized, "internal/file.txt");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_upload_rejects_escaping_paths() -> Result<()> {
+    common::init_test_logging();
+    let (_temp_dir, server_dir, outside_dir) = setup_dirs()?;
+    let port = 9005;
+    common::setup_server(port, server_dir.clone()).await?;
+    let http = reqwest::Client::new();
+    let url = format!("http://localhost:{}/upload", port);
+    let content = b"malicious";
+
+    for path in attack_paths(&outside_dir) {
+        let response = http
+            .post(&url)
+            .json(&json!({
+                "file_info": file_info(&path, content),
+                "content": content.to_vec(),
+                "directory": "project",
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 400, "upload of {:?} accepted", path);
+    }
+
+    for directory in attack_directories() {
+        let response = http
+            .post(&url)
+            .json(&json!({
+                "file_info": file_info("escape.txt", content),
+                "content": content.to_vec(),
+                "directory": directory,
+            }))
+            .send()
+            .await?;
+        assert_eq!(
+            response.status(),
+            400,
+            "upload into {:?} accepted",
+            directory
+        );
+    }
+
+    assert_outside_untouched(&outside_dir);
+    assert!(!server_dir.join("escape.txt").exists());
+    assert!(!server_dir.join("project").join("server_state.db").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_download_rejects_escaping_paths() -> Result<()> {
+    common::init_test_logging();
+    let (_temp_dir, server_dir, outside_dir) = setup_dirs()?;
+    let port = 9006;
+    common::setup_server(port, server_dir).await?;
+    let http = reqwest::Client::new();
+
+    let mut paths = attack_paths(&outside_dir);
+    paths.push("../../outside/victim.txt".to_string());
+    for path in paths {
+        let url = format!(
+            "http://localhost:{}/download/{}?directory=project",
+            port,
+            urlencoding::encode(&path)
+        );
+        let response = http.get(&url).send().await?;
+        assert_eq!(response.status(), 400, "download of {:?} accepted", path);
+        let body = response.text().await?;
+        assert!(!body.contains("keep me"));
+    }
+
+    for directory in ["..", "../outside", "/tmp"] {
+        let url = format!(
+            "http://localhost:{}/download/victim.txt?directory={}",
+            port,
+            urlencoding::encode(directory)
+        );
+        let response = http.get(&url).send().await?;
+        assert_eq!(
+            response.status(),
+            400,
+            "download from {:?} accepted",
+            directory
+        );
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_rejects_escaping_paths() -> Result<()> {
+    common::init_test_logging();
+    let (_temp_dir, server_dir, outside_dir) = setup_dirs()?;
+    let port = 9007;
+    common::setup_server(port, server_dir).await?;
+    let http = reqwest::Client::new();
+    let url = format!("http://localhost:{}/delete", port);
+
+    let mut paths = attack_paths(&outside_dir);
+    paths.push("../../outside/victim.txt".to_string());
+    paths.push(outside_dir.join("victim.txt").to_string_lossy().to_string());
+    for path in paths {
+        let response = http
+            .post(&url)
+            .json(&json!({ "path": path, "directory": "project" }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 400, "delete of {:?} accepted", path);
+    }
+
+    for directory in attack_directories() {
+        let response = http
+            .post(&url)
+            .json(&json!({ "path": "victim.txt", "directory": directory }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 400, "delete in {:?} accepted", directory);
+    }
+
+    assert_outside_untouched(&outside_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delta_routes_reject_escaping_paths() -> Result<()> {
+    common::init_test_logging();
+    let (_temp_dir, server_dir, outside_dir) = setup_dirs()?;
+    let port = 9008;
+    common::setup_server(port, server_dir).await?;
+    let http = reqwest::Client::new();
+    let content = b"malicious";
+
+    for path in attack_paths(&outside_dir) {
+        let response = http
+            .post(format!("http://localhost:{}/delta/init", port))
+            .json(&json!({
+                "file_info": file_info(&path, content),
+                "block_hashes": [],
+                "block_size": 1024 * 1024,
+                "directory": "project",
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 400, "delta init of {:?} accepted", path);
+
+        let response = http
+            .post(format!("http://localhost:{}/delta/upload", port))
+            .json(&json!({
+                "session_id": "unknown",
+                "path": path,
+                "directory": "project",
+                "index": 0,
+                "content": content.to_vec(),
+            }))
+            .send()
+            .await?;
+        assert_eq!(
+            response.status(),
+            400,
+            "block upload of {:?} accepted",
+            path
+        );
+
+        let response = http
+            .post(format!("http://localhost:{}/delta/complete", port))
+            .json(&json!({
+                "session_id": "unknown",
+                "path": path,
+                "directory": "project",
+                "client_id": null,
+                "expected_hash": "",
+            }))
+            .send()
+            .await?;
+        assert_eq!(
+            response.status(),
+            400,
+            "delta complete of {:?} accepted",
+            path
+        );
+    }
+
+    for directory in attack_directories() {
+        let response = http
+            .post(format!("http://localhost:{}/delta/upload", port))
+            .json(&json!({
+                "session_id": "unknown",
+                "path": "escape.txt",
+                "directory": directory,
+                "index": 0,
+                "content": content.to_vec(),
+            }))
+            .send()
+            .await?;
+        assert_eq!(
+            response.status(),
+            400,
+            "block upload into {:?} accepted",
+            directory
+        );
+    }
+
+    assert_outside_untouched(&outside_dir);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sync_rejects_escaping_paths() -> Result<()> {
+    common::init_test_logging();
+    let (_temp_dir, server_dir, outside_dir) = setup_dirs()?;
+    let port = 9009;
+    common::setup_server(port, server_dir).await?;
+    let http = reqwest::Client::new();
+    let url = format!("http://localhost:{}/sync", port);
+
+    // Invalid entries are skipped and reported without failing the rest of the sync
+    for path in attack_paths(&outside_dir) {
+        let response = http
+            .post(&url)
+            .json(&json!({
+                "files": {
+                    path.clone(): file_info(&path, b"x"),
+                    "valid.txt": file_info("valid.txt", b"x"),
+                },
+                "deleted_files": {},
+                "last_sync": chrono::Utc::now(),
+                "directory": "project",
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200, "sync of {:?} failed", path);
+        let sync: SyncResponse = response.json().await?;
+        assert_eq!(sync.rejected_paths, vec![path.clone()]);
+        assert_eq!(sync.files_to_upload, vec!["valid.txt".to_string()]);
+
+        let response = http
+            .post(&url)
+            .json(&json!({
+                "files": {},
+                "deleted_files": { path.clone(): chrono::Utc::now() },
+                "last_sync": chrono::Utc::now(),
+                "directory": "project",
+            }))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200, "sync deletion of {:?} failed", path);
+        let sync: SyncResponse = response.json().await?;
+        assert_eq!(sync.rejected_paths, vec![path.clone()]);
+    }
+
+    for directory in attack_directories() {
+        let response = http
+            .post(&url)
+            .json(&json!({
+                "files": {},
+                "deleted_files": {},
+                "last_sync": chrono::Utc::now(),
+                "directory": directory,
+            }))
+            .send()
+            .await?;
+        assert_eq!(
+            response.status(),
+            400,
+            "sync of directory {:?} accepted",
+            directory
+        );
+    }
+
+    assert_outside_untouched(&outside_dir);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_symlink_escape_rejected_by_server() -> Result<()> {
+    common::init_test_logging();
+    let (_temp_dir, server_dir, outside_dir) = setup_dirs()?;
+    std::os::unix::fs::symlink(&outside_dir, server_dir.join("project").join("link"))?;
+
+    let port = 9010;
+    common::setup_server(port, server_dir).await?;
+    let http = reqwest::Client::new();
+    let content = b"malicious";
+
+    let response = http
+        .post(format!("http://localhost:{}/upload", port))
+        .json(&json!({
+            "file_info": file_info("link/escape.txt", content),
+            "content": content.to_vec(),
+            "directory": "project",
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    let response = http
+        .get(format!(
+            "http://localhost:{}/download/{}?directory=project",
+            port,
+            urlencoding::encode("link/victim.txt")
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    let response = http
+        .post(format!("http://localhost:{}/delete", port))
+        .json(&json!({ "path": "link/victim.txt", "directory": "project" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    assert_outside_untouched(&outside_dir);
+    Ok(())
+}
@@ -0,0 +1,303 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::time::Duration;
+use syncpair::client::SimpleClient;
+use syncpair::server::SimpleServer;
+use syncpair::types::{
+    HistoryConfig, RestoreRequest, RestoreResponse, SyncRequest, SyncResponse, TrashConfig,
+};
+use tokio::time::sleep;
+
+#[path = "common/mod.rs"]
+mod common;
+
+async fn setup_server(
+    port: u16,
+    storage_dir: PathBuf,
+    history: HistoryConfig,
+    trash: TrashConfig,
+) -> Result<()> {
+    let server = SimpleServer::new(storage_dir)?
+        .with_history(&history)
+        .with_trash(&trash);
+    common::start_server(port, server).await
+}
+
+async fn download(http: &reqwest::Client, base: &str, path: &str) -> Result<Option<String>> {
+    let response = http
+        .get(format!("{}/files/{}?directory=docs", base, path))
+        .send()
+        .await?;
+    if response.status() == 404 {
+        return Ok(None);
+    }
+    assert_eq!(response.status(), 200);
+    Ok(Some(response.text().await?))
+}
+
+/// The current time, between writes that happen before and after it
+async fn instant() -> chrono::DateTime<chrono::Utc> {
+    sleep(Duration::from_millis(20)).await;
+    let at = chrono::Utc::now();
+    sleep(Duration::from_millis(20)).await;
+    at
+}
+
+#[tokio::test]
+async fn test_restore_path_to_point_in_time() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9033;
+    setup_server(
+        port,
+        temp_dir.path().join("storage"),
+        HistoryConfig::default(),
+        TrashConfig::default(),
+    )
+    .await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let before_anything = instant().await;
+    common::upload(&http, &base, "docs", "notes%2Fa.txt", "one").await?;
+    common::upload(&http, &base, "docs", "other.txt", "other one").await?;
+    let after_one = instant().await;
+    common::upload(&http, &base, "docs", "notes%2Fa.txt", "two").await?;
+    common::upload(&http, &base, "docs", "notes%2Fb.txt", "new").await?;
+    common::upload(&http, &base, "docs", "other.txt", "other two").await?;
+    let after_two = instant().await;
+
+    let restore = |path: Option<&str>, at| {
+        http.post(format!("{}/restore", base))
+            .json(&RestoreRequest {
+                directory: Some("docs".to_string()),
+                path: path.map(str::to_string),
+                at,
+                client_id: None,
+            })
+            .send()
+    };
+
+    // Only the given folder goes back; files created since are deleted
+    let response = restore(Some("notes"), after_one).await?;
+    assert_eq!(response.status(), 200);
+    let response: RestoreResponse = response.json().await?;
+    assert_eq!(response.restored, vec!["notes/a.txt".to_string()]);
+    assert_eq!(response.deleted, vec!["notes/b.txt".to_string()]);
+    assert_eq!(
+        download(&http, &base, "notes%2Fa.txt").await?.as_deref(),
+        Some("one")
+    );
+    assert_eq!(download(&http, &base, "notes%2Fb.txt").await?, None);
+    assert_eq!(
+        download(&http, &base, "other.txt").await?.as_deref(),
+        Some("other two")
+    );
+
+    // Restoring to the present changes nothing
+    let response: RestoreResponse = restore(Some("notes/a.txt"), instant().await)
+        .await?
+        .json()
+        .await?;
+    assert!(response.restored.is_empty());
+    let response: RestoreResponse = restore(None, before_anything).await?.json().await?;
+    assert_eq!(
+        response.deleted,
+        vec!["notes/a.txt".to_string(), "other.txt".to_string()]
+    );
+    assert_eq!(response.unavailable, vec!["notes/b.txt".to_string()]);
+    assert_eq!(download(&http, &base, "notes%2Fa.txt").await?, None);
+
+    // Deleted files come back from the tombstones they left
+    let response: RestoreResponse = restore(None, after_one).await?.json().await?;
+    assert_eq!(
+        response.restored,
+        vec!["notes/a.txt".to_string(), "other.txt".to_string()]
+    );
+    assert_eq!(
+        download(&http, &base, "other.txt").await?.as_deref(),
+        Some("other one")
+    );
+    let response: RestoreResponse = restore(Some("notes/b.txt"), after_two)
+        .await?
+        .json()
+        .await?;
+    assert_eq!(response.restored, vec!["notes/b.txt".to_string()]);
+    assert_eq!(
+        download(&http, &base, "notes%2Fb.txt").await?.as_deref(),
+        Some("new")
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_restore_reaches_clients_through_sync() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9034;
+    setup_server(
+        port,
+        temp_dir.path().join("storage"),
+        HistoryConfig::default(),
+        TrashConfig::default(),
+    )
+    .await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client_a = SimpleClient::new(server_url.clone(), client_a_dir.clone())
+        .with_directory("docs".to_string())
+        .with_client_id("alice:docs".to_string());
+    let client_b = SimpleClient::new(server_url.clone(), client_b_dir.clone())
+        .with_directory("docs".to_string())
+        .with_client_id("bob:docs".to_string());
+
+    std::fs::write(client_a_dir.join("report.txt"), "good")?;
+    std::fs::write(client_a_dir.join("data.txt"), "data")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    let good = instant().await;
+
+    // A bad edit and a mistaken deletion reach everyone
+    std::fs::write(client_a_dir.join("report.txt"), "bad")?;
+    std::fs::remove_file(client_a_dir.join("data.txt"))?;
+    std::fs::write(client_a_dir.join("scratch.txt"), "scratch")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert!(!client_b_dir.join("data.txt").exists());
+
+    let response = client_b.restore(None, good).await?;
+    assert_eq!(
+        response.restored,
+        vec!["data.txt".to_string(), "report.txt".to_string()]
+    );
+    assert_eq!(response.deleted, vec!["scratch.txt".to_string()]);
+
+    for (client, dir) in [(&client_a, &client_a_dir), (&client_b, &client_b_dir)] {
+        client.initial_sync().await?;
+        assert_eq!(std::fs::read_to_string(dir.join("report.txt"))?, "good");
+        assert_eq!(std::fs::read_to_string(dir.join("data.txt"))?, "data");
+        assert!(!dir.join("scratch.txt").exists());
+        assert!(client.conflicts()?.is_empty());
+    }
+
+    // Without a history or trash there is nothing to restore from
+    let disabled_port = 9035;
+    setup_server(
+        disabled_port,
+        temp_dir.path().join("storage_without_history"),
+        HistoryConfig {
+            enabled: false,
+            ..HistoryConfig::default()
+        },
+        TrashConfig {
+            enabled: false,
+            ..TrashConfig::default()
+        },
+    )
+    .await?;
+    let client_c_dir = temp_dir.path().join("client_c");
+    std::fs::create_dir_all(&client_c_dir)?;
+    std::fs::write(client_c_dir.join("report.txt"), "good")?;
+    let client_c = SimpleClient::new(
+        format!("http://localhost:{}", disabled_port),
+        client_c_dir.clone(),
+    )
+    .with_directory("docs".to_string());
+    client_c.initial_sync().await?;
+    assert!(client_c.restore(None, good).await.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_failed_restore_keeps_what_was_restored() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9074;
+    setup_server(
+        port,
+        storage.clone(),
+        HistoryConfig::default(),
+        TrashConfig::default(),
+    )
+    .await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    common::upload(&http, &base, "docs", "a.txt", "a one").await?;
+    common::upload(&http, &base, "docs", "b.txt", "b one").await?;
+    let after_one = instant().await;
+    common::upload(&http, &base, "docs", "a.txt", "a two").await?;
+    common::upload(&http, &base, "docs", "b.txt", "b two").await?;
+
+    // The old version of b.txt went missing from storage
+    for entry in std::fs::read_dir(storage.join("docs/.syncpair/versions"))? {
+        let path = entry?.path();
+        if std::fs::read_to_string(&path)? == "b one" {
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    let response = http
+        .post(format!("{}/restore", base))
+        .json(&RestoreRequest {
+            directory: Some("docs".to_string()),
+            path: None,
+            at: after_one,
+            client_id: None,
+        })
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    let response: RestoreResponse = response.json().await?;
+    assert_eq!(response.restored, vec!["a.txt".to_string()]);
+    assert_eq!(response.unavailable, vec!["b.txt".to_string()]);
+
+    // The restored file was recorded in the saved directory state
+    let restarted_port = 9075;
+    setup_server(
+        restarted_port,
+        storage.clone(),
+        HistoryConfig::default(),
+        TrashConfig::default(),
+    )
+    .await?;
+    let response: SyncResponse = http
+        .post(format!("http://localhost:{}/sync", restarted_port))
+        .json(&SyncRequest {
+            files: Default::default(),
+            deleted_files: Default::default(),
+            deleted_versions: Default::default(),
+            deleted_base_hashes: Default::default(),
+            last_sync: chrono::Utc::now(),
+            client_id: None,
+            directory: Some("docs".to_string()),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    let recorded: Vec<_> = response
+        .files_to_download
+        .iter()
+        .map(|file| (file.path.as_str(), file.hash.clone()))
+        .collect();
+    assert!(
+        recorded.contains(&("a.txt", common::hash("a one"))),
+        "{:?}",
+        recorded
+    );
+    assert!(
+        recorded.contains(&("b.txt", common::hash("b two"))),
+        "{:?}",
+        recorded
+    );
+
+    Ok(())
+}
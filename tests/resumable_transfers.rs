@@ -0,0 +1,273 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use syncpair::client::SimpleClient;
+use syncpair::types::{
+    ByteRange, DownloadProgress, FileInfo, UploadProgress, UploadSessionRequest,
+    UploadSessionResponse, VersionVector,
+};
+use syncpair::utils::{
+    get_file_info, load_download_progress, load_upload_progress, partial_download_path,
+    save_download_progress, save_upload_progress,
+};
+
+#[path = "common/mod.rs"]
+mod common;
+
+/// Large enough to be transferred in two chunks
+const LARGE_FILE_SIZE: usize = 9 * 1024 * 1024;
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+fn large_content(seed: u8) -> Vec<u8> {
+    (0..LARGE_FILE_SIZE)
+        .map(|i| (i % 251) as u8 ^ seed)
+        .collect()
+}
+
+async fn open_session(
+    http: &reqwest::Client,
+    base: &str,
+    file_info: FileInfo,
+) -> Result<UploadSessionResponse> {
+    Ok(http
+        .post(format!("{}/uploads", base))
+        .json(&UploadSessionRequest {
+            file_info,
+            client_id: Some("alice:docs".to_string()),
+            directory: Some("docs".to_string()),
+            base_hash: None,
+        })
+        .send()
+        .await?
+        .json()
+        .await?)
+}
+
+async fn upload_chunk(
+    http: &reqwest::Client,
+    base: &str,
+    session_id: &str,
+    offset: u64,
+    content: &[u8],
+) -> Result<reqwest::Response> {
+    Ok(http
+        .put(format!("{}/uploads/{}?directory=docs", base, session_id))
+        .header("x-syncpair-offset", offset)
+        .body(content.to_vec())
+        .send()
+        .await?)
+}
+
+async fn download(http: &reqwest::Client, base: &str, path: &str) -> Result<reqwest::Response> {
+    Ok(http
+        .get(format!("{}/files/{}?directory=docs", base, path))
+        .send()
+        .await?)
+}
+
+#[tokio::test]
+async fn test_upload_sessions_track_received_ranges() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9044;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let content = b"0123456789";
+    let session = open_session(
+        &http,
+        &base,
+        FileInfo {
+            path: "a.txt".to_string(),
+            hash: common::hash(content),
+            size: content.len() as u64,
+            modified: chrono::Utc::now(),
+            version: Default::default(),
+        },
+    )
+    .await?;
+    assert!(session.success, "{}", session.message);
+    let session_id = session.session_id.expect("a session should be opened");
+
+    // Chunks can arrive in any order
+    let response: UploadSessionResponse = upload_chunk(&http, &base, &session_id, 6, b"6789")
+        .await?
+        .json()
+        .await?;
+    assert_eq!(response.received, vec![ByteRange { start: 6, end: 10 }]);
+    assert!(!response.completed);
+
+    // Bytes past the end of the file are refused
+    let response = upload_chunk(&http, &base, &session_id, 8, b"89X").await?;
+    assert_eq!(response.status(), 400);
+
+    let response: UploadSessionResponse = upload_chunk(&http, &base, &session_id, 0, b"0123")
+        .await?
+        .json()
+        .await?;
+    assert!(!response.completed);
+    let status: UploadSessionResponse = http
+        .get(format!("{}/uploads/{}?directory=docs", base, session_id))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(
+        status.received,
+        vec![
+            ByteRange { start: 0, end: 4 },
+            ByteRange { start: 6, end: 10 }
+        ]
+    );
+    // Nothing is stored until the file is complete
+    assert_eq!(download(&http, &base, "a.txt").await?.status(), 404);
+
+    let response: UploadSessionResponse = upload_chunk(&http, &base, &session_id, 4, b"45")
+        .await?
+        .json()
+        .await?;
+    assert!(response.completed, "{}", response.message);
+    assert_eq!(
+        download(&http, &base, "a.txt").await?.bytes().await?,
+        &content[..]
+    );
+
+    // A completed session is closed
+    let response = http
+        .get(format!("{}/uploads/{}?directory=docs", base, session_id))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_downloads_honor_range_requests() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9045;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let content = b"0123456789";
+    http.put(format!("{}/files/a.txt?directory=docs", base))
+        .header("x-syncpair-hash", common::hash(content))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(content.to_vec())
+        .send()
+        .await?;
+    let etag = format!("\"{}\"", common::hash(content));
+
+    let ranged = |range: &str, if_range: &str| {
+        http.get(format!("{}/files/a.txt?directory=docs", base))
+            .header("range", range.to_string())
+            .header("if-range", if_range.to_string())
+            .send()
+    };
+    let response = ranged("bytes=4-", &etag).await?;
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.headers()["content-range"], "bytes 4-9/10");
+    assert_eq!(response.headers()["etag"], etag.as_str());
+    assert_eq!(response.bytes().await?, &content[4..]);
+
+    // A range of another version gets the whole file
+    let response = ranged("bytes=4-", "\"other\"").await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.bytes().await?, &content[..]);
+
+    let response = ranged("bytes=10-", &etag).await?;
+    assert_eq!(response.status(), 416);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_resumes_interrupted_transfers() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9046;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+    let client = |dir: &PathBuf, id: &str| {
+        SimpleClient::new(base.clone(), dir.clone())
+            .with_directory("docs".to_string())
+            .with_client_id(format!("{}:docs", id))
+    };
+    let client_a = client(&client_a_dir, "alice");
+    let client_b = client(&client_b_dir, "bob");
+    let state_db = |dir: &Path| dir.join(".syncpair_state.db");
+
+    // An upload of big.bin broke off after its first chunk
+    let big = large_content(0);
+    std::fs::write(client_a_dir.join("big.bin"), &big)?;
+    let mut file_info = get_file_info(&client_a_dir.join("big.bin"), "big.bin")?;
+    file_info.version = VersionVector::default().incremented("alice:docs");
+    let session_id = open_session(&http, &base, file_info)
+        .await?
+        .session_id
+        .expect("a session should be opened");
+    let response: UploadSessionResponse =
+        upload_chunk(&http, &base, &session_id, 0, &big[..CHUNK_SIZE])
+            .await?
+            .json()
+            .await?;
+    assert!(!response.completed);
+    let progress = UploadProgress {
+        session_id: session_id.clone(),
+        hash: common::hash(&big),
+        received: CHUNK_SIZE as u64,
+    };
+    save_upload_progress(&state_db(&client_a_dir), "big.bin", Some(&progress))?;
+
+    // The next sync finishes that session instead of starting over
+    std::fs::write(client_a_dir.join("other.bin"), large_content(1))?;
+    client_a.initial_sync().await?;
+    let response = http
+        .get(format!("{}/uploads/{}?directory=docs", base, session_id))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+    assert_eq!(download(&http, &base, "big.bin").await?.bytes().await?, big);
+    assert!(load_upload_progress(&state_db(&client_a_dir))?.is_empty());
+
+    // Downloads continue from their partial file: a partial other.bin with
+    // corrupt first bytes spoils the download, which starts over next time
+    let partial = partial_download_path(&client_b_dir, "other.bin");
+    std::fs::create_dir_all(partial.parent().unwrap())?;
+    std::fs::write(&partial, vec![0u8; CHUNK_SIZE])?;
+    let progress = DownloadProgress {
+        hash: common::hash(large_content(1)),
+        received: CHUNK_SIZE as u64,
+    };
+    save_download_progress(&state_db(&client_b_dir), "other.bin", Some(&progress))?;
+    // A partial big.bin with the right first bytes completes
+    let partial = partial_download_path(&client_b_dir, "big.bin");
+    std::fs::write(&partial, &big[..CHUNK_SIZE])?;
+    let progress = DownloadProgress {
+        hash: common::hash(&big),
+        received: CHUNK_SIZE as u64,
+    };
+    save_download_progress(&state_db(&client_b_dir), "big.bin", Some(&progress))?;
+
+    let _ = client_b.initial_sync().await;
+    assert_eq!(std::fs::read(client_b_dir.join("big.bin"))?, big);
+    assert!(!partial.exists());
+    assert!(!client_b_dir.join("other.bin").exists());
+    assert!(load_download_progress(&state_db(&client_b_dir))?.is_empty());
+
+    client_b.initial_sync().await?;
+    assert_eq!(
+        std::fs::read(client_b_dir.join("other.bin"))?,
+        large_content(1)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,529 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use syncpair::s3::S3Storage;
+use syncpair::server::SimpleServer;
+use syncpair::storage::{is_not_found, LocalStorage, MemoryStorage, ObjectReader, StorageBackend};
+use syncpair::types::{DeleteRequest, S3Config, ServerConfig, VersionListResponse};
+use tokio::time::sleep;
+use warp::http::{HeaderMap, Method, Response, StatusCode};
+use warp::Filter;
+
+#[path = "common/mod.rs"]
+mod common;
+
+const BUCKET: &str = "syncpair-test";
+const ACCESS_KEY_ID: &str = "test-access-key";
+
+/// Objects of the S3 stand-in, by key
+type Bucket = Arc<Mutex<BTreeMap<String, (Vec<u8>, chrono::DateTime<chrono::Utc>)>>>;
+
+/// Serve a minimal S3-compatible object store on `port`, the way MinIO does for a
+/// single path-style bucket: PUT (and copy), GET with ranges, HEAD, DELETE and
+/// ListObjectsV2 in pages of two. Requests must carry SigV4 credentials.
+async fn start_s3_stand_in(port: u16) -> Result<Bucket> {
+    let bucket: Bucket = Arc::default();
+    let objects = bucket.clone();
+    let routes = warp::method()
+        .and(warp::path::full())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .map(
+            move |method: Method,
+                  path: warp::path::FullPath,
+                  query: String,
+                  headers: HeaderMap,
+                  body: warp::hyper::body::Bytes| {
+                handle_s3_request(&objects, method, path.as_str(), &query, &headers, body)
+            },
+        );
+    tokio::spawn(warp::serve(routes).run(([127, 0, 0, 1], port)));
+
+    sleep(Duration::from_millis(100)).await;
+    Ok(bucket)
+}
+
+fn handle_s3_request(
+    bucket: &Bucket,
+    method: Method,
+    path: &str,
+    query: &str,
+    headers: &HeaderMap,
+    body: warp::hyper::body::Bytes,
+) -> Response<Vec<u8>> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let signed = header("authorization").is_some_and(|authorization| {
+        authorization.starts_with(&format!("AWS4-HMAC-SHA256 Credential={}/", ACCESS_KEY_ID))
+    }) && header("x-amz-date").is_some()
+        && header("x-amz-content-sha256").is_some();
+    if !signed {
+        return s3_error(StatusCode::FORBIDDEN, "AccessDenied");
+    }
+
+    let path = urlencoding::decode(path).unwrap().into_owned();
+    let Some(key) = path
+        .strip_prefix('/')
+        .and_then(|path| path.strip_prefix(BUCKET))
+    else {
+        return s3_error(StatusCode::NOT_FOUND, "NoSuchBucket");
+    };
+    let key = key.strip_prefix('/').unwrap_or_default().to_string();
+    let query: BTreeMap<String, String> = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| {
+            (
+                urlencoding::decode(name).unwrap().into_owned(),
+                urlencoding::decode(value).unwrap().into_owned(),
+            )
+        })
+        .collect();
+    let mut objects = bucket.lock().unwrap();
+
+    match method {
+        Method::GET if key.is_empty() => {
+            let prefix = query.get("prefix").cloned().unwrap_or_default();
+            let after = query.get("continuation-token").cloned().unwrap_or_default();
+            let matching: Vec<_> = objects
+                .iter()
+                .filter(|(key, _)| key.starts_with(&prefix) && **key > after)
+                .collect();
+            let page = &matching[..matching.len().min(2)];
+            let mut xml = format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ListBucketResult><Name>{}</Name><IsTruncated>{}</IsTruncated>",
+                BUCKET,
+                matching.len() > page.len()
+            );
+            for (key, (content, modified)) in page {
+                xml.push_str(&format!(
+                    "<Contents><Key>{}</Key><Size>{}</Size><LastModified>{}</LastModified></Contents>",
+                    xml_escape(key),
+                    content.len(),
+                    modified.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+                ));
+            }
+            if matching.len() > page.len() {
+                let (last, _) = page.last().unwrap();
+                xml.push_str(&format!(
+                    "<NextContinuationToken>{}</NextContinuationToken>",
+                    xml_escape(last)
+                ));
+            }
+            xml.push_str("</ListBucketResult>");
+            Response::new(xml.into_bytes())
+        }
+        Method::GET | Method::HEAD => {
+            let Some((content, modified)) = objects.get(&key) else {
+                return s3_error(StatusCode::NOT_FOUND, "NoSuchKey");
+            };
+            let response = Response::builder().header(
+                "last-modified",
+                modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+            );
+            if method == Method::HEAD {
+                return response
+                    .header("content-length", content.len())
+                    .body(Vec::new())
+                    .unwrap();
+            }
+            match header("range").and_then(|range| range.strip_prefix("bytes=")) {
+                Some(range) => {
+                    let (start, end) = range.split_once('-').unwrap();
+                    let start: usize = start.parse().unwrap();
+                    let end = end.parse::<usize>().unwrap().min(content.len() - 1);
+                    response
+                        .status(StatusCode::PARTIAL_CONTENT)
+                        .body(content[start..=end].to_vec())
+                        .unwrap()
+                }
+                None => response.body(content.clone()).unwrap(),
+            }
+        }
+        Method::PUT => {
+            let content = match header("x-amz-copy-source") {
+                Some(source) => {
+                    let source = urlencoding::decode(source).unwrap();
+                    let source = source
+                        .trim_start_matches('/')
+                        .strip_prefix(&format!("{}/", BUCKET))
+                        .unwrap_or_default();
+                    match objects.get(source) {
+                        Some((content, _)) => content.clone(),
+                        None => return s3_error(StatusCode::NOT_FOUND, "NoSuchKey"),
+                    }
+                }
+                None => body.to_vec(),
+            };
+            objects.insert(key, (content, chrono::Utc::now()));
+            Response::new(Vec::new())
+        }
+        Method::DELETE => {
+            objects.remove(&key);
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Vec::new())
+                .unwrap()
+        }
+        _ => s3_error(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+    }
+}
+
+fn s3_error(status: StatusCode, code: &str) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .body(format!("<Error><Code>{}</Code></Error>", code).into_bytes())
+        .unwrap()
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn s3_config(port: u16, prefix: &str) -> S3Config {
+    S3Config {
+        endpoint: format!("http://127.0.0.1:{}", port),
+        bucket: BUCKET.to_string(),
+        region: "us-east-1".to_string(),
+        access_key_id: ACCESS_KEY_ID.to_string(),
+        secret_access_key: "test-secret-key".to_string(),
+        prefix: prefix.to_string(),
+    }
+}
+
+fn put(storage: &dyn StorageBackend, key: &str, content: &[u8]) -> Result<()> {
+    storage.put(key, &mut Cursor::new(content), content.len() as u64)
+}
+
+fn get(storage: &dyn StorageBackend, key: &str, range: Option<(u64, u64)>) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    storage.get(key, range)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+fn keys(storage: &dyn StorageBackend, prefix: &str) -> Result<Vec<String>> {
+    Ok(storage
+        .list(prefix)?
+        .into_iter()
+        .map(|object| object.key)
+        .collect())
+}
+
+/// The behavior every backend shares
+fn check_backend(storage: Arc<dyn StorageBackend>) -> Result<()> {
+    // Missing objects
+    assert!(storage.stat("docs/a.txt")?.is_none());
+    assert!(is_not_found(
+        &get(storage.as_ref(), "docs/a.txt", None).unwrap_err()
+    ));
+    assert!(keys(storage.as_ref(), "")?.is_empty());
+
+    // Whole objects and ranges
+    put(storage.as_ref(), "docs/a.txt", b"hello world")?;
+    let stat = storage.stat("docs/a.txt")?.unwrap();
+    assert_eq!(stat.key, "docs/a.txt");
+    assert_eq!(stat.size, 11);
+    assert!((chrono::Utc::now() - stat.modified).num_seconds().abs() < 60);
+    assert_eq!(get(storage.as_ref(), "docs/a.txt", None)?, b"hello world");
+    assert_eq!(get(storage.as_ref(), "docs/a.txt", Some((6, 5)))?, b"world");
+    assert_eq!(get(storage.as_ref(), "docs/a.txt", Some((3, 0)))?, b"");
+
+    // Overwriting replaces the content
+    put(storage.as_ref(), "docs/a.txt", b"hi")?;
+    assert_eq!(get(storage.as_ref(), "docs/a.txt", None)?, b"hi");
+
+    // Large objects stream through in parts
+    let large: Vec<u8> = (0..3 * 1024 * 1024 + 17).map(|n| (n % 251) as u8).collect();
+    put(storage.as_ref(), "docs/large.bin", &large)?;
+    assert_eq!(
+        storage.stat("docs/large.bin")?.unwrap().size,
+        large.len() as u64
+    );
+    assert_eq!(
+        common::hash(&get(storage.as_ref(), "docs/large.bin", None)?),
+        common::hash(&large)
+    );
+    let mut reader = ObjectReader::new(storage.clone(), "docs/large.bin", large.len() as u64);
+    reader.seek(SeekFrom::Start(2 * 1024 * 1024))?;
+    let mut part = vec![0u8; 1000];
+    reader.read_exact(&mut part)?;
+    assert_eq!(part, &large[2 * 1024 * 1024..2 * 1024 * 1024 + 1000]);
+
+    // Content shorter than announced is not stored
+    let mut short = Cursor::new(b"abc".to_vec());
+    assert!(storage.put("docs/short.txt", &mut short, 10).is_err());
+    assert!(storage.stat("docs/short.txt")?.is_none());
+
+    // Listing by prefix, sorted by key
+    put(storage.as_ref(), "docs/sub/b.txt", b"b")?;
+    put(storage.as_ref(), "docs-old/c.txt", b"c")?;
+    put(storage.as_ref(), "other/d.txt", b"d")?;
+    assert_eq!(
+        keys(storage.as_ref(), "docs/")?,
+        vec!["docs/a.txt", "docs/large.bin", "docs/sub/b.txt"]
+    );
+    assert_eq!(keys(storage.as_ref(), "docs")?.len(), 4);
+    assert_eq!(keys(storage.as_ref(), "")?.len(), 5);
+    assert!(keys(storage.as_ref(), "missing/")?.is_empty());
+    let listed = storage.list("docs/sub/")?;
+    assert_eq!(listed[0].size, 1);
+
+    // Renaming, into places that don't exist yet
+    storage.rename("docs/a.txt", "docs/moved/deeper/a.txt")?;
+    assert!(storage.stat("docs/a.txt")?.is_none());
+    assert_eq!(
+        get(storage.as_ref(), "docs/moved/deeper/a.txt", None)?,
+        b"hi"
+    );
+    assert!(is_not_found(
+        &storage.rename("docs/a.txt", "docs/b.txt").unwrap_err()
+    ));
+
+    // Deleting, which is fine for missing objects
+    storage.delete("docs/moved/deeper/a.txt")?;
+    storage.delete("docs/moved/deeper/a.txt")?;
+    assert!(storage.stat("docs/moved/deeper/a.txt")?.is_none());
+
+    // Keys can't escape the storage
+    for key in ["", "../a.txt", "docs/../../a.txt", "/a.txt", "docs//a.txt"] {
+        assert!(put(storage.as_ref(), key, b"x").is_err(), "{:?}", key);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_local_storage() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let storage = Arc::new(LocalStorage::new(temp_dir.path()));
+    check_backend(storage.clone())?;
+
+    // Objects are plain files, and staging files aren't objects
+    assert_eq!(std::fs::read(temp_dir.path().join("docs/sub/b.txt"))?, b"b");
+    std::fs::write(temp_dir.path().join("docs/.syncpair-tmp-1-1"), "partial")?;
+    assert_eq!(keys(storage.as_ref(), "docs/")?.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_memory_storage() -> Result<()> {
+    check_backend(Arc::new(MemoryStorage::new()))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_s3_storage() -> Result<()> {
+    let port = 9060;
+    let bucket = start_s3_stand_in(port).await?;
+
+    tokio::task::spawn_blocking(move || {
+        check_backend(Arc::new(S3Storage::new(&s3_config(port, ""))?))
+    })
+    .await??;
+    assert_eq!(bucket.lock().unwrap().len(), 4);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_s3_storage_prefix_and_credentials() -> Result<()> {
+    let port = 9061;
+    let bucket = start_s3_stand_in(port).await?;
+
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let storage = S3Storage::new(&s3_config(port, "/team/syncpair/"))?;
+        let other = S3Storage::new(&s3_config(port, "team/other"))?;
+        put(&storage, "docs/a.txt", b"a")?;
+        put(&other, "docs/b.txt", b"b")?;
+
+        // Each storage sees only the objects under its prefix
+        assert_eq!(keys(&storage, "")?, vec!["docs/a.txt"]);
+        assert_eq!(keys(&other, "docs/")?, vec!["docs/b.txt"]);
+        assert!(storage.stat("docs/b.txt")?.is_none());
+        storage.rename("docs/a.txt", "docs/c.txt")?;
+        assert_eq!(get(&storage, "docs/c.txt", None)?, b"a");
+
+        // Requests need valid credentials
+        let anonymous = S3Storage::new(&S3Config {
+            access_key_id: "someone-else".to_string(),
+            ..s3_config(port, "team/syncpair")
+        })?;
+        assert!(anonymous.stat("docs/c.txt").is_err());
+        assert!(put(&anonymous, "docs/d.txt", b"d").is_err());
+        Ok(())
+    })
+    .await??;
+
+    let stored: Vec<String> = bucket.lock().unwrap().keys().cloned().collect();
+    assert_eq!(
+        stored,
+        vec!["team/other/docs/b.txt", "team/syncpair/docs/c.txt"]
+    );
+
+    Ok(())
+}
+
+async fn download(
+    http: &reqwest::Client,
+    base: &str,
+    path: &str,
+    range: Option<&str>,
+) -> Result<Vec<u8>> {
+    let mut request = http.get(format!("{}/{}", base, path));
+    if let Some(range) = range {
+        request = request.header("range", range);
+    }
+    let response = request.send().await?;
+    assert!(response.status().is_success(), "{}", response.status());
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// The files the server keeps in its local storage directory
+fn local_files(storage_dir: &std::path::Path) -> Vec<String> {
+    walkdir::WalkDir::new(storage_dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Upload a file, replace and delete it, and read back every version
+async fn exercise_server(base: &str) -> Result<()> {
+    let http = reqwest::Client::new();
+    common::upload(&http, base, "docs", "notes%2Fa.txt", b"first version").await?;
+    common::upload(&http, base, "docs", "notes%2Fa.txt", b"second version").await?;
+
+    let file_url = "files/notes%2Fa.txt?directory=docs";
+    assert_eq!(
+        download(&http, base, file_url, None).await?,
+        b"second version"
+    );
+    assert_eq!(
+        download(&http, base, file_url, Some("bytes=7-")).await?,
+        b"version"
+    );
+
+    let versions: VersionListResponse = http
+        .get(format!("{}/versions/notes%2Fa.txt?directory=docs", base))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(versions.versions.len(), 1);
+    let version_url = format!(
+        "versions/notes%2Fa.txt/{}?directory=docs",
+        versions.versions[0].id
+    );
+    assert_eq!(
+        download(&http, base, &version_url, None).await?,
+        b"first version"
+    );
+
+    let response = http
+        .post(format!("{}/delete", base))
+        .json(&DeleteRequest {
+            path: "notes/a.txt".to_string(),
+            client_id: None,
+            directory: Some("docs".to_string()),
+            base_hash: None,
+            version: Default::default(),
+        })
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    let response = http.get(format!("{}/{}", base, file_url)).send().await?;
+    assert_eq!(response.status(), 404);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_server_keeps_content_in_storage_backend() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage_dir = temp_dir.path().join("storage");
+    let storage = Arc::new(MemoryStorage::new());
+    let server = SimpleServer::new(storage_dir.clone())?.with_storage(storage.clone())?;
+    let port = 9062;
+    common::start_server(port, server).await?;
+
+    exercise_server(&format!("http://localhost:{}", port)).await?;
+
+    // The current file went to the trash, its old content to the history
+    let stored = keys(storage.as_ref(), "docs/")?;
+    assert_eq!(stored.len(), 2, "{:?}", stored);
+    assert!(stored[0].starts_with("docs/.syncpair/trash/"));
+    assert!(stored[1].starts_with("docs/.syncpair/versions/"));
+    // Only the directory state is kept locally
+    let local = local_files(&storage_dir);
+    assert!(
+        local.iter().all(|name| name.starts_with("server_state.db")),
+        "{:?}",
+        local
+    );
+
+    Ok(())
+}
+
+// On a single thread, shared with the object store stand-in, so a server that
+// blocked it while waiting for the object store would never get an answer
+#[tokio::test]
+async fn test_server_with_s3_storage_and_dedup() -> Result<()> {
+    common::init_test_logging();
+    let s3_port = 9063;
+    let bucket = start_s3_stand_in(s3_port).await?;
+    let temp_dir = common::create_temp_dir()?;
+    let storage_dir = temp_dir.path().join("storage");
+    let config: ServerConfig = serde_yaml::from_str(&format!(
+        r#"
+storage:
+  backend: s3
+  endpoint: http://127.0.0.1:{}
+  bucket: {}
+  access_key_id: {}
+  secret_access_key: test-secret-key
+  prefix: server
+dedup:
+  enabled: true
+"#,
+        s3_port, BUCKET, ACCESS_KEY_ID
+    ))?;
+    let server = {
+        let storage_dir = storage_dir.clone();
+        tokio::task::spawn_blocking(move || SimpleServer::from_config(storage_dir, &config))
+            .await??
+    };
+    let port = 9064;
+    common::start_server(port, server.clone()).await?;
+
+    exercise_server(&format!("http://localhost:{}", port)).await?;
+
+    // File content is in chunks in the bucket; nothing is stored locally
+    let stored: Vec<String> = bucket.lock().unwrap().keys().cloned().collect();
+    assert!(stored
+        .iter()
+        .any(|key| key.starts_with("server/.syncpair/chunks/")));
+    assert!(stored.iter().all(|key| key.starts_with("server/")));
+    let local = local_files(&storage_dir);
+    assert!(
+        local.iter().all(|name| name.starts_with("server_state.db")),
+        "{:?}",
+        local
+    );
+
+    // Chunks of the history and trash stay referenced
+    let removed = tokio::task::spawn_blocking(move || -> Result<usize> {
+        server.collect_chunk_garbage()?;
+        server.collect_chunk_garbage()
+    })
+    .await??;
+    assert_eq!(removed, 0);
+
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use syncpair::types::{DownloadResponse, UploadResponse};
+
+#[path = "common/mod.rs"]
+mod common;
+
+fn binary_content(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i * 7 % 256) as u8).collect()
+}
+
+#[tokio::test]
+async fn test_raw_upload_and_download() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9019;
+    common::setup_server(port, storage.clone()).await?;
+    let http = reqwest::Client::new();
+    let url = format!(
+        "http://localhost:{}/files/{}?directory=media",
+        port,
+        urlencoding::encode("videos/clip one.bin")
+    );
+
+    let content = binary_content(3 * 1024 * 1024 + 17);
+    let modified = chrono::Utc::now();
+    let response = http
+        .put(&url)
+        .header("content-type", "application/octet-stream")
+        .header("x-syncpair-hash", common::hash(&content))
+        .header("x-syncpair-modified", modified.to_rfc3339())
+        .body(content.clone())
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    let upload: UploadResponse = response.json().await?;
+    assert!(upload.success, "{}", upload.message);
+    assert_eq!(
+        std::fs::read(storage.join("media/videos/clip one.bin"))?,
+        content
+    );
+
+    // The raw download carries the metadata in headers
+    let response = http.get(&url).send().await?;
+    assert_eq!(response.status(), 200);
+    let headers = response.headers().clone();
+    assert_eq!(headers["content-type"], "application/octet-stream");
+    assert_eq!(
+        headers["content-length"],
+        content.len().to_string().as_str()
+    );
+    assert_eq!(headers["x-syncpair-hash"], common::hash(&content).as_str());
+    assert_eq!(
+        urlencoding::decode(headers["x-syncpair-path"].to_str()?)?,
+        "videos/clip one.bin"
+    );
+    assert_eq!(response.bytes().await?.as_ref(), content.as_slice());
+
+    // The JSON endpoint still serves the same file
+    let response: DownloadResponse = http
+        .get(format!(
+            "http://localhost:{}/download/{}?directory=media",
+            port,
+            urlencoding::encode("videos/clip one.bin")
+        ))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(response.success);
+    assert_eq!(response.content.unwrap(), content);
+    let file_info = response.file_info.unwrap();
+    assert_eq!(file_info.hash, common::hash(&content));
+    assert_eq!(file_info.size, content.len() as u64);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_raw_endpoints_reject_bad_requests() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9020;
+    common::setup_server(port, storage.clone()).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+    let content = b"some bytes".to_vec();
+
+    // Missing metadata headers
+    let response = http
+        .put(format!("{}/files/a.bin?directory=media", base))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(content.clone())
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    let response = http
+        .put(format!("{}/files/a.bin?directory=media", base))
+        .header("x-syncpair-hash", common::hash(&content))
+        .header("x-syncpair-modified", "yesterday")
+        .body(content.clone())
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    // Missing directory
+    let response = http
+        .put(format!("{}/files/a.bin", base))
+        .header("x-syncpair-hash", common::hash(&content))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(content.clone())
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    // Escaping paths
+    let response = http
+        .put(format!(
+            "{}/files/{}?directory=media",
+            base,
+            urlencoding::encode("../escape.bin")
+        ))
+        .header("x-syncpair-hash", common::hash(&content))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(content.clone())
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+    assert!(!storage.join("escape.bin").exists());
+
+    // Content that doesn't match the announced hash is not recorded
+    let response = http
+        .put(format!("{}/files/a.bin?directory=media", base))
+        .header("x-syncpair-hash", common::hash(b"other bytes"))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .body(content.clone())
+        .send()
+        .await?;
+    let upload: UploadResponse = response.json().await?;
+    assert!(!upload.success);
+    assert!(upload.message.contains("Hash mismatch"));
+
+    // Unknown files
+    let response = http
+        .get(format!("{}/files/missing.bin?directory=media", base))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+    let download: DownloadResponse = response.json().await?;
+    assert!(!download.success);
+
+    let response = http
+        .get(format!(
+            "{}/files/{}?directory=media",
+            base,
+            urlencoding::encode("../storage/media/a.bin")
+        ))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 400);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_cors_preflight_allows_metadata_headers() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9067;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+
+    let response = http
+        .request(
+            reqwest::Method::OPTIONS,
+            format!("http://localhost:{}/files/a.bin?directory=media", port),
+        )
+        .header("origin", "http://example.com")
+        .header("access-control-request-method", "PUT")
+        .header(
+            "access-control-request-headers",
+            "x-syncpair-hash,x-syncpair-modified,x-syncpair-base-hash,x-syncpair-version",
+        )
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    let allowed = response
+        .headers()
+        .get("access-control-allow-headers")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    for header in [
+        "x-syncpair-hash",
+        "x-syncpair-base-hash",
+        "x-syncpair-offset",
+    ] {
+        assert!(
+            allowed.contains(header),
+            "{} not allowed: {}",
+            header,
+            allowed
+        );
+    }
+
+    Ok(())
+}
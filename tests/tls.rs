@@ -0,0 +1,463 @@
+use anyhow::Result;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType, IsCa};
+use std::path::{Path, PathBuf};
+use syncpair::client::SimpleClient;
+use syncpair::server::SimpleServer;
+use syncpair::tls::{
+    certificate_fingerprint, client_tls_config, load_certificates, ClientTlsOptions,
+};
+use syncpair::types::ServerConfig;
+
+#[path = "common/mod.rs"]
+mod common;
+
+/// Certificate and key files for a server, plus the CA that signed it (if any)
+struct TestCerts {
+    cert: PathBuf,
+    key: PathBuf,
+    ca: Option<PathBuf>,
+}
+
+fn write_self_signed(dir: &Path) -> Result<TestCerts> {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+    std::fs::write(dir.join("server.pem"), cert.serialize_pem()?)?;
+    std::fs::write(dir.join("server.key"), cert.serialize_private_key_pem())?;
+    Ok(TestCerts {
+        cert: dir.join("server.pem"),
+        key: dir.join("server.key"),
+        ca: None,
+    })
+}
+
+fn new_ca() -> Result<Certificate> {
+    let mut ca_params = CertificateParams::new(Vec::new());
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    Ok(Certificate::from_params(ca_params)?)
+}
+
+fn write_ca_signed(dir: &Path) -> Result<TestCerts> {
+    let ca = new_ca()?;
+
+    let cert = Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()]))?;
+    std::fs::write(dir.join("ca.pem"), ca.serialize_pem()?)?;
+    std::fs::write(dir.join("server.pem"), cert.serialize_pem_with_signer(&ca)?)?;
+    std::fs::write(dir.join("server.key"), cert.serialize_private_key_pem())?;
+    Ok(TestCerts {
+        cert: dir.join("server.pem"),
+        key: dir.join("server.key"),
+        ca: Some(dir.join("ca.pem")),
+    })
+}
+
+/// Write a client certificate signed by `ca`, named by its CN or, without a CN, by a DNS SAN
+fn write_client_cert(
+    dir: &Path,
+    ca: &Certificate,
+    name: &str,
+    common_name: bool,
+) -> Result<(PathBuf, PathBuf)> {
+    let mut params = if common_name {
+        CertificateParams::new(Vec::new())
+    } else {
+        CertificateParams::new(vec![name.to_string()])
+    };
+    params.distinguished_name = DistinguishedName::new();
+    if common_name {
+        params.distinguished_name.push(DnType::CommonName, name);
+    }
+    let cert = Certificate::from_params(params)?;
+
+    let cert_path = dir.join(format!("{}.pem", name));
+    let key_path = dir.join(format!("{}.key", name));
+    std::fs::write(&cert_path, cert.serialize_pem_with_signer(ca)?)?;
+    std::fs::write(&key_path, cert.serialize_private_key_pem())?;
+    Ok((cert_path, key_path))
+}
+
+async fn setup_tls_server(port: u16, storage_dir: PathBuf, certs: &TestCerts) -> Result<()> {
+    let server = SimpleServer::new(storage_dir)?.with_tls(&certs.cert, &certs.key, None)?;
+    common::start_server(port, server).await
+}
+
+fn verify_with(ca_cert: Option<&Path>, pinned: Option<&str>) -> Result<rustls::ClientConfig> {
+    client_tls_config(&ClientTlsOptions {
+        ca_cert: ca_cert.map(Path::to_path_buf),
+        pinned_cert_sha256: pinned.map(str::to_string),
+        ..Default::default()
+    })
+}
+
+fn fingerprint_of(cert_path: &Path) -> Result<String> {
+    Ok(certificate_fingerprint(&load_certificates(cert_path)?[0]))
+}
+
+#[tokio::test]
+async fn test_sync_over_https_with_custom_ca() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let certs = write_ca_signed(temp_dir.path())?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9014;
+    setup_tls_server(port, temp_dir.path().join("storage"), &certs).await?;
+    let server_url = format!("https://localhost:{}", port);
+    let tls_config = verify_with(certs.ca.as_deref(), None)?;
+
+    let client_a = SimpleClient::new(server_url.clone(), client_a_dir.clone())
+        .with_directory("secure".to_string())
+        .with_tls_config(tls_config.clone())?;
+    let client_b = SimpleClient::new(server_url, client_b_dir.clone())
+        .with_directory("secure".to_string())
+        .with_tls_config(tls_config)?;
+
+    std::fs::write(client_a_dir.join("secret.txt"), "encrypted in transit")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("secret.txt"))?,
+        "encrypted in transit"
+    );
+
+    // Plain HTTP is not served on the TLS port
+    let response = reqwest::Client::new()
+        .get(format!(
+            "http://localhost:{}/download/secret.txt?directory=secure",
+            port
+        ))
+        .send()
+        .await;
+    assert!(response.is_err() || !response?.status().is_success());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_untrusted_certificate_is_rejected() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let server_certs = write_self_signed(temp_dir.path())?;
+    let other_dir = temp_dir.path().join("other");
+    std::fs::create_dir_all(&other_dir)?;
+    let other_certs = write_ca_signed(&other_dir)?;
+    let client_dir = temp_dir.path().join("client");
+    std::fs::create_dir_all(&client_dir)?;
+    std::fs::write(client_dir.join("file.txt"), "data")?;
+
+    let port = 9015;
+    setup_tls_server(port, temp_dir.path().join("storage"), &server_certs).await?;
+    let server_url = format!("https://localhost:{}", port);
+
+    // System roots don't know the self-signed certificate
+    let client = SimpleClient::new(server_url.clone(), client_dir.clone())
+        .with_directory("secure".to_string());
+    assert!(client.initial_sync().await.is_err());
+
+    // Neither does an unrelated CA
+    let client = SimpleClient::new(server_url, client_dir)
+        .with_directory("secure".to_string())
+        .with_tls_config(verify_with(other_certs.ca.as_deref(), None)?)?;
+    assert!(client.initial_sync().await.is_err());
+
+    assert!(!temp_dir.path().join("storage/secure/file.txt").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_certificate_pinning() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let certs = write_self_signed(temp_dir.path())?;
+    let client_dir = temp_dir.path().join("client");
+    std::fs::create_dir_all(&client_dir)?;
+    std::fs::write(client_dir.join("file.txt"), "pinned")?;
+
+    let port = 9016;
+    setup_tls_server(port, temp_dir.path().join("storage"), &certs).await?;
+    let server_url = format!("https://localhost:{}", port);
+
+    assert!(verify_with(None, Some("not-a-fingerprint")).is_err());
+
+    // A different certificate's fingerprint must not be accepted
+    let wrong_fingerprint = "00".repeat(32);
+    let client = SimpleClient::new(server_url.clone(), client_dir.clone())
+        .with_directory("secure".to_string())
+        .with_tls_config(verify_with(None, Some(&wrong_fingerprint))?)?;
+    assert!(client.initial_sync().await.is_err());
+    assert!(!temp_dir.path().join("storage/secure/file.txt").exists());
+
+    // The openssl "AB:CD:..." notation is accepted too
+    let fingerprint = fingerprint_of(&certs.cert)?;
+    let openssl_style = fingerprint
+        .to_uppercase()
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| std::str::from_utf8(pair).unwrap())
+        .collect::<Vec<_>>()
+        .join(":");
+    let client = SimpleClient::new(server_url, client_dir)
+        .with_directory("secure".to_string())
+        .with_tls_config(verify_with(None, Some(&openssl_style))?)?;
+    client.initial_sync().await?;
+
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.path().join("storage/secure/file.txt"))?,
+        "pinned"
+    );
+    Ok(())
+}
+
+const MTLS_SERVER_CONFIG: &str = r#"
+auth:
+  identities:
+    - name: alice-laptop
+      permissions:
+        - directory: "team_*"
+          access: write
+    - name: bob-laptop
+      tokens: ["bob-token"]
+      permissions:
+        - directory: "team_*"
+          access: read
+"#;
+
+async fn setup_mtls_server(
+    port: u16,
+    storage_dir: PathBuf,
+    certs: &TestCerts,
+    client_ca: &Path,
+) -> Result<()> {
+    let config: ServerConfig = serde_yaml::from_str(MTLS_SERVER_CONFIG)?;
+    let server = SimpleServer::from_config(storage_dir, &config)?.with_tls(
+        &certs.cert,
+        &certs.key,
+        Some(client_ca),
+    )?;
+    common::start_server(port, server).await
+}
+
+fn client_with_cert(
+    server_url: &str,
+    local_dir: &Path,
+    directory: &str,
+    ca_cert: &Path,
+    client_cert: Option<&(PathBuf, PathBuf)>,
+) -> Result<SimpleClient> {
+    std::fs::create_dir_all(local_dir)?;
+    let tls_config = client_tls_config(&ClientTlsOptions {
+        ca_cert: Some(ca_cert.to_path_buf()),
+        client_cert: client_cert.map(|(cert, _)| cert.clone()),
+        client_key: client_cert.map(|(_, key)| key.clone()),
+        ..Default::default()
+    })?;
+    SimpleClient::new(server_url.to_string(), local_dir.to_path_buf())
+        .with_directory(directory.to_string())
+        .with_tls_config(tls_config)
+}
+
+#[tokio::test]
+async fn test_client_certificate_identity() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let base = temp_dir.path();
+    let certs = write_ca_signed(base)?;
+    let server_ca = certs.ca.clone().unwrap();
+    let storage = base.join("storage");
+
+    let client_ca = new_ca()?;
+    std::fs::write(base.join("client_ca.pem"), client_ca.serialize_pem()?)?;
+    let alice = write_client_cert(base, &client_ca, "alice-laptop", true)?;
+    let bob = write_client_cert(base, &client_ca, "bob-laptop", false)?;
+
+    let port = 9017;
+    setup_mtls_server(port, storage.clone(), &certs, &base.join("client_ca.pem")).await?;
+    let server_url = format!("https://localhost:{}", port);
+
+    // Alice is known by the CN of her certificate
+    let alice_team = base.join("alice_team");
+    let alice_private = base.join("alice_private");
+    let alice_team_client = client_with_cert(
+        &server_url,
+        &alice_team,
+        "team_docs",
+        &server_ca,
+        Some(&alice),
+    )?;
+    let alice_private_client = client_with_cert(
+        &server_url,
+        &alice_private,
+        "alice-laptop:notes",
+        &server_ca,
+        Some(&alice),
+    )?;
+    std::fs::write(alice_team.join("plan.txt"), "team plan")?;
+    std::fs::write(alice_private.join("diary.txt"), "private")?;
+    alice_team_client.initial_sync().await?;
+    alice_private_client.initial_sync().await?;
+    assert!(storage.join("team_docs/plan.txt").exists());
+    assert!(storage.join("alice-laptop:notes/diary.txt").exists());
+
+    // Bob (known by a DNS SAN) can't reach Alice's private directory...
+    let bob_private = base.join("bob_private");
+    let bob_as_alice = client_with_cert(
+        &server_url,
+        &bob_private,
+        "alice-laptop:notes",
+        &server_ca,
+        Some(&bob),
+    )?
+    .with_client_id("alice-laptop:notes".to_string());
+    let error = bob_as_alice.initial_sync().await.unwrap_err();
+    assert!(error.to_string().contains("403"), "{}", error);
+
+    // ...and only has read access to the shared one
+    let bob_team = base.join("bob_team");
+    let bob_team_client =
+        client_with_cert(&server_url, &bob_team, "team_docs", &server_ca, Some(&bob))?;
+    std::fs::write(bob_team.join("bob.txt"), "from bob")?;
+    bob_team_client.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(bob_team.join("plan.txt"))?,
+        "team plan"
+    );
+    assert!(!storage.join("team_docs/bob.txt").exists());
+
+    // Without a certificate or token the request is anonymous
+    let anonymous = client_with_cert(
+        &server_url,
+        &base.join("anonymous"),
+        "team_docs",
+        &server_ca,
+        None,
+    )?;
+    let error = anonymous.initial_sync().await.unwrap_err();
+    assert!(error.to_string().contains("401"), "{}", error);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unknown_client_certificates() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let base = temp_dir.path();
+    let certs = write_ca_signed(base)?;
+    let server_ca = certs.ca.clone().unwrap();
+    let storage = base.join("storage");
+
+    let client_ca = new_ca()?;
+    std::fs::write(base.join("client_ca.pem"), client_ca.serialize_pem()?)?;
+    let mallory = write_client_cert(base, &client_ca, "mallory", true)?;
+    let rogue_ca = new_ca()?;
+    let rogue_dir = base.join("rogue");
+    std::fs::create_dir_all(&rogue_dir)?;
+    let forged = write_client_cert(&rogue_dir, &rogue_ca, "alice-laptop", true)?;
+
+    let port = 9018;
+    setup_mtls_server(port, storage.clone(), &certs, &base.join("client_ca.pem")).await?;
+    let server_url = format!("https://localhost:{}", port);
+
+    // A certificate from another CA never completes the handshake
+    let forged_dir = base.join("forged");
+    let forged_client = client_with_cert(
+        &server_url,
+        &forged_dir,
+        "alice-laptop:notes",
+        &server_ca,
+        Some(&forged),
+    )?;
+    std::fs::write(forged_dir.join("evil.txt"), "evil")?;
+    assert!(forged_client.initial_sync().await.is_err());
+    assert!(!storage.join("alice-laptop:notes").exists());
+
+    // A valid certificate without configured permissions only owns its private directories
+    let mallory_private = base.join("mallory_private");
+    let mallory_client = client_with_cert(
+        &server_url,
+        &mallory_private,
+        "mallory:stuff",
+        &server_ca,
+        Some(&mallory),
+    )?;
+    std::fs::write(mallory_private.join("mine.txt"), "mine")?;
+    mallory_client.initial_sync().await?;
+    assert!(storage.join("mallory:stuff/mine.txt").exists());
+
+    let mallory_team = client_with_cert(
+        &server_url,
+        &base.join("mallory_team"),
+        "team_docs",
+        &server_ca,
+        Some(&mallory),
+    )?;
+    let error = mallory_team.initial_sync().await.unwrap_err();
+    assert!(error.to_string().contains("403"), "{}", error);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_client_certificates_without_auth_section() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let base = temp_dir.path();
+    let certs = write_ca_signed(base)?;
+    let server_ca = certs.ca.clone().unwrap();
+    let storage = base.join("storage");
+
+    let client_ca = new_ca()?;
+    std::fs::write(base.join("client_ca.pem"), client_ca.serialize_pem()?)?;
+    let alice = write_client_cert(base, &client_ca, "alice-laptop", true)?;
+    let mallory = write_client_cert(base, &client_ca, "mallory", true)?;
+
+    // Client certificates alone, with no identities configured
+    let port = 9072;
+    let server = SimpleServer::new(storage.clone())?.with_tls(
+        &certs.cert,
+        &certs.key,
+        Some(&base.join("client_ca.pem")),
+    )?;
+    common::start_server(port, server).await?;
+    let server_url = format!("https://localhost:{}", port);
+
+    let alice_private = base.join("alice_private");
+    let alice_client = client_with_cert(
+        &server_url,
+        &alice_private,
+        "alice-laptop:notes",
+        &server_ca,
+        Some(&alice),
+    )?;
+    std::fs::write(alice_private.join("diary.txt"), "private")?;
+    alice_client.initial_sync().await?;
+    assert!(storage.join("alice-laptop:notes/diary.txt").exists());
+
+    // Another certificate can't reach alice's private directory...
+    let mallory_client = client_with_cert(
+        &server_url,
+        &base.join("mallory"),
+        "alice-laptop:notes",
+        &server_ca,
+        Some(&mallory),
+    )?
+    .with_client_id("alice-laptop:notes".to_string());
+    let error = mallory_client.initial_sync().await.unwrap_err();
+    assert!(error.to_string().contains("403"), "{}", error);
+
+    // ...and neither can a client without one
+    let anonymous = client_with_cert(
+        &server_url,
+        &base.join("anonymous"),
+        "alice-laptop:notes",
+        &server_ca,
+        None,
+    )?
+    .with_client_id("alice-laptop:notes".to_string());
+    let error = anonymous.initial_sync().await.unwrap_err();
+    assert!(error.to_string().contains("401"), "{}", error);
+
+    Ok(())
+}
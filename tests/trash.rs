@@ -0,0 +1,228 @@
+use anyhow::Result;
+use std::sync::Arc;
+use syncpair::client::SimpleClient;
+use syncpair::history::VersionHistory;
+use syncpair::storage::{LocalStorage, StorageBackend};
+use syncpair::trash::Trash;
+use syncpair::types::{
+    ChangeKind, DeleteRequest, FileInfo, TrashConfig, TrashRequest, TrashResponse,
+    VersionListResponse, VersionVector,
+};
+
+#[path = "common/mod.rs"]
+mod common;
+
+async fn delete(http: &reqwest::Client, base: &str, path: &str) -> Result<()> {
+    let response = http
+        .post(format!("{}/delete", base))
+        .json(&DeleteRequest {
+            path: path.to_string(),
+            client_id: None,
+            directory: Some("docs".to_string()),
+            base_hash: None,
+            version: Default::default(),
+        })
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    Ok(())
+}
+
+async fn list_trash(http: &reqwest::Client, base: &str) -> Result<TrashResponse> {
+    let response = http
+        .get(format!("{}/trash?directory=docs", base))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    Ok(response.json().await?)
+}
+
+fn trash_request(id: Option<u64>) -> TrashRequest {
+    TrashRequest {
+        directory: Some("docs".to_string()),
+        id,
+        client_id: None,
+    }
+}
+
+#[tokio::test]
+async fn test_deleted_files_go_to_trash() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9036;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    common::upload(&http, &base, "docs", "notes%2Fa.txt", "a").await?;
+    common::upload(&http, &base, "docs", "b.txt", "b").await?;
+    common::upload(&http, &base, "docs", "c.txt", "c").await?;
+    delete(&http, &base, "notes/a.txt").await?;
+    delete(&http, &base, "b.txt").await?;
+
+    let items = list_trash(&http, &base).await?.items;
+    let paths: Vec<_> = items
+        .iter()
+        .map(|item| item.file_info.path.clone())
+        .collect();
+    assert_eq!(paths, vec!["b.txt".to_string(), "notes/a.txt".to_string()]);
+    assert_eq!(items[1].file_info.hash, common::hash("a"));
+    // Deletions are in the trash, not the history
+    let versions: VersionListResponse = http
+        .get(format!("{}/versions/notes%2Fa.txt?directory=docs", base))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(versions.versions.is_empty());
+
+    // A restored file is back at its path
+    let response = http
+        .post(format!("{}/trash/restore", base))
+        .json(&trash_request(Some(items[1].id)))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    let response = http
+        .get(format!("{}/files/notes%2Fa.txt?directory=docs", base))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.text().await?, "a");
+    let response = http
+        .post(format!("{}/trash/restore", base))
+        .json(&trash_request(Some(items[1].id)))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 404);
+
+    // A file created at the path since is never overwritten
+    common::upload(&http, &base, "docs", "b.txt", "new b").await?;
+    let response = http
+        .post(format!("{}/trash/restore", base))
+        .json(&trash_request(Some(items[0].id)))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 409);
+
+    // Purging removes one file, or everything
+    delete(&http, &base, "c.txt").await?;
+    let response: TrashResponse = http
+        .post(format!("{}/trash/purge", base))
+        .json(&trash_request(Some(items[0].id)))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(response.items.len(), 1);
+    let response: TrashResponse = http
+        .post(format!("{}/trash/purge", base))
+        .json(&trash_request(None))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(response.items[0].file_info.path, "c.txt");
+    assert!(list_trash(&http, &base).await?.items.is_empty());
+    let stored = std::fs::read_dir(temp_dir.path().join("storage/docs/.syncpair/trash"))?.count();
+    assert_eq!(stored, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_files_deleted_by_a_client_can_be_restored() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9037;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let base = format!("http://localhost:{}", port);
+    let client_a = SimpleClient::new(base.clone(), client_a_dir.clone())
+        .with_directory("docs".to_string())
+        .with_client_id("alice:docs".to_string());
+    let client_b = SimpleClient::new(base.clone(), client_b_dir.clone())
+        .with_directory("docs".to_string())
+        .with_client_id("bob:docs".to_string());
+
+    std::fs::write(client_a_dir.join("report.txt"), "report")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    std::fs::remove_file(client_b_dir.join("report.txt"))?;
+    client_b.initial_sync().await?;
+    client_a.initial_sync().await?;
+    assert!(!client_a_dir.join("report.txt").exists());
+
+    let http = reqwest::Client::new();
+    let items = list_trash(&http, &base).await?.items;
+    assert_eq!(items.len(), 1);
+    let response = http
+        .post(format!("{}/trash/restore", base))
+        .json(&trash_request(Some(items[0].id)))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+
+    for (client, dir) in [(&client_a, &client_a_dir), (&client_b, &client_b_dir)] {
+        client.initial_sync().await?;
+        assert_eq!(std::fs::read_to_string(dir.join("report.txt"))?, "report");
+        assert!(client.conflicts()?.is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_expired_trash_moves_to_history() -> Result<()> {
+    let temp_dir = common::create_temp_dir()?;
+    let storage: Arc<dyn StorageBackend> = Arc::new(LocalStorage::new(temp_dir.path()));
+    let conn = rusqlite::Connection::open(temp_dir.path().join("server_state.db"))?;
+    Trash::create_schema(&conn)?;
+    VersionHistory::create_schema(&conn)?;
+    let trash = Trash::open(temp_dir.path(), storage.clone(), "")?;
+
+    let mut ids = Vec::new();
+    for name in ["old.txt", "recent.txt"] {
+        let file_path = temp_dir.path().join(name);
+        std::fs::write(&file_path, name)?;
+        let file_info = FileInfo {
+            path: name.to_string(),
+            hash: common::hash(name),
+            size: name.len() as u64,
+            modified: chrono::Utc::now(),
+            version: VersionVector::default(),
+        };
+        ids.push(trash.put(name, &file_info)?);
+        assert!(!file_path.exists());
+    }
+    let deleted_at = chrono::Utc::now() - chrono::Duration::days(31);
+    conn.execute(
+        "UPDATE trashed_files SET deleted_at = ? WHERE id = ?",
+        rusqlite::params![deleted_at.timestamp_millis(), ids[0] as i64],
+    )?;
+
+    let history = VersionHistory::open(temp_dir.path(), storage, "")?;
+    let expired = trash.expire(&TrashConfig::default(), |item, content_key| {
+        history.archive_deleted(content_key, &item.file_info, item.deleted_at)?;
+        Ok(())
+    })?;
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].file_info.path, "old.txt");
+
+    let remaining = trash.list()?;
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0].file_info.path, "recent.txt");
+    let versions = history.list("old.txt")?;
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].replaced_by, ChangeKind::Deleted);
+    assert_eq!(
+        versions[0].archived_at.timestamp_millis(),
+        deleted_at.timestamp_millis()
+    );
+
+    Ok(())
+}
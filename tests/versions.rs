@@ -0,0 +1,257 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use syncpair::client::SimpleClient;
+use syncpair::types::{FileInfo, SyncRequest, SyncResponse, VersionOrder, VersionVector};
+
+#[path = "common/mod.rs"]
+mod common;
+
+fn version(counts: &[(&str, u64)]) -> VersionVector {
+    let mut version = VersionVector::default();
+    for &(client, count) in counts {
+        for _ in 0..count {
+            version = version.incremented(client);
+        }
+    }
+    version
+}
+
+#[test]
+fn test_version_vector_order() {
+    let base = version(&[("alice", 1)]);
+    let bob_edit = base.incremented("bob");
+    let alice_edit = base.incremented("alice");
+
+    assert_eq!(base.compare(&base), VersionOrder::Equal);
+    assert_eq!(bob_edit.compare(&base), VersionOrder::Dominates);
+    assert_eq!(base.compare(&bob_edit), VersionOrder::Dominated);
+    assert_eq!(bob_edit.compare(&alice_edit), VersionOrder::Concurrent);
+    assert_eq!(
+        VersionVector::default().compare(&base),
+        VersionOrder::Dominated
+    );
+
+    let merged = bob_edit.merged(&alice_edit);
+    assert_eq!(merged, version(&[("alice", 2), ("bob", 1)]));
+    assert_eq!(merged.compare(&bob_edit), VersionOrder::Dominates);
+    assert_eq!(merged.compare(&alice_edit), VersionOrder::Dominates);
+}
+
+#[tokio::test]
+async fn test_sync_orders_by_version_not_clock() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let port = 9029;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    let response = http
+        .put(format!("{}/files/a.txt?directory=docs", base))
+        .header("x-syncpair-hash", common::hash("alice's"))
+        .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+        .header("x-syncpair-version", "%7B%22alice%22%3A1%7D")
+        .body("alice's")
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+
+    // The client's clock is a day behind; only the version tells the edits apart
+    let skewed = chrono::Utc::now() - chrono::Duration::days(1);
+    let sync = |version: VersionVector| {
+        let file = FileInfo {
+            path: "a.txt".to_string(),
+            hash: common::hash("bob's"),
+            size: 5,
+            modified: skewed,
+            version,
+        };
+        http.post(format!("{}/sync", base))
+            .json(&SyncRequest {
+                files: HashMap::from([("a.txt".to_string(), file)]),
+                deleted_files: Default::default(),
+                deleted_versions: Default::default(),
+                deleted_base_hashes: Default::default(),
+                last_sync: skewed,
+                client_id: Some("bob".to_string()),
+                directory: Some("docs".to_string()),
+            })
+            .send()
+    };
+
+    // An edit made on top of the server's version replaces it
+    let response: SyncResponse = sync(version(&[("alice", 1), ("bob", 1)]))
+        .await?
+        .json()
+        .await?;
+    assert_eq!(response.files_to_upload, vec!["a.txt".to_string()]);
+    assert!(response.conflicts.is_empty());
+
+    // An edit that never saw the server's version is a conflict
+    let response: SyncResponse = sync(version(&[("bob", 1)])).await?.json().await?;
+    assert!(response.files_to_upload.is_empty());
+    assert_eq!(response.conflicts.len(), 1);
+    assert_eq!(
+        response.conflicts[0].server_file.version,
+        version(&[("alice", 1)])
+    );
+
+    // Without any history, the older timestamp loses
+    let response: SyncResponse = sync(VersionVector::default()).await?.json().await?;
+    assert!(response.files_to_upload.is_empty());
+    assert!(response.conflicts.is_empty());
+    assert_eq!(response.files_to_download[0].hash, common::hash("alice's"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_concurrent_edit_and_deletion_keep_the_edit() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let storage = temp_dir.path().join("storage");
+    let port = 9070;
+    common::setup_server(port, storage.clone()).await?;
+    let http = reqwest::Client::new();
+    let base = format!("http://localhost:{}", port);
+
+    // Alice edited both files twice
+    for (path, content) in [("a.txt", "alice's"), ("b.txt", "alice's b")] {
+        let response = http
+            .put(format!("{}/files/{}?directory=docs", base, path))
+            .header("x-syncpair-hash", common::hash(content))
+            .header("x-syncpair-modified", chrono::Utc::now().to_rfc3339())
+            .header("x-syncpair-version", "%7B%22alice%22%3A2%7D")
+            .body(content)
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200);
+    }
+
+    // Bob's clock is a day ahead, and his deletion never saw alice's second edit
+    let ahead = chrono::Utc::now() + chrono::Duration::days(1);
+    let deletion_version = version(&[("alice", 1), ("bob", 1)]);
+    let response: SyncResponse = http
+        .post(format!("{}/sync", base))
+        .json(&SyncRequest {
+            files: Default::default(),
+            deleted_files: HashMap::from([("a.txt".to_string(), ahead)]),
+            deleted_versions: HashMap::from([("a.txt".to_string(), deletion_version.clone())]),
+            deleted_base_hashes: Default::default(),
+            last_sync: ahead,
+            client_id: Some("bob".to_string()),
+            directory: Some("docs".to_string()),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(storage.join("docs/a.txt").exists());
+    assert!(response
+        .files_to_download
+        .iter()
+        .any(|file| file.path == "a.txt"));
+
+    // Likewise the other way around: a deletion on the server doesn't win over a
+    // concurrent edit just because the deleting clock is ahead
+    let response = http
+        .post(format!("{}/delete", base))
+        .json(&serde_json::json!({
+            "path": "b.txt",
+            "directory": "docs",
+            "version": deletion_version,
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    let edit = FileInfo {
+        path: "b.txt".to_string(),
+        hash: common::hash("carol's b"),
+        size: 9,
+        modified: chrono::Utc::now() - chrono::Duration::days(1),
+        version: version(&[("alice", 2), ("carol", 1)]),
+    };
+    let response: SyncResponse = http
+        .post(format!("{}/sync", base))
+        .json(&SyncRequest {
+            files: HashMap::from([("b.txt".to_string(), edit)]),
+            deleted_files: Default::default(),
+            deleted_versions: Default::default(),
+            deleted_base_hashes: Default::default(),
+            last_sync: chrono::Utc::now(),
+            client_id: Some("carol".to_string()),
+            directory: Some("docs".to_string()),
+        })
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(response.files_to_delete.is_empty());
+    assert!(response.files_to_upload.contains(&"b.txt".to_string()));
+
+    Ok(())
+}
+
+fn set_modified(path: &Path, modified: SystemTime) -> Result<()> {
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(modified)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_edit_with_skewed_clock_is_not_lost() -> Result<()> {
+    common::init_test_logging();
+    let temp_dir = common::create_temp_dir()?;
+    let client_a_dir = temp_dir.path().join("client_a");
+    let client_b_dir = temp_dir.path().join("client_b");
+    std::fs::create_dir_all(&client_a_dir)?;
+    std::fs::create_dir_all(&client_b_dir)?;
+
+    let port = 9030;
+    common::setup_server(port, temp_dir.path().join("storage")).await?;
+    let server_url = format!("http://localhost:{}", port);
+    let client_a = SimpleClient::new(server_url.clone(), client_a_dir.clone())
+        .with_directory("docs".to_string())
+        .with_client_id("alice:docs".to_string());
+    // No client ID: versions are keyed by an ID kept in the state database
+    let client_b = SimpleClient::new(server_url.clone(), client_b_dir.clone())
+        .with_directory("docs".to_string());
+
+    std::fs::write(client_a_dir.join("notes.txt"), "v1")?;
+    std::fs::write(client_a_dir.join("todo.txt"), "todo")?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+
+    // Bob's clock is an hour behind, so his edit looks older than the original
+    let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    std::fs::write(client_b_dir.join("notes.txt"), "bob's edit")?;
+    set_modified(&client_b_dir.join("notes.txt"), an_hour_ago)?;
+    std::fs::remove_file(client_b_dir.join("todo.txt"))?;
+    client_b.initial_sync().await?;
+    client_a.initial_sync().await?;
+
+    assert_eq!(
+        std::fs::read_to_string(client_a_dir.join("notes.txt"))?,
+        "bob's edit"
+    );
+    assert!(!client_a_dir.join("todo.txt").exists());
+    assert!(client_a.conflicts()?.is_empty());
+    assert!(client_b.conflicts()?.is_empty());
+
+    // Once seen, the edit is built on like any other
+    std::fs::write(client_a_dir.join("notes.txt"), "alice's edit")?;
+    set_modified(&client_a_dir.join("notes.txt"), an_hour_ago)?;
+    client_a.initial_sync().await?;
+    client_b.initial_sync().await?;
+    assert_eq!(
+        std::fs::read_to_string(client_b_dir.join("notes.txt"))?,
+        "alice's edit"
+    );
+    assert!(client_b.conflicts()?.is_empty());
+
+    Ok(())
+}